@@ -0,0 +1,693 @@
+//! Command-line interface definition. Running with no subcommand switches
+//! profiles.
+
+use crate::FileFormat;
+use clap::{ArgGroup, Args, Parser, Subcommand};
+use std::path::PathBuf;
+
+#[derive(Debug, Parser)]
+#[command(
+    name = "gs",
+    about = "Switch between Git profiles instantly",
+    after_help = "Profiles are stored in profiles.json in the platform's config directory, or in ~/.config/gs if it already has them ($GS_CONFIG_DIR overrides the directory, --config the file; `gs paths` shows which)."
+)]
+pub struct Cli {
+    #[command(subcommand)]
+    pub command: Option<Commands>,
+
+    /// Switch straight to this profile (a unique prefix is enough)
+    #[arg(value_name = "PROFILE")]
+    pub profile: Option<String>,
+
+    /// Switch to the previous profile instead of the next
+    #[arg(long, conflicts_with = "profile")]
+    pub back: bool,
+
+    /// Choose the profile to switch to from a list
+    #[arg(long, conflicts_with_all = ["profile", "back"])]
+    pub pick: bool,
+
+    /// Show changes to ~/.ssh/config before they are written
+    #[arg(long, global = true)]
+    pub show_diff: bool,
+
+    /// Print everything without color
+    #[arg(long, global = true)]
+    pub no_color: bool,
+
+    /// Read and write this profiles file instead of profiles.json. Git and
+    /// SSH config in your home directory are still changed unless you also
+    /// pass --dry-run
+    #[arg(long, global = true, value_name = "PATH")]
+    pub config: Option<PathBuf>,
+
+    /// Leave git and SSH config alone and print what would change instead
+    #[arg(long, global = true)]
+    pub dry_run: bool,
+
+    /// Print errors, and the output of history, audit, paths and check, as
+    /// JSON
+    #[arg(long, global = true)]
+    pub json: bool,
+
+    /// Answer yes instead of asking: before removing profiles and purging
+    /// keys (rm), rolling back over edited SSH config (rollback) and the
+    /// first SSH config edit. Without it, these fail when there's no
+    /// terminal to ask at
+    #[arg(short = 'y', long, global = true)]
+    pub yes: bool,
+
+    /// Use PROFILE's identity in the current git worktree only
+    #[arg(long, value_name = "PROFILE")]
+    pub worktree: Option<String>,
+
+    /// Only update this SSH host's block (repeatable)
+    #[arg(long = "host", value_name = "HOST")]
+    pub hosts: Vec<String>,
+
+    /// Update SSH config but leave git config alone
+    #[arg(long)]
+    pub ssh_only: bool,
+
+    /// Clear the screen after switching
+    #[arg(long, conflicts_with = "no_clear")]
+    pub clear: bool,
+
+    /// Don't clear the screen after switching
+    #[arg(long)]
+    pub no_clear: bool,
+
+    /// Log files read and written, commands run and decisions made to
+    /// stderr; -vv also logs file contents
+    #[arg(short, long, global = true, action = clap::ArgAction::Count)]
+    pub verbose: u8,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum Commands {
+    /// Add new profile
+    Setup {
+        #[command(flatten)]
+        fields: ProfileFields,
+
+        /// Pre-fill the host and key path: github, gitlab, bitbucket, ghe,
+        /// codeberg, sourcehut, gitea or one from settings
+        #[arg(long, value_name = "PRESET")]
+        preset: Option<String>,
+
+        /// SSH host the profile is for, e.g. github.example.com (default: the preset's, or github.com)
+        #[arg(long, value_name = "HOST")]
+        host: Option<String>,
+
+        /// User to connect to the host as (default: the forge's, or git)
+        #[arg(long, value_name = "USER")]
+        ssh_user: Option<String>,
+
+        /// If a profile with this name exists, update it with the values
+        /// given instead of failing
+        #[arg(long)]
+        update_existing: bool,
+    },
+
+    /// Show all profiles
+    List {
+        /// Also show key fingerprints and whether the live config matches
+        #[arg(short, long)]
+        long: bool,
+
+        /// Print one line per profile from a template using {name},
+        /// {email}, {ssh_key}, {host} and {current}; \t and \n are escapes
+        #[arg(long, value_name = "TEMPLATE", conflicts_with = "long")]
+        format: Option<String>,
+
+        /// Show profiles in this order instead of the stored one, which
+        /// switching by position and cycling follow
+        #[arg(long, value_enum)]
+        sort: Option<ListSort>,
+
+        /// Reverse the order
+        #[arg(long)]
+        reverse: bool,
+    },
+
+    /// Edit an existing profile
+    Edit {
+        /// Profile to edit (prompts when omitted)
+        #[arg(value_name = "PROFILE")]
+        profile: Option<String>,
+
+        /// Edit all profiles as JSON in $VISUAL or $EDITOR, checked before saving
+        #[arg(long, conflicts_with = "profile")]
+        raw: bool,
+
+        #[command(flatten)]
+        fields: ProfileFields,
+    },
+
+    /// Rename a profile
+    Rename {
+        #[arg(value_name = "PROFILE")]
+        profile: String,
+        #[arg(value_name = "NEW_NAME")]
+        new_name: String,
+    },
+
+    /// Create a new profile from an existing one
+    #[command(alias = "cp")]
+    Copy {
+        #[arg(value_name = "PROFILE")]
+        profile: String,
+        #[arg(value_name = "NEW_NAME")]
+        new_name: String,
+    },
+
+    /// Remove one or more profiles. Asks first, twice; --yes skips asking
+    #[command(alias = "remove")]
+    Rm {
+        /// Profiles to remove (prompts when omitted)
+        #[arg(value_name = "NAME")]
+        names: Vec<String>,
+
+        /// Also unload the SSH key from the agent and delete the key pair,
+        /// after asking (--yes skips asking)
+        #[arg(long)]
+        purge: bool,
+
+        /// Allow --purge to delete keys outside ~/.ssh
+        #[arg(long, requires = "purge")]
+        force: bool,
+    },
+
+    /// Make gs available as `git profile`
+    InstallGitAlias {
+        /// Create a git-profile link in DIR instead of setting a git alias
+        #[arg(long, value_name = "DIR")]
+        link: Option<PathBuf>,
+    },
+
+    /// Check recent commits for identities that don't match a profile
+    VerifyCommits {
+        /// Number of commits to check
+        #[arg(short = 'n', value_name = "N", default_value_t = 20)]
+        count: usize,
+
+        /// Only accept commits made with this profile's email
+        #[arg(long, value_name = "PROFILE")]
+        expect: Option<String>,
+    },
+
+    /// Install post-checkout and post-merge hooks that warn when this
+    /// repository's identity isn't the profile it expects
+    InstallHook {
+        /// The profile this repository expects, kept as gs.profile in its
+        /// git config (without it, any profile's email passes)
+        #[arg(long, value_name = "PROFILE", conflicts_with = "remove")]
+        profile: Option<String>,
+
+        /// Install a pre-commit hook instead, checking before every commit
+        #[arg(long)]
+        pre_commit: bool,
+
+        /// Make the pre-commit hook refuse commits with the wrong identity
+        #[arg(long, requires = "pre_commit", conflicts_with = "remove")]
+        enforce: bool,
+
+        /// Take the hooks out again, restoring any they replaced (only the
+        /// pre-commit hook with --pre-commit)
+        #[arg(long)]
+        remove: bool,
+    },
+
+    /// Check this repository's user.email against the profile it expects
+    /// (run by the hooks from install-hook)
+    CheckRepo {
+        /// Say nothing when the identity is right
+        #[arg(long, short)]
+        quiet: bool,
+
+        /// Say that the commit is blocked on a mismatch (for the enforcing
+        /// pre-commit hook)
+        #[arg(long)]
+        enforce: bool,
+    },
+
+    /// Check that a profile's SSH key authenticates with its hosts
+    Test {
+        /// Profile to test (default: current)
+        #[arg(value_name = "PROFILE")]
+        profile: Option<String>,
+
+        /// Test every profile
+        #[arg(long, conflicts_with = "profile")]
+        all: bool,
+
+        /// Seconds to wait for each host
+        #[arg(long, value_name = "SECONDS", default_value_t = 10)]
+        timeout: u64,
+    },
+
+    /// Compare two profiles field by field
+    Diff {
+        #[arg(value_name = "PROFILE")]
+        first: String,
+
+        /// Profile to compare with (default: active)
+        #[arg(value_name = "OTHER")]
+        second: Option<String>,
+    },
+
+    /// Print a profile's public key
+    Pubkey {
+        /// Profile whose key to print (default: active)
+        #[arg(value_name = "PROFILE")]
+        profile: Option<String>,
+
+        /// Put the key on the clipboard instead of printing it
+        #[arg(long)]
+        copy: bool,
+    },
+
+    /// Show which account the current profile's key authenticates as
+    Whoami {
+        /// Seconds to wait for the host
+        #[arg(long, value_name = "SECONDS", default_value_t = 10)]
+        timeout: u64,
+    },
+
+    /// Amend the last commit with the current profile's identity
+    FixAuthor {
+        /// Amend even if there are staged changes
+        #[arg(long)]
+        allow_staged: bool,
+    },
+
+    /// Export profiles to a file or stdout
+    Export {
+        /// Write to FILE instead of stdout
+        #[arg(value_name = "FILE")]
+        file: Option<PathBuf>,
+
+        /// File format (detected from the extension by default)
+        #[arg(long, value_enum)]
+        format: Option<FileFormat>,
+    },
+
+    /// Import profiles from a file
+    Import {
+        #[arg(value_name = "FILE", required_unless_present = "from_gitconfig")]
+        file: Option<PathBuf>,
+
+        /// File format (detected from the extension by default)
+        #[arg(long, value_enum)]
+        format: Option<FileFormat>,
+
+        /// Create profiles from the includeIf sections of your global git
+        /// config, and autoswitch rules for their directories
+        #[arg(long, conflicts_with_all = ["file", "format", "on_conflict"])]
+        from_gitconfig: bool,
+
+        /// What to do with a profile whose name is already taken (default:
+        /// ask at a terminal, skip otherwise)
+        #[arg(long, value_enum, value_name = "STRATEGY")]
+        on_conflict: Option<OnConflict>,
+    },
+
+    /// Create profiles for SSH keys in ~/.ssh that no profile uses yet
+    ImportKeys,
+
+    /// Create profiles from another switcher's configuration
+    MigrateFrom {
+        /// git-profiles (~/.git-profiles ini), git-profile (npm JSON), or a
+        /// path to a file in either format
+        #[arg(value_name = "TOOL|PATH")]
+        source: String,
+    },
+
+    /// Share profiles through a dotfiles directory or git repo
+    #[command(group(ArgGroup::new("direction").args(["push", "pull"]).required(true)))]
+    Sync {
+        /// Write local profiles to the sync remote
+        #[arg(long, conflicts_with = "pull")]
+        push: bool,
+
+        /// Merge profiles from the sync remote
+        #[arg(long)]
+        pull: bool,
+
+        /// What to do with a pulled profile whose name is already taken
+        /// (default: ask at a terminal, skip otherwise)
+        #[arg(long, value_enum, value_name = "STRATEGY", requires = "pull")]
+        on_conflict: Option<OnConflict>,
+    },
+
+    /// Switch to a group of profiles, or list the groups
+    Group {
+        #[arg(value_name = "NAME")]
+        name: Option<String>,
+    },
+
+    /// Mark a profile as the default, or print which one is
+    Default {
+        #[arg(value_name = "PROFILE")]
+        profile: Option<String>,
+    },
+
+    /// Switch to the default profile
+    Reset,
+
+    /// Show or change settings
+    Settings {
+        #[arg(value_name = "KEY")]
+        key: Option<String>,
+        #[arg(value_name = "VALUE", requires = "key")]
+        value: Option<String>,
+    },
+
+    /// Revert the last change to your profiles
+    Undo {
+        /// Show what each undo step would restore
+        #[arg(long)]
+        list: bool,
+    },
+
+    /// Put global git settings and SSH config back the way they were before
+    /// the last switch, leaving no profile current. Asks before restoring
+    /// SSH config edited since; --yes skips asking
+    Rollback,
+
+    /// List or restore earlier copies of profiles.json
+    Backups {
+        #[command(subcommand)]
+        command: BackupsCommand,
+    },
+
+    /// Show recent profile switches
+    History {
+        /// Number of switches to show
+        #[arg(short = 'n', value_name = "N", default_value_t = 20)]
+        count: usize,
+    },
+
+    /// Show recent changes gs made to git and SSH config
+    Audit {
+        /// Number of changes to show
+        #[arg(short = 'n', value_name = "N", default_value_t = 20)]
+        count: usize,
+    },
+
+    /// Show the files gs reads and writes
+    Paths,
+
+    /// Check profiles, git and SSH config for problems
+    Doctor {
+        /// Add missing host keys to ~/.ssh/known_hosts, checked against the
+        /// published fingerprints for github.com and gitlab.com, and write
+        /// missing or stale .pub files from their private keys
+        #[arg(long)]
+        fix: bool,
+    },
+
+    /// Validate a profiles file without changing or running anything
+    Check {
+        /// File to check (default: the live profiles.json)
+        #[arg(value_name = "FILE")]
+        file: Option<PathBuf>,
+
+        /// File format (detected from the extension by default)
+        #[arg(long, value_enum)]
+        format: Option<FileFormat>,
+
+        /// Don't check that SSH keys exist
+        #[arg(long)]
+        no_fs: bool,
+    },
+
+    /// Print the active profile
+    Current {
+        /// Template using {name}, {email}, {ssh_key}, {host} and {current}
+        #[arg(long, value_name = "TEMPLATE")]
+        format: Option<String>,
+    },
+
+    /// Show the active profile and where git's identity comes from
+    Status,
+
+    /// Print shell exports that make git use a profile
+    Env {
+        /// Profile to export (default: active)
+        #[arg(value_name = "PROFILE")]
+        profile: Option<String>,
+    },
+
+    /// Run one command with a profile's identity, without switching
+    Exec {
+        #[arg(value_name = "PROFILE")]
+        profile: String,
+
+        /// Command and arguments, after `--`
+        #[arg(last = true, required = true, value_name = "COMMAND")]
+        command: Vec<String>,
+    },
+
+    /// Start a shell that uses a profile's identity until you exit it
+    Shell {
+        #[arg(value_name = "PROFILE")]
+        profile: String,
+    },
+
+    /// Print (or write to ./.envrc) a direnv snippet for a profile
+    Direnv {
+        #[arg(value_name = "PROFILE", required_unless_present = "update")]
+        profile: Option<String>,
+
+        /// Add the snippet to ./.envrc, replacing an earlier gs section
+        #[arg(long)]
+        write: bool,
+
+        /// Regenerate the gs section already in ./.envrc
+        #[arg(long, conflicts_with_all = ["profile", "write"])]
+        update: bool,
+    },
+
+    /// Print a short segment for your shell prompt
+    Prompt {
+        /// `starship`, `p10k`, or a template using {icon}, {name}, {email}
+        /// and {group}
+        #[arg(long, value_name = "FORMAT")]
+        format: Option<String>,
+
+        /// Never add color
+        #[arg(long)]
+        plain: bool,
+    },
+
+    /// Manage a profile's SSH key
+    Key {
+        #[command(subcommand)]
+        command: KeyCommand,
+    },
+
+    /// Print a shell hook that switches profiles as you change directory
+    Hook {
+        #[arg(value_enum)]
+        shell: HookShell,
+    },
+
+    /// Switch to the profile mapped to a directory (run by `gs hook`)
+    Autoswitch {
+        /// Directory to look up
+        #[arg(long, value_name = "DIR")]
+        dir: PathBuf,
+    },
+
+    /// Browse and manage profiles in a full-screen view
+    Ui,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum BackupsCommand {
+    /// Show the backups, newest first
+    List,
+
+    /// Replace profiles.json with a backup
+    Restore {
+        /// Timestamp of the backup (a unique prefix is enough), or "latest"
+        #[arg(value_name = "TIMESTAMP")]
+        backup: String,
+    },
+}
+
+#[derive(Debug, Subcommand)]
+pub enum KeyCommand {
+    /// Replace a profile's key pair, keeping the old one as a backup
+    Rotate {
+        #[arg(value_name = "PROFILE")]
+        profile: String,
+
+        /// Show what would happen without changing anything
+        #[arg(long)]
+        dry_run: bool,
+
+        /// Add the new key to the SSH agent
+        #[arg(long)]
+        add: bool,
+    },
+
+    /// Add a profile's public key to your account on a forge
+    #[command(group(ArgGroup::new("forge").args(["github", "gitlab", "gitea"])))]
+    Upload {
+        #[arg(value_name = "PROFILE")]
+        profile: String,
+
+        /// Upload to GitHub (or GitHub Enterprise, from the profile's host)
+        #[arg(long)]
+        github: bool,
+
+        /// Upload to GitLab (gitlab.com, or the profile's self-hosted host)
+        #[arg(long)]
+        gitlab: bool,
+
+        /// Upload to Gitea or Forgejo (codeberg.org, or the profile's
+        /// self-hosted host)
+        #[arg(long)]
+        gitea: bool,
+
+        /// GitHub API base URL or GitLab/Gitea instance URL, overriding the
+        /// one derived from the profile's host
+        #[arg(long, value_name = "URL")]
+        url: Option<String>,
+
+        /// API token (default: $GITHUB_TOKEN or `gh auth token`; $GITLAB_TOKEN; $GITEA_TOKEN)
+        #[arg(long, value_name = "TOKEN")]
+        token: Option<String>,
+    },
+}
+
+#[derive(Debug, Clone, Copy, clap::ValueEnum)]
+pub enum ListSort {
+    /// Alphabetically by name
+    Name,
+    /// Most recently switched to first
+    Recent,
+    /// Oldest first, by creation time
+    Created,
+}
+
+impl ListSort {
+    pub fn label(self) -> &'static str {
+        match self {
+            ListSort::Name => "name",
+            ListSort::Recent => "last use",
+            ListSort::Created => "creation time",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, clap::ValueEnum)]
+pub enum OnConflict {
+    /// Keep the local profile
+    Skip,
+    /// Replace the local profile with the imported one
+    Overwrite,
+    /// Import it as <name>-imported
+    Rename,
+    /// Show how they differ and ask for each profile
+    Ask,
+}
+
+#[derive(Debug, Clone, Copy, clap::ValueEnum)]
+pub enum HookShell {
+    Zsh,
+    Bash,
+    Fish,
+}
+
+/// Profile fields passed as flags to `setup` and `edit`, for use without a
+/// terminal.
+#[derive(Debug, Default, Args)]
+pub struct ProfileFields {
+    /// Profile name, the label you switch by
+    #[arg(long, value_name = "NAME")]
+    pub name: Option<String>,
+
+    /// Name to commit under, written as git's user.name (default, or
+    /// empty: the profile name)
+    #[arg(long, value_name = "NAME")]
+    pub git_name: Option<String>,
+
+    /// Git email
+    #[arg(long, value_name = "EMAIL")]
+    pub email: Option<String>,
+
+    /// Path to the SSH private key, or "" to use whatever keys the SSH agent
+    /// holds
+    #[arg(long = "key", value_name = "PATH")]
+    pub ssh_key: Option<String>,
+
+    /// Free-form notes (empty to clear)
+    #[arg(long, value_name = "TEXT")]
+    pub description: Option<String>,
+
+    /// Group to cycle within (empty to clear)
+    #[arg(long, value_name = "GROUP")]
+    pub group: Option<String>,
+
+    /// Commit message template to use as commit.template (empty to clear)
+    #[arg(long, value_name = "PATH")]
+    pub commit_template: Option<String>,
+
+    /// Global gitignore to use as core.excludesFile (empty to clear)
+    #[arg(long, value_name = "PATH")]
+    pub excludes_file: Option<String>,
+
+    /// Branch name to use as init.defaultBranch (empty to clear)
+    #[arg(long, value_name = "BRANCH")]
+    pub default_branch: Option<String>,
+
+    /// Sign commits with the profile's SSH key
+    #[arg(long, value_name = "BOOL")]
+    pub signing: Option<bool>,
+
+    /// Icon shown next to the name in list, switch and prompt output (empty to clear)
+    #[arg(long, value_name = "ICON")]
+    pub icon: Option<String>,
+
+    /// Color for the name, e.g. blue, bright green or #ff8800 (empty to clear)
+    #[arg(long, value_name = "COLOR")]
+    pub color: Option<String>,
+
+    /// Extra line for the profile's SSH Host block, e.g. "ProxyJump bastion"
+    /// (repeatable; replaces the list, "" clears it)
+    #[arg(long = "ssh-option", value_name = "LINE")]
+    pub ssh_options: Vec<String>,
+
+    /// Agent socket for the profile's SSH Host blocks, e.g. 1Password's
+    /// (empty to clear)
+    #[arg(long, value_name = "SOCKET")]
+    pub identity_agent: Option<String>,
+
+    /// Account name on the profile's main host, checked by `gs whoami`
+    /// (empty to clear)
+    #[arg(long, value_name = "NAME")]
+    pub username: Option<String>,
+
+    /// Don't refuse an SSH key another profile already uses
+    #[arg(long)]
+    pub allow_shared_key: bool,
+}
+
+impl ProfileFields {
+    pub fn is_empty(&self) -> bool {
+        self.name.is_none() && self.git_name.is_none() && self.email.is_none() && self.ssh_key.is_none() && self.description.is_none()
+            && self.group.is_none()
+            && self.commit_template.is_none()
+            && self.excludes_file.is_none()
+            && self.default_branch.is_none()
+            && self.signing.is_none()
+            && self.icon.is_none()
+            && self.color.is_none()
+            && self.ssh_options.is_empty()
+            && self.identity_agent.is_none()
+            && self.username.is_none()
+    }
+}
@@ -0,0 +1,155 @@
+//! Exit codes, and the error type that carries one up to `main`.
+
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+/// Process exit codes. Scripts rely on these, so never renumber them; see
+/// the Exit codes section of the README.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExitCode {
+    /// Anything not covered below, a name or file that's taken included.
+    Error = 1,
+    /// Bad arguments or input. clap uses this for parse errors too.
+    Usage = 2,
+    /// There are no profiles yet.
+    NoProfiles = 3,
+    /// The named profile (or the current one), or a key or file it needs,
+    /// doesn't exist.
+    NotFound = 4,
+    /// The user declined a confirmation or closed the input.
+    Aborted = 5,
+    /// git, ssh-keygen or another program failed or couldn't be run.
+    External = 6,
+}
+
+impl ExitCode {
+    /// The stable name `--json` reports for this code.
+    pub fn name(self) -> &'static str {
+        match self {
+            ExitCode::Error => "error",
+            ExitCode::Usage => "usage",
+            ExitCode::NoProfiles => "no_profiles",
+            ExitCode::NotFound => "not_found",
+            ExitCode::Aborted => "aborted",
+            ExitCode::External => "external",
+        }
+    }
+}
+
+/// An error with a specific exit code. Attach it with `failure` or
+/// `ExternalContext::external`; anything else exits with `ExitCode::Error`.
+/// The profile and path, when set, are reported as fields under `--json`.
+#[derive(Debug)]
+pub struct Failure {
+    pub code: ExitCode,
+    pub message: String,
+    pub profile: Option<String>,
+    pub path: Option<PathBuf>,
+}
+
+impl Failure {
+    pub fn new(code: ExitCode, message: impl Into<String>) -> Self {
+        Failure { code, message: message.into(), profile: None, path: None }
+    }
+
+    pub fn profile(mut self, name: &str) -> Self {
+        self.profile = Some(name.to_string());
+        self
+    }
+
+    pub fn path(mut self, path: impl AsRef<Path>) -> Self {
+        self.path = Some(path.as_ref().to_path_buf());
+        self
+    }
+}
+
+impl fmt::Display for Failure {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.message)
+    }
+}
+
+impl std::error::Error for Failure {}
+
+pub fn failure(code: ExitCode, message: impl Into<String>) -> anyhow::Error {
+    anyhow::Error::new(Failure::new(code, message))
+}
+
+/// The outermost `Failure` in an error's chain.
+fn find_failure(err: &anyhow::Error) -> Option<&Failure> {
+    err.downcast_ref::<Failure>()
+        .or_else(|| err.chain().find_map(|e| e.downcast_ref::<Failure>()))
+}
+
+/// The exit code for an error: the outermost `Failure` in its chain.
+pub fn exit_code(err: &anyhow::Error) -> ExitCode {
+    find_failure(err).map_or(ExitCode::Error, |f| f.code)
+}
+
+/// An error as the single JSON object `--json` prints: the code's name and
+/// number, the outermost message, the rest of the chain as `causes`, and
+/// the profile and path the error is about when known.
+pub fn to_json(err: &anyhow::Error) -> serde_json::Value {
+    let code = exit_code(err);
+    let mut object = serde_json::json!({
+        "code": code.name(),
+        "exit_code": code as u8,
+        "message": err.to_string(),
+        "causes": err.chain().skip(1).map(ToString::to_string).collect::<Vec<_>>(),
+    });
+    if let Some(failure) = find_failure(err) {
+        if let Some(profile) = &failure.profile {
+            object["profile"] = profile.as_str().into();
+        }
+        if let Some(path) = &failure.path {
+            object["path"] = path.display().to_string().into();
+        }
+    }
+    object
+}
+
+/// Like anyhow's `context`, for spawning external programs: the error also
+/// carries `ExitCode::External`.
+pub trait ExternalContext<T> {
+    fn external(self, what: &str) -> anyhow::Result<T>;
+}
+
+impl<T> ExternalContext<T> for std::io::Result<T> {
+    fn external(self, what: &str) -> anyhow::Result<T> {
+        self.map_err(|e| {
+            anyhow::Error::new(e).context(Failure::new(ExitCode::External, what))
+        })
+    }
+}
+
+/// Like anyhow's `context`, for reading or writing a file: the error names
+/// the path and what the OS said, tells a missing file, a permission
+/// problem and a directory apart, and suggests a fix for permissions.
+pub trait FileContext<T> {
+    fn file_context(self, action: &str, path: &Path) -> anyhow::Result<T>;
+}
+
+impl<T> FileContext<T> for std::io::Result<T> {
+    fn file_context(self, action: &str, path: &Path) -> anyhow::Result<T> {
+        self.map_err(|e| file_error(e, action, path))
+    }
+}
+
+pub fn file_error(err: std::io::Error, action: &str, path: &Path) -> anyhow::Error {
+    let shown = path.display();
+    let message = match err.kind() {
+        std::io::ErrorKind::NotFound => format!("Can't {} {}: it doesn't exist ({})", action, shown, err),
+        std::io::ErrorKind::PermissionDenied => {
+            let mode = if path.is_dir() { "u+rwx" } else { "u+rw" };
+            format!(
+                "Can't {} {} ({}). If it belongs to another user, fix it with: sudo chown \"$USER\" '{}' && chmod {} '{}'",
+                action, shown, err, shown, mode, shown
+            )
+        }
+        std::io::ErrorKind::IsADirectory => {
+            format!("Can't {} {}: it's a directory, not a file ({})", action, shown, err)
+        }
+        _ => format!("Can't {} {}: {}", action, shown, err),
+    };
+    Failure::new(ExitCode::Error, message).path(path).into()
+}
@@ -0,0 +1,336 @@
+//! Registering public keys with a forge's API, for `gs key upload`.
+//!
+//! Transport and HTTP failures are turned into messages that say what to do
+//! about them here, so callers can pass errors straight up.
+
+use crate::error::{failure, ExitCode};
+use anyhow::Result;
+use serde_json::{json, Value};
+use std::time::Duration;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Forge {
+    GitHub,
+    GitLab,
+    /// Gitea and Forgejo servers, Codeberg among them.
+    Gitea,
+}
+
+impl Forge {
+    pub fn label(self) -> &'static str {
+        match self {
+            Forge::GitHub => "GitHub",
+            Forge::GitLab => "GitLab",
+            Forge::Gitea => "Gitea",
+        }
+    }
+
+    /// Environment variable holding an API token.
+    pub fn token_env(self) -> &'static str {
+        match self {
+            Forge::GitHub => "GITHUB_TOKEN",
+            Forge::GitLab => "GITLAB_TOKEN",
+            Forge::Gitea => "GITEA_TOKEN",
+        }
+    }
+
+    /// The instance to use for a profile's host. A profile without its own
+    /// host defaults to github.com, which means gitlab.com for GitLab and
+    /// codeberg.org for Gitea.
+    pub fn instance_host(self, host: &str) -> &str {
+        match self {
+            Forge::GitLab if host == "github.com" => "gitlab.com",
+            Forge::Gitea if host == "github.com" => "codeberg.org",
+            _ => host,
+        }
+    }
+
+    /// API root for a forge host: the hosted service or a self-hosted
+    /// server on the same host as the git remote.
+    pub fn api_url(self, host: &str) -> String {
+        match self {
+            Forge::GitHub if host == "github.com" => "https://api.github.com".to_string(),
+            Forge::GitHub => format!("https://{}/api/v3", host),
+            Forge::GitLab => format!("https://{}/api/v4", host),
+            Forge::Gitea => format!("https://{}/api/v1", host),
+        }
+    }
+
+    /// Whether a failed upload means the key is registered already, on this
+    /// account or another.
+    fn is_duplicate(self, status: u16, body: &Value) -> bool {
+        match self {
+            Forge::GitHub => status == 422 && mentions(body, "already in use"),
+            Forge::GitLab => status == 400 && mentions(body, "has already been taken"),
+            Forge::Gitea => status == 422 && mentions(body, "has been used"),
+        }
+    }
+
+    fn scope_hint(self) -> &'static str {
+        match self {
+            Forge::GitHub => "Classic tokens need the admin:public_key scope; fine-grained ones the \"Git SSH keys\" write permission.",
+            Forge::GitLab => "Personal access tokens need the api scope.",
+            Forge::Gitea => "Access tokens need the write:user scope.",
+        }
+    }
+
+    /// Query string for one page of the user's keys, as large as allowed.
+    fn keys_page(self) -> &'static str {
+        match self {
+            Forge::GitHub | Forge::GitLab => "per_page=100",
+            Forge::Gitea => "limit=50",
+        }
+    }
+}
+
+/// What happened to the key. Both carry the forge's ID for it.
+#[derive(Debug, PartialEq)]
+pub enum Outcome {
+    Added(u64),
+    AlreadyPresent(u64),
+}
+
+/// Add `key` to the token owner's account under `title`.
+pub fn upload_key(forge: Forge, api_url: &str, token: &str, title: &str, key: &str) -> Result<Outcome> {
+    let api_url = api_url.trim_end_matches('/');
+    let (status, body) = send(forge, &format!("{}/user/keys", api_url), token, Some(json!({ "title": title, "key": key })))?;
+
+    match status {
+        200 | 201 => Ok(Outcome::Added(key_id(forge, &body)?)),
+        status if forge.is_duplicate(status, &body) => {
+            // Either it's already on this account, or someone else has it
+            let (status, keys) = send(forge, &format!("{}/user/keys?{}", api_url, forge.keys_page()), token, None)?;
+            if status != 200 {
+                return Err(status_error(forge, status, &keys));
+            }
+            let material = key_material(key);
+            let existing = keys
+                .as_array()
+                .into_iter()
+                .flatten()
+                .find(|k| k["key"].as_str().map(key_material).as_ref() == Some(&material));
+            match existing {
+                Some(existing) => Ok(Outcome::AlreadyPresent(key_id(forge, existing)?)),
+                None => Err(failure(
+                    ExitCode::External,
+                    format!(
+                        "{} says this key is already in use by another account. A key can only belong to one account; rotate it with `gs key rotate`.",
+                        forge.label()
+                    ),
+                )),
+            }
+        }
+        _ => Err(status_error(forge, status, &body)),
+    }
+}
+
+/// POST `body` to `url`, or GET it without one. Returns the status and the
+/// parsed response, with anything other than JSON kept as a string.
+fn send(forge: Forge, url: &str, token: &str, body: Option<Value>) -> Result<(u16, Value)> {
+    let agent: ureq::Agent = ureq::Agent::config_builder()
+        .http_status_as_error(false)
+        .timeout_global(Some(Duration::from_secs(30)))
+        .user_agent(concat!("gs/", env!("CARGO_PKG_VERSION")))
+        .build()
+        .into();
+
+    let (auth_header, auth_value) = match forge {
+        Forge::GitHub => ("Authorization", format!("Bearer {}", token)),
+        Forge::GitLab => ("PRIVATE-TOKEN", token.to_string()),
+        Forge::Gitea => ("Authorization", format!("token {}", token)),
+    };
+    let response = match body {
+        Some(body) => agent
+            .post(url)
+            .header(auth_header, &auth_value)
+            .header("Accept", "application/json")
+            .send_json(body),
+        None => agent
+            .get(url)
+            .header(auth_header, &auth_value)
+            .header("Accept", "application/json")
+            .call(),
+    };
+    let mut response = response.map_err(|err| network_error(forge, url, err))?;
+
+    let status = response.status().as_u16();
+    let text = response.body_mut().read_to_string().unwrap_or_default();
+    let body = serde_json::from_str(&text).unwrap_or(Value::String(text));
+    Ok((status, body))
+}
+
+fn network_error(forge: Forge, url: &str, err: ureq::Error) -> anyhow::Error {
+    let reason = match &err {
+        ureq::Error::HostNotFound => "the host name doesn't resolve".to_string(),
+        ureq::Error::ConnectionFailed => "the connection was refused".to_string(),
+        ureq::Error::Timeout(_) => "the request timed out".to_string(),
+        err => err.to_string(),
+    };
+    failure(
+        ExitCode::External,
+        format!(
+            "Could not reach the {} API at {}: {}. Check your network connection, or pass --url if the server lives elsewhere.",
+            forge.label(),
+            url,
+            reason
+        ),
+    )
+}
+
+fn status_error(forge: Forge, status: u16, body: &Value) -> anyhow::Error {
+    let message = match status {
+        401 => format!(
+            "{} rejected the token (HTTP 401). Check that it's valid and hasn't expired.",
+            forge.label()
+        ),
+        403 | 404 => format!(
+            "The token isn't allowed to add SSH keys (HTTP {}). {}",
+            status,
+            forge.scope_hint()
+        ),
+        _ => format!("The {} API answered HTTP {}: {}", forge.label(), status, error_text(body)),
+    };
+    failure(ExitCode::External, message)
+}
+
+fn key_id(forge: Forge, body: &Value) -> Result<u64> {
+    body["id"].as_u64().ok_or_else(|| {
+        failure(
+            ExitCode::External,
+            format!("Unexpected response from the {} API: {}", forge.label(), error_text(body)),
+        )
+    })
+}
+
+/// Whether an error response mentions `needle` anywhere in its messages.
+fn mentions(body: &Value, needle: &str) -> bool {
+    error_text(body).contains(needle)
+}
+
+fn error_text(body: &Value) -> String {
+    match body {
+        Value::String(text) => text.trim().to_string(),
+        body => body.to_string(),
+    }
+}
+
+/// `ssh-ed25519 AAAA...` without the comment, which forges drop.
+fn key_material(key: &str) -> String {
+    key.split_whitespace().take(2).collect::<Vec<_>>().join(" ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::exit_code;
+    use std::io::{BufRead, BufReader, Read, Write};
+    use std::net::TcpListener;
+    use std::thread::JoinHandle;
+
+    const KEY: &str = "ssh-ed25519 AAAAC3NzaC1lZDI1NTE5AAAAIOr4 jane@laptop";
+
+    /// A server on a free local port that answers one request per entry in
+    /// `responses`, in order, and hands back the requests it got: the
+    /// request line, then the body.
+    fn serve(responses: &[(u16, &str)]) -> (String, JoinHandle<Vec<(String, String)>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/api/v4", listener.local_addr().unwrap());
+        let responses: Vec<(u16, String)> = responses.iter().map(|(status, body)| (*status, body.to_string())).collect();
+        let server = std::thread::spawn(move || {
+            let mut requests = Vec::new();
+            for (status, body) in responses {
+                let (mut stream, _) = listener.accept().unwrap();
+                let mut reader = BufReader::new(stream.try_clone().unwrap());
+                let mut request_line = String::new();
+                reader.read_line(&mut request_line).unwrap();
+                let mut length = 0;
+                loop {
+                    let mut header = String::new();
+                    reader.read_line(&mut header).unwrap();
+                    if header.trim().is_empty() {
+                        break;
+                    }
+                    if let Some((name, value)) = header.split_once(':') {
+                        match name.to_ascii_lowercase().as_str() {
+                            "content-length" => length = value.trim().parse().unwrap(),
+                            "private-token" => assert_eq!(value.trim(), "secret"),
+                            _ => {}
+                        }
+                    }
+                }
+                let mut request_body = vec![0; length];
+                reader.read_exact(&mut request_body).unwrap();
+                requests.push((request_line.trim().to_string(), String::from_utf8(request_body).unwrap()));
+                write!(
+                    stream,
+                    "HTTP/1.1 {} Mock\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                    status,
+                    body.len(),
+                    body
+                )
+                .unwrap();
+            }
+            requests
+        });
+        (url, server)
+    }
+
+    fn upload(responses: &[(u16, &str)]) -> (Result<Outcome>, Vec<(String, String)>) {
+        let (url, server) = serve(responses);
+        let outcome = upload_key(Forge::GitLab, &url, "secret", "gs: work", KEY);
+        (outcome, server.join().unwrap())
+    }
+
+    const TAKEN: (u16, &str) = (400, r#"{"message": {"fingerprint_sha256": ["has already been taken"]}}"#);
+
+    #[test]
+    fn new_key_is_added() {
+        let (outcome, requests) = upload(&[(201, r#"{"id": 42, "title": "gs: work"}"#)]);
+        assert_eq!(outcome.unwrap(), Outcome::Added(42));
+        assert_eq!(requests[0].0, "POST /api/v4/user/keys HTTP/1.1");
+        let body: Value = serde_json::from_str(&requests[0].1).unwrap();
+        assert_eq!(body, json!({"title": "gs: work", "key": KEY}));
+    }
+
+    #[test]
+    fn key_already_on_the_account_is_found() {
+        // Forges drop the comment, so the listed key only matches by material
+        let listed = r#"[{"id": 3, "key": "ssh-ed25519 AAAAC3NzaC1lZDI1NTE5AAAAIOther"}, {"id": 7, "key": "ssh-ed25519 AAAAC3NzaC1lZDI1NTE5AAAAIOr4"}]"#;
+        let (outcome, requests) = upload(&[TAKEN, (200, listed)]);
+        assert_eq!(outcome.unwrap(), Outcome::AlreadyPresent(7));
+        assert_eq!(requests[1].0, "GET /api/v4/user/keys?per_page=100 HTTP/1.1");
+    }
+
+    #[test]
+    fn key_on_another_account_is_an_error() {
+        let (outcome, _) = upload(&[TAKEN, (200, r#"[{"id": 3, "key": "ssh-ed25519 AAAAC3NzaC1lZDI1NTE5AAAAIOther"}]"#)]);
+        let err = outcome.unwrap_err();
+        assert!(err.to_string().contains("already in use by another account"), "{}", err);
+        assert_eq!(exit_code(&err), ExitCode::External);
+    }
+
+    #[test]
+    fn rejected_tokens_say_what_to_fix() {
+        let cases = [
+            (401, r#"{"message": "401 Unauthorized"}"#, "GitLab rejected the token (HTTP 401)"),
+            (403, r#"{"message": "403 Forbidden"}"#, "isn't allowed to add SSH keys (HTTP 403). Personal access tokens need the api scope."),
+            (500, "oops", "The GitLab API answered HTTP 500: oops"),
+        ];
+        for (status, body, expected) in cases {
+            let (outcome, requests) = upload(&[(status, body)]);
+            let err = outcome.unwrap_err();
+            assert!(err.to_string().contains(expected), "HTTP {}: {}", status, err);
+            assert_eq!(exit_code(&err), ExitCode::External);
+            assert_eq!(requests.len(), 1, "HTTP {} is not retried or followed up", status);
+        }
+    }
+
+    #[test]
+    fn unreachable_servers_are_reported() {
+        // Bound and dropped, so nothing listens there
+        let port = TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap().port();
+        let err = upload_key(Forge::GitLab, &format!("http://127.0.0.1:{}/api/v4", port), "secret", "gs: work", KEY).unwrap_err();
+        assert!(err.to_string().starts_with("Could not reach the GitLab API"), "{}", err);
+        assert_eq!(exit_code(&err), ExitCode::External);
+    }
+}
@@ -0,0 +1,406 @@
+//! Just enough of the git-config(1) file syntax to make every change a
+//! switch needs to the global config in one write, rather than running
+//! `git config` once per key.
+//!
+//! Section and variable names are case-insensitive; subsection names are
+//! not, except in the legacy `[section.subsection]` form. Values may be
+//! quoted, a backslash escapes `"`, `\`, `n`, `t` and `b`, a backslash at
+//! the end of a line continues the value on the next, and `#` or `;`
+//! outside quotes starts a comment. Lines that aren't edited are kept
+//! byte for byte.
+
+/// One change, named after the `git config` options that would make it.
+/// Keys are written the way `git config` takes them: `section.name` or
+/// `section.subsection.name`.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Edit {
+    /// `--unset-all`, then `--add` for each value: the key ends up with
+    /// exactly these values, or unset with none. Existing entries are
+    /// rewritten where they are.
+    Replace { key: String, values: Vec<String> },
+    /// `--add`.
+    Add { key: String, value: String },
+    /// `--fixed-value --unset-all`: drop the entries with this value.
+    Remove { key: String, value: String },
+}
+
+impl Edit {
+    fn key(&self) -> &str {
+        match self {
+            Edit::Replace { key, .. } | Edit::Add { key, .. } | Edit::Remove { key, .. } => key,
+        }
+    }
+}
+
+/// A key split into its parts, with the case-insensitive ones lowercased.
+struct Key<'a> {
+    section: String,
+    subsection: Option<&'a str>,
+    name: &'a str,
+}
+
+fn parse_key(key: &str) -> Option<Key<'_>> {
+    let (section, rest) = key.split_once('.')?;
+    let (subsection, name) = match rest.rsplit_once('.') {
+        Some((subsection, name)) => (Some(subsection), name),
+        None => (None, rest),
+    };
+    (!section.is_empty() && !name.is_empty()).then(|| Key { section: section.to_lowercase(), subsection, name })
+}
+
+/// A `[section]` or `[section "subsection"]` header.
+struct Section {
+    name: String,
+    subsection: Option<String>,
+    /// Index of the header line.
+    line: usize,
+}
+
+/// A variable, on lines `start..end`: more than one when its value is
+/// continued.
+struct Entry {
+    section: usize,
+    start: usize,
+    end: usize,
+    /// Lowercased.
+    name: String,
+    value: String,
+}
+
+fn parse(lines: &[&str]) -> (Vec<Section>, Vec<Entry>) {
+    let mut sections = Vec::new();
+    let mut entries = Vec::new();
+    let mut i = 0;
+    while i < lines.len() {
+        let trimmed = lines[i].trim_start();
+        if trimmed.starts_with('[') {
+            if let Some((name, subsection)) = parse_header(trimmed) {
+                sections.push(Section { name, subsection, line: i });
+            }
+            i += 1;
+            continue;
+        }
+        let name_len = trimmed.find(|c: char| !c.is_ascii_alphanumeric() && c != '-').unwrap_or(trimmed.len());
+        if sections.is_empty() || !trimmed.starts_with(|c: char| c.is_ascii_alphabetic()) {
+            i += 1;
+            continue;
+        }
+        let start = i;
+        let rest = trimmed[name_len..].trim_start_matches([' ', '\t']);
+        let value = match rest.strip_prefix('=') {
+            Some(raw) => {
+                let (value, end) = read_value(lines, i, raw);
+                i = end;
+                value
+            }
+            // `[section] name` alone is a boolean
+            None => {
+                i += 1;
+                String::new()
+            }
+        };
+        entries.push(Entry {
+            section: sections.len() - 1,
+            start,
+            end: i,
+            name: trimmed[..name_len].to_ascii_lowercase(),
+            value,
+        });
+    }
+    (sections, entries)
+}
+
+/// The section and subsection a header line opens.
+fn parse_header(line: &str) -> Option<(String, Option<String>)> {
+    let inner = line.strip_prefix('[')?;
+    let name_len = inner.find(|c: char| !c.is_ascii_alphanumeric() && c != '-' && c != '.')?;
+    let name = &inner[..name_len];
+    let rest = inner[name_len..].trim_start_matches([' ', '\t']);
+    if rest.starts_with(']') {
+        return Some(match name.split_once('.') {
+            Some((name, subsection)) => (name.to_lowercase(), Some(subsection.to_lowercase())),
+            None => (name.to_lowercase(), None),
+        });
+    }
+
+    let mut chars = rest.strip_prefix('"')?.chars();
+    let mut subsection = String::new();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => subsection.push(chars.next()?),
+            '"' => return chars.as_str().starts_with(']').then(|| (name.to_lowercase(), Some(subsection))),
+            c => subsection.push(c),
+        }
+    }
+    None
+}
+
+/// The value that starts with `raw`, on line `i`, and the line after the
+/// last one it takes up.
+fn read_value(lines: &[&str], mut i: usize, raw: &str) -> (String, usize) {
+    let mut value = String::new();
+    // Whitespace outside quotes only counts when something follows it
+    let mut pending = String::new();
+    let mut started = false;
+    let mut quoted = false;
+    let mut chars = raw.chars().peekable();
+    loop {
+        let Some(c) = chars.next() else {
+            return (value, i + 1);
+        };
+        match c {
+            '\n' => return (value, i + 1),
+            '\r' if chars.peek() == Some(&'\n') => {}
+            '\\' => match chars.next() {
+                None | Some('\n') | Some('\r') => {
+                    i += 1;
+                    if i == lines.len() {
+                        return (value, i);
+                    }
+                    chars = lines[i].chars().peekable();
+                }
+                Some(escaped) => {
+                    value.push_str(&pending);
+                    pending.clear();
+                    started = true;
+                    value.push(match escaped {
+                        'n' => '\n',
+                        't' => '\t',
+                        'b' => '\u{8}',
+                        other => other,
+                    });
+                }
+            },
+            '"' => {
+                value.push_str(&pending);
+                pending.clear();
+                started = true;
+                quoted = !quoted;
+            }
+            '#' | ';' if !quoted => return (value, i + 1),
+            c if c.is_whitespace() && !quoted => {
+                if started {
+                    pending.push(c);
+                }
+            }
+            c => {
+                value.push_str(&pending);
+                pending.clear();
+                started = true;
+                value.push(c);
+            }
+        }
+    }
+}
+
+/// A value as git writes it: escaped, and quoted when whitespace at either
+/// end or a comment character would otherwise be lost.
+fn quote_value(value: &str) -> String {
+    let quote = value.starts_with(char::is_whitespace) || value.ends_with(char::is_whitespace) || value.contains(['#', ';']);
+    let mut out = String::new();
+    if quote {
+        out.push('"');
+    }
+    for c in value.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\t' => out.push_str("\\t"),
+            '\u{8}' => out.push_str("\\b"),
+            c => out.push(c),
+        }
+    }
+    if quote {
+        out.push('"');
+    }
+    out
+}
+
+fn entry_line(key: &Key, value: &str) -> String {
+    format!("\t{} = {}\n", key.name, quote_value(value))
+}
+
+fn header_line(key: &Key) -> String {
+    match key.subsection {
+        Some(subsection) => format!("[{} \"{}\"]\n", key.section, subsection.replace('\\', "\\\\").replace('"', "\\\"")),
+        None => format!("[{}]\n", key.section),
+    }
+}
+
+/// `content` with `edits` made in order.
+pub fn edit(content: &str, edits: &[Edit]) -> String {
+    edits.iter().fold(content.to_string(), |content, edit| apply(&content, edit))
+}
+
+fn apply(content: &str, edit: &Edit) -> String {
+    let Some(key) = parse_key(edit.key()) else {
+        return content.to_string();
+    };
+    let lines: Vec<&str> = content.split_inclusive('\n').collect();
+    let (sections, entries) = parse(&lines);
+    let in_section = |section: &Section| section.name == key.section && section.subsection.as_deref() == key.subsection;
+    let matching: Vec<&Entry> = entries
+        .iter()
+        .filter(|entry| in_section(&sections[entry.section]) && entry.name.eq_ignore_ascii_case(key.name))
+        .collect();
+
+    // Entries rewritten in place (empty text drops them), and values that
+    // need new lines
+    let mut rewrites: Vec<(usize, usize, String)> = Vec::new();
+    let mut added: Vec<&str> = Vec::new();
+    match edit {
+        Edit::Replace { values, .. } => {
+            for (i, entry) in matching.iter().enumerate() {
+                let text = values.get(i).map_or_else(String::new, |value| entry_line(&key, value));
+                rewrites.push((entry.start, entry.end, text));
+            }
+            added.extend(values.iter().skip(matching.len()).map(String::as_str));
+        }
+        Edit::Add { value, .. } => added.push(value),
+        Edit::Remove { value, .. } => {
+            for entry in matching.iter().filter(|entry| entry.value == *value) {
+                rewrites.push((entry.start, entry.end, String::new()));
+            }
+        }
+    }
+
+    // New values go after the key's last entry, else at the end of the
+    // last section of its kind, else into a new section at the end
+    let mut insertion = added.iter().map(|value| entry_line(&key, value)).collect::<String>();
+    let at = match matching.last() {
+        _ if insertion.is_empty() => lines.len(),
+        Some(entry) => entry.end,
+        None => match sections.iter().enumerate().rev().find(|(_, section)| in_section(section)) {
+            Some((index, section)) => entries.iter().filter(|e| e.section == index).map(|e| e.end).max().unwrap_or(section.line + 1),
+            None => {
+                insertion.insert_str(0, &header_line(&key));
+                lines.len()
+            }
+        },
+    };
+
+    let mut out = String::new();
+    let mut i = 0;
+    loop {
+        if i == at && !insertion.is_empty() {
+            if !out.is_empty() && !out.ends_with('\n') {
+                out.push('\n');
+            }
+            out.push_str(&insertion);
+        }
+        if i >= lines.len() {
+            break;
+        }
+        match rewrites.iter().find(|(start, _, _)| *start == i) {
+            Some((_, end, text)) => {
+                out.push_str(text);
+                i = *end;
+            }
+            None => {
+                out.push_str(lines[i]);
+                i += 1;
+            }
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn replace(key: &str, values: &[&str]) -> Edit {
+        Edit::Replace { key: key.to_string(), values: values.iter().map(|v| v.to_string()).collect() }
+    }
+
+    /// The values of `key` in `content`, as git would list them.
+    fn values(content: &str, key: &str) -> Vec<String> {
+        let key = parse_key(key).unwrap();
+        let lines: Vec<&str> = content.split_inclusive('\n').collect();
+        let (sections, entries) = parse(&lines);
+        entries
+            .into_iter()
+            .filter(|e| {
+                let section = &sections[e.section];
+                section.name == key.section && section.subsection.as_deref() == key.subsection && e.name.eq_ignore_ascii_case(key.name)
+            })
+            .map(|e| e.value)
+            .collect()
+    }
+
+    #[test]
+    fn values_are_read_like_git_reads_them() {
+        let content = "[user]\n\tname = \"Jane  Doe\" ; the full name\n\temail=jane@example.com# work\n\
+            [core]\n  sshCommand = ssh \\\n    -i ~/.ssh/work\n  pager = \"less \\\"-R\\\"\"\n  bare\n\
+            [url \"git@github.com:\"]\n\tinsteadOf = https://github.com/\n\
+            [Section.Sub]\n\tkey = \\tvalue\\\\ \n";
+        assert_eq!(values(content, "user.name"), ["Jane  Doe"]);
+        assert_eq!(values(content, "USER.Email"), ["jane@example.com"]);
+        assert_eq!(values(content, "core.sshcommand"), ["ssh     -i ~/.ssh/work"]);
+        assert_eq!(values(content, "core.pager"), ["less \"-R\""]);
+        assert_eq!(values(content, "core.bare"), [""]);
+        assert_eq!(values(content, "url.git@github.com:.insteadOf"), ["https://github.com/"]);
+        assert!(values(content, "url.GIT@github.com:.insteadOf").is_empty());
+        assert_eq!(values(content, "section.sub.key"), ["\tvalue\\"]);
+    }
+
+    #[test]
+    fn replace_rewrites_entries_in_place() {
+        let content = "# mine\n[user]\n\tname = A  # keep?\n\temail = a@example.com\n[core]\n\teditor = vim\n";
+        let edited = edit(content, &[replace("user.name", &["B"]), replace("user.email", &["b@example.com"])]);
+        assert_eq!(edited, "# mine\n[user]\n\tname = B\n\temail = b@example.com\n[core]\n\teditor = vim\n");
+
+        // A continued value is replaced whole
+        let edited = edit("[core]\n\tsshCommand = ssh \\\n\t\t-i a\n\tpager = less\n", &[replace("core.sshCommand", &["ssh -i b"])]);
+        assert_eq!(edited, "[core]\n\tsshCommand = ssh -i b\n\tpager = less\n");
+    }
+
+    #[test]
+    fn replace_with_no_values_unsets() {
+        let content = "[user]\n\tname = A\n\tsigningkey = x\n\tsigningkey = y\n[gpg]\n\tformat = ssh\n";
+        let edited = edit(content, &[replace("user.signingKey", &[]), replace("gpg.format", &[]), replace("commit.gpgsign", &[])]);
+        assert_eq!(edited, "[user]\n\tname = A\n[gpg]\n");
+    }
+
+    #[test]
+    fn new_keys_go_into_their_section_or_a_new_one() {
+        let content = "[user]\n\tname = A\n\n[core]\n\teditor = vim\n# end";
+        let edited = edit(
+            content,
+            &[
+                replace("user.email", &["a@example.com"]),
+                replace("init.defaultBranch", &["main"]),
+                Edit::Add { key: "url.git@host-work:.insteadOf".to_string(), value: "git@host:".to_string() },
+            ],
+        );
+        assert_eq!(
+            edited,
+            "[user]\n\tname = A\n\temail = a@example.com\n\n[core]\n\teditor = vim\n# end\n\
+             [init]\n\tdefaultBranch = main\n[url \"git@host-work:\"]\n\tinsteadOf = git@host:\n"
+        );
+        assert_eq!(edit("", &[replace("user.name", &["A"])]), "[user]\n\tname = A\n");
+        // An empty section still takes them
+        assert_eq!(edit("[user]\n[core]\n", &[replace("user.name", &["A"])]), "[user]\n\tname = A\n[core]\n");
+    }
+
+    #[test]
+    fn add_and_remove_work_on_single_values() {
+        let content = "[url \"git@work:\"]\n\tinsteadOf = a\n\tinsteadOf = b\n\tinsteadOf = a\n";
+        let key = "url.git@work:.insteadOf".to_string();
+        let edited = edit(content, &[Edit::Remove { key: key.clone(), value: "a".to_string() }]);
+        assert_eq!(edited, "[url \"git@work:\"]\n\tinsteadOf = b\n");
+        let edited = edit(&edited, &[Edit::Add { key: key.clone(), value: "c".to_string() }]);
+        assert_eq!(edited, "[url \"git@work:\"]\n\tinsteadOf = b\n\tinsteadOf = c\n");
+        // Removing what isn't there changes nothing
+        assert_eq!(edit(&edited, &[Edit::Remove { key, value: "z".to_string() }]), edited);
+    }
+
+    #[test]
+    fn written_values_read_back_unchanged() {
+        for value in ["plain", " leading", "trailing ", "a # b", "semi;colon", "quote \"q\"", "back\\slash", "tab\there", "line\nbreak"] {
+            let edited = edit("", &[replace("core.value", &[value])]);
+            assert_eq!(values(&edited, "core.value"), [value], "{}", edited);
+        }
+    }
+}
@@ -0,0 +1,38 @@
+//! The forges gs knows by host name: the SSH user to connect as, how their
+//! `ssh -T` greeting names the account, and which API takes key uploads.
+//! Hosts not listed here are self-hosted servers, whose SSH user setup asks
+//! for.
+
+use crate::forge::Forge;
+
+pub struct KnownHost {
+    pub host: &'static str,
+    /// The account everyone connects as, the `User` of the Host block.
+    pub user: &'static str,
+    /// What comes right before the account name in the `ssh -T` greeting.
+    pub greeting: &'static str,
+    /// The API `gs key upload` uses for this host, if it has one gs speaks.
+    pub forge: Option<Forge>,
+}
+
+/// The SSH user for hosts that aren't listed.
+const DEFAULT_USER: &str = "git";
+
+const KNOWN: &[KnownHost] = &[
+    KnownHost { host: "github.com", user: "git", greeting: "Hi ", forge: Some(Forge::GitHub) },
+    KnownHost { host: "gitlab.com", user: "git", greeting: "Welcome to GitLab, @", forge: Some(Forge::GitLab) },
+    KnownHost { host: "bitbucket.org", user: "git", greeting: "logged in as ", forge: None },
+    // Forgejo, and Gitea before it
+    KnownHost { host: "codeberg.org", user: "git", greeting: "Hi there, ", forge: Some(Forge::Gitea) },
+    // Keys go through meta.sr.ht's GraphQL API, which upload doesn't speak
+    KnownHost { host: "git.sr.ht", user: "git", greeting: "Hi ~", forge: None },
+];
+
+pub fn find(host: &str) -> Option<&'static KnownHost> {
+    KNOWN.iter().find(|known| known.host.eq_ignore_ascii_case(host))
+}
+
+/// The user to connect to `host` as, unless a profile says otherwise.
+pub fn default_user(host: &str) -> &'static str {
+    find(host).map_or(DEFAULT_USER, |known| known.user)
+}
@@ -0,0 +1,262 @@
+//! Seeding ~/.ssh/known_hosts for the hosts profiles use, for
+//! `gs doctor --fix`, so the first connection on a new machine doesn't stop
+//! at the host key prompt.
+//!
+//! Keys come from `ssh-keyscan`. For hosts whose fingerprints are published,
+//! every fetched key has to match one of them; other hosts are trusted on
+//! first use, as ssh itself would. Existing entries are never touched.
+//!
+//! A profile's `Port` and `ProxyJump` options are followed: keys for another
+//! port are filed under `[host]:port` as ssh looks them up, and a host behind
+//! a jump host is scanned from the last jump host, where it can be reached.
+
+use crate::error::{failure, ExitCode, ExternalContext, FileContext};
+use crate::ssh_config;
+use crate::trace::LoggedCommand;
+use anyhow::Result;
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::path::Path;
+use std::process::{Command, Stdio};
+
+/// SHA256 fingerprints the forges publish for their SSH host keys.
+const PUBLISHED: &[(&str, &[&str])] = &[
+    (
+        // https://docs.github.com/en/authentication/keeping-your-account-and-data-secure/githubs-ssh-key-fingerprints
+        "github.com",
+        &[
+            "SHA256:uNiVztksCsDhcc0u9e8BujQXVUpKZIDTMczCvj3tD2s",
+            "SHA256:p2QAMXNIC1TJYWeIOttrVc98/R1BUFWu3/LiyKgUfQM",
+            "SHA256:+DiY3wvvV6TuJJhbpZisF/zLDA0zPMSvHdkr4UvCOqU",
+        ],
+    ),
+    (
+        // https://docs.gitlab.com/ee/user/gitlab_com/#ssh-host-keys-fingerprints
+        "gitlab.com",
+        &[
+            "SHA256:ROQFvPThGrW4RuWLoL9tq9I9zJ42fK4XywyRtbOz/EQ",
+            "SHA256:HbW3g8zUjNSksFbqTiUWPWg2Bq1x8xdGUrliXFzSnUw",
+            "SHA256:eUXGGm1YGsMAS7vkcx6JOJdOGHPem5gQp4taiCfCLB8",
+        ],
+    ),
+];
+
+/// What seeding one host did.
+pub enum Seeded {
+    /// Keys were appended; `verified` says whether they matched published
+    /// fingerprints or were taken on trust.
+    Added { keys: usize, verified: bool },
+    /// A fetched key isn't one the forge publishes. Nothing was written.
+    Mismatch { fingerprint: String },
+}
+
+/// How ssh reaches a host, from a profile's SSH options.
+#[derive(Debug, Clone, Default, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Route {
+    /// The port, when it isn't 22.
+    pub port: Option<u16>,
+    /// The `ProxyJump` hops, in order.
+    pub jumps: Vec<String>,
+}
+
+impl Route {
+    /// The route `options` set. As in ssh, the first value of an option wins.
+    pub fn from_options(options: &[String]) -> Route {
+        let mut port = None;
+        let mut jump = None;
+        for line in options.iter().map(|option| ssh_config::tokenize(option)) {
+            match (line.keyword.as_deref(), line.args.first()) {
+                (Some("port"), Some(value)) if port.is_none() => port = Some(value.parse().ok()),
+                (Some("proxyjump"), Some(value)) if jump.is_none() => jump = Some(value.clone()),
+                _ => {}
+            }
+        }
+        Route {
+            port: port.flatten().filter(|&port| port != 22),
+            jumps: jump
+                .filter(|jump| !jump.eq_ignore_ascii_case("none"))
+                .map(|jump| jump.split(',').map(str::to_string).collect())
+                .unwrap_or_default(),
+        }
+    }
+
+    /// The name ssh files `host`'s keys under: `[host]:port` off port 22.
+    pub fn entry(&self, host: &str) -> String {
+        match self.port {
+            Some(port) => format!("[{}]:{}", host, port),
+            None => host.to_string(),
+        }
+    }
+}
+
+/// Whether `known_hosts` already has an entry for `entry`, hashed or not.
+pub fn has_entry(known_hosts: &Path, entry: &str) -> bool {
+    known_hosts.exists()
+        && Command::new("ssh-keygen")
+            .arg("-F")
+            .arg(entry)
+            .arg("-f")
+            .arg(known_hosts)
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .logged_status()
+            .is_ok_and(|status| status.success())
+}
+
+/// Fetch `host`'s keys along `route`, check them against the published
+/// fingerprints when there are any, and append them to `known_hosts`.
+pub fn seed(known_hosts: &Path, host: &str, route: &Route) -> Result<Seeded> {
+    let mut command = keyscan(host, route);
+    let output = command.stdin(Stdio::null()).logged_output().external(if route.jumps.is_empty() {
+        "Failed to run ssh-keyscan"
+    } else {
+        "Failed to run ssh-keyscan on the jump host"
+    })?;
+    let entries: String = String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .filter(|line| !line.trim().is_empty() && !line.starts_with('#'))
+        .map(|line| format!("{}\n", line))
+        .collect();
+    if entries.is_empty() {
+        return Err(failure(ExitCode::External, format!("ssh-keyscan returned no keys for {}", route.entry(host))));
+    }
+
+    let published = PUBLISHED.iter().find(|(name, _)| name.eq_ignore_ascii_case(host)).map(|(_, fps)| *fps);
+    if let Some(published) = published {
+        for fingerprint in fingerprints(&entries)? {
+            if !published.contains(&fingerprint.as_str()) {
+                return Ok(Seeded::Mismatch { fingerprint });
+            }
+        }
+    }
+
+    if let Some(dir) = known_hosts.parent()
+        && !dir.exists()
+    {
+        fs::create_dir_all(dir).file_context("create", dir)?;
+    }
+    // Start on a new line if the file doesn't end with one
+    let needs_newline = fs::read(known_hosts).is_ok_and(|data| data.last().is_some_and(|&b| b != b'\n'));
+    tracing::debug!("append {} key(s) for {} to {}", entries.lines().count(), route.entry(host), known_hosts.display());
+    let mut file = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(known_hosts)
+        .file_context("write", known_hosts)?;
+    if needs_newline {
+        file.write_all(b"\n").file_context("write", known_hosts)?;
+    }
+    file.write_all(entries.as_bytes()).file_context("write", known_hosts)?;
+
+    Ok(Seeded::Added { keys: entries.lines().count(), verified: published.is_some() })
+}
+
+/// The `ssh-keyscan` that fetches `host`'s keys: run here, or over ssh on the
+/// last jump host (reached through the ones before it) when there are any.
+fn keyscan(host: &str, route: &Route) -> Command {
+    let mut scan = vec!["ssh-keyscan".to_string(), "-T".to_string(), "10".to_string()];
+    if let Some(port) = route.port {
+        scan.extend(["-p".to_string(), port.to_string()]);
+    }
+    scan.extend(["-t".to_string(), "ed25519,ecdsa,rsa".to_string(), host.to_string()]);
+
+    let Some((last, before)) = route.jumps.split_last() else {
+        let mut command = Command::new(&scan[0]);
+        command.args(&scan[1..]);
+        return command;
+    };
+    let mut command = Command::new("ssh");
+    command.args(["-o", "BatchMode=yes"]);
+    if !before.is_empty() {
+        command.arg("-J").arg(before.join(","));
+    }
+    // A URI, since a jump host may carry a port the plain form can't
+    command.arg(if last.starts_with("ssh://") { last.clone() } else { format!("ssh://{}", last) });
+    command.args(scan);
+    command
+}
+
+/// SHA256 fingerprints of known_hosts-format lines, as `ssh-keygen -l` prints
+/// them.
+fn fingerprints(entries: &str) -> Result<Vec<String>> {
+    let mut command = Command::new("ssh-keygen");
+    command
+        .args(["-l", "-E", "sha256", "-f", "-"])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null());
+    tracing::debug!("run {}", crate::trace::describe(&command));
+    let mut child = command.spawn().external("Failed to run ssh-keygen")?;
+    child
+        .stdin
+        .take()
+        .expect("stdin is piped")
+        .write_all(entries.as_bytes())
+        .external("Failed to run ssh-keygen")?;
+    let output = child.wait_with_output().external("Failed to run ssh-keygen")?;
+    if !output.status.success() {
+        return Err(failure(ExitCode::External, format!("ssh-keygen -l exited with {}", output.status)));
+    }
+    // "256 SHA256:... github.com (ED25519)"
+    Ok(String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .filter_map(|line| line.split_whitespace().nth(1).map(str::to_string))
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn options(lines: &[&str]) -> Vec<String> {
+        lines.iter().map(|line| line.to_string()).collect()
+    }
+
+    #[test]
+    fn routes_take_the_first_port_and_jump() {
+        let cases: &[(&[&str], Option<u16>, &[&str])] = &[
+            (&[], None, &[]),
+            (&["Compression yes"], None, &[]),
+            (&["Port 2222"], Some(2222), &[]),
+            (&["port=2222", "Port 443"], Some(2222), &[]),
+            (&["Port 22"], None, &[]),
+            (&["Port ssh"], None, &[]),
+            (&["ProxyJump bastion.corp"], None, &["bastion.corp"]),
+            (&["ProxyJump none", "ProxyJump bastion.corp"], None, &[]),
+            (&["ProxyJump a,jump@b:2200", "Port 2222"], Some(2222), &["a", "jump@b:2200"]),
+        ];
+        for &(lines, port, jumps) in cases {
+            let route = Route::from_options(&options(lines));
+            assert_eq!(route, Route { port, jumps: options(jumps) }, "{:?}", lines);
+        }
+    }
+
+    #[test]
+    fn entries_bracket_hosts_off_port_22() {
+        assert_eq!(Route::default().entry("github.com"), "github.com");
+        assert_eq!(Route { port: Some(2222), jumps: Vec::new() }.entry("git.corp"), "[git.corp]:2222");
+    }
+
+    #[test]
+    fn keyscan_runs_on_the_last_jump_host() {
+        let cases: &[(&[&str], &str, &[&str])] = &[
+            (&[], "ssh-keyscan", &["-T", "10", "-t", "ed25519,ecdsa,rsa", "git.corp"]),
+            (&["Port 2222"], "ssh-keyscan", &["-T", "10", "-p", "2222", "-t", "ed25519,ecdsa,rsa", "git.corp"]),
+            (
+                &["ProxyJump bastion.corp"],
+                "ssh",
+                &["-o", "BatchMode=yes", "ssh://bastion.corp", "ssh-keyscan", "-T", "10", "-t", "ed25519,ecdsa,rsa", "git.corp"],
+            ),
+            (
+                &["ProxyJump a,b:2200,ssh://c", "Port 2222"],
+                "ssh",
+                &["-o", "BatchMode=yes", "-J", "a,b:2200", "ssh://c", "ssh-keyscan", "-T", "10", "-p", "2222", "-t", "ed25519,ecdsa,rsa", "git.corp"],
+            ),
+        ];
+        for &(lines, program, args) in cases {
+            let command = keyscan("git.corp", &Route::from_options(&options(lines)));
+            assert_eq!(command.get_program(), program, "{:?}", lines);
+            assert_eq!(command.get_args().collect::<Vec<_>>(), args, "{:?}", lines);
+        }
+    }
+}
@@ -1,530 +1,7511 @@
 use anyhow::{anyhow, Context, Result};
-use clap::{Command};
+use clap::{CommandFactory, FromArgMatches};
+use cli::{BackupsCommand, Cli, Commands, HookShell, KeyCommand, ListSort, OnConflict, ProfileFields};
+use error::{failure, ExitCode, ExternalContext, Failure, FileContext};
+use forge::Forge;
 use colored::Colorize;
 use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet};
 use std::fs::{self, File};
-use std::io::{self, BufReader, Read, Write};
+use std::io::{self, BufReader, IsTerminal, Write};
 use std::path::{Path, PathBuf};
 use std::process;
+use std::sync::OnceLock;
+use trace::LoggedCommand;
 
-#[derive(Debug, Serialize, Deserialize, Clone)]
+mod cli;
+mod error;
+mod forge;
+mod git_config;
+mod hosts;
+mod known_hosts;
+mod migrate;
+mod picker;
+mod prompt;
+mod repo_hook;
+mod ssh_config;
+mod trace;
+mod tui;
+
+#[derive(Debug, Default, Serialize, Deserialize, Clone, PartialEq)]
 struct Profile {
+    /// The label profiles are listed, switched to and matched by.
     name: String,
+    /// Written as git's user.name. Profiles from before it was separate
+    /// from the label get the label copied in when they're loaded.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    git_name: Option<String>,
     email: String,
+    /// Empty for a profile without a key file, which connects with whatever
+    /// keys the SSH agent holds, e.g. 1Password's or a FIDO2 key's. Text
+    /// rather than a path, because it's written as text into SSH and git
+    /// config; a key path that isn't UTF-8 can't be stored.
     ssh_key: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    description: Option<String>,
+    /// Bare `gs` only cycles through profiles in the current one's group.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    group: Option<String>,
+    /// The SSH hosts this profile manages and the key each should use.
+    /// Empty means github.com with `ssh_key`; a profile for other hosts
+    /// that should also manage github.com lists it here.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    hosts: BTreeMap<String, String>,
+    /// SSH users for hosts that don't take the forge's usual one (`git`
+    /// unless the host is a known forge's).
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    ssh_users: BTreeMap<String, String>,
+    /// `url.<base>.insteadOf` rules installed while this profile is active.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    url_rewrites: Vec<UrlRewrite>,
+    /// Applied as `commit.template`; unset for profiles without one. Like
+    /// every path in profiles.json it has to be UTF-8 to be saved.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    commit_template: Option<PathBuf>,
+    /// Applied as `core.excludesFile`; unset for profiles without one.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    excludes_file: Option<PathBuf>,
+    /// Applied as `init.defaultBranch`; unset for profiles without one.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    default_branch: Option<String>,
+    /// Sign commits with the profile's SSH key.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    signing: bool,
+    /// Shown before the name in list and switch output, and by
+    /// `gs prompt --format` as `{icon}`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    icon: Option<String>,
+    /// Color name or `#rrggbb` for the name wherever it's shown, e.g. "blue".
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    color: Option<String>,
+    /// Extra lines for the profile's SSH Host blocks, e.g. "ProxyJump
+    /// bastion". Written between markers after IdentityFile.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    ssh_options: Vec<String>,
+    /// Written as `IdentityAgent` in the profile's Host blocks, for agents
+    /// like 1Password's that listen on their own socket.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    identity_agent: Option<String>,
+    /// The account on the profile's main host, which `gs whoami` expects
+    /// the key to authenticate as.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    username: Option<String>,
+    /// When the profile was made here. None for profiles from before gs
+    /// recorded it.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    created_at: Option<chrono::DateTime<chrono::Local>>,
+    /// Last edit, rename or key rotation. Switching doesn't count.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    updated_at: Option<chrono::DateTime<chrono::Local>>,
+    /// The profile `gs reset` switches back to. At most one has it.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    default: bool,
+    /// Kept in state.json, not profiles.json. Still read from profiles.json
+    /// written by older versions.
+    #[serde(default, skip_serializing)]
     current: bool,
+    /// Where this profile is stored, so a save writes it back there.
+    #[serde(skip)]
+    source: ProfileSource,
+    /// Keys this version doesn't know, from a newer gs or added by hand,
+    /// written back as they were.
+    #[serde(flatten)]
+    extra: serde_json::Map<String, serde_json::Value>,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+/// Which file a profile is saved in. Set when profiles are loaded, and
+/// kept through renames and edits, so a profile never moves between
+/// profiles.json and profiles.d on its own.
+#[derive(Debug, Default, Clone, PartialEq)]
+enum ProfileSource {
+    /// Not saved yet: goes to profiles.d when there is one, otherwise
+    /// profiles.json.
+    #[default]
+    New,
+    /// profiles.json.
+    Main,
+    /// A file in profiles.d.
+    File(PathBuf),
+}
+
+impl ProfileSource {
+    /// The profiles.d file, if that's where the profile lives.
+    fn file(&self) -> Option<&PathBuf> {
+        match self {
+            ProfileSource::File(path) => Some(path),
+            _ => None,
+        }
+    }
+}
+
+/// Machine state that changes on every switch, kept apart from the profile
+/// definitions so switching doesn't rewrite them.
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+struct State {
+    current: Option<String>,
+    previous: Option<String>,
+    /// When each profile last became current, for `gs list --sort recent`.
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    last_used: BTreeMap<String, chrono::DateTime<chrono::Local>>,
+}
+
+/// Make git fetch and push `instead_of` URLs through `base`, e.g. send
+/// `git@github.com:` through a `git@github.com-work:` host alias.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq, PartialOrd, Ord)]
+struct UrlRewrite {
+    base: String,
+    instead_of: String,
+}
+
+impl UrlRewrite {
+    fn key(&self) -> String {
+        format!("url.{}.insteadOf", self.base)
+    }
+}
+
+impl Profile {
+    /// The name commits are made under.
+    fn git_name(&self) -> &str {
+        self.git_name.as_deref().unwrap_or(&self.name)
+    }
+
+    /// Record a change to the profile itself.
+    fn touch(&mut self) {
+        self.updated_at = Some(chrono::Local::now());
+    }
+
+    /// The name as list and the switch message show it: with the icon in
+    /// front and in the profile's color, or bright green without one.
+    fn styled_name(&self) -> String {
+        let name = match self.color.as_deref().and_then(parse_color) {
+            Some(color) => self.name.color(color).bold(),
+            None => self.name.bright_green(),
+        };
+        match &self.icon {
+            Some(icon) => format!("{} {}", icon, name),
+            None => name.to_string(),
+        }
+    }
+
+    /// Every SSH host this profile manages, with the key for each:
+    /// github.com only for a profile without hosts of its own.
+    fn ssh_hosts(&self) -> Vec<(&str, &str)> {
+        if self.hosts.is_empty() {
+            return vec![(GITHUB_HOST, self.ssh_key.as_str())];
+        }
+        self.hosts.iter().map(|(host, key)| (host.as_str(), key.as_str())).collect()
+    }
+
+    /// The lines gs writes between the option markers of a Host block
+    /// using `key`: the agent settings, then the profile's own options. With
+    /// no key file, `IdentitiesOnly no` lets ssh offer the agent's keys even
+    /// where an IdentitiesOnly line further down says otherwise, since the
+    /// first value ssh reads wins.
+    fn block_options(&self, key: &str) -> Vec<String> {
+        let mut options = Vec::new();
+        if key.is_empty() {
+            options.push("IdentitiesOnly no".to_string());
+        }
+        if let Some(agent) = &self.identity_agent {
+            options.push(format!("IdentityAgent {}", ssh_config::quote_arg(agent)));
+        }
+        options.extend(self.ssh_options.iter().cloned());
+        options
+    }
+
+    /// The user to connect to `host` as.
+    fn ssh_user(&self, host: &str) -> &str {
+        self.ssh_users.get(host).map_or_else(|| hosts::default_user(host), String::as_str)
+    }
+
+    /// The host a profile is mainly for: the first of its extra hosts, or
+    /// github.com without any.
+    fn primary_host(&self) -> &str {
+        self.hosts.keys().next().map_or(GITHUB_HOST, String::as_str)
+    }
+
+    /// Rewrite paths under the home directory as `~/...`, the form
+    /// profiles.json stores so it works for any username.
+    fn collapse_paths(&mut self) {
+        self.ssh_key = collapse_tilde(&self.ssh_key);
+        for key in self.hosts.values_mut() {
+            *key = collapse_tilde(key);
+        }
+        for path in [&mut self.commit_template, &mut self.excludes_file].into_iter().flatten() {
+            *path = collapse_path(path);
+        }
+    }
+}
+
+/// Which parts of a profile a switch applies. The default is everything.
+#[derive(Debug, Default)]
+struct ApplyScope {
+    /// Only touch these SSH hosts.
+    hosts: Option<Vec<String>>,
+    /// Leave git config alone.
+    ssh_only: bool,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
 struct Config {
+    /// Format of the file. Files written before this field existed are 1.
+    #[serde(default = "config_version")]
+    version: u32,
     profiles: Vec<Profile>,
+    /// Top-level keys this version doesn't know, kept across saves.
+    #[serde(flatten)]
+    extra: serde_json::Map<String, serde_json::Value>,
+}
+
+impl Default for Config {
+    fn default() -> Config {
+        Config { version: CONFIG_VERSION, profiles: Vec::new(), extra: serde_json::Map::new() }
+    }
+}
+
+fn config_version() -> u32 {
+    CONFIG_VERSION
+}
+
+/// Persistent preferences, stored next to the profiles in settings.json.
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(default)]
+struct Settings {
+    /// Clear the terminal after switching profiles.
+    clear_screen: bool,
+    /// Record every switch in switch.log for `gs history`.
+    log_switches: bool,
+    /// Record every change to git and SSH config in audit.log for `gs audit`.
+    audit_log: bool,
+    /// Show the pending ~/.ssh/config diff and ask before writing it.
+    confirm_ssh_edits: bool,
+    /// Add `IdentitiesOnly yes` to the managed Host block.
+    identities_only: bool,
+    /// Where `gs sync` keeps the shared profile set: a directory (usually
+    /// inside a dotfiles repo) or a git URL.
+    sync_remote: Option<String>,
+    /// Extra `gs setup --preset` entries, or overrides of the built-in ones.
+    presets: BTreeMap<String, Preset>,
+    /// Directory → profile for `gs autoswitch`. The deepest matching
+    /// directory wins.
+    auto_switch: BTreeMap<String, String>,
+    /// How many earlier copies of profiles.json to keep in backups/. 0
+    /// turns backups off.
+    backup_count: usize,
+    /// Set once you've agreed to gs editing your SSH config. Until then the
+    /// first edit is shown and asked about.
+    ssh_config_consent: bool,
+    /// On every switch, take other profiles' keys out of the SSH agent and
+    /// add the new profile's, so the agent can't offer the wrong account's
+    /// key first. Keys gs doesn't know are left alone.
+    agent_exclusive: bool,
+}
+
+/// Pre-filled answers for `gs setup --preset`.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+struct Preset {
+    /// SSH host the profile is for. None means ask, as for an enterprise
+    /// host whose name differs per company.
+    #[serde(default)]
+    host: Option<String>,
+    /// SSH user for an asked-for host. None means ask for that too, as
+    /// Gitea servers use `git` or `gitea`. Known forges' hosts bring their
+    /// own.
+    #[serde(default)]
+    user: Option<String>,
+}
+
+/// Presets that ship with gs; settings.json can add to or replace them.
+fn builtin_presets() -> BTreeMap<String, Preset> {
+    [
+        ("github", Some("github.com"), None),
+        ("gitlab", Some("gitlab.com"), None),
+        ("bitbucket", Some("bitbucket.org"), None),
+        ("codeberg", Some("codeberg.org"), None),
+        ("sourcehut", Some("git.sr.ht"), None),
+        ("ghe", None, Some("git")),
+        ("gitea", None, None),
+    ]
+    .into_iter()
+    .map(|(name, host, user)| {
+        (name.to_string(), Preset { host: host.map(str::to_string), user: user.map(str::to_string) })
+    })
+    .collect()
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Settings {
+            clear_screen: false,
+            log_switches: true,
+            audit_log: true,
+            confirm_ssh_edits: false,
+            identities_only: true,
+            sync_remote: None,
+            presets: BTreeMap::new(),
+            auto_switch: BTreeMap::new(),
+            backup_count: 10,
+            ssh_config_consent: false,
+            agent_exclusive: false,
+        }
+    }
 }
 
-const CONFIG_DIR: &str = ".config/gs";
+/// The config directory's name under the platform's config location.
+const CONFIG_DIR: &str = "gs";
+/// Where versions before per-platform locations kept their files, on every
+/// platform. Still used when it holds profiles.
+const LEGACY_CONFIG_DIR: &str = ".config/gs";
+/// Written to a legacy config directory once its location notice is shown.
+const LOCATION_NOTICE_FILE: &str = ".location-notice";
 const CONFIG_FILE: &str = "profiles.json";
+/// Newest profiles.json format this build understands.
+const CONFIG_VERSION: u32 = 1;
+const CONFIG_DIR_ENV: &str = "GS_CONFIG_DIR";
+const PROFILE_ENV: &str = "GS_PROFILE";
+/// Set inside `gs shell`.
+const ACTIVE_PROFILE_ENV: &str = "GS_ACTIVE_PROFILE";
+const SETTINGS_FILE: &str = "settings.json";
+const HISTORY_DIR: &str = "history";
+const PROFILES_DIR: &str = "profiles.d";
+const HISTORY_LIMIT: usize = 20;
+const BACKUPS_DIR: &str = "backups";
+/// Backups are named `<profiles file>.<this>`, so they sort oldest first.
+const BACKUP_TIMESTAMP: &str = "%Y%m%dT%H%M%S%.6f";
+const SWITCH_LOG_FILE: &str = "switch.log";
+const AUDIT_LOG_FILE: &str = "audit.log";
+const GIT_SUBCOMMAND_BIN: &str = "git-profile";
+/// The repository git config key naming the profile it expects.
+const REPO_PROFILE_KEY: &str = "gs.profile";
+/// The hooks `gs install-hook` installs to run `gs check-repo`.
+const CHECK_HOOKS: [&str; 2] = ["post-checkout", "post-merge"];
+/// The hook `gs install-hook --pre-commit` installs instead.
+const PRE_COMMIT_HOOK: &str = "pre-commit";
+/// Set to skip `gs check-repo`, for committing past an enforcing hook in an
+/// emergency.
+const SKIP_CHECK_ENV: &str = "GS_SKIP_CHECK";
+const GITHUB_HOST: &str = "github.com";
+/// How profiles without a group are shown when others have one.
+const UNGROUPED: &str = "ungrouped";
+const SYNC_DIR: &str = "sync";
+const SYNC_FILE: &str = "gs-profiles.json";
+const MANAGED_REWRITES_FILE: &str = "managed-rewrites.json";
+const ALLOWED_SIGNERS_FILE: &str = "allowed_signers";
+const STATE_FILE: &str = "state.json";
+const ROLLBACK_FILE: &str = "rollback.json";
+const KNOWN_HOSTS_FILE: &str = "known_hosts";
 
-fn main() -> Result<()> {
-    let matches = Command::new("gs")
-        .about("Switch between Git profiles instantly")
-        .subcommand(Command::new("setup").about("Add new profile"))
-        .subcommand(Command::new("list").about("Show all profiles"))
-        .subcommand(Command::new("edit").about("Edit an existing profile"))
-        .subcommand(
-            Command::new("rm")
-                .alias("remove")  // Set "remove" as an alias for "rm"
-                .about("Remove a profile")
-        )
+/// Flags that apply to every command.
+#[derive(Debug, Default)]
+struct GlobalOptions {
+    show_diff: bool,
+    no_color: bool,
+    config: Option<PathBuf>,
+    dry_run: bool,
+    json: bool,
+    yes: bool,
+}
+
+static OPTIONS: OnceLock<GlobalOptions> = OnceLock::new();
+
+fn options() -> &'static GlobalOptions {
+    OPTIONS.get_or_init(GlobalOptions::default)
+}
+
+fn main() -> process::ExitCode {
+    match run() {
+        Ok(()) => process::ExitCode::SUCCESS,
+        Err(err) if options().json => {
+            eprintln!("{}", error::to_json(&err));
+            process::ExitCode::from(error::exit_code(&err) as u8)
+        }
+        Err(err) => {
+            eprintln!("Error: {:?}", err);
+            process::ExitCode::from(error::exit_code(&err) as u8)
+        }
+    }
+}
+
+fn run() -> Result<()> {
+    let matches = Cli::command()
+        .name(program_name())
+        .bin_name(program_name())
         .get_matches();
+    let cli = Cli::from_arg_matches(&matches).unwrap_or_else(|e| e.exit());
+    // Only the global flags can come before a subcommand
+    let switch_args = cli.profile.is_some()
+        || cli.back
+        || cli.pick
+        || cli.worktree.is_some()
+        || !cli.hosts.is_empty()
+        || cli.ssh_only
+        || cli.clear
+        || cli.no_clear;
+    if cli.command.is_some() && switch_args {
+        Cli::command()
+            .name(program_name())
+            .error(
+                clap::error::ErrorKind::ArgumentConflict,
+                "switching options and PROFILE can't be combined with a subcommand",
+            )
+            .exit();
+    }
+    prompt::handle_interrupts();
+
+    OPTIONS
+        .set(GlobalOptions {
+            show_diff: cli.show_diff,
+            no_color: cli.no_color,
+            config: cli.config.clone(),
+            dry_run: cli.dry_run,
+            json: cli.json,
+            yes: cli.yes,
+        })
+        .expect("options are only set once");
+    if cli.no_color {
+        colored::control::set_override(false);
+    }
+    trace::init(cli.verbose, colored::control::SHOULD_COLORIZE.should_colorize() && io::stderr().is_terminal());
+    legacy_location_notice();
+
+    match cli.command {
+        Some(Commands::Setup { fields, preset, host, ssh_user, update_existing }) => {
+            setup_flow(fields, preset.as_deref(), host, ssh_user, update_existing)?
+        }
+        Some(Commands::List { long, format, sort, reverse }) => list_profiles(long, format.as_deref(), sort, reverse)?,
+        Some(Commands::Edit { raw: true, fields, .. }) => {
+            if !fields.is_empty() {
+                return Err(failure(ExitCode::Usage, "--raw edits the whole file; field flags can't be combined with it"));
+            }
+            edit_raw()?
+        }
+        Some(Commands::Edit { profile, fields, .. }) => edit_profile(profile.as_deref(), fields)?,
+        Some(Commands::Rename { profile, new_name }) => rename_profile(&profile, &new_name)?,
+        Some(Commands::Copy { profile, new_name }) => copy_profile(&profile, &new_name)?,
+        Some(Commands::Rm { names, purge, force }) => remove_profile(&names, purge, force)?,
+        Some(Commands::VerifyCommits { count, expect }) => verify_commits(count, expect.as_deref())?,
+        Some(Commands::FixAuthor { allow_staged }) => fix_author(allow_staged)?,
+        Some(Commands::Test { profile, all, timeout }) => test_auth(profile.as_deref(), all, timeout)?,
+        Some(Commands::Whoami { timeout }) => whoami(timeout)?,
+        Some(Commands::Pubkey { profile, copy }) => print_public_key(profile.as_deref(), copy)?,
+        Some(Commands::Diff { first, second }) => diff_profiles(&first, second.as_deref())?,
+        Some(Commands::Export { file, format }) => export_profiles(file.as_deref(), format)?,
+        Some(Commands::Import { from_gitconfig: true, .. }) => import_from_gitconfig()?,
+        Some(Commands::Import { file, format, on_conflict, .. }) => {
+            import_profiles(&file.expect("clap requires FILE without --from-gitconfig"), format, on_conflict)?
+        }
+        Some(Commands::ImportKeys) => import_keys()?,
+        Some(Commands::MigrateFrom { source }) => migrate_from(&source)?,
+        Some(Commands::InstallGitAlias { link }) => install_git_alias(link)?,
+        Some(Commands::InstallHook { profile, pre_commit, enforce, remove }) => {
+            install_hook(profile.as_deref(), pre_commit, enforce, remove)?
+        }
+        Some(Commands::CheckRepo { quiet, enforce }) => check_repo(quiet, enforce)?,
+        Some(Commands::Group { name }) => switch_group(name.as_deref())?,
+        Some(Commands::Default { profile }) => set_default(profile.as_deref())?,
+        Some(Commands::Reset) => reset_to_default()?,
+        Some(Commands::Doctor { fix }) => doctor(fix)?,
+        Some(Commands::Check { file, format, no_fs }) => check_config(file.as_deref(), format, no_fs, cli.json)?,
+        Some(Commands::Current { format }) => show_current(format.as_deref())?,
+        Some(Commands::Status) => show_status()?,
+        Some(Commands::Env { profile }) => print_env(profile.as_deref())?,
+        Some(Commands::Exec { profile, command }) => exec_as(&profile, &command)?,
+        Some(Commands::Shell { profile }) => profile_shell(&profile)?,
+        Some(Commands::Prompt { format, plain }) => print_prompt(format.as_deref(), plain)?,
+        Some(Commands::Direnv { profile, write, update }) => direnv(profile.as_deref(), write, update)?,
+        Some(Commands::Key { command }) => match command {
+            KeyCommand::Rotate { profile, dry_run, add } => rotate_key(&profile, dry_run, add)?,
+            KeyCommand::Upload { profile, github, gitlab, gitea, url, token } => {
+                let forge = match (github, gitlab, gitea) {
+                    (true, _, _) => Some(Forge::GitHub),
+                    (_, true, _) => Some(Forge::GitLab),
+                    (_, _, true) => Some(Forge::Gitea),
+                    _ => None,
+                };
+                upload_key(&profile, forge, url.as_deref(), token)?
+            }
+        },
+        Some(Commands::Hook { shell }) => print_hook(shell)?,
+        Some(Commands::Autoswitch { dir }) => auto_switch(&dir)?,
+        Some(Commands::Ui) => tui::run()?,
+        Some(Commands::Paths) => show_paths(cli.json)?,
+        Some(Commands::History { count }) => show_history(count, cli.json)?,
+        Some(Commands::Audit { count }) => show_audit(count, cli.json)?,
+        Some(Commands::Undo { list }) => undo(list)?,
+        Some(Commands::Rollback) => rollback()?,
+        Some(Commands::Backups { command }) => match command {
+            BackupsCommand::List => list_backups()?,
+            BackupsCommand::Restore { backup } => restore_backup(&backup)?,
+        },
+        Some(Commands::Sync { push, on_conflict, .. }) => sync_profiles(push, on_conflict)?,
+        Some(Commands::Settings { key, value }) => settings_command(key.as_deref(), value.as_deref())?,
+        None => match cli.worktree {
+            Some(name) => set_worktree_profile(&name)?,
+            None => {
+                let clear = if cli.clear {
+                    true
+                } else if cli.no_clear {
+                    false
+                } else {
+                    load_settings()?.clear_screen
+                };
+                let scope = ApplyScope {
+                    hosts: (!cli.hosts.is_empty()).then_some(cli.hosts),
+                    ssh_only: cli.ssh_only,
+                };
+                switch_profile(cli.profile.as_deref(), cli.back, cli.pick, clear, &scope)?
+            }
+        },
+    }
+
+    Ok(())
+}
+
+/// Name the user invoked us by: "git profile" when run through the
+/// git-profile link, "gs" otherwise.
+fn program_name() -> &'static str {
+    static NAME: OnceLock<&'static str> = OnceLock::new();
+    NAME.get_or_init(|| {
+        let invoked_as = std::env::args_os()
+            .next()
+            .map(PathBuf::from)
+            .and_then(|p| p.file_stem().map(|s| s.to_string_lossy().into_owned()));
+
+        match invoked_as.as_deref() {
+            Some(GIT_SUBCOMMAND_BIN) => "git profile",
+            _ => "gs",
+        }
+    })
+}
+
+/// How the config directory was chosen.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum ConfigDirSource {
+    /// $GS_CONFIG_DIR.
+    Env,
+    /// ~/.config/gs, because it already has profiles.
+    Legacy,
+    /// The platform's config location: $XDG_CONFIG_HOME (or ~/.config) on
+    /// Linux, ~/Library/Application Support on macOS, %APPDATA% on Windows.
+    Platform,
+}
+
+impl ConfigDirSource {
+    fn label(self) -> &'static str {
+        match self {
+            ConfigDirSource::Env => CONFIG_DIR_ENV,
+            ConfigDirSource::Legacy => "existing ~/.config/gs",
+            ConfigDirSource::Platform => "platform default",
+        }
+    }
+}
+
+/// The platform's config directory for gs, whether or not it's the one in
+/// use.
+fn platform_config_dir() -> Result<PathBuf> {
+    let base = dirs::config_dir().ok_or_else(|| anyhow!("Could not find the config directory"))?;
+    Ok(base.join(CONFIG_DIR))
+}
+
+/// Where the config directory is, and why, without creating it. A
+/// ~/.config/gs with profiles in it wins over the platform location, so
+/// upgrading never loses anyone's profiles.
+fn config_dir_resolution() -> Result<(PathBuf, ConfigDirSource)> {
+    if let Some(dir) = std::env::var_os(CONFIG_DIR_ENV).filter(|dir| !dir.is_empty()) {
+        return Ok((PathBuf::from(dir), ConfigDirSource::Env));
+    }
+    let home_dir = dirs::home_dir().ok_or_else(|| anyhow!("Could not find home directory"))?;
+    let legacy = home_dir.join(LEGACY_CONFIG_DIR);
+    let platform = platform_config_dir()?;
+    if legacy != platform && legacy.join(CONFIG_FILE).exists() {
+        return Ok((legacy, ConfigDirSource::Legacy));
+    }
+    Ok((platform, ConfigDirSource::Platform))
+}
+
+fn config_dir_location() -> Result<PathBuf> {
+    Ok(config_dir_resolution()?.0)
+}
+
+/// Say once, per legacy directory, why gs isn't using the platform's
+/// location and how to move over.
+fn legacy_location_notice() {
+    let Ok((config_dir, ConfigDirSource::Legacy)) = config_dir_resolution() else {
+        return;
+    };
+    let marker = config_dir.join(LOCATION_NOTICE_FILE);
+    if marker.exists() {
+        return;
+    }
+    let Ok(platform) = platform_config_dir() else {
+        return;
+    };
+    eprintln!(
+        "{} gs keeps using {}, which has your profiles. This platform's default is {}; move the directory there, or set {}, to change it. (Shown once.)",
+        "Note:".cyan(),
+        config_dir.display(),
+        platform.display(),
+        CONFIG_DIR_ENV
+    );
+    tracing::debug!("write {}", marker.display());
+    if let Err(err) = fs::write(&marker, "") {
+        tracing::debug!("  -> {}", err);
+    }
+}
+
+fn get_config_dir() -> Result<PathBuf> {
+    let config_dir = config_dir_location()?;
+
+    // Create config directory if it doesn't exist
+    if !config_dir.exists() {
+        fs::create_dir_all(&config_dir).context("Failed to create config directory")?;
+    }
+
+    Ok(config_dir)
+}
+
+/// profiles.json, or the file passed with --config, without creating
+/// anything.
+fn config_path_location() -> Result<PathBuf> {
+    match &options().config {
+        Some(path) => Ok(path.clone()),
+        None => Ok(config_dir_location()?.join(CONFIG_FILE)),
+    }
+}
+
+fn get_config_path() -> Result<PathBuf> {
+    let path = config_path_location()?;
+    if let Some(dir) = path.parent()
+        && !dir.as_os_str().is_empty()
+        && !dir.exists()
+    {
+        fs::create_dir_all(dir).with_context(|| format!("Failed to create {}", dir.display()))?;
+    }
+    Ok(path)
+}
+
+/// state.json lives next to the profiles it points into, so an alternate
+/// set from --config keeps its own current profile.
+fn state_path() -> Result<PathBuf> {
+    Ok(config_path_location()?.with_file_name(STATE_FILE))
+}
+
+/// The `gs rollback` snapshot, machine state like state.json.
+fn rollback_path() -> Result<PathBuf> {
+    Ok(config_path_location()?.with_file_name(ROLLBACK_FILE))
+}
+
+fn load_settings() -> Result<Settings> {
+    let settings_path = get_config_dir()?.join(SETTINGS_FILE);
+    if !settings_path.exists() {
+        return Ok(Settings::default());
+    }
+
+    let data = fs::read_to_string(&settings_path).context("Failed to read settings file")?;
+    serde_json::from_str(&data).context("Failed to parse settings file")
+}
+
+fn save_settings(settings: &Settings) -> Result<()> {
+    let settings_path = get_config_dir()?.join(SETTINGS_FILE);
+    let json_data = serde_json::to_string_pretty(settings).context("Failed to serialize settings")?;
+    tracing::debug!("write {}", settings_path.display());
+    fs::write(settings_path, json_data).context("Failed to save settings file")?;
+    Ok(())
+}
+
+fn settings_command(key: Option<&str>, value: Option<&str>) -> Result<()> {
+    let settings = load_settings()?;
+    let serde_json::Value::Object(mut values) = serde_json::to_value(&settings)? else {
+        unreachable!("settings always serialize to an object");
+    };
+
+    let Some(key) = key else {
+        for (key, value) in &values {
+            println!("{} = {}", key, value);
+        }
+        return Ok(());
+    };
+
+    if !values.contains_key(key) {
+        let known: Vec<&str> = values.keys().map(String::as_str).collect();
+        return Err(failure(
+            ExitCode::Usage,
+            format!("Unknown setting '{}'. Known settings: {}", key, known.join(", ")),
+        ));
+    }
+
+    let Some(value) = value else {
+        println!("{} = {}", key, values[key]);
+        return Ok(());
+    };
+
+    // Accept bare words for string settings, JSON for everything else
+    let parsed = serde_json::from_str(value).unwrap_or_else(|_| serde_json::Value::String(value.to_string()));
+    values.insert(key.to_string(), parsed);
+    let updated: Settings = serde_json::from_value(serde_json::Value::Object(values))
+        .with_context(|| format!("Invalid value for '{}': {}", key, value))?;
+
+    save_settings(&updated)?;
+    eprintln!("Set {} = {}", key, value);
+    Ok(())
+}
+
+fn load_config() -> Result<Config> {
+    let config_path = get_config_path()?;
+
+    // If file doesn't exist, start from an empty config
+    let mut config: Config = if config_path.exists() {
+        tracing::debug!("read {}", config_path.display());
+        let file = File::open(&config_path).file_context("read", &config_path)?;
+        let reader = BufReader::new(file);
+        serde_json::from_reader(reader).with_context(|| format!("Failed to parse {}", config_path.display()))?
+    } else {
+        tracing::debug!("{} doesn't exist, starting with no profiles", config_path.display());
+        Config::default()
+    };
+    for profile in &mut config.profiles {
+        profile.source = ProfileSource::Main;
+    }
+    load_profile_files(&mut config)?;
+    // Older versions stored absolute paths and used the label as
+    // user.name; the next save rewrites both
+    for profile in &mut config.profiles {
+        profile.collapse_paths();
+        if profile.git_name.is_none() {
+            profile.git_name = Some(profile.name.clone());
+        }
+    }
+    // Without state.json yet, the current flags older versions wrote in
+    // profiles.json stand until the next save moves them over
+    if let Some(state) = load_state()? {
+        set_current(&mut config, state.current.as_deref());
+        if let Some(name) = state.current.filter(|name| !config.profiles.iter().any(|p| &p.name == name)) {
+            tracing::debug!("state.json names '{}', which isn't a profile; none is active", name);
+        }
+    }
+    // Hand edits to those flags can mark several profiles current. Keep the
+    // first so every command agrees on one; the next save records it
+    let marked: Vec<String> = config.profiles.iter().filter(|p| p.current).map(|p| p.name.clone()).collect();
+    if marked.len() > 1 {
+        static WARNED: std::sync::Once = std::sync::Once::new();
+        WARNED.call_once(|| {
+            eprintln!(
+                "{} profiles {} are all marked current; using '{}'",
+                "Warning:".yellow(),
+                marked.iter().map(|name| format!("'{}'", name)).collect::<Vec<_>>().join(", "),
+                marked[0]
+            )
+        });
+        set_current(&mut config, Some(&marked[0]));
+    }
+
+    Ok(config)
+}
+
+/// profiles.d, next to the profiles file.
+fn profiles_dir() -> Result<PathBuf> {
+    Ok(config_path_location()?.with_file_name(PROFILES_DIR))
+}
+
+/// The *.json files in profiles.d, sorted by name. Empty if there's no
+/// such directory.
+fn profile_files() -> Result<Vec<PathBuf>> {
+    let dir = profiles_dir()?;
+    if !dir.is_dir() {
+        return Ok(Vec::new());
+    }
+    let mut files: Vec<PathBuf> = fs::read_dir(&dir)
+        .with_context(|| format!("Failed to read {}", dir.display()))?
+        .filter_map(|e| e.ok().map(|e| e.path()))
+        .filter(|p| p.extension().is_some_and(|ext| ext == "json"))
+        .collect();
+    files.sort();
+    Ok(files)
+}
+
+/// Add the one-profile files from profiles.d after the profiles in
+/// profiles.json. A name defined twice is an error, since there'd be no
+/// telling which copy an edit should change.
+fn load_profile_files(config: &mut Config) -> Result<()> {
+    for file in profile_files()? {
+        tracing::debug!("read {}", file.display());
+        let data = fs::read_to_string(&file).file_context("read", &file)?;
+        let mut profile: Profile =
+            serde_json::from_str(&data).with_context(|| format!("Failed to parse {}", file.display()))?;
+        if let Some(existing) = config.profiles.iter().find(|p| p.name == profile.name) {
+            let other = match existing.source.file() {
+                Some(source) => source.display().to_string(),
+                None => CONFIG_FILE.to_string(),
+            };
+            return Err(anyhow!(
+                "Profile '{}' is defined in both {} and {}; remove one of them",
+                profile.name,
+                other,
+                file.display()
+            ));
+        }
+        profile.source = ProfileSource::File(file);
+        config.profiles.push(profile);
+    }
+    Ok(())
+}
+
+/// Write profiles that came from profiles.d back to their files, give new
+/// profiles a file there, and delete the files of profiles that are gone.
+/// Unchanged files aren't touched, so read-only ones only matter when a
+/// command actually edits them.
+fn write_profile_files(config: &mut Config) -> Result<()> {
+    let dir = profiles_dir()?;
+    if !dir.is_dir() {
+        return Ok(());
+    }
+
+    for i in 0..config.profiles.len() {
+        if config.profiles[i].source == ProfileSource::New {
+            config.profiles[i].source = ProfileSource::File(new_profile_file(&dir, &config.profiles[i].name, config));
+        }
+    }
+
+    for profile in &config.profiles {
+        let Some(path) = profile.source.file() else {
+            continue;
+        };
+        let data = serde_json::to_string_pretty(profile).context("Failed to serialize profile")?;
+        if fs::read_to_string(path).is_ok_and(|old| old == data) {
+            tracing::debug!("{} is unchanged", path.display());
+            continue;
+        }
+        // A rename would replace a read-only file anyway, so ask first
+        if fs::OpenOptions::new().append(true).open(path).is_err_and(|err| err.kind() == io::ErrorKind::PermissionDenied) {
+            let origin = match fs::read_link(path) {
+                Ok(target) => format!(" (a link to {})", target.display()),
+                Err(_) => String::new(),
+            };
+            return Err(anyhow!(
+                "Profile '{}' lives in {}{}, which is read-only. Change it where it comes from, or make the file writable.",
+                profile.name,
+                path.display(),
+                origin
+            ));
+        }
+        tracing::debug!("write {}", path.display());
+        write_atomically(path, &data)?;
+    }
+
+    for file in profile_files()? {
+        if !config.profiles.iter().any(|p| p.source.file() == Some(&file)) {
+            tracing::debug!("remove {}", file.display());
+            fs::remove_file(&file).file_context("remove", &file)?;
+        }
+    }
+    Ok(())
+}
+
+/// profiles.d/<name>.json, or with a number added if that's taken.
+fn new_profile_file(dir: &Path, name: &str, config: &Config) -> PathBuf {
+    let slug: String = name
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() || c == '-' || c == '_' || c == '.' { c } else { '_' })
+        .collect();
+    let taken = |path: &PathBuf| path.exists() || config.profiles.iter().any(|p| p.source.file() == Some(path));
+    let mut path = dir.join(format!("{}.json", slug));
+    let mut n = 2;
+    while taken(&path) {
+        path = dir.join(format!("{}-{}.json", slug, n));
+        n += 1;
+    }
+    path
+}
+
+fn set_current(config: &mut Config, name: Option<&str>) {
+    for profile in &mut config.profiles {
+        profile.current = Some(profile.name.as_str()) == name;
+    }
+}
+
+/// state.json, or None if it hasn't been written yet.
+fn load_state() -> Result<Option<State>> {
+    let path = state_path()?;
+    if !path.exists() {
+        return Ok(None);
+    }
+    tracing::debug!("read {}", path.display());
+    let data = fs::read_to_string(&path).file_context("read", &path)?;
+    Ok(Some(serde_json::from_str(&data).context("Failed to parse state file")?))
+}
+
+/// Record which profile is current, remembering the one it replaces.
+fn save_current(config: &Config) -> Result<()> {
+    if options().dry_run {
+        tracing::debug!("dry run, not recording the current profile");
+        return Ok(());
+    }
+    let existing = load_state()?;
+    let old = existing.clone().unwrap_or_default();
+    let current = config.profiles.iter().find(|p| p.current).map(|p| p.name.clone());
+    let mut last_used = old.last_used.clone();
+    if let Some(name) = current.as_ref().filter(|name| old.current.as_ref() != Some(*name)) {
+        last_used.insert(name.clone(), chrono::Local::now());
+    }
+    let previous = match old.current {
+        Some(old_current) if Some(&old_current) != current.as_ref() => Some(old_current),
+        _ => old.previous.clone(),
+    };
+    last_used.retain(|name, _| config.profiles.iter().any(|p| p.name == *name));
+    let state = State { current, previous, last_used };
+    if existing.as_ref() == Some(&state) {
+        return Ok(());
+    }
+    let json_data = serde_json::to_string_pretty(&state).context("Failed to serialize state")?;
+    get_config_path()?;
+    let path = state_path()?;
+    tracing::debug!("write {} (current: {:?})", path.display(), state.current);
+    fs::write(&path, json_data).file_context("write", &path)
+}
+
+/// Save the profiles, journaled for `gs undo`.
+fn save_config(config: &Config) -> Result<()> {
+    let _critical = prompt::critical();
+    // Keep the state we're about to replace so `gs undo` can bring it back
+    journal_config()?;
+    write_config(config)
+}
+
+/// Write the profiles. Under `--dry-run` nothing is written, whatever path
+/// got here.
+fn write_config(config: &Config) -> Result<()> {
+    if options().dry_run {
+        tracing::debug!("dry run, not saving profiles");
+        return Ok(());
+    }
+    let _critical = prompt::critical();
+    let config_path = get_config_path()?;
+    let mut config = config.clone();
+    for profile in &mut config.profiles {
+        profile.collapse_paths();
+    }
+
+    // Profiles stay where they were loaded from; new ones go to profiles.d
+    // when it exists
+    write_profile_files(&mut config)?;
+
+    let main = Config {
+        profiles: config.profiles.iter().filter(|p| p.source.file().is_none()).cloned().collect(),
+        ..config.clone()
+    };
+    let json_data = serde_json::to_string_pretty(&main).context("Failed to serialize config")?;
+    backup_config(&config_path, &json_data)?;
+    tracing::debug!("write {}", config_path.display());
+    write_atomically(&config_path, &json_data)?;
+    save_current(&config)?;
+    write_allowed_signers(&config)
+}
+
+/// Write `data` to a file next to `path` and rename it into place, so an
+/// interrupted write never leaves a half-written profiles file. A symlinked
+/// file is replaced where the link points, keeping the link.
+fn write_atomically(path: &Path, data: &str) -> Result<()> {
+    let path = &fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf());
+    let mut temp = path.as_os_str().to_owned();
+    temp.push(".tmp");
+    let temp = PathBuf::from(temp);
+    fs::write(&temp, data).file_context("write", &temp)?;
+    fs::rename(&temp, path).file_context("write", path)
+}
+
+/// The allowed_signers file git uses to verify SSH signatures: one
+/// `<email> namespaces="git" <public key>` line per profile whose public key
+/// can be read.
+fn allowed_signers(config: &Config) -> String {
+    let mut lines = String::new();
+    for profile in &config.profiles {
+        let public_key = expand_path(Path::new(&format!("{}.pub", profile.ssh_key)));
+        if let Ok(key) = fs::read_to_string(&public_key)
+            && !key.trim().is_empty()
+        {
+            lines.push_str(&format!("{} namespaces=\"git\" {}\n", profile.email, key.trim()));
+        }
+    }
+    lines
+}
+
+/// Regenerate allowed_signers after profiles change, if anything in it would.
+fn write_allowed_signers(config: &Config) -> Result<()> {
+    let path = get_config_dir()?.join(ALLOWED_SIGNERS_FILE);
+    let wanted = allowed_signers(config);
+    let existing = fs::read_to_string(&path).ok();
+    if existing.as_deref() == Some(wanted.as_str()) || (existing.is_none() && wanted.is_empty()) {
+        return Ok(());
+    }
+    fs::write(&path, wanted).context("Failed to write allowed_signers")
+}
+
+/// A snapshot of profiles.json taken right before a command changed it.
+#[derive(Debug, Serialize, Deserialize)]
+struct JournalEntry {
+    timestamp: chrono::DateTime<chrono::Local>,
+    command: String,
+    config: Config,
+    /// The current profile then. Entries from older versions have it in
+    /// the config's flags instead.
+    #[serde(default)]
+    current: Option<String>,
+    /// Which profiles.d file each profile came from.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    sources: BTreeMap<String, PathBuf>,
+}
+
+fn get_history_dir() -> Result<PathBuf> {
+    let history_dir = get_config_dir()?.join(HISTORY_DIR);
+    if !history_dir.exists() {
+        fs::create_dir_all(&history_dir).context("Failed to create history directory")?;
+    }
+    Ok(history_dir)
+}
+
+fn journal_config() -> Result<()> {
+    if options().dry_run {
+        return Ok(());
+    }
+    let config = load_config()?;
+    let entry = JournalEntry {
+        timestamp: chrono::Local::now(),
+        command: std::env::args().skip(1).fold(program_name().to_string(), |cmd, arg| cmd + " " + &arg),
+        current: config.profiles.iter().find(|p| p.current).map(|p| p.name.clone()),
+        sources: config
+            .profiles
+            .iter()
+            .filter_map(|p| Some((p.name.clone(), p.source.file()?.clone())))
+            .collect(),
+        config,
+    };
+
+    let history_dir = get_history_dir()?;
+    let file_name = format!("{}.json", entry.timestamp.format("%Y%m%dT%H%M%S%.6f"));
+    let json_data = serde_json::to_string_pretty(&entry).context("Failed to serialize history entry")?;
+    tracing::debug!("write {}", history_dir.join(&file_name).display());
+    fs::write(history_dir.join(file_name), json_data).context("Failed to write history entry")?;
+
+    // Prune the oldest entries
+    let entries = journal_entries()?;
+    for (path, _) in entries.iter().skip(HISTORY_LIMIT) {
+        fs::remove_file(path).context("Failed to prune history")?;
+    }
+
+    Ok(())
+}
+
+/// Journal entries, newest first.
+fn journal_entries() -> Result<Vec<(PathBuf, JournalEntry)>> {
+    let mut paths: Vec<PathBuf> = fs::read_dir(get_history_dir()?)
+        .context("Failed to read history directory")?
+        .filter_map(|e| e.ok().map(|e| e.path()))
+        .filter(|p| p.extension().is_some_and(|ext| ext == "json"))
+        .collect();
+    paths.sort();
+    paths.reverse();
+
+    let mut entries = Vec::new();
+    for path in paths {
+        let data = fs::read_to_string(&path).context("Failed to read history entry")?;
+        let mut entry: JournalEntry = serde_json::from_str(&data)
+            .with_context(|| format!("Failed to parse history entry {}", path.display()))?;
+        if let Some(current) = entry.current.clone() {
+            set_current(&mut entry.config, Some(&current));
+        }
+        for profile in &mut entry.config.profiles {
+            profile.source = entry.sources.get(&profile.name).cloned().map_or(ProfileSource::Main, ProfileSource::File);
+        }
+        entries.push((path, entry));
+    }
+    Ok(entries)
+}
+
+fn undo(list: bool) -> Result<()> {
+    let entries = journal_entries()?;
+
+    if list {
+        if entries.is_empty() {
+            eprintln!("Nothing to undo.");
+            return Ok(());
+        }
+
+        println!("=== Undo History (newest first) ===");
+        for (i, (_, entry)) in entries.iter().enumerate() {
+            println!(
+                "{}. {}  before '{}'",
+                i + 1,
+                entry.timestamp.format("%Y-%m-%d %H:%M:%S"),
+                entry.command
+            );
+            println!("   restores: {}", describe_config(&entry.config).dimmed());
+        }
+        return Ok(());
+    }
+
+    let Some((path, entry)) = entries.into_iter().next() else {
+        eprintln!("Nothing to undo.");
+        return Ok(());
+    };
+
+    let before = load_config()?;
+    let old_current = before.profiles.iter().find(|p| p.current);
+
+    // Switches aren't journaled, so the profile active now stays active.
+    // Only when the undo takes it away does the entry's current one return
+    let mut restored = entry.config;
+    if let Some(current) = old_current.filter(|c| restored.profiles.iter().any(|p| p.name == c.name)) {
+        set_current(&mut restored, Some(&current.name));
+    }
+
+    // Re-apply git/SSH config when the active profile, or what it holds,
+    // changes as a result
+    let mut plan = None;
+    if let Some(profile) = restored.profiles.iter().find(|p| p.current)
+        && old_current != Some(profile)
+    {
+        plan = Some(plan_apply(profile, &ApplyScope::default())?);
+    }
+
+    if options().dry_run {
+        if let Some(plan) = &plan {
+            run_apply(plan)?;
+        }
+        eprintln!("Would undo '{}'", entry.command);
+        eprintln!("Profiles would be: {}", describe_config(&restored));
+        return Ok(());
+    }
+
+    // Restoring is not itself journaled, so repeated undos walk further
+    // back. As with a save, the profiles are only written once applied
+    let _critical = prompt::critical();
+    if let Some(plan) = &plan {
+        run_apply(plan)?;
+    }
+    write_config(&restored)?;
+    fs::remove_file(&path).context("Failed to remove history entry")?;
+    if let Some(plan) = &plan
+        && old_current.is_none_or(|p| p.name != plan.profile.name)
+    {
+        log_switch(old_current.map(|p| p.name.as_str()), &plan.profile.name);
+    }
+
+    eprintln!("Undid '{}'", entry.command);
+    eprintln!("Profiles now: {}", describe_config(&restored));
+    Ok(())
+}
+
+/// backups/ next to the profiles file, like state.json, so the copies of
+/// a --config file never prune or stand in for those of profiles.json.
+fn backups_dir() -> Result<PathBuf> {
+    Ok(config_path_location()?.with_file_name(BACKUPS_DIR))
+}
+
+fn get_backups_dir() -> Result<PathBuf> {
+    let backups_dir = backups_dir()?;
+    if !backups_dir.exists() {
+        fs::create_dir_all(&backups_dir).file_context("create", &backups_dir)?;
+    }
+    Ok(backups_dir)
+}
+
+/// Copy the profiles file about to be replaced with `new` into backups/,
+/// then prune all but the newest `backup_count` copies. Nothing is copied
+/// when the content wouldn't change.
+fn backup_config(path: &Path, new: &str) -> Result<()> {
+    let keep = load_settings()?.backup_count;
+    if keep == 0 || !path.exists() {
+        return Ok(());
+    }
+    let old = fs::read_to_string(path).file_context("read", path)?;
+    if old == new {
+        return Ok(());
+    }
+
+    let file_name = path.file_name().unwrap_or_default().to_string_lossy();
+    let backup = get_backups_dir()?.join(format!("{}.{}", file_name, chrono::Local::now().format(BACKUP_TIMESTAMP)));
+    tracing::debug!("write {}", backup.display());
+    fs::write(&backup, old).file_context("write", &backup)?;
+
+    for (old_backup, _) in config_backups()?.iter().skip(keep) {
+        tracing::debug!("remove {}", old_backup.display());
+        fs::remove_file(old_backup).file_context("remove", old_backup)?;
+    }
+    Ok(())
+}
+
+/// Backups of the profiles file in use, newest first, with the timestamp
+/// each is named by.
+fn config_backups() -> Result<Vec<(PathBuf, String)>> {
+    let dir = backups_dir()?;
+    if !dir.is_dir() {
+        return Ok(Vec::new());
+    }
+    let config_path = config_path_location()?;
+    let prefix = format!("{}.", config_path.file_name().unwrap_or_default().to_string_lossy());
+    let mut backups: Vec<(PathBuf, String)> = fs::read_dir(&dir)
+        .file_context("read", &dir)?
+        .filter_map(|e| e.ok().map(|e| e.path()))
+        .filter_map(|path| {
+            let timestamp = path.file_name()?.to_str()?.strip_prefix(&prefix)?.to_string();
+            // work.json.<timestamp>, not work.json.old.<timestamp>
+            chrono::NaiveDateTime::parse_from_str(&timestamp, BACKUP_TIMESTAMP).ok()?;
+            Some((path, timestamp))
+        })
+        .collect();
+    backups.sort_by(|a, b| b.1.cmp(&a.1));
+    Ok(backups)
+}
+
+fn list_backups() -> Result<()> {
+    let backups = config_backups()?;
+    if backups.is_empty() {
+        eprintln!("No backups yet.");
+        return Ok(());
+    }
+
+    println!("=== Backups (newest first) ===");
+    for (path, timestamp) in &backups {
+        let when = chrono::NaiveDateTime::parse_from_str(timestamp, BACKUP_TIMESTAMP)
+            .map(|t| t.format("%Y-%m-%d %H:%M:%S").to_string())
+            .unwrap_or_default();
+        let summary = fs::read_to_string(path)
+            .ok()
+            .and_then(|data| serde_json::from_str::<Config>(&data).ok())
+            .map_or_else(|| "unreadable".to_string(), |config| describe_config(&config));
+        println!("{}  {}", timestamp, when.dimmed());
+        println!("   {}", summary.dimmed());
+    }
+    Ok(())
+}
+
+/// Put a backup back in place of the profiles file: `latest` or a unique
+/// prefix of its timestamp. The backup is checked first, and the file it
+/// replaces is backed up and journaled so the restore can be undone. Git
+/// and SSH config are left alone.
+fn restore_backup(which: &str) -> Result<()> {
+    let backups = config_backups()?;
+    let matches: Vec<&(PathBuf, String)> = match which {
+        "latest" => backups.iter().take(1).collect(),
+        prefix => backups.iter().filter(|(_, timestamp)| timestamp.starts_with(prefix)).collect(),
+    };
+    let (path, timestamp) = match matches.as_slice() {
+        [backup] => *backup,
+        [] => return Err(failure(ExitCode::NotFound, format!("No backup matches '{}'", which))),
+        _ => {
+            let timestamps: Vec<&str> = matches.iter().map(|(_, t)| t.as_str()).collect();
+            return Err(failure(
+                ExitCode::Usage,
+                format!("'{}' matches several backups: {}", which, timestamps.join(", ")),
+            ));
+        }
+    };
+
+    let data = fs::read_to_string(path).file_context("read", path)?;
+    let findings = check_profiles_data(&data, FileFormat::Json, true);
+    if let Some(finding) = findings.iter().find(|f| f.severity == Severity::Error) {
+        return Err(Failure::new(ExitCode::Error, format!("Backup {} is not valid: {}", timestamp, finding.message))
+            .path(path)
+            .into());
+    }
+    let restored: Config = serde_json::from_str(&data).with_context(|| format!("Failed to parse {}", path.display()))?;
+
+    let config_path = get_config_path()?;
+    check_writable(&config_path)?;
+    journal_config()?;
+    backup_config(&config_path, &data)?;
+    tracing::debug!("write {}", config_path.display());
+    write_atomically(&config_path, &data)?;
+    // Keep the current profile if the backup still has it; otherwise none is
+    let config = load_config()?;
+    save_current(&config)?;
+
+    eprintln!("Restored {} from the backup of {}", config_path.display(), timestamp);
+    eprintln!("Profiles now: {}", describe_config(&restored));
+    Ok(())
+}
+
+/// One line of switch.log.
+#[derive(Debug, Serialize, Deserialize)]
+struct SwitchLogEntry {
+    timestamp: chrono::DateTime<chrono::Local>,
+    from: Option<String>,
+    to: String,
+    directory: String,
+}
+
+/// Append a switch to the log. Logging is best-effort and never fails the
+/// switch itself, and a dry run didn't switch.
+fn log_switch(from: Option<&str>, to: &str) {
+    if options().dry_run {
+        return;
+    }
+    let write = || -> Result<()> {
+        if !load_settings()?.log_switches {
+            return Ok(());
+        }
+
+        let entry = SwitchLogEntry {
+            timestamp: chrono::Local::now(),
+            from: from.map(str::to_string),
+            to: to.to_string(),
+            directory: std::env::current_dir()
+                .map(|d| d.display().to_string())
+                .unwrap_or_default(),
+        };
+
+        let mut file = fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(get_config_dir()?.join(SWITCH_LOG_FILE))?;
+        writeln!(file, "{}", serde_json::to_string(&entry)?)?;
+        Ok(())
+    };
+
+    let _ = write();
+}
+
+fn show_history(count: usize, json: bool) -> Result<()> {
+    let log_path = get_config_dir()?.join(SWITCH_LOG_FILE);
+    let content = if log_path.exists() {
+        fs::read_to_string(&log_path).context("Failed to read switch log")?
+    } else {
+        String::new()
+    };
+
+    // Skip lines we can't parse rather than refusing to show anything
+    let entries: Vec<SwitchLogEntry> = content
+        .lines()
+        .filter_map(|line| serde_json::from_str(line).ok())
+        .collect();
+    let entries = &entries[entries.len().saturating_sub(count)..];
+
+    if json {
+        println!("{}", serde_json::to_string_pretty(entries)?);
+        return Ok(());
+    }
+
+    if entries.is_empty() {
+        eprintln!("No switches recorded yet.");
+        return Ok(());
+    }
+
+    let width = entries
+        .iter()
+        .map(|e| e.from.as_deref().unwrap_or("-").len())
+        .max()
+        .unwrap_or(4)
+        .max(4);
+    let to_width = entries.iter().map(|e| e.to.len()).max().unwrap_or(2).max(2);
+
+    println!(
+        "{:<19}  {:<width$}  {:<to_width$}  DIRECTORY",
+        "TIME", "FROM", "TO"
+    );
+    for entry in entries {
+        println!(
+            "{:<19}  {:<width$}  {:<to_width$}  {}",
+            entry.timestamp.format("%Y-%m-%d %H:%M:%S"),
+            entry.from.as_deref().unwrap_or("-"),
+            entry.to,
+            entry.directory.dimmed()
+        );
+    }
+
+    Ok(())
+}
+
+/// One change gs made to git or SSH config, as a line of audit.log.
+#[derive(Debug, Serialize, Deserialize)]
+struct AuditEntry {
+    timestamp: chrono::DateTime<chrono::Local>,
+    #[serde(flatten)]
+    change: AuditChange,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+enum AuditChange {
+    /// A global git setting. None is unset; several values are joined with
+    /// ", ".
+    Git { key: String, old: Option<String>, new: Option<String> },
+    /// An SSH config file, with the 1-based lines that changed in the new
+    /// contents. `restored` marks a rollback after a failed switch.
+    Ssh {
+        path: PathBuf,
+        #[serde(default)]
+        hosts: Vec<String>,
+        #[serde(default)]
+        lines: Vec<usize>,
+        #[serde(default)]
+        restored: bool,
+    },
+}
+
+/// Append a change to the audit log. Like the switch log, this is
+/// best-effort and never fails the change itself.
+fn audit(change: AuditChange) {
+    let write = || -> Result<()> {
+        if !load_settings()?.audit_log {
+            return Ok(());
+        }
+
+        let entry = AuditEntry { timestamp: chrono::Local::now(), change };
+        let mut file = fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(get_config_dir()?.join(AUDIT_LOG_FILE))?;
+        writeln!(file, "{}", serde_json::to_string(&entry)?)?;
+        Ok(())
+    };
+
+    let _ = write();
+}
+
+/// Lines of `new` that differ from `old`, counting from 1. A removal
+/// counts as the line that follows it.
+fn changed_lines(old: &str, new: &str) -> Vec<usize> {
+    let diff = similar::TextDiff::from_lines(old, new);
+    let mut lines: Vec<usize> = diff
+        .ops()
+        .iter()
+        .filter(|op| op.tag() != similar::DiffTag::Equal)
+        .flat_map(|op| {
+            let range = op.new_range();
+            if range.is_empty() { range.start..range.start + 1 } else { range }
+        })
+        .map(|i| i + 1)
+        .collect();
+    lines.dedup();
+    lines
+}
+
+fn show_audit(count: usize, json: bool) -> Result<()> {
+    let log_path = get_config_dir()?.join(AUDIT_LOG_FILE);
+    let content = if log_path.exists() {
+        fs::read_to_string(&log_path).context("Failed to read audit log")?
+    } else {
+        String::new()
+    };
+
+    let entries: Vec<AuditEntry> = content
+        .lines()
+        .filter_map(|line| serde_json::from_str(line).ok())
+        .collect();
+    let entries = &entries[entries.len().saturating_sub(count)..];
+
+    if json {
+        println!("{}", serde_json::to_string_pretty(entries)?);
+        return Ok(());
+    }
+
+    if entries.is_empty() {
+        eprintln!("No changes recorded yet.");
+        return Ok(());
+    }
+
+    for entry in entries {
+        let was = |old: &Option<String>| match old {
+            Some(old) => format!(" (was {})", old).dimmed().to_string(),
+            None => String::new(),
+        };
+        let description = match &entry.change {
+            AuditChange::Git { key, old, new: Some(new) } => format!("set {}={}{}", key, new, was(old)),
+            AuditChange::Git { key, old, new: None } => format!("unset {}{}", key, was(old)),
+            AuditChange::Ssh { path, hosts, lines, restored } => {
+                let path = collapse_tilde(&path.display().to_string());
+                let action = match (restored, hosts.as_slice()) {
+                    (true, _) => format!("restored {}", path),
+                    (false, []) => format!("updated {}", path),
+                    (false, hosts) => format!("updated IdentityFile for {} in {}", hosts.join(", "), path),
+                };
+                match lines.as_slice() {
+                    [] => action,
+                    [line] => format!("{} line {}", action, line),
+                    lines => format!(
+                        "{} lines {}",
+                        action,
+                        lines.iter().map(|l| l.to_string()).collect::<Vec<_>>().join(", ")
+                    ),
+                }
+            }
+        };
+        println!("{}  {}", entry.timestamp.format("%Y-%m-%d %H:%M:%S"), description);
+    }
+
+    Ok(())
+}
+
+fn describe_config(config: &Config) -> String {
+    if config.profiles.is_empty() {
+        return "(no profiles)".to_string();
+    }
+
+    config
+        .profiles
+        .iter()
+        .map(|p| if p.current { format!("{} (current)", p.name) } else { p.name.clone() })
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, clap::ValueEnum)]
+enum FileFormat {
+    Json,
+    Yaml,
+}
+
+impl FileFormat {
+    fn resolve(flag: Option<FileFormat>, path: Option<&Path>) -> Result<FileFormat> {
+        if let Some(format) = flag {
+            return Ok(format);
+        }
+
+        let Some(path) = path else {
+            return Ok(FileFormat::Json);
+        };
+
+        match path.extension().and_then(|e| e.to_str()).map(str::to_lowercase).as_deref() {
+            Some("json") => Ok(FileFormat::Json),
+            Some("yaml") | Some("yml") => Ok(FileFormat::Yaml),
+            _ => Err(anyhow!(
+                "Can't tell the format of {} from its extension; pass --format json|yaml",
+                path.display()
+            )),
+        }
+    }
+}
+
+fn export_profiles(file: Option<&Path>, format: Option<FileFormat>) -> Result<()> {
+    let format = FileFormat::resolve(format, file)?;
+    let mut config = load_config()?;
+
+    // The current marker is machine state, not part of the profile definitions
+    for profile in &mut config.profiles {
+        profile.current = false;
+    }
+
+    let data = match format {
+        FileFormat::Json => serde_json::to_string_pretty(&config).context("Failed to serialize profiles")?,
+        FileFormat::Yaml => serde_yaml::to_string(&config).context("Failed to serialize profiles")?,
+    };
+
+    match file {
+        Some(path) => {
+            fs::write(path, data).with_context(|| format!("Failed to write {}", path.display()))?;
+            eprintln!("Exported {} profiles to {}", config.profiles.len(), path.display());
+        }
+        None => println!("{}", data.trim_end()),
+    }
+
+    Ok(())
+}
+
+fn import_profiles(file: &Path, format: Option<FileFormat>, on_conflict: Option<OnConflict>) -> Result<()> {
+    let format = FileFormat::resolve(format, Some(file))?;
+    let data = fs::read_to_string(file).with_context(|| format!("Failed to read {}", file.display()))?;
+    let imported: Config = match format {
+        FileFormat::Json => serde_json::from_str(&data).context("Failed to parse JSON profiles")?,
+        FileFormat::Yaml => serde_yaml::from_str(&data).context("Failed to parse YAML profiles")?,
+    };
+    merge_profiles(imported, on_conflict)
+}
+
+/// Merge profiles from elsewhere (`gs import`, `gs sync --pull`) into the
+/// local ones, settling each name that's already taken by `on_conflict`.
+fn merge_profiles(imported: Config, on_conflict: Option<OnConflict>) -> Result<()> {
+    let on_conflict = on_conflict.unwrap_or(match io::stdin().is_terminal() {
+        true => OnConflict::Ask,
+        false => OnConflict::Skip,
+    });
+
+    let mut config = load_config()?;
+    let original = config.clone();
+    let was_empty = config.profiles.is_empty();
+    // What happened to each profile in the file, in order
+    let mut outcomes: Vec<(String, ImportOutcome)> = Vec::new();
+    let mut reapply = None;
+
+    for mut profile in imported.profiles {
+        let name = profile.name.clone();
+        // Files from before the git name was separate commit under the name
+        profile.git_name.get_or_insert_with(|| profile.name.clone());
+        // Importing never changes which profile is active
+        profile.current = false;
+        profile.collapse_paths();
+        profile.created_at.get_or_insert_with(chrono::Local::now);
+
+        let existing = existing_profile(&config, &profile.name);
+        let strategy = match existing {
+            None => None,
+            Some(index) if same_definition(&config.profiles[index], &profile) => {
+                outcomes.push((name, ImportOutcome::Unchanged));
+                continue;
+            }
+            Some(index) if on_conflict == OnConflict::Ask => Some(ask_import_conflict(&config.profiles[index], &profile)?),
+            Some(_) => Some(on_conflict),
+        };
+
+        match (strategy, existing) {
+            (Some(OnConflict::Skip), _) => outcomes.push((name, ImportOutcome::Skipped("kept the local profile".to_string()))),
+            (Some(OnConflict::Overwrite), Some(index)) => {
+                let local = &config.profiles[index];
+                // Which profile is current or default here, where it's
+                // stored and when it was made stay as they were
+                profile.name.clone_from(&local.name);
+                profile.current = local.current;
+                profile.default = local.default;
+                profile.source.clone_from(&local.source);
+                profile.created_at = local.created_at;
+                profile.touch();
+                if profile.current {
+                    reapply = Some(index);
+                }
+                config.profiles[index] = profile;
+                outcomes.push((name, ImportOutcome::Overwritten));
+            }
+            (Some(OnConflict::Rename), _) => {
+                let renamed = match checked_name(&config, None, &imported_name(&config, &name)) {
+                    Ok(renamed) => renamed,
+                    Err(err) => {
+                        outcomes.push((name, ImportOutcome::Skipped(err.to_string())));
+                        continue;
+                    }
+                };
+                profile.name.clone_from(&renamed);
+                profile.default = false;
+                config.profiles.push(profile);
+                outcomes.push((name, ImportOutcome::Renamed(renamed)));
+            }
+            _ => {
+                if let Err(err) = checked_name(&config, None, &profile.name) {
+                    outcomes.push((name, ImportOutcome::Skipped(err.to_string())));
+                    continue;
+                }
+                if profile.default && config.profiles.iter().any(|p| p.default) {
+                    eprintln!("{} '{}' is marked default in the file, but this machine already has a default", "Note:".yellow(), profile.name);
+                    profile.default = false;
+                }
+                config.profiles.push(profile);
+                outcomes.push((name, ImportOutcome::Added));
+            }
+        }
+    }
+
+    // Everything is decided before anything is written: the merged profiles
+    // are saved in one go, and not at all if the current profile was
+    // overwritten and can't be applied
+    let plan = reapply.map(|i| plan_apply(&config.profiles[i], &ApplyScope::default())).transpose()?;
+    let _critical = prompt::critical();
+    if let Some(plan) = &plan {
+        run_apply(plan).context("Failed to apply the overwritten current profile; nothing was imported")?;
+    }
+    if config != original {
+        save_config(&config)?;
+    }
+
+    for (name, outcome) in &outcomes {
+        match outcome {
+            ImportOutcome::Added => eprintln!("{} {}", "Imported".green(), name),
+            ImportOutcome::Overwritten => eprintln!("{} {} with the imported profile", "Overwrote".yellow(), name),
+            ImportOutcome::Renamed(renamed) => eprintln!("{} {} as {}", "Imported".green(), name, renamed),
+            ImportOutcome::Unchanged => eprintln!("{} {} (already the same here)", "Unchanged".dimmed(), name),
+            ImportOutcome::Skipped(reason) => eprintln!("{} {} ({})", "Skipped".yellow(), name, reason),
+        }
+    }
+    let count = |wanted: fn(&ImportOutcome) -> bool| outcomes.iter().filter(|(_, o)| wanted(o)).count();
+    eprintln!(
+        "{} imported, {} overwritten, {} unchanged, {} skipped.",
+        count(|o| matches!(o, ImportOutcome::Added | ImportOutcome::Renamed(_))),
+        count(|o| matches!(o, ImportOutcome::Overwritten)),
+        count(|o| matches!(o, ImportOutcome::Unchanged)),
+        count(|o| matches!(o, ImportOutcome::Skipped(_))),
+    );
+    if reapply.is_some() {
+        eprintln!("Re-applied the current profile");
+    }
+
+    // On a machine that had no profiles yet, start on the default
+    let default = config.profiles.iter().position(|p| p.default);
+    if let Some(index) = default.filter(|_| was_empty) {
+        let profile = activate_profile(&mut config, index, &ApplyScope::default())?;
+        let switched = if options().dry_run { "Would switch to" } else { "Switched to" };
+        eprintln!("{} the default profile: {} ({})", switched, profile.styled_name(), profile.email);
+    }
+    if options().dry_run {
+        eprintln!("Dry run: nothing was saved.");
+    }
+    Ok(())
+}
+
+/// What `gs import` did with one profile from the file.
+enum ImportOutcome {
+    Added,
+    Overwritten,
+    /// Imported under the new name.
+    Renamed(String),
+    /// The local profile is already the same.
+    Unchanged,
+    Skipped(String),
+}
+
+/// Whether two profiles define the same identity, ignoring what's only
+/// about this machine.
+fn same_definition(local: &Profile, imported: &Profile) -> bool {
+    comparable_fields(local)[1..] == comparable_fields(imported)[1..]
+}
+
+/// `<name>-imported`, or `<name>-imported-2` and so on if that's taken too.
+fn imported_name(config: &Config, name: &str) -> String {
+    let base = format!("{}-imported", name);
+    (1..)
+        .map(|n| match n {
+            1 => base.clone(),
+            n => format!("{}-{}", base, n),
+        })
+        .find(|candidate| existing_profile(config, candidate).is_none())
+        .expect("some suffix is free")
+}
+
+/// Show how an imported profile differs from the local one of the same name
+/// and ask what to do with it.
+fn ask_import_conflict(local: &Profile, imported: &Profile) -> Result<OnConflict> {
+    eprintln!("{} already exists and differs from the imported one:", local.name.bold());
+    let unset = "(unset)".to_string();
+    for ((field, ours), (_, theirs)) in comparable_fields(local).into_iter().zip(comparable_fields(imported)).skip(1) {
+        if ours != theirs {
+            eprintln!("  {}", field.bold());
+            eprintln!("    local:    {}", ours.as_ref().unwrap_or(&unset).red());
+            eprintln!("    imported: {}", theirs.as_ref().unwrap_or(&unset).green());
+        }
+    }
+    let choice = prompt::prompt("conflict", "Keep [l]ocal, [o]verwrite or import as [r]enamed? ")?.to_lowercase();
+    Ok(match choice.as_str() {
+        "o" | "overwrite" => OnConflict::Overwrite,
+        "r" | "rename" | "renamed" => OnConflict::Rename,
+        _ => OnConflict::Skip,
+    })
+}
+
+/// `gs import --from-gitconfig`: a profile for each identity fragment the
+/// global git config includes with `[includeIf "gitdir:..."]`, and an
+/// autoswitch rule for each directory that includes it. Only gs's own
+/// profiles and settings change.
+fn import_from_gitconfig() -> Result<()> {
+    let (Some(gitconfig), _) = resolve_git_global_config() else {
+        return Err(anyhow!("Could not find your global git config"));
+    };
+    if !gitconfig.is_file() {
+        return Err(Failure::new(ExitCode::NotFound, format!("No git config at {}", gitconfig.display())).path(&gitconfig).into());
+    }
+    let shown = collapse_tilde(&gitconfig.display().to_string());
+    let base = gitconfig.parent().unwrap_or(Path::new("")).to_path_buf();
+
+    // Fragment → the directories that include it
+    let mut fragments: Vec<(PathBuf, Vec<String>)> = Vec::new();
+    let mut skipped = Vec::new();
+    for (condition, path) in gitconfig_includes(&gitconfig)? {
+        let fragment = expand_path(Path::new(&path));
+        let fragment = if fragment.is_relative() { base.join(fragment) } else { fragment };
+        match gitdir_directory(&condition, &base) {
+            Ok(dir) => match fragments.iter_mut().find(|(f, _)| *f == fragment) {
+                Some((_, dirs)) => dirs.push(dir),
+                None => fragments.push((fragment, vec![dir])),
+            },
+            Err(reason) => skipped.push((format!("includeIf \"{}\"", condition), reason)),
+        }
+    }
+
+    let global_name = read_git_config(&["--file", path_text(&gitconfig)?], "user.name");
+    let mut config = load_config()?;
+    let mut settings = load_settings()?;
+    let existing = config.profiles.len();
+    let mut mapped = Vec::new();
+    for (fragment, dirs) in fragments {
+        let label = collapse_tilde(&fragment.display().to_string());
+        let values = match read_fragment(&fragment) {
+            Ok(values) => values,
+            Err(reason) => {
+                skipped.push((label, reason));
+                continue;
+            }
+        };
+        let Some(email) = values.get("user.email").filter(|email| is_valid_email(email)) else {
+            skipped.push((label, "no valid user.email".to_string()));
+            continue;
+        };
+        // Named after the file, or else after the directory it's for
+        let candidates = [fragment_name(&fragment), dirs.first().and_then(|dir| Path::new(dir).file_name()).map(|n| n.to_string_lossy().into_owned())];
+        let name = match candidates.into_iter().flatten().find(|name| checked_name(&config, None, name).is_ok()) {
+            Some(name) => name,
+            None => {
+                skipped.push((label, "no usable profile name (is there already one for it?)".to_string()));
+                continue;
+            }
+        };
+        let ssh_key = values.get("core.sshcommand").and_then(|command| ssh_command_key(command)).map(|key| expand_tilde(&key)).transpose()?;
+        if let Some(key) = ssh_key.as_ref().filter(|key| !Path::new(key).exists()) {
+            skipped.push((label, format!("SSH key not found at {}", key)));
+            continue;
+        }
+
+        let mut profile = Profile {
+            name: name.clone(),
+            // A fragment without user.name commits under the global one
+            git_name: values.get("user.name").cloned().or_else(|| global_name.clone()),
+            email: email.clone(),
+            // Without an sshCommand the fragment left ssh to the agent
+            ssh_key: ssh_key.unwrap_or_default(),
+            description: Some(format!("Imported from {}", label)),
+            default_branch: values.get("init.defaultbranch").cloned(),
+            created_at: Some(chrono::Local::now()),
+            ..Default::default()
+        };
+        profile.collapse_paths();
+        config.profiles.push(profile);
+        for dir in dirs {
+            match settings.auto_switch.get(&dir) {
+                Some(other) => skipped.push((dir, format!("autoswitch already maps it to '{}'", other))),
+                None => mapped.push((dir, name.clone())),
+            }
+        }
+    }
+
+    let added = &config.profiles[existing..];
+    eprintln!("=== Profiles from the includeIf sections of {} ===", shown);
+    for profile in added {
+        eprintln!("{} {} <{}>", "+".green(), profile.name, profile.email);
+        eprintln!("  SSH Key: {}", key_label(&profile.ssh_key));
+        for (dir, _) in mapped.iter().filter(|(_, name)| *name == profile.name) {
+            eprintln!("  Autoswitch: {}", dir);
+        }
+    }
+    for (label, reason) in &skipped {
+        eprintln!("{} {} ({})", "Skipped".yellow(), label, reason);
+    }
+    if added.is_empty() {
+        eprintln!("Nothing to import.");
+        return Ok(());
+    }
+    if options().dry_run {
+        eprintln!("Dry run: {} profile(s) would be added.", added.len());
+        return Ok(());
+    }
+    if !options().yes && !prompt::confirm(&format!("Add {} profile(s)?", added.len()))? {
+        return Err(failure(ExitCode::Aborted, "Aborted"));
+    }
+
+    let count = added.len();
+    save_config(&config)?;
+    if !mapped.is_empty() {
+        settings.auto_switch.extend(mapped);
+        save_settings(&settings)?;
+    }
+    eprintln!("{} imported. {} is unchanged; its includeIf sections still apply.", count, shown);
+    Ok(())
+}
+
+/// The `[includeIf "<condition>"] path = <file>` entries of a git config
+/// file, in order.
+fn gitconfig_includes(path: &Path) -> Result<Vec<(String, String)>> {
+    let output = process::Command::new("git")
+        .arg("config")
+        .arg("--file")
+        .arg(path)
+        .args(["--null", "--get-regexp", r"^includeif\..*\.path$"])
+        .logged_output()
+        .external("Failed to run git config")?;
+    // 1 means there are none
+    if output.status.code() == Some(1) {
+        return Ok(Vec::new());
+    }
+    if !output.status.success() {
+        return Err(failure(
+            ExitCode::External,
+            format!("Can't read {}: {}", path.display(), String::from_utf8_lossy(&output.stderr).trim()),
+        ));
+    }
+    // "includeif.<condition>.path\n<file>\0", with the condition as written
+    Ok(String::from_utf8_lossy(&output.stdout)
+        .split('\0')
+        .filter_map(|entry| {
+            let (key, value) = entry.split_once('\n')?;
+            let condition = key.strip_prefix("includeif.")?.strip_suffix(".path")?;
+            Some((condition.to_string(), value.to_string()))
+        })
+        .collect())
+}
+
+/// The directory a `gitdir:` or `gitdir/i:` condition covers, in `~/...`
+/// form, or why it doesn't name one autoswitch can use. Relative `./`
+/// patterns are relative to the including file's directory, `base`.
+fn gitdir_directory(condition: &str, base: &Path) -> std::result::Result<String, String> {
+    let Some(pattern) = condition.strip_prefix("gitdir:").or_else(|| condition.strip_prefix("gitdir/i:")) else {
+        return Err("only gitdir conditions say which directories an identity is for".to_string());
+    };
+    // A trailing slash covers everything below; a path to a .git directory,
+    // that one repository
+    let dir = pattern.trim_end_matches("**").trim_end_matches('/');
+    let dir = dir.strip_suffix("/.git").unwrap_or(dir);
+    if dir.contains(['*', '?', '[']) {
+        return Err(format!("'{}' is a wildcard pattern, not one directory", pattern));
+    }
+    let dir = if let Some(relative) = dir.strip_prefix("./") {
+        path_text(&base.join(relative)).map_err(|err| err.to_string())?.to_string()
+    } else if dir.starts_with('/') || dir.starts_with("~/") {
+        expand_tilde(dir).map_err(|err| err.to_string())?
+    } else {
+        // git matches these anywhere, as if they started with **/
+        return Err(format!("'{}' matches in any directory", pattern));
+    };
+    Ok(collapse_tilde(&dir))
+}
+
+/// A fragment's settings, keys lowercased the way git prints them, or why
+/// it can't be read.
+fn read_fragment(path: &Path) -> std::result::Result<BTreeMap<String, String>, String> {
+    if !path.is_file() {
+        return Err("file not found".to_string());
+    }
+    let output = process::Command::new("git")
+        .arg("config")
+        .arg("--file")
+        .arg(path)
+        .args(["--null", "--list"])
+        .logged_output()
+        .map_err(|err| err.to_string())?;
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        return Err(stderr.lines().next().unwrap_or("git can't parse it").trim().to_string());
+    }
+    // Later values win, as they do for git
+    Ok(String::from_utf8_lossy(&output.stdout)
+        .split('\0')
+        .filter_map(|entry| entry.split_once('\n'))
+        .map(|(key, value)| (key.to_string(), value.to_string()))
+        .collect())
+}
+
+/// A profile name for a fragment file: `~/.gitconfig-work`,
+/// `~/work.gitconfig` and `~/.config/git/work.inc` give "work". A file named
+/// only `config` or `.gitconfig` gives none.
+fn fragment_name(path: &Path) -> Option<String> {
+    let file = path.file_name()?.to_string_lossy();
+    let mut name = file.trim_start_matches('.').to_string();
+    for word in ["gitconfig", "config", "inc"] {
+        if let Some(rest) = name.strip_prefix(word) {
+            name = rest.to_string();
+        }
+        if let Some(rest) = name.strip_suffix(word) {
+            name = rest.to_string();
+        }
+    }
+    let name = name.trim_matches(['-', '_', '.']);
+    (!name.is_empty()).then(|| name.to_string())
+}
+
+/// The key file a `core.sshCommand` passes with `-i`.
+fn ssh_command_key(command: &str) -> Option<String> {
+    let mut words = command.split_whitespace();
+    while let Some(word) = words.next() {
+        if word == "-i" {
+            return words.next().map(|key| key.trim_matches(['"', '\'']).to_string());
+        }
+        if let Some(key) = word.strip_prefix("-i") {
+            return Some(key.trim_matches(['"', '\'']).to_string());
+        }
+    }
+    None
+}
+
+/// Create profiles from another switcher's file: a tool `migrate` knows, at
+/// its usual path, or a path to read in whichever format it's in. Entries
+/// are checked like setup checks them and the ones that fail are skipped;
+/// the rest are shown, and saved once confirmed (or with --yes).
+fn migrate_from(source: &str) -> Result<()> {
+    let path = expand_path(Path::new(migrate::find_tool(source).map_or(source, |tool| tool.path)));
+    if !path.is_file() {
+        let tools: Vec<&str> = migrate::TOOLS.iter().map(|tool| tool.name).collect();
+        return Err(Failure::new(
+            ExitCode::NotFound,
+            format!("No file at {}; name a file or one of: {}", path.display(), tools.join(", ")),
+        )
+        .path(&path)
+        .into());
+    }
+    let content = fs::read_to_string(&path).file_context("read", &path)?;
+    let Some((format, entries)) = migrate::parse(&content).filter(|(_, entries)| entries.iter().any(|e| e.email.is_some())) else {
+        return Err(failure(
+            ExitCode::Usage,
+            format!(
+                "Can't read {} as a git-profiles ini or git-profile JSON file. Convert it to gs's own format \
+                 (what `gs export` writes, described under Configuration in the README) and use `gs import`",
+                path.display()
+            ),
+        ));
+    };
+    tracing::debug!("read {} entries from {} as {}", entries.len(), path.display(), format.label());
+
+    let mut config = load_config()?;
+    let existing = config.profiles.len();
+    let mut skipped = Vec::new();
+    for entry in entries {
+        // The entry's label names the profile; its user.name is for commits
+        let name = match entry.name.as_deref().filter(|_| checked_name(&config, None, &entry.label).is_err()) {
+            Some(name) => name.trim().to_string(),
+            None => entry.label.trim().to_string(),
+        };
+        let problem = match (checked_name(&config, None, &name), &entry.email, &entry.key) {
+            (Err(err), _, _) => Some(err.to_string()),
+            (_, None, _) => Some("no email".to_string()),
+            (_, Some(email), _) if !is_valid_email(email) => Some(format!("'{}' is not a valid email address", email)),
+            (_, _, Some(key)) if !expand_path(Path::new(key)).exists() => Some(format!("SSH key not found at {}", key)),
+            _ => None,
+        };
+        if let Some(problem) = problem {
+            skipped.push((entry.label, problem));
+            continue;
+        }
+        let mut profile = Profile {
+            name,
+            git_name: entry.name,
+            email: entry.email.unwrap_or_default(),
+            // Without a key, the other tool left ssh to the agent
+            ssh_key: entry.key.as_deref().map(expand_tilde).transpose()?.unwrap_or_default(),
+            description: Some(format!("Migrated from {} [{}]", collapse_tilde(&path.display().to_string()), entry.label)),
+            created_at: Some(chrono::Local::now()),
+            ..Default::default()
+        };
+        profile.collapse_paths();
+        config.profiles.push(profile);
+    }
+
+    let added = &config.profiles[existing..];
+    eprintln!("=== Profiles from {} ({}) ===", path.display(), format.label());
+    for (i, profile) in added.iter().enumerate() {
+        eprintln!("{} {} <{}>", "+".green(), profile.name, profile.email);
+        eprintln!("  SSH Key: {}", key_label(&profile.ssh_key));
+        for warning in duplicate_warnings(&config, Some(existing + i), &profile.email) {
+            eprintln!("  {} {}", "Warning:".yellow(), warning);
+        }
+    }
+    for (label, reason) in &skipped {
+        eprintln!("{} {} ({})", "Skipped".yellow(), label, reason);
+    }
+    if added.is_empty() {
+        eprintln!("Nothing to migrate.");
+        return Ok(());
+    }
+    if options().dry_run {
+        eprintln!("Dry run: {} profile(s) would be added.", added.len());
+        return Ok(());
+    }
+    if !options().yes && !prompt::confirm(&format!("Add {} profile(s)?", added.len()))? {
+        return Err(failure(ExitCode::Aborted, "Aborted"));
+    }
+
+    let count = added.len();
+    save_config(&config)?;
+    eprintln!("{} migrated, {} skipped.", count, skipped.len());
+    eprintln!("Switch to one with '{} <name>' to start using it.", program_name());
+    Ok(())
+}
+
+/// Offer every key pair in ~/.ssh that no profile uses, and create a profile
+/// for each one picked. Only profiles.json changes; switch to a new profile
+/// to apply it.
+fn import_keys() -> Result<()> {
+    let mut config = load_config()?;
+    let ssh_dir = get_ssh_dir()?;
+    let used: Vec<String> = config
+        .profiles
+        .iter()
+        .flat_map(|p| p.ssh_hosts().into_iter().map(|(_, key)| expand_tilde(key)))
+        .collect::<Result<_>>()?;
+
+    let mut keys: Vec<PathBuf> = fs::read_dir(&ssh_dir)
+        .with_context(|| format!("Failed to read {}", ssh_dir.display()))?
+        .filter_map(|entry| entry.ok().map(|e| e.path()))
+        .filter(|path| {
+            let name = path.file_name().unwrap_or_default().to_string_lossy();
+            // Private keys have a .pub next to them; old.<date> pairs are
+            // backups left by `gs key rotate`
+            path.is_file()
+                && !name.ends_with(".pub")
+                && !name.contains(".old.")
+                && Path::new(&format!("{}.pub", path.display())).is_file()
+                && !used.contains(&path.display().to_string())
+        })
+        .collect();
+    keys.sort();
+
+    if keys.is_empty() {
+        eprintln!("No unused SSH keys found in {}", ssh_dir.display());
+        return Ok(());
+    }
+
+    eprintln!("=== Unused SSH Keys ===");
+    for (i, key) in keys.iter().enumerate() {
+        match public_key_comment(key) {
+            Some(comment) => eprintln!("{}. {} {}", i + 1, collapse_tilde(&key.display().to_string()), comment.dimmed()),
+            None => eprintln!("{}. {}", i + 1, collapse_tilde(&key.display().to_string())),
+        }
+    }
+
+    let input = prompt::prompt("key numbers", "Import which keys? (e.g. 1,3; Enter for all): ")?;
+    let mut selected = Vec::new();
+    for part in input.split(|c: char| c == ',' || c.is_whitespace()).filter(|p| !p.is_empty()) {
+        match part.parse::<usize>() {
+            Ok(n) if n > 0 && n <= keys.len() => selected.push(keys[n - 1].clone()),
+            _ => return Err(failure(ExitCode::Usage, format!("Invalid key number: {}", part))),
+        }
+    }
+    if selected.is_empty() {
+        selected = keys;
+    }
+    // "1,3,1" is keys 1 and 3, once each
+    selected.sort_unstable();
+    selected.dedup();
+
+    // Ask about every key before saving anything
+    let mut added = Vec::new();
+    for key in selected {
+        let ssh_key = collapse_tilde(path_text(&key)?);
+        eprintln!();
+        eprintln!("{}", ssh_key.bold());
+
+        let suggested = name_from_key_file(&key.file_name().unwrap_or_default().to_string_lossy());
+        let name = prompt_name(&config, None, &format!("Profile name [{}]: ", suggested), &suggested)?;
+        let git_name = prompt::prompt("git name", &format!("Name for commits [{}]: ", name))?;
+        let git_name = if git_name.is_empty() { name.clone() } else { git_name };
+
+        // ssh-keygen's default comment is user@hostname, which isn't one
+        let comment = public_key_comment(&key)
+            .filter(|c| is_valid_email(c) && c.split_once('@').is_some_and(|(_, domain)| domain.contains('.')));
+        let email = match comment {
+            Some(comment) => {
+                let answer = prompt_email(&format!("Email [{}]: ", comment), true)?;
+                if answer.is_empty() { comment } else { answer }
+            }
+            None => prompt_email("Email: ", false)?,
+        };
+        let host = prompt::prompt("host", &format!("Host [{}]: ", GITHUB_HOST))?;
+        let (hosts, ssh_users) = match host.as_str() {
+            "" | GITHUB_HOST => (BTreeMap::new(), BTreeMap::new()),
+            _ => (BTreeMap::from([(host.clone(), ssh_key.clone())]), setup_ssh_user(None, Some(&host), None, true)?),
+        };
+
+        added.push(name.clone());
+        config.profiles.push(Profile {
+            name,
+            git_name: Some(git_name),
+            email,
+            ssh_key,
+            hosts,
+            ssh_users,
+            created_at: Some(chrono::Local::now()),
+            ..Default::default()
+        });
+    }
+
+    save_config(&config)?;
+    eprintln!();
+    for name in &added {
+        eprintln!("{} {}", "Created".green(), name);
+    }
+    eprintln!("Switch to one with '{} <name>' to start using it.", program_name());
+    Ok(())
+}
+
+/// The comment at the end of a key's .pub file, usually an email address.
+fn public_key_comment(key: &Path) -> Option<String> {
+    let public_key = fs::read_to_string(format!("{}.pub", key.display())).ok()?;
+    let comment = public_key.split_whitespace().skip(2).collect::<Vec<_>>().join(" ");
+    (!comment.is_empty()).then_some(comment)
+}
+
+/// A profile name from a key's file name: id_ed25519_github_personal
+/// becomes github-personal.
+fn name_from_key_file(file_name: &str) -> String {
+    let mut name = file_name.strip_prefix("id_").unwrap_or(file_name);
+    for key_type in ["ed25519_sk", "ecdsa_sk", "ed25519", "ecdsa", "rsa", "dsa"] {
+        if let Some(rest) = name.strip_prefix(key_type).and_then(|r| r.strip_prefix('_')) {
+            name = rest;
+            break;
+        }
+    }
+    name.replace('_', "-")
+}
+
+/// Push or pull the profile set to the configured sync remote.
+fn sync_profiles(push: bool, on_conflict: Option<OnConflict>) -> Result<()> {
+    let remote = load_settings()?.sync_remote.ok_or_else(|| {
+        anyhow!(
+            "No sync remote configured. Run '{} settings sync_remote <DIR or git URL>' first",
+            program_name()
+        )
+    })?;
+    let dir = sync_dir(&remote)?;
+    let is_repo = run_git_in(&dir, &["rev-parse", "--show-toplevel"]).is_ok_and(|top| Path::new(&top) == dir);
+    let tracks_upstream = is_repo && run_git_in(&dir, &["rev-parse", "--abbrev-ref", "@{upstream}"]).is_ok();
+
+    if push {
+        sync_push(&dir, is_repo, tracks_upstream)
+    } else {
+        if tracks_upstream {
+            run_git_in(&dir, &["pull", "--ff-only"])?;
+        }
+        sync_pull(&dir, on_conflict)
+    }
+}
+
+/// The local directory the sync file lives in. A git URL (or bare repo) is
+/// cloned into the config directory once and reused afterwards.
+fn sync_dir(remote: &str) -> Result<PathBuf> {
+    let local = expand_path(Path::new(remote));
+    let is_bare = local.is_dir()
+        && run_git_in(&local, &["rev-parse", "--is-bare-repository"]).is_ok_and(|out| out == "true");
+    if local.is_dir() && !is_bare {
+        return Ok(local);
+    }
+    if !is_bare && !remote.contains(':') {
+        return Err(anyhow!("Sync remote {} is not a directory", local.display()));
+    }
+
+    // Start over if the remote was changed since the last clone
+    let clone = get_config_dir()?.join(SYNC_DIR);
+    if clone.exists() && run_git_in(&clone, &["remote", "get-url", "origin"]).ok().as_deref() != Some(remote) {
+        fs::remove_dir_all(&clone).context("Failed to remove the old sync clone")?;
+    }
+    if !clone.exists() {
+        let output = process::Command::new("git")
+            .args(["clone", "--quiet", remote])
+            .arg(&clone)
+            .logged_output()
+            .external("Failed to run git clone")?;
+        if !output.status.success() {
+            return Err(failure(
+                ExitCode::External,
+                format!(
+                    "git clone {} failed: {}",
+                    remote,
+                    String::from_utf8_lossy(&output.stderr).trim()
+                ),
+            ));
+        }
+    }
+    Ok(clone)
+}
+
+fn run_git_in(dir: &Path, args: &[&str]) -> Result<String> {
+    let output = process::Command::new("git")
+        .arg("-C")
+        .arg(dir)
+        .args(args)
+        .logged_output()
+        .external("Failed to run git")?;
+    if !output.status.success() {
+        return Err(failure(
+            ExitCode::External,
+            format!(
+                "git {} failed: {}",
+                args.join(" "),
+                String::from_utf8_lossy(&output.stderr).trim()
+            ),
+        ));
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).trim().to_string())
+}
+
+fn sync_push(dir: &Path, is_repo: bool, tracks_upstream: bool) -> Result<()> {
+    let config = load_config()?;
+    let home = dirs::home_dir().ok_or_else(|| anyhow!("Could not find home directory"))?;
+
+    let mut shared = Config::default();
+    for profile in &config.profiles {
+        match portable_profile(profile, &home) {
+            Some(profile) => shared.profiles.push(profile),
+            None => eprintln!(
+                "{} {} (its SSH key is outside your home directory)",
+                "Not synced".yellow(),
+                profile.name
+            ),
+        }
+    }
+
+    let path = dir.join(SYNC_FILE);
+    let data = serde_json::to_string_pretty(&shared).context("Failed to serialize profiles")?;
+    fs::write(&path, data + "\n").with_context(|| format!("Failed to write {}", path.display()))?;
+    eprintln!("Wrote {} profiles to {}", shared.profiles.len(), path.display());
+
+    if is_repo {
+        run_git_in(dir, &["add", SYNC_FILE])?;
+        if run_git_in(dir, &["diff", "--cached", "--quiet", "--", SYNC_FILE]).is_err() {
+            run_git_in(dir, &["commit", "--quiet", "-m", "Update gs profiles", "--", SYNC_FILE])?;
+            eprintln!("Committed {}", SYNC_FILE);
+        }
+        // A fresh clone of an empty repo has no upstream yet
+        if tracks_upstream {
+            run_git_in(dir, &["push", "--quiet"])?;
+        } else if run_git_in(dir, &["remote"])?.lines().any(|r| r == "origin") {
+            run_git_in(dir, &["push", "--quiet", "-u", "origin", "HEAD"])?;
+        } else {
+            return Ok(());
+        }
+        eprintln!("Pushed to {}", run_git_in(dir, &["rev-parse", "--abbrev-ref", "@{upstream}"])?);
+    }
+    Ok(())
+}
+
+/// A copy of `profile` fit to share between machines: not current, and with
+/// key paths written relative to `~`. None if a key lives outside home,
+/// since that path won't mean anything elsewhere.
+fn portable_profile(profile: &Profile, home: &Path) -> Option<Profile> {
+    let portable = |key: &str| -> Option<String> {
+        if key.is_empty() || key.starts_with("~/") {
+            return Some(key.to_string());
+        }
+        let relative = Path::new(key).strip_prefix(home).ok()?;
+        Some(format!("~/{}", relative.display()))
+    };
+
+    let mut shared = profile.clone();
+    shared.current = false;
+    shared.ssh_key = portable(&profile.ssh_key)?;
+    for key in shared.hosts.values_mut() {
+        *key = portable(key)?;
+    }
+    Some(shared)
+}
+
+fn sync_pull(dir: &Path, on_conflict: Option<OnConflict>) -> Result<()> {
+    let path = dir.join(SYNC_FILE);
+    let data = fs::read_to_string(&path).with_context(|| format!("Failed to read {}", path.display()))?;
+    let remote: Config = serde_json::from_str(&data).context("Failed to parse synced profiles")?;
+    merge_profiles(remote, on_conflict)
+}
+
+/// Find the profile a user meant by `query`: an exact name, then a
+/// case-insensitive match, then a unique case-insensitive prefix. Ambiguous
+/// matches list the candidates rather than picking one.
+fn resolve_profile(config: &Config, query: &str) -> Result<usize> {
+    let candidates = profile_candidates(config, query);
+    match candidates.as_slice() {
+        [index] => Ok(*index),
+        [] => Err(Failure::new(ExitCode::NotFound, format!("Profile '{}' not found", query))
+            .profile(query)
+            .into()),
+        _ => {
+            let names: Vec<&str> = candidates.iter().map(|&i| config.profiles[i].name.as_str()).collect();
+            Err(Failure::new(ExitCode::Usage, format!("'{}' matches several profiles: {}", query, names.join(", ")))
+                .profile(query)
+                .into())
+        }
+    }
+}
+
+/// Profiles `query` could mean: an exact name, else a case-insensitive
+/// name, else every name it's a prefix of.
+fn profile_candidates(config: &Config, query: &str) -> Vec<usize> {
+    if let Some(index) = config.profiles.iter().position(|p| p.name == query) {
+        return vec![index];
+    }
+
+    let query_lower = query.to_lowercase();
+    let matching = |pred: &dyn Fn(&str) -> bool| -> Vec<usize> {
+        config
+            .profiles
+            .iter()
+            .enumerate()
+            .filter(|(_, p)| pred(&p.name.to_lowercase()))
+            .map(|(i, _)| i)
+            .collect()
+    };
+
+    let mut candidates = matching(&|name| name == query_lower);
+    if candidates.is_empty() {
+        candidates = matching(&|name| name.starts_with(&query_lower));
+    }
+    candidates
+}
+
+fn no_profiles() -> anyhow::Error {
+    failure(
+        ExitCode::NoProfiles,
+        format!("No profiles found. Run '{} setup' to create your first profile.", program_name()),
+    )
+}
+
+fn switch_profile(target: Option<&str>, back: bool, pick: bool, clear: bool, scope: &ApplyScope) -> Result<()> {
+    let mut config = load_config()?;
+
+    if config.profiles.is_empty() {
+        return Err(no_profiles());
+    }
+
+    // At a terminal, a prefix that fits several profiles opens the picker
+    // on those instead of failing
+    let title = "Switch to Profile";
+    let target = match target {
+        _ if pick => Some(picker::pick_one(title, &config.profiles, "")?),
+        Some(name) if profile_candidates(&config, name).len() > 1 && io::stdin().is_terminal() => {
+            Some(picker::pick_one(title, &config.profiles, name)?)
+        }
+        Some(name) => Some(resolve_profile(&config, name)?),
+        None => None,
+    };
+    if target.is_none() && config.profiles.len() == 1 {
+        eprintln!("Only one profile exists. Run '{} setup' to create another profile.", program_name());
+        return Ok(());
+    }
+
+    // Find current profile and switch to the next one in its group (or
+    // previous with --back). If no current profile found, set first as current
+    let current = config.profiles.iter().position(|p| p.current);
+    let new_index = match (target, current) {
+        (Some(index), _) => index,
+        (None, Some(current)) => {
+            let group = &config.profiles[current].group;
+            let members: Vec<usize> = (0..config.profiles.len()).filter(|&i| config.profiles[i].group == *group).collect();
+            if members.len() == 1 {
+                eprintln!(
+                    "'{}' is the only profile in group '{}'. Use '{} group <name>' to change groups.",
+                    config.profiles[current].name,
+                    group.as_deref().unwrap_or(UNGROUPED),
+                    program_name()
+                );
+                return Ok(());
+            }
+            let position = members.iter().position(|&i| i == current).unwrap();
+            let step = if back { members.len() - 1 } else { 1 };
+            members[(position + step) % members.len()]
+        }
+        (None, None) => 0,
+    };
+
+    let new_profile = activate_profile(&mut config, new_index, scope)?;
+
+    if clear {
+        clear_screen();
+    }
+    eprintln!(
+        "{} profile: {} ({})",
+        if options().dry_run { "Would switch to" } else { "Switched to" },
+        new_profile.styled_name(),
+        new_profile.email
+    );
+    eprintln!("{}", switched_key_line(&new_profile).dimmed());
+    if let Some(description) = &new_profile.description {
+        eprintln!("{}", description.dimmed());
+    }
+
+    Ok(())
+}
+
+/// Mark a profile as the default `gs reset` returns to, or without a name,
+/// print which one is.
+fn set_default(name: Option<&str>) -> Result<()> {
+    let mut config = load_config()?;
+    if config.profiles.is_empty() {
+        return Err(no_profiles());
+    }
+    let Some(name) = name else {
+        match config.profiles.iter().find(|p| p.default) {
+            Some(profile) => println!("{}", profile.name),
+            None => eprintln!("No default profile; set one with '{} default <name>'.", program_name()),
+        }
+        return Ok(());
+    };
+
+    let index = resolve_profile(&config, name)?;
+    if config.profiles[index].default {
+        eprintln!("'{}' is already the default profile.", config.profiles[index].name);
+        return Ok(());
+    }
+    for (i, profile) in config.profiles.iter_mut().enumerate() {
+        if profile.default != (i == index) {
+            profile.default = i == index;
+            profile.touch();
+        }
+    }
+    save_config(&config)?;
+    eprintln!("'{}' is now the default profile; '{} reset' switches back to it.", config.profiles[index].name, program_name());
+    Ok(())
+}
+
+/// Switch to the default profile.
+fn reset_to_default() -> Result<()> {
+    let config = load_config()?;
+    if config.profiles.is_empty() {
+        return Err(no_profiles());
+    }
+    let Some(profile) = config.profiles.iter().find(|p| p.default) else {
+        return Err(failure(
+            ExitCode::NotFound,
+            format!("No default profile; set one with '{} default <name>'", program_name()),
+        ));
+    };
+    let name = profile.name.clone();
+    switch_profile(Some(&name), false, false, load_settings()?.clear_screen, &ApplyScope::default())
+}
+
+/// Switch to a group: make its first profile current, unless the current one
+/// already belongs to it. Without a name, list the groups.
+fn switch_group(name: Option<&str>) -> Result<()> {
+    let config = load_config()?;
+    if config.profiles.is_empty() {
+        return Err(no_profiles());
+    }
+
+    let groups = group_names(&config);
+    let active = config.profiles.iter().find(|p| p.current).map(|p| p.group.as_deref());
+
+    let Some(name) = name else {
+        for group in groups {
+            let count = config.profiles.iter().filter(|p| p.group.as_deref() == group).count();
+            let marker = if active == Some(group) { " (active)".green().to_string() } else { String::new() };
+            println!("{} ({} profiles){}", group.unwrap_or(UNGROUPED), count, marker);
+        }
+        return Ok(());
+    };
+
+    let Some(first) = config.profiles.iter().find(|p| p.group.as_deref() == Some(name)) else {
+        let known: Vec<&str> = groups.into_iter().flatten().collect();
+        return Err(failure(
+            ExitCode::NotFound,
+            match known.is_empty() {
+                true => format!("No group named '{}'; no profile has a group yet", name),
+                false => format!("No group named '{}'. Groups: {}", name, known.join(", ")),
+            },
+        ));
+    };
+
+    if active == Some(Some(name)) {
+        eprintln!("Group '{}' is already active.", name);
+        return Ok(());
+    }
+    let first = first.name.clone();
+    switch_profile(Some(&first), false, false, load_settings()?.clear_screen, &ApplyScope::default())
+}
+
+/// Every group in order of first appearance, with ungrouped (None) last.
+fn group_names(config: &Config) -> Vec<Option<&str>> {
+    let mut groups: Vec<Option<&str>> = Vec::new();
+    for profile in &config.profiles {
+        if profile.group.is_some() && !groups.contains(&profile.group.as_deref()) {
+            groups.push(profile.group.as_deref());
+        }
+    }
+    if config.profiles.iter().any(|p| p.group.is_none()) {
+        groups.push(None);
+    }
+    groups
+}
+
+/// Make the profile at `index` current: apply it, save, and log the
+/// switch. Every command that switches profiles, and `gs ui`, goes through
+/// here.
+fn activate_profile(config: &mut Config, index: usize, scope: &ApplyScope) -> Result<Profile> {
+    let previous = config.profiles.iter().position(|p| p.current);
+    let profile = config.profiles[index].clone();
+
+    // Hosts the outgoing profile managed but the new one doesn't keep
+    // their current key. With --host, naming such a host is an error instead.
+    if let Some(previous) = previous
+        && scope.hosts.is_none()
+    {
+        for (host, _) in config.profiles[previous].ssh_hosts() {
+            if !profile.ssh_hosts().iter().any(|(h, _)| *h == host) {
+                eprintln!(
+                    "{}",
+                    format!("Skipping Host {}: not configured on profile '{}'", host, profile.name).dimmed()
+                );
+            }
+        }
+    }
+
+    apply_profile_scoped(&profile, scope)?;
+    for (i, p) in config.profiles.iter_mut().enumerate() {
+        p.current = i == index;
+    }
+    // Only the pointer changes; profiles.json is left alone
+    save_current(config)?;
+    log_switch(previous.map(|i| config.profiles[i].name.as_str()), &profile.name);
+    if !options().dry_run && load_settings()?.agent_exclusive {
+        // The switch went through either way
+        if let Err(err) = make_agent_exclusive(config, &profile) {
+            eprintln!("{} couldn't update the SSH agent: {:#}", "Warning:".yellow(), err);
+        }
+    }
+    Ok(profile)
+}
+
+/// SHA256 fingerprints of the keys the SSH agent holds. Fails when there's
+/// no agent to ask.
+fn agent_fingerprints() -> Result<Vec<String>> {
+    let output = process::Command::new("ssh-add")
+        .args(["-l", "-E", "sha256"])
+        .logged_output()
+        .external("Failed to run ssh-add")?;
+    // 1 means the agent holds no keys; anything else is no agent at all
+    match output.status.code() {
+        Some(0) => {}
+        Some(1) => return Ok(Vec::new()),
+        _ => {
+            let stderr = String::from_utf8_lossy(&output.stderr);
+            return Err(anyhow!("{}", stderr.lines().next().unwrap_or("the SSH agent can't be reached").trim()));
+        }
+    }
+    // "256 SHA256:... comment (ED25519)"
+    Ok(String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .filter_map(|line| line.split_whitespace().nth(1).map(str::to_string))
+        .collect())
+}
+
+/// For `agent_exclusive`: unload the keys of every other profile that the
+/// agent holds, matched by fingerprint, then load `profile`'s own. A key
+/// two profiles share stays.
+fn make_agent_exclusive(config: &Config, profile: &Profile) -> Result<()> {
+    let loaded = agent_fingerprints()?;
+    let own: Vec<PathBuf> = profile
+        .ssh_hosts()
+        .into_iter()
+        .filter(|(_, key)| !key.is_empty())
+        .map(|(_, key)| expand_path(Path::new(key)))
+        .collect();
+
+    let mut others: Vec<(PathBuf, &str)> = Vec::new();
+    for other in config.profiles.iter().filter(|p| p.name != profile.name) {
+        for (_, key) in other.ssh_hosts().into_iter().filter(|(_, key)| !key.is_empty()) {
+            let path = expand_path(Path::new(key));
+            if !own.contains(&path) && !others.iter().any(|(p, _)| *p == path) {
+                others.push((path, &other.name));
+            }
+        }
+    }
+
+    for (key, owner) in others {
+        if !key_fingerprint(&key).is_some_and(|fingerprint| loaded.contains(&fingerprint)) {
+            continue;
+        }
+        let shown = collapse_path(&key);
+        let output = process::Command::new("ssh-add").arg("-d").arg(&key).logged_output().external("Failed to run ssh-add")?;
+        if output.status.success() {
+            eprintln!("{} {} ('{}') from the SSH agent", "Removed".green(), shown.display(), owner);
+        } else {
+            eprintln!(
+                "{} couldn't remove {} from the SSH agent: {}",
+                "Warning:".yellow(),
+                shown.display(),
+                String::from_utf8_lossy(&output.stderr).trim()
+            );
+        }
+    }
+
+    // A profile with its own IdentityAgent doesn't use this agent
+    if profile.identity_agent.is_some() {
+        return Ok(());
+    }
+    for key in own {
+        if !key.exists() || key_fingerprint(&key).is_some_and(|fingerprint| loaded.contains(&fingerprint)) {
+            continue;
+        }
+        let shown = collapse_path(&key);
+        // Inherits the terminal, for ssh-add to ask for a passphrase
+        let status = process::Command::new("ssh-add").arg("-q").arg(&key).logged_status().external("Failed to run ssh-add")?;
+        if status.success() {
+            eprintln!("{} {} to the SSH agent", "Added".green(), shown.display());
+        } else {
+            eprintln!("{} ssh-add {} exited with {}", "Warning:".yellow(), shown.display(), status);
+        }
+    }
+    Ok(())
+}
+
+/// Rename a profile. Only the label changes; commits keep the git name.
+fn rename_profile(query: &str, new_name: &str) -> Result<()> {
+    let mut config = load_config()?;
+    let index = resolve_profile(&config, query)?;
+    let new_name = checked_name(&config, Some(index), new_name)?;
+
+    let profile = &mut config.profiles[index];
+    profile.git_name.get_or_insert_with(|| profile.name.clone());
+    let old_name = std::mem::replace(&mut profile.name, new_name.clone());
+    profile.touch();
+    save_config(&config)?;
+
+    // Autoswitch rules follow the profile
+    let mut settings = load_settings()?;
+    let mut rules = 0;
+    for name in settings.auto_switch.values_mut().filter(|name| **name == old_name) {
+        name.clone_from(&new_name);
+        rules += 1;
+    }
+    if rules > 0 && !options().dry_run {
+        save_settings(&settings)?;
+    }
+
+    eprintln!("Renamed profile '{}' to '{}'", old_name, new_name.bright_green());
+    if rules > 0 {
+        eprintln!("Updated {} autoswitch rule(s) to use '{}'", rules, new_name);
+    }
+    // gs.profile lives in each repository's own config, out of reach here
+    eprintln!(
+        "{} repositories set up with '{} install-hook --profile {}' still expect '{}'; run 'git config {} {}' in each",
+        "Note:".yellow(),
+        program_name(),
+        old_name,
+        old_name,
+        REPO_PROFILE_KEY,
+        new_name
+    );
+    Ok(())
+}
+
+/// Duplicate a profile under a new name, as a starting point for a similar
+/// identity. The copy isn't made current.
+fn copy_profile(query: &str, new_name: &str) -> Result<()> {
+    let mut config = load_config()?;
+    let index = resolve_profile(&config, query)?;
+    let new_name = checked_name(&config, None, new_name)?;
+
+    let mut copy = config.profiles[index].clone();
+    copy.name = new_name.clone();
+    copy.current = false;
+    copy.default = false;
+    copy.source = ProfileSource::New;
+    copy.created_at = Some(chrono::Local::now());
+    copy.updated_at = None;
+    config.profiles.push(copy);
+    save_config(&config)?;
+
+    eprintln!(
+        "Copied profile '{}' to '{}'. Run '{} edit {}' to change it.",
+        config.profiles[index].name,
+        new_name.bright_green(),
+        program_name(),
+        new_name
+    );
+    Ok(())
+}
+
+/// Why `name` can't be given to a profile, if it can't. The name can be
+/// typed where gs expects a command or option, so it mustn't be empty or
+/// look like either.
+fn name_problem(name: &str) -> Option<String> {
+    let name = name.trim();
+    if name.is_empty() {
+        return Some("a profile name can't be empty".to_string());
+    }
+    if name.starts_with('-') {
+        return Some("a profile name can't start with '-', which reads as an option".to_string());
+    }
+    let command = Cli::command();
+    let reserved = command
+        .get_subcommands()
+        .flat_map(|sub| std::iter::once(sub.get_name()).chain(sub.get_all_aliases()))
+        .chain(["help"]);
+    for word in reserved {
+        if word.eq_ignore_ascii_case(name) {
+            return Some(format!("'{}' is a gs command", word));
+        }
+    }
+    None
+}
+
+/// `name`, trimmed, if it can be a new name for the profile at `skip` (or
+/// for a new profile): valid, and not another profile's, ignoring case. A
+/// profile keeping its own name always passes.
+fn checked_name(config: &Config, skip: Option<usize>, name: &str) -> Result<String> {
+    let name = name.trim();
+    if skip.is_some_and(|i| config.profiles[i].name == name) {
+        return Ok(name.to_string());
+    }
+    if let Some(problem) = name_problem(name) {
+        return Err(Failure::new(ExitCode::Usage, format!("Invalid profile name '{}': {}", name, problem))
+            .profile(name)
+            .into());
+    }
+    let existing = config.profiles.iter().enumerate().find(|&(i, p)| Some(i) != skip && p.name.eq_ignore_ascii_case(name));
+    if let Some((_, existing)) = existing {
+        let message = match existing.name == name {
+            true => format!("Profile with name '{}' already exists", name),
+            false => format!("Profile '{}' already exists; names can't differ only in case", existing.name),
+        };
+        return Err(Failure::new(ExitCode::Error, message).profile(name).into());
+    }
+    Ok(name.to_string())
+}
+
+/// Ask for a profile name until `checked_name` takes it, with `default`
+/// for an empty answer. Piped input can't be asked again, so a bad answer
+/// there is an error.
+fn prompt_name(config: &Config, skip: Option<usize>, question: &str, default: &str) -> Result<String> {
+    loop {
+        let answer = prompt::prompt("name", question)?;
+        let answer = if answer.is_empty() { default } else { &answer };
+        match checked_name(config, skip, answer) {
+            Ok(name) => return Ok(name),
+            Err(err) if io::stdin().is_terminal() => eprintln!("{}", err),
+            Err(err) => return Err(err),
+        }
+    }
+}
+
+/// The profile a setup name would clash with, if any; names differing only
+/// in case clash too.
+fn existing_profile(config: &Config, name: &str) -> Option<usize> {
+    config.profiles.iter().position(|p| p.name.eq_ignore_ascii_case(name.trim()))
+}
+
+/// Ask for a new profile's name. One that's taken can update that profile
+/// instead, or be swapped for another name; `update_existing` picks the
+/// update without asking. Returns the name and the profile to update.
+fn prompt_setup_name(config: &Config, update_existing: bool) -> Result<(String, Option<usize>)> {
+    loop {
+        let answer = prompt::prompt("name", "Enter profile name: ")?;
+        let Some(index) = existing_profile(config, &answer) else {
+            match checked_name(config, None, &answer) {
+                Ok(name) => return Ok((name, None)),
+                Err(err) if io::stdin().is_terminal() => {
+                    eprintln!("{}", err);
+                    continue;
+                }
+                Err(err) => return Err(err),
+            }
+        };
+        let existing = &config.profiles[index].name;
+        if update_existing {
+            eprintln!("Updating the existing profile '{}'", existing);
+            return Ok((existing.clone(), Some(index)));
+        }
+        let question = format!("Profile '{}' already exists. [u]pdate it, pick a [d]ifferent name or [a]bort? ", existing);
+        match prompt::prompt("existing profile", &question)?.to_lowercase().as_str() {
+            "u" | "update" => return Ok((existing.clone(), Some(index))),
+            "d" | "different" => continue,
+            _ => return Err(failure(ExitCode::Aborted, "Aborted")),
+        }
+    }
+}
+
+fn setup_flow(
+    fields: ProfileFields,
+    preset: Option<&str>,
+    host: Option<String>,
+    ssh_user: Option<String>,
+    update_existing: bool,
+) -> Result<()> {
+    let preset = preset.map(find_preset).transpose()?;
+    let group = fields.group.clone().filter(|g| !g.is_empty());
+    let mut commit_template = fields.commit_template.as_deref().filter(|t| !t.is_empty()).map(checked_path).transpose()?;
+    let mut excludes_file = fields.excludes_file.as_deref().filter(|f| !f.is_empty()).map(checked_path).transpose()?;
+    let mut default_branch = fields.default_branch.as_deref().filter(|b| !b.is_empty()).map(checked_branch).transpose()?;
+    let mut icon = fields.icon.clone().filter(|i| !i.is_empty());
+    let mut color = fields.color.as_deref().filter(|c| !c.is_empty()).map(checked_color).transpose()?;
+    let ssh_options = checked_ssh_options(&fields.ssh_options)?;
+    let identity_agent = fields.identity_agent.as_deref().filter(|a| !a.is_empty()).map(collapse_tilde);
+    let mut git_name = fields.git_name.clone().filter(|n| !n.trim().is_empty());
+    let generate_key;
+    let mut config = load_config()?;
+    let existing;
+
+    let (name, email, ssh_key, description, host, ssh_users) = if fields.is_empty() {
+        eprintln!("=== Git Profile Setup ===");
+        let name;
+        (name, existing) = prompt_setup_name(&config, update_existing)?;
+        let current_git_name = existing.map_or(name.as_str(), |i| config.profiles[i].git_name());
+        let answer = prompt::prompt("git name", &format!("Enter the name to commit under (git user.name) [{}]: ", current_git_name))?;
+        git_name = (!answer.is_empty()).then_some(answer);
+        let email = prompt_email("Enter email: ", false)?;
+        let host = setup_host(host, preset.as_ref(), true)?;
+        let ssh_users = setup_ssh_user(ssh_user, host.as_deref(), preset.as_ref(), true)?;
+        let ssh_key = match &preset {
+            Some(_) => {
+                let suggested = suggested_key_path(&name);
+                let answer = prompt::prompt(
+                    "ssh key",
+                    &format!("Enter path to SSH private key, or '-' to use the agent's keys [{}]: ", suggested),
+                )?;
+                expand_tilde(if answer.is_empty() { &suggested } else { &answer })?
+            }
+            None => expand_tilde(&prompt::prompt_required("ssh key", "Enter path to SSH private key, or '-' to use the agent's keys: ")?)?,
+        };
+        // '-' is a profile without a key file
+        let ssh_key = if ssh_key == "-" { String::new() } else { ssh_key };
+        generate_key = ensure_ssh_key(&ssh_key, preset.is_some())?;
+
+        let description = prompt::prompt("description", "Enter description (optional): ")?;
+        let excludes = prompt::prompt("excludes file", "Enter path to a global gitignore (optional): ")?;
+        if !excludes.is_empty() {
+            excludes_file = Some(checked_path(&excludes)?);
+        }
+        let template = prompt::prompt("commit template", "Enter path to a commit message template (optional): ")?;
+        if !template.is_empty() {
+            commit_template = Some(checked_path(&template)?);
+        }
+        let branch = prompt::prompt("default branch", "Enter the default branch for new repositories, e.g. main (optional): ")?;
+        default_branch = (!branch.is_empty()).then(|| checked_branch(&branch)).transpose()?;
+        let answer = prompt::prompt("icon", "Enter an icon to tell this profile apart, e.g. 💼 (optional): ")?;
+        icon = (!answer.is_empty()).then_some(answer);
+        let answer = prompt::prompt("color", "Enter a color, e.g. red or #ff8800 (optional): ")?;
+        color = (!answer.is_empty()).then(|| checked_color(&answer)).transpose()?;
+        (name, email, ssh_key, (!description.is_empty()).then_some(description), host, ssh_users)
+    } else {
+        let ssh_key = match (&preset, fields.ssh_key, &fields.name) {
+            (_, Some(key), _) => Some(key),
+            (Some(_), None, Some(name)) => Some(suggested_key_path(name)),
+            _ => None,
+        };
+        let (Some(name), Some(email), Some(ssh_key)) = (fields.name, fields.email, ssh_key) else {
+            return Err(failure(ExitCode::Usage, "--name, --email and --key are all required"));
+        };
+        let email = checked_email(&email)?;
+        existing = existing_profile(&config, &name);
+        let name = match existing {
+            Some(index) if update_existing => config.profiles[index].name.clone(),
+            Some(index) => {
+                let message = format!("Profile '{}' already exists; pass --update-existing to update it", config.profiles[index].name);
+                return Err(Failure::new(ExitCode::Error, message).profile(&name).into());
+            }
+            None => checked_name(&config, None, &name)?,
+        };
+        let host = setup_host(host, preset.as_ref(), io::stdin().is_terminal())?;
+        let ssh_users = setup_ssh_user(ssh_user, host.as_deref(), preset.as_ref(), io::stdin().is_terminal())?;
+        let ssh_key = expand_tilde(&ssh_key)?;
+        generate_key = ensure_ssh_key(&ssh_key, preset.is_some() && io::stdin().is_terminal())?;
+
+        (name, email, ssh_key, fields.description.filter(|d| !d.is_empty()), host, ssh_users)
+    };
+
+    let signing = fields.signing.unwrap_or_else(|| existing.is_some_and(|i| config.profiles[i].signing));
+    if signing && ssh_key.is_empty() {
+        return Err(failure(ExitCode::Usage, "Signing needs a key file; a profile using the agent's keys can't sign"));
+    }
+    if !confirm_duplicates(&config, existing, &email, &collapse_tilde(&ssh_key), fields.allow_shared_key)? {
+        return Err(failure(ExitCode::Aborted, "Aborted"));
+    }
+
+    // Create new profile
+    let is_first_profile = config.profiles.is_empty();
+    let hosts = host
+        .filter(|h| h != GITHUB_HOST)
+        .map(|h| BTreeMap::from([(h, collapse_tilde(&ssh_key))]))
+        .unwrap_or_default();
+    if let Some(index) = existing {
+        let mut profile = config.profiles[index].clone();
+        // Only what was given changes; everything else is kept
+        if let Some(git_name) = git_name {
+            profile.git_name = Some(git_name);
+        }
+        profile.email = email;
+        let old_key = std::mem::replace(&mut profile.ssh_key, collapse_tilde(&ssh_key));
+        for key in profile.hosts.values_mut().filter(|key| **key == old_key) {
+            key.clone_from(&profile.ssh_key);
+        }
+        profile.hosts.extend(hosts);
+        profile.ssh_users.extend(ssh_users);
+        profile.description = description.or(profile.description);
+        profile.group = group.or(profile.group);
+        profile.commit_template = commit_template.or(profile.commit_template);
+        profile.excludes_file = excludes_file.or(profile.excludes_file);
+        profile.default_branch = default_branch.or(profile.default_branch);
+        profile.signing = signing;
+        profile.icon = icon.or(profile.icon);
+        profile.color = color.or(profile.color);
+        if !ssh_options.is_empty() {
+            profile.ssh_options = ssh_options;
+        }
+        profile.identity_agent = identity_agent.or(profile.identity_agent);
+        profile.username = fields.username.clone().filter(|u| !u.is_empty()).or(profile.username);
+        profile.touch();
+
+        // The current profile's changes go live, like an edit's
+        let plan = profile.current.then(|| plan_apply(&profile, &ApplyScope::default())).transpose()?;
+        if generate_key {
+            generate_ssh_key(&expand_tilde(&profile.ssh_key)?, &profile.email)?;
+        }
+        config.profiles[index] = profile;
+        apply_and_save(&config, plan.as_ref())?;
+        match options().dry_run {
+            true => eprintln!("Dry run: profile '{}' would be updated.", config.profiles[index].name),
+            false => eprintln!("Profile '{}' updated successfully!", config.profiles[index].name),
+        }
+        return Ok(());
+    }
+
+    let new_profile = Profile {
+        // Without one, commits go under the profile name as before
+        git_name: Some(git_name.unwrap_or_else(|| name.clone())),
+        name,
+        email,
+        ssh_key: collapse_tilde(&ssh_key),
+        description,
+        group,
+        hosts,
+        ssh_users,
+        commit_template,
+        excludes_file,
+        default_branch,
+        signing,
+        icon,
+        color,
+        ssh_options,
+        identity_agent,
+        username: fields.username.clone().filter(|u| !u.is_empty()),
+        created_at: Some(chrono::Local::now()),
+        current: true, // New profile is set as current
+        ..Default::default()
+    };
+
+    let previous = config.profiles.iter().find(|p| p.current).map(|p| p.name.clone());
+
+    // If this is not the first profile, make others non-current
+    if !is_first_profile {
+        for profile in &mut config.profiles {
+            profile.current = false;
+        }
+    }
+
+    // Settle the git and SSH changes, and save once they're made
+    let plan = plan_apply(&new_profile, &ApplyScope::default())?;
+    if generate_key {
+        generate_ssh_key(&expand_tilde(&new_profile.ssh_key)?, &new_profile.email)?;
+    }
+    config.profiles.push(new_profile.clone());
+    apply_and_save(&config, Some(&plan))?;
+    log_switch(previous.as_deref(), &new_profile.name);
+
+    match options().dry_run {
+        true => eprintln!("Dry run: profile '{}' would be created.", new_profile.name),
+        false => eprintln!("Profile '{}' created successfully!", new_profile.name),
+    }
+    Ok(())
+}
+
+fn find_preset(name: &str) -> Result<Preset> {
+    let mut presets = builtin_presets();
+    presets.extend(load_settings()?.presets);
+
+    presets.remove(name).ok_or_else(|| {
+        let known: Vec<&str> = presets.keys().map(String::as_str).collect();
+        failure(ExitCode::Usage, format!("Unknown preset '{}'. Known presets: {}", name, known.join(", ")))
+    })
+}
+
+fn preset_host(preset: &Preset) -> Result<String> {
+    match &preset.host {
+        Some(host) => Ok(host.clone()),
+        None => prompt::prompt_required("hostname", "Enter the server's hostname (e.g. git.example.com): "),
+    }
+}
+
+/// The SSH host a new profile is for: `--host`, else the preset's (asking
+/// for it when the preset has none), else None for github.com. A hostname
+/// the user typed is checked; one that doesn't resolve only warns, and asks
+/// first when `confirm` is set, since setting up offline is fine.
+fn setup_host(flag: Option<String>, preset: Option<&Preset>, confirm: bool) -> Result<Option<String>> {
+    let typed = flag.is_some() || preset.is_some_and(|p| p.host.is_none());
+    let host = match (flag, preset) {
+        (Some(host), _) => host.trim().to_string(),
+        (None, Some(preset)) => preset_host(preset)?.trim().to_string(),
+        (None, None) => return Ok(None),
+    };
+    if host.is_empty() || host.contains(|c: char| c.is_whitespace() || "@:/".contains(c)) {
+        return Err(failure(
+            ExitCode::Usage,
+            format!("'{}' is not a hostname; give just the host, e.g. github.example.com", host),
+        ));
+    }
+
+    if typed && !resolves(&host) {
+        eprintln!("{} {} doesn't resolve from here", "Warning:".yellow(), host);
+        if confirm && !prompt::confirm("Use it anyway?")? {
+            return Err(failure(ExitCode::Aborted, "Aborted"));
+        }
+    }
+    Ok(Some(host))
+}
+
+/// The SSH user for a new profile's host: `--ssh-user`, else the known
+/// forge's, else the preset's, else asked for when `interactive`, since
+/// self-hosted servers don't all use `git`. Returns the entry to store on
+/// the profile, which is empty when the host's default will do.
+fn setup_ssh_user(
+    flag: Option<String>,
+    host: Option<&str>,
+    preset: Option<&Preset>,
+    interactive: bool,
+) -> Result<BTreeMap<String, String>> {
+    let host = host.unwrap_or(GITHUB_HOST);
+    let default = hosts::default_user(host);
+    let user = match (flag, preset.and_then(|p| p.user.clone())) {
+        (Some(user), _) => user.trim().to_string(),
+        (None, _) if hosts::find(host).is_some() => return Ok(BTreeMap::new()),
+        (None, Some(user)) => user,
+        (None, None) if interactive => {
+            let answer = prompt::prompt("ssh user", &format!("Enter the SSH user for {} [{}]: ", host, default))?;
+            if answer.is_empty() { default.to_string() } else { answer }
+        }
+        (None, None) => return Ok(BTreeMap::new()),
+    };
+    if user.is_empty() || user.contains(|c: char| c.is_whitespace() || "@:/".contains(c)) {
+        return Err(failure(ExitCode::Usage, format!("'{}' is not an SSH user name, e.g. git", user)));
+    }
+
+    if user == default {
+        return Ok(BTreeMap::new());
+    }
+    Ok(BTreeMap::from([(host.to_string(), user)]))
+}
+
+/// Whether DNS knows `host`.
+fn resolves(host: &str) -> bool {
+    use std::net::ToSocketAddrs;
+    let resolved = (host, 22).to_socket_addrs().is_ok_and(|mut addrs| addrs.next().is_some());
+    tracing::debug!("{} {}", host, if resolved { "resolves" } else { "doesn't resolve" });
+    resolved
+}
+
+/// The conventional key location for a profile, `~/.ssh/id_ed25519_<name>`.
+fn suggested_key_path(name: &str) -> String {
+    let slug: String = name
+        .to_lowercase()
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+        .collect();
+    format!("~/.ssh/id_ed25519_{}", slug)
+}
+
+/// Make sure the key exists, offering to generate it with ssh-keygen when
+/// `offer_generate` is set. Returns true if the user wants it generated,
+/// which setup does once nothing else can abort. An empty path, for the
+/// agent's keys, has nothing to check.
+fn ensure_ssh_key(ssh_key: &str, offer_generate: bool) -> Result<bool> {
+    if ssh_key.is_empty() {
+        return Ok(false);
+    }
+    if Path::new(ssh_key).exists() {
+        check_public_key(ssh_key)?;
+        return Ok(false);
+    }
+    if !offer_generate || !prompt::confirm(&format!("No key at {}. Generate a new ed25519 key there?", ssh_key))? {
+        return Err(Failure::new(ExitCode::NotFound, format!("SSH key not found at: {}", ssh_key)).path(ssh_key).into());
+    }
+    Ok(true)
+}
+
+/// Register a profile's public key with a forge, so pushing over SSH works
+/// without a trip to the browser. Without a `forge`, the profile's host
+/// picks it, if it's a known forge's.
+fn upload_key(query: &str, forge: Option<Forge>, url: Option<&str>, token: Option<String>) -> Result<()> {
+    let config = load_config()?;
+    let profile = &config.profiles[resolve_profile(&config, query)?];
+    let forge = match forge {
+        Some(forge) => forge,
+        None => hosts::find(profile.primary_host()).and_then(|known| known.forge).ok_or_else(|| {
+            failure(
+                ExitCode::Usage,
+                format!(
+                    "gs doesn't know an API for adding keys on {}; pass --github, --gitlab or --gitea if it has one",
+                    profile.primary_host()
+                ),
+            )
+        })?,
+    };
+    let host = forge.instance_host(profile.primary_host());
+    let key = profile.ssh_hosts().into_iter().find(|(h, _)| *h == host).map_or(profile.ssh_key.as_str(), |(_, k)| k);
+    if key.is_empty() {
+        return Err(failure(
+            ExitCode::Usage,
+            format!("Profile '{}' uses the SSH agent's keys for {}; add the public key from the agent instead", profile.name, host),
+        ));
+    }
+
+    let public_key_path = format!("{}.pub", expand_tilde(key)?);
+    let public_key = fs::read_to_string(&public_key_path)
+        .map_err(|_| Failure::new(ExitCode::NotFound, format!("No public key at {}", public_key_path)).path(&public_key_path))?;
+    let token = match token {
+        Some(token) => token,
+        None => forge_token(forge, host)?,
+    };
+    let api_url = match url {
+        // GitLab and Gitea take the instance URL, like the web UI's
+        Some(url) if forge == Forge::GitLab => format!("{}/api/v4", url.trim_end_matches('/').trim_end_matches("/api/v4")),
+        Some(url) if forge == Forge::Gitea => format!("{}/api/v1", url.trim_end_matches('/').trim_end_matches("/api/v1")),
+        Some(url) => url.to_string(),
+        None => forge.api_url(host),
+    };
+    let title = format!("gs:{}@{}", profile.name, machine_name());
+
+    eprintln!("Uploading {} to {} as '{}'...", public_key_path, api_url, title);
+    match forge::upload_key(forge, &api_url, &token, &title, public_key.trim())? {
+        forge::Outcome::Added(id) => {
+            eprintln!("{} the key to your {} account", "Added".green(), forge.label());
+            println!("{}", id);
+        }
+        forge::Outcome::AlreadyPresent(id) => {
+            eprintln!("The key is already on your {} account", forge.label());
+            println!("{}", id);
+        }
+    }
+    Ok(())
+}
+
+/// A token for the forge API: $GITHUB_TOKEN/$GITLAB_TOKEN/$GITEA_TOKEN, or for GitHub
+/// what the gh CLI is logged in with.
+fn forge_token(forge: Forge, host: &str) -> Result<String> {
+    if let Ok(token) = std::env::var(forge.token_env())
+        && !token.is_empty()
+    {
+        return Ok(token);
+    }
+    if forge == Forge::GitHub
+        && let Ok(output) = process::Command::new("gh").args(["auth", "token", "--hostname", host]).logged_output()
+        && output.status.success()
+    {
+        let token = String::from_utf8_lossy(&output.stdout).trim().to_string();
+        if !token.is_empty() {
+            return Ok(token);
+        }
+    }
+
+    let hint = match forge {
+        Forge::GitHub => ", or run `gh auth login`",
+        Forge::GitLab | Forge::Gitea => "",
+    };
+    Err(failure(
+        ExitCode::Usage,
+        format!("No {} token for {}: pass --token or set {}{}", forge.label(), host, forge.token_env(), hint),
+    ))
+}
+
+/// This machine's name, to tell keys apart in the forge's key list.
+fn machine_name() -> String {
+    process::Command::new("hostname")
+        .logged_output()
+        .ok()
+        .filter(|o| o.status.success())
+        .map(|o| String::from_utf8_lossy(&o.stdout).trim().to_string())
+        .filter(|name| !name.is_empty())
+        .unwrap_or_else(|| "unknown".to_string())
+}
+
+/// Run ssh-keygen for a new ed25519 pair at `ssh_key`. It asks for the
+/// passphrase itself; its report goes to stderr like our other progress.
+fn generate_ssh_key(ssh_key: &str, email: &str) -> Result<()> {
+    if options().dry_run {
+        eprintln!("{} a new ed25519 key at {}", "Would generate".yellow(), ssh_key);
+        return Ok(());
+    }
+    if let Some(dir) = Path::new(ssh_key).parent() {
+        fs::create_dir_all(dir).with_context(|| format!("Failed to create {}", dir.display()))?;
+    }
+    let status = process::Command::new("ssh-keygen")
+        .args(["-t", "ed25519", "-C", email, "-f", ssh_key])
+        .stdout(io::stderr())
+        .logged_status()
+        .external("Failed to run ssh-keygen")?;
+    if !status.success() {
+        return Err(failure(ExitCode::External, format!("ssh-keygen exited with {}", status)));
+    }
+    Ok(())
+}
+
+/// Every setting of a profile as `gs diff` compares them, None where it's
+/// unset.
+fn comparable_fields(profile: &Profile) -> Vec<(&'static str, Option<String>)> {
+    let joined = |items: Vec<String>| (!items.is_empty()).then(|| items.join(", "));
+    let key = expand_path(Path::new(&profile.ssh_key));
+    let path_field = |path: &Option<PathBuf>| path.as_ref().map(|p| p.display().to_string());
+    vec![
+        ("name", Some(profile.name.clone())),
+        ("git name", Some(profile.git_name().to_string())),
+        ("email", Some(profile.email.clone())),
+        // Unset for a profile using the agent's keys
+        ("ssh key", (!profile.ssh_key.is_empty()).then(|| profile.ssh_key.clone())),
+        ("fingerprint", key.exists().then(|| key_fingerprint(&key)).flatten()),
+        ("hosts", joined(profile.ssh_hosts().iter().map(|(host, key)| format!("{}: {}", host, key_label(key))).collect())),
+        ("ssh users", joined(profile.ssh_users.iter().map(|(host, user)| format!("{}@{}", user, host)).collect())),
+        ("ssh options", joined(profile.ssh_options.clone())),
+        ("identity agent", profile.identity_agent.clone()),
+        ("username", profile.username.clone()),
+        ("signing", Some(profile.signing.to_string())),
+        ("commit template", path_field(&profile.commit_template)),
+        ("global gitignore", path_field(&profile.excludes_file)),
+        ("default branch", profile.default_branch.clone()),
+        (
+            "url rewrites",
+            joined(profile.url_rewrites.iter().map(|rule| format!("{} -> {}", rule.instead_of, rule.base)).collect()),
+        ),
+        ("group", profile.group.clone()),
+        ("description", profile.description.clone()),
+        ("icon", profile.icon.clone()),
+        ("color", profile.color.clone()),
+        ("other keys", joined(profile.extra.iter().map(|(key, value)| format!("{}={}", key, value)).collect())),
+    ]
+}
+
+/// Compare two profiles field by field, or one with the active profile.
+/// Differences are listed in full and identical fields by name only.
+fn diff_profiles(first: &str, second: Option<&str>) -> Result<()> {
+    let config = load_config()?;
+    let a = &config.profiles[resolve_profile(&config, first)?];
+    let b = match second {
+        Some(name) => &config.profiles[resolve_profile(&config, name)?],
+        None => active_profile(&config).ok_or_else(|| failure(ExitCode::NotFound, "No active profile to compare with"))?.0,
+    };
+    let fields: Vec<_> = comparable_fields(a).into_iter().zip(comparable_fields(b)).map(|((field, a), (_, b))| (field, a, b)).collect();
+
+    if options().json {
+        let fields: Vec<serde_json::Value> = fields
+            .iter()
+            .map(|(field, a, b)| serde_json::json!({ "field": field, "a": a, "b": b, "same": a == b }))
+            .collect();
+        let report = serde_json::json!({ "a": a.name, "b": b.name, "fields": fields });
+        println!("{}", serde_json::to_string_pretty(&report)?);
+        return Ok(());
+    }
+
+    let unset = "(unset)".to_string();
+    println!("=== {} vs {} ===", a.name, b.name);
+    let width = a.name.len().max(b.name.len());
+    for (field, value_a, value_b) in fields.iter().filter(|(_, a, b)| a != b) {
+        println!("{}", field.bold());
+        println!("  {:<width$}  {}", a.name, value_a.as_ref().unwrap_or(&unset).red());
+        println!("  {:<width$}  {}", b.name, value_b.as_ref().unwrap_or(&unset).green());
+    }
+    let same: Vec<&str> = fields.iter().filter(|(_, a, b)| a == b).map(|(field, _, _)| *field).collect();
+    if !same.is_empty() {
+        println!("{}", format!("Identical: {}", same.join(", ")).dimmed());
+    }
+    Ok(())
+}
+
+/// Print the public key of the named or active profile, or put it on the
+/// clipboard with `copy`.
+fn print_public_key(name: Option<&str>, copy: bool) -> Result<()> {
+    let config = load_config()?;
+    let profile = match name {
+        Some(name) => &config.profiles[resolve_profile(&config, name)?],
+        None => active_profile(&config).ok_or_else(|| failure(ExitCode::NotFound, "No active profile"))?.0,
+    };
+    let public_key = read_public_key(profile)?;
+
+    if copy {
+        let tool = copy_to_clipboard(&public_key)?;
+        eprintln!("Copied the public key of '{}' to the clipboard ({})", profile.name, tool);
+    } else {
+        println!("{}", public_key);
+    }
+    Ok(())
+}
+
+/// A profile's public key: its `.pub`, or when that's gone, derived from
+/// the private key with `ssh-keygen -y`. That only works for keys without a
+/// passphrase, since gs won't ask for one.
+fn read_public_key(profile: &Profile) -> Result<String> {
+    if profile.ssh_key.is_empty() {
+        return Err(failure(
+            ExitCode::Usage,
+            format!("Profile '{}' uses the SSH agent's keys; `ssh-add -L` lists them", profile.name),
+        ));
+    }
+    let key = expand_tilde(&profile.ssh_key)?;
+    let public_key_path = format!("{}.pub", key);
+    if let Ok(public_key) = fs::read_to_string(&public_key_path) {
+        tracing::debug!("read {}", public_key_path);
+        return Ok(public_key.trim().to_string());
+    }
+    if !Path::new(&key).exists() {
+        return Err(Failure::new(ExitCode::NotFound, format!("SSH key not found at: {}", key)).path(&key).into());
+    }
+
+    tracing::debug!("{} is missing, deriving it from the private key", public_key_path);
+    derive_public_key(&key)?.ok_or_else(|| {
+        let message = format!(
+            "{} is missing and {} has a passphrase; recreate it with `ssh-keygen -y -f {} > {}`",
+            public_key_path, key, key, public_key_path
+        );
+        Failure::new(ExitCode::External, message).path(&key).into()
+    })
+}
+
+/// The public half of the private key at `key`, as `ssh-keygen -y` prints
+/// it, or None when the key has a passphrase. ssh-keygen is never left to
+/// ask for one.
+fn derive_public_key(key: &str) -> Result<Option<String>> {
+    let output = process::Command::new("ssh-keygen")
+        .args(["-y", "-P", "", "-f", key])
+        .stdin(process::Stdio::null())
+        .logged_output()
+        .external("Failed to run ssh-keygen")?;
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        if stderr.contains("passphrase") {
+            return Ok(None);
+        }
+        return Err(Failure::new(ExitCode::External, format!("Couldn't derive the public key from {}: {}", key, stderr.trim()))
+            .path(key)
+            .into());
+    }
+    Ok(Some(String::from_utf8_lossy(&output.stdout).trim().to_string()))
+}
+
+/// How the .pub next to a private key compares with the key itself.
+enum PublicKeyState {
+    Matches,
+    /// There's no .pub; `derived` is what it should hold.
+    Missing { derived: String },
+    /// The .pub is another key's, typically left from before the private
+    /// key was replaced under the same name.
+    Mismatch { derived: String },
+    /// The private key has a passphrase, so it can't be compared without
+    /// asking for it.
+    Locked,
+}
+
+fn public_key_state(key: &str) -> Result<PublicKeyState> {
+    let Some(derived) = derive_public_key(key)? else {
+        return Ok(PublicKeyState::Locked);
+    };
+    // Only the type and the key itself count; comments differ freely
+    let key_part = |line: &str| line.split_whitespace().take(2).collect::<Vec<_>>().join(" ");
+    Ok(match fs::read_to_string(format!("{}.pub", key)) {
+        Err(_) => PublicKeyState::Missing { derived },
+        Ok(existing) if key_part(&existing) == key_part(&derived) => PublicKeyState::Matches,
+        Ok(_) => PublicKeyState::Mismatch { derived },
+    })
+}
+
+/// Write `key`'s .pub from its derived public half.
+fn write_public_key(key: &str, derived: &str) -> Result<()> {
+    let path = PathBuf::from(format!("{}.pub", key));
+    tracing::debug!("write {}", path.display());
+    fs::write(&path, format!("{}\n", derived)).file_context("write", &path)
+}
+
+/// Check the .pub of a key setup or edit is about to use, and offer to
+/// write it from the private key when it's missing or belongs to another
+/// key. A key with a passphrase is explained rather than unlocked.
+fn check_public_key(key: &str) -> Result<()> {
+    let public_key_path = format!("{}.pub", key);
+    let (problem, question, derived) = match public_key_state(key) {
+        Ok(PublicKeyState::Matches) => return Ok(()),
+        Ok(PublicKeyState::Locked) => {
+            eprintln!(
+                "{}",
+                format!(
+                    "{} has a passphrase, so gs can't check {} against it; `ssh-keygen -y -f {}` prints the right public key",
+                    key, public_key_path, key
+                )
+                .dimmed()
+            );
+            return Ok(());
+        }
+        Ok(PublicKeyState::Missing { derived }) => {
+            (format!("{} is missing", public_key_path), "Write it from the private key?", derived)
+        }
+        Ok(PublicKeyState::Mismatch { derived }) => (
+            format!("{} doesn't match {}; it may be left from an older key", public_key_path, key),
+            "Replace it with the private key's public half?",
+            derived,
+        ),
+        Err(err) => {
+            eprintln!("{} {:#}", "Warning:".yellow(), err);
+            return Ok(());
+        }
+    };
+
+    eprintln!("{} {}", "Warning:".yellow(), problem);
+    if options().yes || (io::stdin().is_terminal() && prompt::confirm(question)?) {
+        write_public_key(key, &derived)?;
+        eprintln!("Wrote {}", public_key_path);
+    } else {
+        eprintln!("Left as is; `ssh-keygen -y -f {} > {}` fixes it.", key, public_key_path);
+    }
+    Ok(())
+}
+
+/// Put `text` on the clipboard with the platform's copy command, returning
+/// the one that worked.
+fn copy_to_clipboard(text: &str) -> Result<&'static str> {
+    let candidates: &[(&str, &[&str])] = if cfg!(target_os = "macos") {
+        &[("pbcopy", &[])]
+    } else if cfg!(windows) {
+        &[("clip.exe", &[])]
+    } else {
+        // Wayland, then X11, then WSL's Windows clipboard
+        &[
+            ("wl-copy", &[]),
+            ("xclip", &["-selection", "clipboard"]),
+            ("xsel", &["--clipboard", "--input"]),
+            ("clip.exe", &[]),
+        ]
+    };
+    for (program, args) in candidates {
+        let mut command = process::Command::new(program);
+        command.args(*args).stdin(process::Stdio::piped()).stdout(process::Stdio::null()).stderr(process::Stdio::null());
+        tracing::debug!("run {}", trace::describe(&command));
+        let Ok(mut child) = command.spawn() else {
+            continue;
+        };
+        let written = child.stdin.take().expect("stdin is piped").write_all(text.as_bytes()).is_ok();
+        if child.wait().is_ok_and(|status| status.success()) && written {
+            return Ok(program);
+        }
+    }
+    let tried: Vec<&str> = candidates.iter().map(|(program, _)| *program).collect();
+    Err(failure(
+        ExitCode::External,
+        format!("Couldn't copy to the clipboard (tried {}); run without --copy to print the key", tried.join(", ")),
+    ))
+}
+
+/// Replace a profile's key pair with a fresh one at the same path. The old
+/// files are renamed, never deleted: they're still what the servers know
+/// until the new public key is uploaded.
+fn rotate_key(query: &str, dry_run: bool, add_to_agent: bool) -> Result<()> {
+    let mut config = load_config()?;
+    let index = resolve_profile(&config, query)?;
+    let profile = config.profiles[index].clone();
+
+    if profile.ssh_key.is_empty() {
+        return Err(failure(
+            ExitCode::Usage,
+            format!("Profile '{}' uses the SSH agent's keys; there's no key file to rotate", profile.name),
+        ));
+    }
+    let key = expand_tilde(&profile.ssh_key)?;
+    if !Path::new(&key).exists() {
+        return Err(Failure::new(ExitCode::NotFound, format!("SSH key not found at: {}", key)).path(&key).into());
+    }
+    let suffix = format!("old.{}", chrono::Local::now().format("%Y-%m-%d"));
+    let mut moves = vec![(PathBuf::from(&key), PathBuf::from(format!("{}.{}", key, suffix)))];
+    let public_key = format!("{}.pub", key);
+    if Path::new(&public_key).exists() {
+        moves.push((PathBuf::from(&public_key), PathBuf::from(format!("{}.{}", public_key, suffix))));
+    }
+    if let Some((_, to)) = moves.iter().find(|(_, to)| to.exists()) {
+        return Err(failure(
+            ExitCode::Error,
+            format!("{} already exists; this key was rotated today already", to.display()),
+        ));
+    }
+
+    let sharing: Vec<&str> = config
+        .profiles
+        .iter()
+        .filter(|p| p.name != profile.name && p.ssh_hosts().iter().any(|(_, k)| expand_path(Path::new(k)) == Path::new(&key)))
+        .map(|p| p.name.as_str())
+        .collect();
+
+    if dry_run {
+        eprintln!("Rotating the key for '{}' would:", profile.name);
+        for (from, to) in &moves {
+            eprintln!("  • rename {} to {}", from.display(), to.display());
+        }
+        eprintln!("  • generate a new ed25519 key at {}", key);
+        if !sharing.is_empty() {
+            eprintln!("  • also change the key of {}, which use the same file", sharing.join(", "));
+        }
+        if profile.current {
+            eprintln!("  • re-apply '{}' to SSH and git config", profile.name);
+        }
+        if add_to_agent {
+            eprintln!("  • add the new key to the SSH agent");
+        }
+        return Ok(());
+    }
+
+    for (from, to) in &moves {
+        fs::rename(from, to).with_context(|| format!("Failed to rename {}", from.display()))?;
+        eprintln!("{} {} to {}", "Moved".green(), from.display(), to.display());
+    }
+    if let Err(err) = generate_ssh_key(&key, &profile.email) {
+        // Put the old pair back so the profile keeps working
+        for (from, to) in &moves {
+            let _ = fs::rename(to, from);
+        }
+        return Err(err.context("Key rotation failed; the old key is back in place"));
+    }
+
+    config.profiles[index].touch();
+    save_config(&config)?;
+    // The path is unchanged, but the public key allowed_signers lists isn't
+    write_allowed_signers(&config)?;
+    if profile.current {
+        activate_profile(&mut config, index, &ApplyScope::default())?;
+    }
+
+    if add_to_agent {
+        let status = process::Command::new("ssh-add")
+            .arg(&key)
+            .logged_status()
+            .external("Failed to run ssh-add")?;
+        if !status.success() {
+            eprintln!("{} ssh-add exited with {}", "Warning:".yellow(), status);
+        }
+    }
+
+    eprintln!();
+    eprintln!("New public key for '{}' (upload it wherever the old one is registered):", profile.name);
+    println!("{}", fs::read_to_string(&public_key).context("Failed to read the new public key")?.trim());
+    eprintln!();
+    eprintln!(
+        "The old pair is kept as {}. Delete it once pushing with the new key works everywhere:",
+        moves[0].1.display()
+    );
+    let old_files: Vec<String> = moves.iter().map(|(_, to)| shell_quote(&to.display().to_string())).collect();
+    eprintln!("  rm {}", old_files.join(" "));
+    Ok(())
+}
+
+fn list_profiles(long: bool, format: Option<&str>, sort: Option<ListSort>, reverse: bool) -> Result<()> {
+    let config = load_config()?;
+    let profiles = sorted_profiles(&config, sort, reverse)?;
+
+    // One line per profile, in file order (unless sorted) and without
+    // color, for scripts
+    if let Some(format) = format {
+        for profile in profiles {
+            print!("{}", format_profile(format, profile, &profile.name)?);
+        }
+        return Ok(());
+    }
+
+    if config.profiles.is_empty() {
+        eprintln!("No profiles found. Run '{} setup' to create your first profile.", program_name());
+        return Ok(());
+    }
+
+    // Say so when the order isn't the stored one, which switching by
+    // position and cycling follow
+    let order = match (sort, reverse) {
+        (None, false) => String::new(),
+        (None, true) => " (stored order, reversed)".to_string(),
+        (Some(sort), reverse) => format!(" (by {}{})", sort.label(), if reverse { ", reversed" } else { "" }),
+    };
+    println!("=== Git Profiles{} ===", order);
+    // Group the listing only once some profile has a group
+    let grouped = config.profiles.iter().any(|p| p.group.is_some());
+    let mut profiles = profiles;
+    if grouped {
+        let groups = group_names(&config);
+        // Stable, so profiles keep their order within a group
+        profiles.sort_by_key(|p| groups.iter().position(|g| *g == p.group.as_deref()));
+    }
+    let mut last_group = None;
+    for profile in profiles {
+        if grouped && last_group != Some(&profile.group) {
+            println!("[{}]", profile.group.as_deref().unwrap_or(UNGROUPED).bold());
+            last_group = Some(&profile.group);
+        }
+        let mut current = if profile.current {
+            " (current)".green().to_string()
+        } else {
+            String::new()
+        };
+        if profile.default {
+            current.push_str(&" (default)".cyan().to_string());
+        }
+        let name = match profile.icon.is_some() || profile.color.is_some() {
+            true => profile.styled_name(),
+            false => profile.name.clone(),
+        };
+        // "work — Jane Doe <jane@corp.com>" when commits use another name
+        let git_name = match profile.git_name() {
+            git_name if git_name == profile.name => String::new(),
+            git_name => format!(" — {}", git_name),
+        };
+        println!("• {}{} <{}>{}",
+            name,
+            git_name,
+            profile.email,
+            current
+        );
+        println!("  SSH Key: {}", key_label(&profile.ssh_key));
+        if let Some(description) = &profile.description {
+            println!("  {}", description.dimmed());
+        }
+        if long {
+            print_profile_details(profile)?;
+        }
+        println!();
+    }
+
+    Ok(())
+}
+
+/// The profiles in the order `gs list` shows them: stored order by default.
+/// Sorting is for display only; profiles.json keeps its order. Profiles
+/// without the timestamp a sort uses go last.
+fn sorted_profiles(config: &Config, sort: Option<ListSort>, reverse: bool) -> Result<Vec<&Profile>> {
+    let mut profiles: Vec<&Profile> = config.profiles.iter().collect();
+    match sort {
+        None => {}
+        Some(ListSort::Name) => profiles.sort_by_key(|p| p.name.to_lowercase()),
+        Some(ListSort::Created) => profiles.sort_by_key(|p| (p.created_at.is_none(), p.created_at)),
+        Some(ListSort::Recent) => {
+            let last_used = load_state()?.unwrap_or_default().last_used;
+            profiles.sort_by_key(|p| match last_used.get(&p.name) {
+                Some(time) => (false, std::cmp::Reverse(Some(*time))),
+                None => (true, std::cmp::Reverse(None)),
+            });
+        }
+    }
+    if reverse {
+        profiles.reverse();
+    }
+    Ok(profiles)
+}
+
+fn print_profile_details(profile: &Profile) -> Result<()> {
+    for (label, time) in [("Created", profile.created_at), ("Updated", profile.updated_at)] {
+        if let Some(time) = time {
+            println!("  {}: {}", label, time.format("%Y-%m-%d %H:%M"));
+        }
+    }
+    for rule in &profile.url_rewrites {
+        let state = if !profile.current {
+            String::new()
+        } else if installed_rewrites(&rule.base)?.contains(&rule.instead_of) {
+            format!(" {}", "(active)".green())
+        } else {
+            format!(" {}", "(not installed)".red())
+        };
+        println!("  Rewrite: {} -> {}{}", rule.instead_of, rule.base, state);
+    }
+
+    if profile.signing {
+        println!("  Signing: commits are signed with {}.pub", profile.ssh_key);
+    }
+    for (label, path) in [("Commit template", &profile.commit_template), ("Global gitignore", &profile.excludes_file)] {
+        let Some(path) = path else { continue };
+        let missing = match expand_path(path).exists() {
+            true => String::new(),
+            false => format!(" {}", "(missing)".red()),
+        };
+        println!("  {}: {}{}", label, path.display(), missing);
+    }
+    if let Some(branch) = &profile.default_branch {
+        println!("  Default branch: {}", branch);
+    }
+    for option in &profile.ssh_options {
+        println!("  SSH option: {}", option);
+    }
+    for (host, user) in &profile.ssh_users {
+        println!("  SSH user: {}@{}", user, host);
+    }
+    if let Some(agent) = &profile.identity_agent {
+        println!("  SSH agent: {}", agent);
+    }
+    if let Some(username) = &profile.username {
+        println!("  Account: {} on {}", username, profile.primary_host());
+    }
+
+    let key_path = expand_path(Path::new(&profile.ssh_key));
+    if key_path.exists() {
+        match key_fingerprint(&key_path) {
+            Some(fingerprint) => println!("  Fingerprint: {}", fingerprint),
+            None => println!("  Fingerprint: {}", "unavailable".yellow()),
+        }
+    } else if !profile.ssh_key.is_empty() {
+        println!("  {}", "SSH key file is missing".red());
+    }
+
+    if profile.current {
+        let drift = detect_drift(profile)?;
+        if drift.is_empty() {
+            println!("  Live config: {}", "in sync".green());
+        }
+        for d in drift {
+            println!(
+                "  {}",
+                format!(
+                    "{} is {} (expected {})",
+                    d.setting,
+                    d.actual.as_deref().unwrap_or("unset"),
+                    d.expected
+                )
+                .red()
+            );
+        }
+    }
+
+    Ok(())
+}
+
+/// SHA256 fingerprint of a key as reported by `ssh-keygen -lf`.
+fn key_fingerprint(key_path: &Path) -> Option<String> {
+    key_listing(key_path)?.split_whitespace().nth(1).map(str::to_string)
+}
+
+/// What `ssh-keygen -lf` says about a key: "256 SHA256:abc... comment
+/// (ED25519)".
+fn key_listing(key_path: &Path) -> Option<String> {
+    let output = process::Command::new("ssh-keygen")
+        .args(["-l", "-E", "sha256", "-f"])
+        .arg(key_path)
+        .logged_output()
+        .ok()?;
+    if !output.status.success() {
+        return None;
+    }
+    Some(String::from_utf8_lossy(&output.stdout).trim().to_string())
+}
+
+/// The key line of the switch message: the fingerprint, comment and type of
+/// the profile's public key, so a glance shows the SSH side changed too.
+/// Missing or unreadable keys are said so briefly rather than failing the
+/// switch that already happened.
+fn switched_key_line(profile: &Profile) -> String {
+    if profile.ssh_key.is_empty() {
+        return format!("Key: {}", key_label(&profile.ssh_key));
+    }
+    let public_key = expand_path(Path::new(&format!("{}.pub", profile.ssh_key)));
+    match key_listing(&public_key) {
+        // Drop the bit count in front
+        Some(listing) => format!("Key: {}", listing.split_once(' ').map_or(listing.as_str(), |(_, rest)| rest)),
+        None if public_key.exists() => format!("Key: fingerprint unavailable for {}.pub", profile.ssh_key),
+        None => format!("Key: fingerprint unavailable, no {}.pub", profile.ssh_key),
+    }
+}
+
+/// A setting whose live value differs from what the profile expects.
+struct Drift {
+    setting: String,
+    expected: String,
+    actual: Option<String>,
+}
+
+/// Compare the live git and SSH configuration against a profile.
+fn detect_drift(profile: &Profile) -> Result<Vec<Drift>> {
+    let mut drift = Vec::new();
+
+    for (setting, expected) in [("user.name", profile.git_name()), ("user.email", &profile.email)] {
+        let actual = read_git_global(setting)?;
+        if actual.as_deref() != Some(expected) {
+            drift.push(Drift { setting: setting.to_string(), expected: expected.to_string(), actual });
+        }
+    }
+
+    for (host, key) in profile.ssh_hosts() {
+        let identity_file = read_ssh_identity_file(host)?;
+        // A profile using the agent's keys expects no IdentityFile at all
+        let expected = (!key.is_empty()).then(|| expand_tilde(key)).transpose()?;
+        if identity_file.as_deref().map(expand_tilde).transpose()? != expected {
+            let setting = if host == GITHUB_HOST {
+                "SSH IdentityFile".to_string()
+            } else {
+                format!("SSH IdentityFile for {}", host)
+            };
+            drift.push(Drift { setting, expected: key_label(key).to_string(), actual: identity_file });
+        }
+    }
+
+    Ok(drift)
+}
+
+fn read_git_global(key: &str) -> Result<Option<String>> {
+    let output = process::Command::new("git")
+        .args(["config", "--global", "--get", key])
+        .logged_output()
+        .external(&format!("Failed to read git {}", key))?;
+    // 1 means it isn't set
+    if !output.status.success() && output.status.code() != Some(1) {
+        return Err(git_failed(&format!("git config --get {}", key), &output));
+    }
+    let value = String::from_utf8_lossy(&output.stdout).trim().to_string();
+    Ok((output.status.success() && !value.is_empty()).then_some(value))
+}
+
+fn edit_profile(target: Option<&str>, fields: ProfileFields) -> Result<()> {
+    // First, load the config and get necessary information
+    let config = load_config()?;
+
+    if config.profiles.is_empty() {
+        return Err(no_profiles());
+    }
+
+    let interactive = fields.is_empty();
+    let allow_shared_key = fields.allow_shared_key;
+    if !interactive && target.is_none() {
+        return Err(failure(ExitCode::Usage, "Name the profile to edit when passing field flags"));
+    }
+
+    let profile_index = match target {
+        Some(name) => resolve_profile(&config, name)?,
+        None => picker::pick_one("Select Profile to Edit", &config.profiles, "")?,
+    };
+
+    // Clone the profile we want to edit and check if it's current
+    let original_profile = config.profiles[profile_index].clone();
+    let was_current = original_profile.current;
+    let mut updated_profile = original_profile.clone();
+
+    let fields = if interactive {
+        // Edit name
+        eprintln!("Current name: {}", updated_profile.name);
+        let name = prompt_name(&config, Some(profile_index), "New name (press Enter to keep current): ", &updated_profile.name)?;
+
+        // Edit git name
+        eprintln!("Current git name: {}", updated_profile.git_name());
+        let git_name = prompt::prompt("git name", "New name to commit under (press Enter to keep current): ")?;
+
+        // Edit email
+        eprintln!("Current email: {}", updated_profile.email);
+        let email = prompt_email("New email (press Enter to keep current): ", true)?;
+
+        // Edit SSH key
+        eprintln!("Current SSH key: {}", key_label(&updated_profile.ssh_key));
+        let ssh_key = match prompt::prompt("ssh key", "New SSH key path (press Enter to keep current, '-' for the agent's keys): ")?.as_str() {
+            "" => None,
+            "-" => Some(String::new()),
+            path => Some(path.to_string()),
+        };
+
+        // Edit description
+        eprintln!(
+            "Current description: {}",
+            updated_profile.description.as_deref().unwrap_or("(none)")
+        );
+        let description = match prompt::prompt("description", "New description (press Enter to keep current, '-' to clear): ")?.as_str() {
+            "" => None,
+            "-" => Some(String::new()),
+            description => Some(description.to_string()),
+        };
+
+        // Edit global gitignore
+        eprintln!(
+            "Current global gitignore: {}",
+            updated_profile.excludes_file.as_deref().map_or("(none)".into(), Path::to_string_lossy)
+        );
+        let excludes_file = match prompt::prompt("excludes file", "New global gitignore path (press Enter to keep current, '-' to clear): ")?.as_str() {
+            "" => None,
+            "-" => Some(String::new()),
+            path => Some(path.to_string()),
+        };
+
+        // Edit commit template
+        eprintln!(
+            "Current commit template: {}",
+            updated_profile.commit_template.as_deref().map_or("(none)".into(), Path::to_string_lossy)
+        );
+        let commit_template = match prompt::prompt("commit template", "New commit template path (press Enter to keep current, '-' to clear): ")?.as_str() {
+            "" => None,
+            "-" => Some(String::new()),
+            path => Some(path.to_string()),
+        };
+
+        // Edit default branch
+        eprintln!("Current default branch: {}", updated_profile.default_branch.as_deref().unwrap_or("(none)"));
+        let default_branch = match prompt::prompt("default branch", "New default branch (press Enter to keep current, '-' to clear): ")?.as_str() {
+            "" => None,
+            "-" => Some(String::new()),
+            branch => Some(branch.to_string()),
+        };
+
+        // Edit icon and color
+        eprintln!("Current icon: {}", updated_profile.icon.as_deref().unwrap_or("(none)"));
+        let icon = match prompt::prompt("icon", "New icon (press Enter to keep current, '-' to clear): ")?.as_str() {
+            "" => None,
+            "-" => Some(String::new()),
+            icon => Some(icon.to_string()),
+        };
+        eprintln!("Current color: {}", updated_profile.color.as_deref().unwrap_or("(none)"));
+        let color = match prompt::prompt("color", "New color (press Enter to keep current, '-' to clear): ")?.as_str() {
+            "" => None,
+            "-" => Some(String::new()),
+            color => Some(color.to_string()),
+        };
+
+        ProfileFields {
+            name: (name != updated_profile.name).then_some(name),
+            git_name: (!git_name.is_empty()).then_some(git_name),
+            email: (!email.is_empty()).then_some(email),
+            ssh_key,
+            description,
+            commit_template,
+            excludes_file,
+            default_branch,
+            icon,
+            color,
+            ..Default::default()
+        }
+    } else {
+        fields
+    };
+
+    if let Some(name) = fields.name {
+        updated_profile.name = checked_name(&config, Some(profile_index), &name)?;
+    }
+    // An empty git name goes back to committing under the profile name
+    if let Some(git_name) = fields.git_name {
+        let git_name = git_name.trim();
+        updated_profile.git_name = Some(if git_name.is_empty() { updated_profile.name.clone() } else { git_name.to_string() });
+    }
+    if let Some(email) = fields.email {
+        updated_profile.email = checked_email(&email)?;
+    }
+    if let Some(ssh_key) = fields.ssh_key {
+        let expanded_key = expand_tilde(&ssh_key)?;
+
+        // Validate SSH key exists; an empty path is the agent's keys
+        if !expanded_key.is_empty() && !Path::new(&expanded_key).exists() {
+            return Err(Failure::new(ExitCode::NotFound, format!("SSH key not found at: {}", expanded_key))
+                .path(&expanded_key)
+                .into());
+        }
+        if !expanded_key.is_empty() {
+            check_public_key(&expanded_key)?;
+        }
+
+        updated_profile.ssh_key = collapse_tilde(&expanded_key);
+    }
+    // An empty description or group clears it
+    if let Some(description) = fields.description {
+        updated_profile.description = (!description.is_empty()).then_some(description);
+    }
+    if let Some(group) = fields.group {
+        updated_profile.group = (!group.is_empty()).then_some(group);
+    }
+    if let Some(template) = fields.commit_template {
+        updated_profile.commit_template = (!template.is_empty()).then(|| checked_path(&template)).transpose()?;
+    }
+    if let Some(signing) = fields.signing {
+        updated_profile.signing = signing;
+    }
+    if let Some(icon) = fields.icon {
+        updated_profile.icon = (!icon.is_empty()).then_some(icon);
+    }
+    if let Some(color) = fields.color {
+        updated_profile.color = (!color.is_empty()).then(|| checked_color(&color)).transpose()?;
+    }
+    if let Some(excludes) = fields.excludes_file {
+        updated_profile.excludes_file = (!excludes.is_empty()).then(|| checked_path(&excludes)).transpose()?;
+    }
+    if let Some(branch) = fields.default_branch {
+        updated_profile.default_branch = (!branch.is_empty()).then(|| checked_branch(&branch)).transpose()?;
+    }
+    if !fields.ssh_options.is_empty() {
+        updated_profile.ssh_options = checked_ssh_options(&fields.ssh_options)?;
+    }
+    if let Some(agent) = fields.identity_agent {
+        updated_profile.identity_agent = (!agent.is_empty()).then(|| collapse_tilde(&agent));
+    }
+    if let Some(username) = fields.username {
+        updated_profile.username = (!username.is_empty()).then_some(username);
+    }
+    if updated_profile.signing && updated_profile.ssh_key.is_empty() {
+        return Err(failure(ExitCode::Usage, "Signing needs a key file; a profile using the agent's keys can't sign"));
+    }
+
+    if updated_profile != original_profile {
+        updated_profile.touch();
+    }
+
+    if !confirm_duplicates(
+        &config,
+        Some(profile_index),
+        &updated_profile.email,
+        &updated_profile.ssh_key,
+        allow_shared_key,
+    )? {
+        return Err(failure(ExitCode::Aborted, "Aborted"));
+    }
+
+    // Update git and SSH configs if this is the current profile; the
+    // change is only saved once they are
+    let plan = was_current.then(|| plan_apply(&updated_profile, &ApplyScope::default())).transpose()?;
+
+    // Now create a new config with the updated profile
+    let mut new_config = config;
+    new_config.profiles[profile_index] = updated_profile;
+    apply_and_save(&new_config, plan.as_ref())?;
+
+    match options().dry_run {
+        true => eprintln!("Dry run: profile '{}' would be updated.", new_config.profiles[profile_index].name),
+        false => eprintln!("Profile '{}' updated successfully!", new_config.profiles[profile_index].name),
+    }
+    Ok(())
+}
+
+/// `gs edit --raw`: all profiles as one JSON file in the user's editor. The
+/// result is checked before anything is saved, and a bad edit can be fixed
+/// in another round instead of being lost.
+fn edit_raw() -> Result<()> {
+    let config = load_config()?;
+    // In the config directory rather than a shared temp directory, and
+    // readable only by the user, like the profiles it holds
+    let path = get_config_dir()?.join(format!(".edit-{}.json", process::id()));
+    let _ = fs::remove_file(&path);
+    tracing::debug!("write {}", path.display());
+    let mut open = fs::OpenOptions::new();
+    open.write(true).create_new(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        open.mode(0o600);
+    }
+    let mut file = open.open(&path).file_context("write", &path)?;
+    file.write_all(raw_config(&config)?.as_bytes()).file_context("write", &path)?;
+    drop(file);
+    let edited = edit_raw_file(&config, &path);
+    let _ = fs::remove_file(&path);
+    let mut edited = edited?;
+
+    // profiles.d files follow their profile's name
+    for profile in &mut edited.profiles {
+        profile.source = config.profiles.iter().find(|p| p.name == profile.name).map_or(ProfileSource::New, |p| p.source.clone());
+    }
+    if edited == config {
+        eprintln!("No changes made.");
+        return Ok(());
+    }
+
+    let before = config.profiles.iter().find(|p| p.current);
+    let after = edited.profiles.iter().position(|p| p.current);
+    let reapply = after.filter(|&i| before != Some(&edited.profiles[i]));
+    for profile in &mut edited.profiles {
+        if config.profiles.iter().find(|p| p.name == profile.name) != Some(profile) {
+            profile.touch();
+        }
+    }
+
+    // Same order as a structured edit: plan, apply, then save
+    let plan = reapply.map(|i| plan_apply(&edited.profiles[i], &ApplyScope::default())).transpose()?;
+    apply_and_save(&edited, plan.as_ref())?;
+    if let Some(i) = reapply
+        && before.is_none_or(|p| p.name != edited.profiles[i].name)
+    {
+        log_switch(before.map(|p| p.name.as_str()), &edited.profiles[i].name);
+    }
+
+    match options().dry_run {
+        true => eprintln!("Dry run: profiles would be updated."),
+        false => eprintln!("Profiles updated successfully!"),
+    }
+    Ok(())
+}
+
+/// The config as `gs edit --raw` shows it: every profile, wherever it's
+/// stored, with `"current": true` on the active one so it can be moved.
+fn raw_config(config: &Config) -> Result<String> {
+    let mut value = serde_json::to_value(config).context("Failed to serialize config")?;
+    if let (Some(index), Some(profiles)) =
+        (config.profiles.iter().position(|p| p.current), value.get_mut("profiles").and_then(|p| p.as_array_mut()))
+        && let Some(profile) = profiles[index].as_object_mut()
+    {
+        profile.insert("current".to_string(), serde_json::Value::Bool(true));
+    }
+    Ok(serde_json::to_string_pretty(&value).context("Failed to serialize config")? + "\n")
+}
+
+/// Open `path` in the editor until what's saved there passes
+/// `raw_problems`, or the user gives up.
+fn edit_raw_file(config: &Config, path: &Path) -> Result<Config> {
+    let editor = std::env::var("VISUAL")
+        .ok()
+        .filter(|e| !e.trim().is_empty())
+        .or_else(|| std::env::var("EDITOR").ok().filter(|e| !e.trim().is_empty()))
+        .unwrap_or_else(|| if cfg!(windows) { "notepad" } else { "vi" }.to_string());
+    loop {
+        // "code --wait" and the like carry their own arguments
+        let mut words = editor.split_whitespace();
+        let status = process::Command::new(words.next().unwrap_or_default())
+            .args(words)
+            .arg(path)
+            .logged_status()
+            .external(&format!("Failed to run the editor '{}'", editor))?;
+        if !status.success() {
+            return Err(failure(ExitCode::External, format!("{} exited with {}; no changes made", editor, status)));
+        }
+
+        let text = fs::read_to_string(path).file_context("read", path)?;
+        let problems = match serde_json::from_str::<Config>(&text) {
+            Ok(edited) => match raw_problems(config, &edited) {
+                problems if problems.is_empty() => return Ok(edited),
+                problems => problems,
+            },
+            Err(err) => vec![format!("Not a valid profiles file: {}", err)],
+        };
+        eprintln!("{} the edited profiles can't be saved:", "Error:".red());
+        for problem in &problems {
+            eprintln!("  - {}", problem);
+        }
+        if !prompt::confirm("Edit again?")? {
+            return Err(failure(ExitCode::Aborted, "Aborted, no changes made"));
+        }
+    }
+}
+
+/// What's wrong with a raw edit of `config`: the checks setup and edit
+/// make one field at a time, made across every profile at once.
+fn raw_problems(config: &Config, edited: &Config) -> Vec<String> {
+    let mut problems = Vec::new();
+    for (i, profile) in edited.profiles.iter().enumerate() {
+        if let Some(problem) = name_problem(&profile.name) {
+            problems.push(format!("Invalid profile name '{}': {}", profile.name, problem));
+        }
+        if let Some(first) = edited.profiles[..i].iter().find(|p| p.name.eq_ignore_ascii_case(&profile.name)) {
+            problems.push(format!("The name '{}' is used twice (also by '{}')", profile.name, first.name));
+        }
+        let key = expand_path(Path::new(&profile.ssh_key));
+        if !profile.ssh_key.is_empty() && !key.exists() {
+            problems.push(format!("Profile '{}': SSH key not found at {}", profile.name, key.display()));
+        }
+        if profile.signing && profile.ssh_key.is_empty() {
+            problems.push(format!("Profile '{}' signs commits but uses the agent's keys", profile.name));
+        }
+    }
+
+    let current = edited.profiles.iter().filter(|p| p.current).count();
+    if current > 1 {
+        problems.push(format!("{} profiles are marked \"current\"; only one can be", current));
+    } else if current == 0 && !edited.profiles.is_empty() && config.profiles.iter().any(|p| p.current) {
+        problems.push("No profile is marked \"current\": true; mark the one to use".to_string());
+    }
+    if edited.profiles.iter().filter(|p| p.default).count() > 1 {
+        problems.push("More than one profile is marked \"default\"; only one can be".to_string());
+    }
+    problems
+}
+
+/// Warnings for other profiles sharing this email or SSH key. `skip` is the
+/// index of the profile being edited, which shouldn't clash with itself.
+fn duplicate_warnings(config: &Config, skip: Option<usize>, email: &str) -> Vec<String> {
+    let mut warnings = Vec::new();
+    for (i, other) in config.profiles.iter().enumerate() {
+        if Some(i) != skip && other.email.eq_ignore_ascii_case(email) {
+            warnings.push(format!("Profile '{}' already uses the email {}", other.name, other.email));
+        }
+    }
+    warnings
+}
+
+/// Print any duplicate warnings and ask once whether to go on anyway.
+/// Sharing a key is worse than sharing an email: the forge treats both
+/// profiles as whichever account holds the key, so without a terminal to
+/// ask at it takes `allow_shared_key` or `--yes`.
+fn confirm_duplicates(
+    config: &Config,
+    skip: Option<usize>,
+    email: &str,
+    ssh_key: &str,
+    allow_shared_key: bool,
+) -> Result<bool> {
+    let warnings = duplicate_warnings(config, skip, email);
+    for warning in &warnings {
+        eprintln!("{} {}", "Warning:".yellow(), warning);
+    }
+
+    let sharing: Vec<&str> = config
+        .profiles
+        .iter()
+        .enumerate()
+        .filter(|&(i, other)| Some(i) != skip && same_key(&other.ssh_key, ssh_key))
+        .map(|(_, other)| other.name.as_str())
+        .collect();
+    let shared = !sharing.is_empty() && !allow_shared_key;
+    if shared {
+        eprintln!(
+            "{} {} is already the key of {}. Pushes from both profiles will authenticate as the same account.",
+            "Warning:".red().bold(),
+            ssh_key,
+            sharing.iter().map(|name| format!("'{}'", name)).collect::<Vec<_>>().join(", ")
+        );
+        if !options().yes && !io::stdin().is_terminal() {
+            return Err(failure(
+                ExitCode::Usage,
+                "Another profile already uses this SSH key; pass --allow-shared-key to share it anyway",
+            ));
+        }
+    }
+
+    match (shared, warnings.is_empty()) {
+        (true, _) => confirm_destructive("Share the key anyway?"),
+        (false, false) => confirm_destructive("Continue anyway?"),
+        (false, true) => Ok(true),
+    }
+}
+
+/// Whether two key paths name the same file once `~` and symlinks are
+/// resolved. Profiles using the agent's keys have no file to share.
+fn same_key(a: &str, b: &str) -> bool {
+    if a.is_empty() || b.is_empty() {
+        return false;
+    }
+    let resolve = |path: &str| {
+        let path = expand_path(Path::new(path));
+        fs::canonicalize(&path).unwrap_or(path)
+    };
+    resolve(a) == resolve(b)
+}
+
+/// A key path as list and edit show it, which for a profile without a key
+/// file says where its keys come from instead.
+fn key_label(key: &str) -> &str {
+    if key.is_empty() { "(the SSH agent's keys)" } else { key }
+}
+
+/// Expand a leading `~` to the home directory. Paths gs keeps as text end
+/// up in SSH and git config, so a home directory that isn't valid UTF-8 is
+/// an error rather than a lossy guess at it.
+fn expand_tilde(path: &str) -> Result<String> {
+    if (path == "~" || path.starts_with("~/"))
+        && let Some(home_dir) = dirs::home_dir()
+    {
+        return Ok(path.replacen('~', path_text(&home_dir)?, 1));
+    }
+    Ok(path.to_string())
+}
+
+/// `expand_tilde` for a path that only needs to be opened, which works
+/// whatever the home directory is called.
+fn expand_path(path: &Path) -> PathBuf {
+    if let Ok(relative) = path.strip_prefix("~")
+        && let Some(home_dir) = dirs::home_dir()
+    {
+        return home_dir.join(relative);
+    }
+    path.to_path_buf()
+}
+
+/// `path` as text, for writing into a config file or command line. gs
+/// never writes a lossy version of a path.
+fn path_text(path: &Path) -> Result<&str> {
+    path.to_str().ok_or_else(|| {
+        Failure::new(ExitCode::Error, format!("{} isn't valid UTF-8, which gs can't write into config", path.display()))
+            .path(path)
+            .into()
+    })
+}
+
+/// A path given for a profile setting, which must already exist. Stored in
+/// `~/...` form like key paths.
+fn checked_path(path: &str) -> Result<PathBuf> {
+    let expanded = expand_tilde(path)?;
+    if !Path::new(&expanded).exists() {
+        return Err(Failure::new(ExitCode::NotFound, format!("File not found: {}", expanded)).path(&expanded).into());
+    }
+    Ok(PathBuf::from(collapse_tilde(&expanded)))
+}
+
+/// An email that passes `is_valid_email`, trimmed.
+fn checked_email(email: &str) -> Result<String> {
+    let email = email.trim();
+    if !is_valid_email(email) {
+        return Err(failure(ExitCode::Usage, format!("'{}' is not a valid email address", email)));
+    }
+    Ok(email.to_string())
+}
+
+/// Ask for an email until the answer looks like one; an empty answer is
+/// fine when `optional`. Piped input fails on a bad answer instead, as in
+/// `prompt::prompt_required`.
+fn prompt_email(question: &str, optional: bool) -> Result<String> {
+    loop {
+        let answer = match optional {
+            true => prompt::prompt("email", question)?,
+            false => prompt::prompt_required("email", question)?,
+        };
+        if (optional && answer.is_empty()) || is_valid_email(&answer) {
+            return Ok(answer);
+        }
+        if !io::stdin().is_terminal() {
+            return Err(failure(
+                ExitCode::Usage,
+                format!("invalid answer for 'email': '{}' is not a valid email address", answer),
+            ));
+        }
+        eprintln!("'{}' is not a valid email address.", answer);
+    }
+}
+
+/// A branch name for `init.defaultBranch`, checked the way git checks new
+/// branch names.
+fn checked_branch(branch: &str) -> Result<String> {
+    let branch = branch.trim();
+    let valid = process::Command::new("git")
+        .args(["check-ref-format", "--branch", branch])
+        .stdout(process::Stdio::null())
+        .stderr(process::Stdio::null())
+        .logged_status()
+        .external("Failed to run git")?
+        .success();
+    // --branch also takes @{-1}, which means nothing in a new repository
+    if !valid || branch.starts_with('@') {
+        return Err(failure(ExitCode::Usage, format!("'{}' is not a valid branch name", branch)));
+    }
+    Ok(branch.to_string())
+}
+
+/// A profile color, checked against the names colored knows and `#rrggbb`.
+fn checked_color(color: &str) -> Result<String> {
+    match parse_color(color) {
+        Some(_) => Ok(color.to_string()),
+        None => Err(failure(
+            ExitCode::Usage,
+            format!("Unknown color '{}'; use a name like red, blue or bright green, or #rrggbb", color),
+        )),
+    }
+}
+
+/// `--ssh-option` lines with the empty ones (which clear the list) dropped.
+/// Each has to be a single option, and not one that escapes the Host block
+/// gs manages.
+fn checked_ssh_options(options: &[String]) -> Result<Vec<String>> {
+    let mut checked = Vec::new();
+    for option in options.iter().map(|o| o.trim()).filter(|o| !o.is_empty()) {
+        if let Some(problem) = ssh_option_problem(option) {
+            return Err(failure(ExitCode::Usage, format!("Invalid SSH option '{}': {}", option, problem)));
+        }
+        checked.push(option.to_string());
+    }
+    Ok(checked)
+}
+
+/// Why a line can't be a profile SSH option, or None if it can.
+fn ssh_option_problem(option: &str) -> Option<String> {
+    if option.contains(['\n', '\r']) {
+        return Some("it must be a single line".to_string());
+    }
+    let line = ssh_config::tokenize(option);
+    match line.keyword.as_deref() {
+        None => Some("it's empty or a comment".to_string()),
+        Some(keyword) if ssh_config::FORBIDDEN_OPTIONS.contains(&keyword) => {
+            Some(format!("{} isn't allowed inside the managed Host block", option.get(..keyword.len()).unwrap_or(keyword)))
+        }
+        Some(_) if line.args.is_empty() => Some("it has no value".to_string()),
+        Some(_) => None,
+    }
+}
+
+fn parse_color(color: &str) -> Option<colored::Color> {
+    if let Some(hex) = color.strip_prefix('#') {
+        if hex.len() != 6 {
+            return None;
+        }
+        let channel = |i: usize| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok();
+        return Some(colored::Color::TrueColor { r: channel(0)?, g: channel(2)?, b: channel(4)? });
+    }
+    color.parse().ok()
+}
+
+/// `collapse_tilde` for a path, without going through text.
+fn collapse_path(path: &Path) -> PathBuf {
+    match dirs::home_dir().and_then(|home_dir| path.strip_prefix(home_dir).ok().map(Path::to_path_buf)) {
+        Some(relative) => Path::new("~").join(relative),
+        None => path.to_path_buf(),
+    }
+}
+
+/// The inverse of `expand_tilde`: paths under the home directory become
+/// `~/...`, anything else is returned unchanged.
+fn collapse_tilde(path: &str) -> String {
+    if let Some(home_dir) = dirs::home_dir()
+        && let Ok(relative) = Path::new(path).strip_prefix(&home_dir)
+    {
+        return match relative.as_os_str().is_empty() {
+            true => "~".to_string(),
+            false => format!("~/{}", relative.display()),
+        };
+    }
+    path.to_string()
+}
+
+/// Ask before doing something that can't be taken back. `--yes` answers
+/// for the user; without it, and without a terminal to ask at, the answer
+/// is an error rather than a read from whatever stdin is.
+fn confirm_destructive(question: &str) -> Result<bool> {
+    if options().yes {
+        tracing::debug!("{} yes (--yes)", question);
+        return Ok(true);
+    }
+    if !io::stdin().is_terminal() {
+        return Err(failure(
+            ExitCode::Usage,
+            format!("Can't ask \"{}\" without a terminal; pass --yes to go ahead", question),
+        ));
+    }
+    prompt::confirm(question)
+}
+
+fn remove_profile(names: &[String], purge: bool, force: bool) -> Result<()> {
+    let mut config = load_config()?;
+
+    if config.profiles.is_empty() {
+        return Err(no_profiles());
+    }
+
+    let mut indices = if names.is_empty() {
+        picker::pick_many("Select Profiles to Remove", &config.profiles)?
+    } else {
+        let mut indices = Vec::new();
+        for name in names {
+            indices.push(resolve_profile(&config, name)?);
+        }
+        indices
+    };
+    indices.sort_unstable();
+    indices.dedup();
+
+    // Confirm once for the whole batch
+    eprintln!("The following profiles will be removed:");
+    for &i in &indices {
+        let profile = &config.profiles[i];
+        let current = if profile.current { " (current)" } else { "" };
+        eprintln!("  • {} <{}>{}", profile.name, profile.email, current);
+    }
+
+    let question = if indices.len() == 1 {
+        "Are you sure you want to remove this profile?".to_string()
+    } else {
+        format!("Are you sure you want to remove these {} profiles?", indices.len())
+    };
+    if !confirm_destructive(&question)? {
+        return Err(failure(ExitCode::Aborted, "Aborted"));
+    }
+
+    // Settle which key files go before removing anything
+    let purged = match purge {
+        true => {
+            let (removed, remaining): (Vec<_>, Vec<_>) =
+                config.profiles.iter().enumerate().partition(|(i, _)| indices.contains(i));
+            let removed: Vec<&Profile> = removed.into_iter().map(|(_, p)| p).collect();
+            let remaining: Vec<&Profile> = remaining.into_iter().map(|(_, p)| p).collect();
+            plan_purge(&removed, &remaining, force)?
+        }
+        false => Vec::new(),
+    };
+
+    let (removed, new_current) = delete_profiles(&mut config, &indices)?;
+
+    if options().dry_run {
+        for profile in &removed {
+            eprintln!("{} {}", "Would remove".yellow(), profile.name);
+        }
+        if let Some(name) = new_current {
+            eprintln!("Would switch to profile: {}", name.bright_green());
+        }
+        for key in &purged {
+            eprintln!("{} {}", "Would delete".yellow(), key.display());
+        }
+        return Ok(());
+    }
+    for profile in &removed {
+        eprintln!("{} {}", "Removed".green(), profile.name);
+    }
+    if let Some(name) = new_current {
+        eprintln!("Switched to profile: {}", name.bright_green());
+    }
+
+    purge_keys(&purged)?;
+    Ok(())
+}
+
+/// Remove the profiles at `indices` (sorted, deduplicated) and save. If the
+/// current profile goes, the first survivor is applied and becomes current;
+/// its name is returned alongside the removed profiles.
+fn delete_profiles(config: &mut Config, indices: &[usize]) -> Result<(Vec<Profile>, Option<String>)> {
+    // Remove from the back so earlier indices stay valid
+    let mut removed = Vec::new();
+    let mut removed_current = None;
+    for &i in indices.iter().rev() {
+        let profile = config.profiles.remove(i);
+        if profile.current {
+            removed_current = Some(profile.name.clone());
+        }
+        removed.push(profile);
+    }
+    removed.reverse();
+    if let Some(profile) = removed.iter().find(|p| p.default) {
+        eprintln!("{} '{}' was the default profile; there's no default now", "Warning:".yellow(), profile.name);
+    }
+
+    // If the current profile was removed, make the default (or else the
+    // first survivor) current and apply it once
+    let mut new_current = None;
+    let mut plan = None;
+    if removed_current.is_some() && !config.profiles.is_empty() {
+        let next = config.profiles.iter().position(|p| p.default).unwrap_or(0);
+        config.profiles[next].current = true;
+        plan = Some(plan_apply(&config.profiles[next], &ApplyScope::default())?);
+        new_current = Some(config.profiles[next].name.clone());
+    }
+
+    // Nobody takes over, so don't leave the old profile's rewrites behind
+    if plan.is_none() && removed_current.is_some() && !options().dry_run {
+        sync_url_rewrites(&[])?;
+    }
+    apply_and_save(config, plan.as_ref())?;
+    if let Some(name) = &new_current {
+        log_switch(removed_current.as_deref(), name);
+    }
+    Ok((removed, new_current))
+}
+
+/// The keys of profiles about to be removed that can be purged, once the
+/// user has confirmed deleting their files.
+fn plan_purge(removed: &[&Profile], remaining: &[&Profile], force: bool) -> Result<Vec<PathBuf>> {
+    let ssh_dir = get_ssh_dir()?;
+    let mut keys: Vec<PathBuf> = Vec::new();
+
+    for profile in removed.iter().filter(|p| !p.ssh_key.is_empty()) {
+        let key = expand_path(Path::new(&profile.ssh_key));
+        if keys.contains(&key) {
+            continue;
+        }
+
+        if let Some(other) = remaining.iter().find(|p| p.ssh_key == profile.ssh_key) {
+            eprintln!(
+                "{} {}: still used by profile '{}'",
+                "Keeping".yellow(),
+                key.display(),
+                other.name
+            );
+            continue;
+        }
+
+        if !key.starts_with(&ssh_dir) && !force {
+            eprintln!(
+                "{} {}: outside {} (pass --force to purge it anyway)",
+                "Keeping".yellow(),
+                key.display(),
+                ssh_dir.display()
+            );
+            continue;
+        }
+
+        keys.push(key);
+    }
+
+    if keys.is_empty() {
+        return Ok(keys);
+    }
+
+    eprintln!("The following will be removed from the SSH agent and deleted:");
+    for file in keys.iter().flat_map(|key| key_pair_files(key)) {
+        eprintln!("  • {}", file.display());
+    }
+    if !confirm_destructive("Purge these key files?")? {
+        return Err(failure(ExitCode::Aborted, "Aborted"));
+    }
+    Ok(keys)
+}
+
+/// A private key and its .pub, whichever of them exist.
+fn key_pair_files(key: &Path) -> Vec<PathBuf> {
+    [key.to_path_buf(), PathBuf::from(format!("{}.pub", key.display()))]
+        .into_iter()
+        .filter(|file| file.exists())
+        .collect()
+}
+
+/// Unload keys from the agent and delete the key pairs.
+fn purge_keys(keys: &[PathBuf]) -> Result<()> {
+    for key in keys {
+        // The key may simply not be loaded; that's fine
+        let unloaded = process::Command::new("ssh-add")
+            .arg("-d")
+            .arg(key)
+            .logged_output()
+            .is_ok_and(|o| o.status.success());
+        if unloaded {
+            eprintln!("{} {} from the SSH agent", "Unloaded".green(), key.display());
+        }
+    }
+
+    for file in keys.iter().flat_map(|key| key_pair_files(key)) {
+        fs::remove_file(&file).with_context(|| format!("Failed to delete {}", file.display()))?;
+        eprintln!("{} {}", "Deleted".green(), file.display());
+    }
+
+    Ok(())
+}
+
+fn verify_commits(count: usize, expect: Option<&str>) -> Result<()> {
+    ensure_git_repo()?;
+
+    let config = load_config()?;
+    let allowed: Vec<String> = match expect {
+        Some(name) => {
+            let profile = &config.profiles[resolve_profile(&config, name)?];
+            vec![profile.email.to_lowercase()]
+        }
+        None => config.profiles.iter().map(|p| p.email.to_lowercase()).collect(),
+    };
+
+    if allowed.is_empty() {
+        return Err(no_profiles());
+    }
+
+    let output = process::Command::new("git")
+        .args(["log", "-n", &count.to_string(), "--format=%h%x09%ae%x09%ce"])
+        .logged_output()
+        .external("Failed to run git log")?;
+    if !output.status.success() {
+        return Err(failure(
+            ExitCode::External,
+            format!(
+                "git log failed: {}",
+                String::from_utf8_lossy(&output.stderr).trim()
+            ),
+        ));
+    }
+
+    // Offending email -> short hashes of commits that used it
+    let mut offending: BTreeMap<String, Vec<String>> = BTreeMap::new();
+    let log = String::from_utf8_lossy(&output.stdout);
+    let mut checked = 0;
+
+    for line in log.lines() {
+        let mut fields = line.split('\t');
+        let (Some(hash), Some(author), Some(committer)) = (fields.next(), fields.next(), fields.next())
+        else {
+            continue;
+        };
+        checked += 1;
+
+        for email in [author, committer] {
+            if !allowed.contains(&email.to_lowercase()) {
+                let hashes = offending.entry(email.to_string()).or_default();
+                if !hashes.iter().any(|h| h == hash) {
+                    hashes.push(hash.to_string());
+                }
+            }
+        }
+    }
+
+    if offending.is_empty() {
+        println!("All {} commits match {}.", checked, match expect {
+            Some(name) => format!("profile '{}'", name),
+            None => "a known profile".to_string(),
+        });
+        return Ok(());
+    }
+
+    println!("=== Commits With Unexpected Identities ===");
+    for (email, hashes) in &offending {
+        println!("• {} ({} commits)", email.red(), hashes.len());
+        println!("  {}", hashes.join(" "));
+    }
+
+    let commits = offending.values().flatten().collect::<BTreeSet<_>>().len();
+    Err(failure(
+        ExitCode::Error,
+        format!(
+            "{} of the last {} commits use an identity other than {}",
+            commits,
+            checked,
+            match expect {
+                Some(name) => format!("profile '{}'", name),
+                None => "a known profile".to_string(),
+            }
+        ),
+    ))
+}
+
+/// The result of trying one profile's key against one host.
+struct AuthCheck {
+    profile: String,
+    host: String,
+    /// The account the host says we authenticated as, or why it failed.
+    outcome: std::result::Result<String, AuthError>,
+}
+
+/// Try SSH authentication with profile keys: the named or current profile,
+/// or every profile with `all`. Hosts are checked concurrently.
+fn test_auth(name: Option<&str>, all: bool, timeout: u64) -> Result<()> {
+    let config = load_config()?;
+    if config.profiles.is_empty() {
+        return Err(no_profiles());
+    }
+    let profiles: Vec<&Profile> = match (name, all) {
+        (_, true) => config.profiles.iter().collect(),
+        (Some(name), false) => vec![&config.profiles[resolve_profile(&config, name)?]],
+        (None, false) => vec![config
+            .profiles
+            .iter()
+            .find(|p| p.current)
+            .ok_or_else(|| failure(ExitCode::NotFound, "No current profile; name one or pass --all"))?],
+    };
+
+    let timeout = std::time::Duration::from_secs(timeout);
+    let checks: Vec<AuthCheck> = std::thread::scope(|scope| {
+        let handles: Vec<_> = profiles
+            .iter()
+            .flat_map(|p| p.ssh_hosts().into_iter().map(move |(host, key)| (*p, host, key)))
+            .map(|(profile, host, key)| {
+                scope.spawn(move || AuthCheck {
+                    profile: profile.name.clone(),
+                    host: host.to_string(),
+                    outcome: ssh_auth_check(host, profile.ssh_user(host), &expand_path(Path::new(key)), &profile.block_options(key), timeout),
+                })
+            })
+            .collect();
+        handles.into_iter().map(|h| h.join().expect("auth check thread panicked")).collect()
+    });
+
+    let width = checks.iter().map(|c| c.profile.len()).max().unwrap_or(0).max("PROFILE".len());
+    let host_width = checks.iter().map(|c| c.host.len()).max().unwrap_or(0).max("HOST".len());
+    println!("{:<width$}  {:<host_width$}  RESULT", "PROFILE", "HOST");
+    for check in &checks {
+        let result = match &check.outcome {
+            Ok(user) => format!("{} as {}", "OK".green(), user),
+            Err(reason) => format!("{} {}", "FAIL".red(), reason),
+        };
+        println!("{:<width$}  {:<host_width$}  {}", check.profile, check.host, result);
+    }
+
+    let mut failed: Vec<&str> = checks.iter().filter(|c| c.outcome.is_err()).map(|c| c.profile.as_str()).collect();
+    failed.dedup();
+    if !failed.is_empty() {
+        return Err(failure(ExitCode::Error, format!("Authentication failed for {}", failed.join(", "))));
+    }
+    Ok(())
+}
+
+/// Authenticate to `host` with exactly `key` and report the account name.
+/// User ssh config is skipped so no other identity can stand in for it.
+/// Without a key file, the agent's keys are offered instead, and whichever
+/// the forge takes is the account reported.
+fn ssh_auth_check(
+    host: &str,
+    user: &str,
+    key: &Path,
+    options: &[String],
+    timeout: std::time::Duration,
+) -> std::result::Result<String, AuthError> {
+    let has_key = !key.as_os_str().is_empty();
+    if has_key && !key.exists() {
+        return Err(AuthError::Denied(format!("key not found at {}", key.display())));
+    }
+    let mut command = process::Command::new("ssh");
+    command.args(["-T", "-F", "none"]);
+    if has_key {
+        command.arg("-i").arg(key).args(["-o", "IdentitiesOnly=yes"]);
+    }
+    command
+        .args(["-o", "BatchMode=yes", "-o", "StrictHostKeyChecking=accept-new"])
+        .arg("-o")
+        .arg(format!("ConnectTimeout={}", timeout.as_secs().max(1)))
+        .args(options.iter().flat_map(|option| ["-o", option.as_str()]))
+        .arg(format!("{}@{}", user, host))
+        .stdin(process::Stdio::null())
+        .stdout(process::Stdio::piped())
+        .stderr(process::Stdio::piped());
+    tracing::debug!("run {}", trace::describe(&command));
+    let mut child = command.spawn().map_err(|e| AuthError::Unreachable(format!("couldn't run ssh: {}", e)))?;
+
+    // Forges print their greeting and hang up, but a stuck hop shouldn't
+    // hold up the whole table
+    let deadline = std::time::Instant::now() + timeout;
+    loop {
+        match child.try_wait() {
+            Ok(Some(_)) => break,
+            Ok(None) if std::time::Instant::now() >= deadline => {
+                let _ = child.kill();
+                let _ = child.wait();
+                return Err(AuthError::Unreachable(format!("timed out after {}s", timeout.as_secs())));
+            }
+            Ok(None) => std::thread::sleep(std::time::Duration::from_millis(50)),
+            Err(e) => return Err(AuthError::Unreachable(e.to_string())),
+        }
+    }
+    let output = child.wait_with_output().map_err(|e| AuthError::Unreachable(e.to_string()))?;
+    let text = format!("{}{}", String::from_utf8_lossy(&output.stdout), String::from_utf8_lossy(&output.stderr));
+
+    if let Some(user) = authenticated_user(host, &text) {
+        return Ok(user);
+    }
+    if text.contains("Permission denied") {
+        return Err(AuthError::Denied("permission denied (key not accepted)".to_string()));
+    }
+    let last_line = text.lines().rev().find(|l| !l.trim().is_empty()).unwrap_or("no response").trim().to_string();
+    match NETWORK_ERRORS.iter().any(|error| text.contains(error)) {
+        true => Err(AuthError::Unreachable(last_line)),
+        false => Err(AuthError::Denied(last_line)),
+    }
+}
+
+/// What ssh prints when it never got to talk to the host.
+const NETWORK_ERRORS: [&str; 7] = [
+    "Could not resolve hostname",
+    "Connection timed out",
+    "Connection refused",
+    "Network is unreachable",
+    "No route to host",
+    "Connection reset",
+    "Connection closed by",
+];
+
+/// Why an SSH authentication check didn't come back with an account.
+enum AuthError {
+    /// The host answered but didn't take the key, or there was no key to
+    /// offer.
+    Denied(String),
+    /// ssh never got an answer from the host: DNS, the network, or the
+    /// timeout.
+    Unreachable(String),
+}
+
+impl std::fmt::Display for AuthError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            AuthError::Denied(reason) | AuthError::Unreachable(reason) => f.write_str(reason),
+        }
+    }
+}
+
+/// Ask the current profile's main host which account its key authenticates
+/// as, and compare it with the profile's `username` when it has one. A
+/// rejected key or a different account exits 1; an unreachable host
+/// exits 6, since it says nothing about the key.
+fn whoami(timeout: u64) -> Result<()> {
+    let config = load_config()?;
+    if config.profiles.is_empty() {
+        return Err(no_profiles());
+    }
+    let profile = config
+        .profiles
+        .iter()
+        .find(|p| p.current)
+        .ok_or_else(|| failure(ExitCode::NotFound, "No current profile"))?;
+    let host = profile.primary_host();
+    let key = profile.ssh_hosts().into_iter().find(|(h, _)| *h == host).map_or(profile.ssh_key.as_str(), |(_, k)| k);
+
+    let timeout = std::time::Duration::from_secs(timeout);
+    let account = match ssh_auth_check(host, profile.ssh_user(host), &expand_path(Path::new(key)), &profile.block_options(key), timeout) {
+        Ok(account) => account,
+        Err(AuthError::Denied(reason)) => {
+            return Err(Failure::new(ExitCode::Error, format!("{} didn't accept the key for '{}': {}", host, profile.name, reason))
+                .profile(&profile.name)
+                .into());
+        }
+        Err(AuthError::Unreachable(reason)) => {
+            return Err(Failure::new(ExitCode::External, format!("Couldn't reach {}: {}", host, reason))
+                .profile(&profile.name)
+                .into());
+        }
+    };
+
+    match &profile.username {
+        Some(expected) if !expected.eq_ignore_ascii_case(&account) => {
+            println!("{} on {} (profile {})", account.red(), host, profile.styled_name());
+            Err(Failure::new(
+                ExitCode::Error,
+                format!("'{}' expects {} account '{}', but the key belongs to '{}'", profile.name, host, expected, account),
+            )
+            .profile(&profile.name)
+            .into())
+        }
+        _ => {
+            println!("{} on {} (profile {})", account.green(), host, profile.styled_name());
+            Ok(())
+        }
+    }
+}
+
+/// The account name in a forge's SSH greeting, e.g. GitHub's "Hi octocat!
+/// You've successfully authenticated" or GitLab's "Welcome to GitLab, @octocat!".
+/// A known forge's own greeting is tried first; other hosts may run
+/// anything, so the usual ones are all tried.
+fn authenticated_user(host: &str, greeting: &str) -> Option<String> {
+    let word_after = |marker: &str| -> Option<String> {
+        let start = greeting.find(marker)? + marker.len();
+        let word = greeting[start..].split(|c: char| c.is_whitespace() || c == '!' || c == ',').next()?;
+        // Bitbucket ends its sentence right after the name
+        let word = word.trim_start_matches(['@', '~']).trim_end_matches('.');
+        (!word.is_empty()).then(|| word.to_string())
+    };
+
+    if let Some(user) = hosts::find(host).and_then(|known| word_after(known.greeting)) {
+        return Some(user);
+    }
+    if greeting.contains("successfully authenticated") {
+        // Gitea and Forgejo say "Hi there, octocat!"
+        return word_after("Hi there, ").or_else(|| word_after("Hi ")).or_else(|| Some("(unknown user)".to_string()));
+    }
+    word_after("Welcome to GitLab, ")
+        .or_else(|| word_after("logged in as "))
+        .or_else(|| greeting.contains("authenticated via").then(|| "(unknown user)".to_string()))
+}
+
+fn fix_author(allow_staged: bool) -> Result<()> {
+    ensure_git_repo()?;
+
+    let config = load_config()?;
+    let profile = config
+        .profiles
+        .iter()
+        .find(|p| p.current)
+        .ok_or_else(|| {
+            failure(ExitCode::NotFound, format!("No current profile. Run '{}' to select one.", program_name()))
+        })?;
+
+    let head = process::Command::new("git")
+        .args(["rev-parse", "--verify", "--quiet", "HEAD"])
+        .logged_output()
+        .external("Failed to run git")?;
+    if !head.status.success() {
+        return Err(anyhow!("There is no commit to amend yet"));
+    }
+
+    // HEAD already reachable from the upstream means the commit was pushed
+    let upstream = process::Command::new("git")
+        .args(["rev-parse", "--abbrev-ref", "--symbolic-full-name", "@{upstream}"])
+        .logged_output()
+        .external("Failed to run git")?;
+    if upstream.status.success() {
+        let pushed = process::Command::new("git")
+            .args(["merge-base", "--is-ancestor", "HEAD", "@{upstream}"])
+            .logged_status()
+            .external("Failed to run git merge-base")?;
+        if pushed.success() {
+            return Err(anyhow!(
+                "The last commit has already been pushed to {}; refusing to rewrite it",
+                String::from_utf8_lossy(&upstream.stdout).trim()
+            ));
+        }
+    }
+
+    if !allow_staged {
+        let staged = process::Command::new("git")
+            .args(["diff", "--cached", "--quiet"])
+            .logged_status()
+            .external("Failed to run git diff")?;
+        if !staged.success() {
+            return Err(anyhow!(
+                "There are staged changes that would be added to the commit. Unstage them or pass --allow-staged"
+            ));
+        }
+    }
+
+    let before = last_commit_author()?;
+    let author = format!("{} <{}>", profile.git_name(), profile.email);
+    let status = process::Command::new("git")
+        .args(["commit", "--amend", "--no-edit", "--allow-empty", "--author", &author])
+        .env("GIT_COMMITTER_NAME", profile.git_name())
+        .env("GIT_COMMITTER_EMAIL", &profile.email)
+        .logged_status()
+        .external("Failed to run git commit --amend")?;
+    if !status.success() {
+        return Err(failure(ExitCode::External, format!("git commit --amend exited with {}", status)));
+    }
+    let after = last_commit_author()?;
+
+    eprintln!("Before: {}", before.red());
+    eprintln!("After:  {}", after.bright_green());
+    Ok(())
+}
+
+fn set_worktree_profile(name: &str) -> Result<()> {
+    let config = load_config()?;
+    let profile = &config.profiles[resolve_profile(&config, name)?];
+
+    if ensure_git_repo().is_err() {
+        return Err(anyhow!(
+            "--worktree needs to run inside a git worktree; cd into the checkout whose identity you want to set"
+        ));
+    }
+
+    // Worktree-scoped config only takes effect with this extension on;
+    // without it `git config --worktree` silently writes to the shared config
+    if !worktree_config_enabled() {
+        let status = process::Command::new("git")
+            .args(["config", "extensions.worktreeConfig", "true"])
+            .logged_status()
+            .external("Failed to run git config")?;
+        if !status.success() {
+            return Err(failure(
+                ExitCode::External,
+                "Could not enable extensions.worktreeConfig (needs git 2.20 or newer)",
+            ));
+        }
+        eprintln!("Enabled extensions.worktreeConfig for this repository");
+    }
+
+    for (key, value) in [("user.name", profile.git_name()), ("user.email", &profile.email)] {
+        let output = process::Command::new("git")
+            .args(["config", "--worktree", key, value])
+            .logged_output()
+            .external(&format!("Failed to set {}", key))?;
+        if !output.status.success() {
+            return Err(failure(
+                ExitCode::External,
+                format!(
+                    "git config --worktree {} failed: {}",
+                    key,
+                    String::from_utf8_lossy(&output.stderr).trim()
+                ),
+            ));
+        }
+    }
+
+    eprintln!(
+        "This worktree now uses profile: {} ({})",
+        profile.name.bright_green(),
+        profile.email
+    );
+    Ok(())
+}
+
+fn worktree_config_enabled() -> bool {
+    read_git_config(&["--local", "--bool"], "extensions.worktreeConfig").as_deref() == Some("true")
+}
+
+/// Read a key from git config with extra scope flags, e.g. `["--global"]`.
+/// Best effort, for display and checks: None also when git can't read it
+/// (outside a repository, say), which `-v` logs with git's message.
+fn read_git_config(flags: &[&str], key: &str) -> Option<String> {
+    let output = process::Command::new("git")
+        .arg("config")
+        .args(flags)
+        .args(["--get", key])
+        .logged_output()
+        .ok()?;
+    let value = String::from_utf8_lossy(&output.stdout).trim().to_string();
+    (output.status.success() && !value.is_empty()).then_some(value)
+}
+
+/// Report problems with the profiles and the live configuration. Doctor
+/// only looks, unless `fix` lets it add host keys and rewrite .pub files.
+fn doctor(fix: bool) -> Result<()> {
+    let config = load_config()?;
+    let mut problems = Vec::new();
+
+    if config.profiles.is_empty() {
+        return Err(no_profiles());
+    }
+
+    for (i, profile) in config.profiles.iter().enumerate() {
+        if let Some(problem) = name_problem(&profile.name) {
+            problems.push(format!("Profile '{}': invalid name ({}); rename it with `gs rename`", profile.name, problem));
+        }
+        let key = expand_tilde(&profile.ssh_key)?;
+        if !key.is_empty() && !Path::new(&key).exists() {
+            problems.push(format!("Profile '{}': SSH key not found at {}", profile.name, profile.ssh_key));
+        } else if !key.is_empty() {
+            match public_key_state(&key) {
+                Ok(PublicKeyState::Missing { derived } | PublicKeyState::Mismatch { derived }) if fix => {
+                    write_public_key(&key, &derived)?;
+                    println!("{} Wrote {}.pub from the private key", "✓".green(), profile.ssh_key);
+                }
+                Ok(PublicKeyState::Missing { .. }) => problems.push(format!(
+                    "Profile '{}': {}.pub is missing ('{} doctor --fix' writes it from the key)",
+                    profile.name,
+                    profile.ssh_key,
+                    program_name()
+                )),
+                Ok(PublicKeyState::Mismatch { .. }) => problems.push(format!(
+                    "Profile '{}': {}.pub doesn't match the private key ('{} doctor --fix' rewrites it)",
+                    profile.name,
+                    profile.ssh_key,
+                    program_name()
+                )),
+                // A key with a passphrase can't be checked without it
+                Ok(PublicKeyState::Matches | PublicKeyState::Locked) => {}
+                Err(err) => problems.push(format!("Profile '{}': {:#}", profile.name, err)),
+            }
+        }
+
+        // Only look forward so each pair is reported once
+        for other in &config.profiles[i + 1..] {
+            if other.name.eq_ignore_ascii_case(&profile.name) {
+                problems.push(format!("Profiles '{}' and '{}' have the same name", profile.name, other.name));
+            }
+            if other.email.eq_ignore_ascii_case(&profile.email) {
+                problems.push(format!("Profiles '{}' and '{}' share the email {}", profile.name, other.name, profile.email));
+            }
+            if same_key(&other.ssh_key, &profile.ssh_key) {
+                problems.push(format!("Profiles '{}' and '{}' share the SSH key {}", profile.name, other.name, profile.ssh_key));
+            }
+        }
+    }
+
+    match config.profiles.iter().find(|p| p.current) {
+        Some(profile) => {
+            for d in detect_drift(profile)? {
+                problems.push(format!(
+                    "{} is {} but profile '{}' expects {}",
+                    d.setting,
+                    d.actual.as_deref().unwrap_or("unset"),
+                    profile.name,
+                    d.expected
+                ));
+            }
+        }
+        None => problems.push("No profile is marked as current".to_string()),
+    }
+
+    if config.profiles.iter().any(|p| p.signing) {
+        let path = get_config_dir()?.join(ALLOWED_SIGNERS_FILE);
+        let actual = fs::read_to_string(&path).unwrap_or_default();
+        let wanted = allowed_signers(&config);
+        for line in wanted.lines().filter(|l| !actual.lines().any(|a| a == *l)) {
+            problems.push(format!("{} is missing the entry for {}", path.display(), line.split(' ').next().unwrap()));
+        }
+        for line in actual.lines().filter(|l| !wanted.lines().any(|w| w == *l)) {
+            problems.push(format!("{} has a stale entry for {}", path.display(), line.split(' ').next().unwrap()));
+        }
+        for profile in config.profiles.iter().filter(|p| p.signing) {
+            if !Path::new(&format!("{}.pub", expand_tilde(&profile.ssh_key)?)).exists() {
+                problems.push(format!("Profile '{}' signs commits but has no public key next to {}", profile.name, profile.ssh_key));
+            }
+        }
+    }
+
+    let mut hosts: Vec<&str> = config.profiles.iter().flat_map(|p| p.ssh_hosts()).map(|(h, _)| h).collect();
+    hosts.sort_unstable();
+    hosts.dedup();
+    let known_hosts = get_ssh_config_path()?.with_file_name(KNOWN_HOSTS_FILE);
+    for host in hosts {
+        let blocks = locate_ssh_blocks(host)?;
+        if blocks.len() > 1 {
+            for block in &blocks[1..] {
+                problems.push(format!(
+                    "Duplicate Host {} block at {} is shadowed by {}",
+                    host,
+                    describe_block(block),
+                    describe_block(&blocks[0])
+                ));
+            }
+        }
+    }
+
+    // Profiles may reach the same host on different ports or through
+    // different jump hosts
+    let mut routes: Vec<(&str, known_hosts::Route)> = config
+        .profiles
+        .iter()
+        .flat_map(|p| {
+            let route = known_hosts::Route::from_options(&p.ssh_options);
+            p.ssh_hosts().into_iter().map(move |(host, _)| (host, route.clone()))
+        })
+        .collect();
+    routes.sort_unstable();
+    // Routes that only differ in their jump hosts share an entry
+    routes.dedup_by(|(host, route), (kept_host, kept)| host == kept_host && route.port == kept.port);
+    for (host, route) in &routes {
+        let entry = route.entry(host);
+        if known_hosts::has_entry(&known_hosts, &entry) {
+            continue;
+        }
+        if !fix {
+            problems.push(format!(
+                "{} has no host key for {}; the first connection will ask to trust it ('{} doctor --fix' adds it)",
+                known_hosts.display(),
+                entry,
+                program_name()
+            ));
+            continue;
+        }
+        match known_hosts::seed(&known_hosts, host, route) {
+            Ok(known_hosts::Seeded::Added { keys, verified }) => {
+                let how = if verified { "matching the published fingerprints" } else { "not verified, trusted on first use" };
+                println!("{} Added {} host key(s) for {} to {} ({})", "✓".green(), keys, entry, known_hosts.display(), how);
+            }
+            Ok(known_hosts::Seeded::Mismatch { fingerprint }) => problems.push(format!(
+                "{} offered host key {}, which isn't one it publishes; nothing was added to {}",
+                host,
+                fingerprint,
+                known_hosts.display()
+            )),
+            Err(err) => problems.push(format!("Couldn't add host keys for {}: {:#}", entry, err)),
+        }
+    }
+
+    if problems.is_empty() {
+        println!("{} No problems found.", "✓".green());
+        return Ok(());
+    }
+
+    for problem in &problems {
+        println!("{} {}", "✗".red(), problem);
+    }
+    println!();
+    println!("{} problem(s) found.", problems.len());
+    Ok(())
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+enum Severity {
+    Error,
+    Warning,
+}
+
+/// A problem found by `gs check`.
+#[derive(Debug, Serialize)]
+struct Finding {
+    severity: Severity,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    profile: Option<String>,
+    message: String,
+}
+
+/// Validate a profiles file (the live one by default) without touching git,
+/// SSH or anything else. Unlike `gs doctor` this runs no programs, so it's
+/// safe in CI and pre-commit hooks.
+fn check_config(file: Option<&Path>, format: Option<FileFormat>, no_fs: bool, json: bool) -> Result<()> {
+    let (path, format) = match file {
+        Some(path) => (path.to_path_buf(), FileFormat::resolve(format, Some(path))?),
+        None => (config_path_location()?, format.unwrap_or(FileFormat::Json)),
+    };
+    if file.is_none() && !path.exists() {
+        return Err(no_profiles());
+    }
+
+    let data = fs::read_to_string(&path).with_context(|| format!("Failed to read {}", path.display()))?;
+    let findings = check_profiles_data(&data, format, no_fs);
+    let errors = findings.iter().filter(|f| f.severity == Severity::Error).count();
+
+    if json {
+        println!("{}", serde_json::to_string_pretty(&serde_json::json!({ "path": path, "findings": findings }))?);
+    } else if findings.is_empty() {
+        println!("{} {} is valid.", "✓".green(), path.display());
+    } else {
+        for finding in &findings {
+            let label = match finding.severity {
+                Severity::Error => "error".red(),
+                Severity::Warning => "warning".yellow(),
+            };
+            match &finding.profile {
+                Some(name) => println!("{}: profile '{}': {}", label, name, finding.message),
+                None => println!("{}: {}", label, finding.message),
+            }
+        }
+        println!();
+        println!("{} error(s), {} warning(s).", errors, findings.len() - errors);
+    }
+
+    if errors > 0 {
+        return Err(Failure::new(ExitCode::Error, format!("{} is not valid", path.display())).path(path).into());
+    }
+    Ok(())
+}
+
+fn check_profiles_data(data: &str, format: FileFormat, no_fs: bool) -> Vec<Finding> {
+    let mut findings = Vec::new();
+    let mut report = |severity, profile: Option<&str>, message: String| {
+        findings.push(Finding { severity, profile: profile.map(str::to_string), message })
+    };
+
+    let parsed: Result<serde_json::Value> = match format {
+        FileFormat::Json => serde_json::from_str(data).map_err(Into::into),
+        FileFormat::Yaml => serde_yaml::from_str(data).map_err(Into::into),
+    };
+    let value = match parsed {
+        Ok(value) => value,
+        Err(err) => {
+            report(Severity::Error, None, format!("Not valid {}: {}", if format == FileFormat::Json { "JSON" } else { "YAML" }, err));
+            return findings;
+        }
+    };
+
+    // Check the version first: a newer format may not parse as this one
+    if let Some(version) = value.get("version") {
+        match version.as_u64() {
+            Some(v) if (1..=CONFIG_VERSION as u64).contains(&v) => {}
+            _ => {
+                report(
+                    Severity::Error,
+                    None,
+                    format!("Unsupported version {} (this gs understands up to {})", version, CONFIG_VERSION),
+                );
+                return findings;
+            }
+        }
+    }
+
+    let config: Config = match serde_json::from_value(value) {
+        Ok(config) => config,
+        Err(err) => {
+            report(Severity::Error, None, format!("Not a valid profiles file: {}", err));
+            return findings;
+        }
+    };
+
+    for (i, profile) in config.profiles.iter().enumerate() {
+        let name = Some(profile.name.as_str());
+        if profile.name.trim().is_empty() {
+            report(Severity::Error, None, format!("Profile {} has an empty name", i + 1));
+        } else if let Some(problem) = name_problem(&profile.name) {
+            report(Severity::Warning, name, format!("Invalid name: {}", problem));
+        }
+        if !is_valid_email(&profile.email) {
+            report(Severity::Error, name, format!("'{}' is not a valid email address", profile.email));
+        }
+        for option in &profile.ssh_options {
+            if let Some(problem) = ssh_option_problem(option) {
+                report(Severity::Error, name, format!("Invalid SSH option '{}': {}", option, problem));
+            }
+        }
+        // No key is a profile using the agent's keys, which can't sign
+        if profile.signing && profile.ssh_key.is_empty() {
+            report(Severity::Error, name, "Signs commits but has no SSH key file to sign with".to_string());
+        }
+        if !no_fs {
+            for (host, key) in profile.ssh_hosts() {
+                if !key.is_empty() && !expand_path(Path::new(key)).exists() {
+                    report(Severity::Error, name, format!("SSH key for {} not found at {}", host, key));
+                }
+            }
+        }
+
+        // Only look forward so each pair is reported once
+        for other in &config.profiles[i + 1..] {
+            if other.name == profile.name {
+                report(Severity::Error, name, "Another profile has the same name".to_string());
+            } else if other.name.eq_ignore_ascii_case(&profile.name) {
+                report(Severity::Warning, name, format!("Differs from '{}' only in case", other.name));
+            }
+            if other.email.eq_ignore_ascii_case(&profile.email) {
+                report(Severity::Warning, name, format!("Shares the email {} with '{}'", profile.email, other.name));
+            }
+            let shared = match no_fs {
+                true => expand_path(Path::new(&other.ssh_key)) == expand_path(Path::new(&profile.ssh_key)),
+                false => same_key(&other.ssh_key, &profile.ssh_key),
+            };
+            if !profile.ssh_key.is_empty() && shared {
+                report(Severity::Warning, name, format!("Shares the SSH key {} with '{}'", profile.ssh_key, other.name));
+            }
+        }
+    }
+
+    let current: Vec<&str> = config.profiles.iter().filter(|p| p.current).map(|p| p.name.as_str()).collect();
+    if current.len() > 1 {
+        report(Severity::Error, None, format!("More than one profile is marked current: {}", current.join(", ")));
+    }
+
+    findings
+}
+
+/// Loose sanity check: one `@` with something on both sides and no spaces.
+fn is_valid_email(email: &str) -> bool {
+    match email.split_once('@') {
+        Some((local, domain)) => {
+            !local.is_empty() && !domain.is_empty() && !domain.contains('@') && !email.contains(char::is_whitespace)
+        }
+        None => false,
+    }
+}
+
+fn show_status() -> Result<()> {
+    let config = load_config()?;
+    match active_profile(&config) {
+        Some((profile, source)) => {
+            let marker = if source == ActiveSource::Env {
+                format!(" (from {})", PROFILE_ENV)
+            } else {
+                String::new()
+            };
+            println!(
+                "Active profile: {}{} <{}>",
+                profile.name.bright_green(),
+                marker,
+                profile.email
+            );
+        }
+        None => println!("Active profile: none"),
+    }
+
+    let in_repo = ensure_git_repo().is_ok();
+    let mut scopes = vec![("global", "--global")];
+    if in_repo {
+        scopes.push(("local", "--local"));
+        if worktree_config_enabled() {
+            scopes.push(("worktree", "--worktree"));
+        }
+    }
+
+    for key in ["user.name", "user.email"] {
+        let effective = read_git_config(&[], key);
+        println!();
+        println!("{}: {}", key, effective.as_deref().unwrap_or("(unset)").bold());
+
+        // Later scopes override earlier ones
+        let mut winner = None;
+        for (scope, _) in &scopes {
+            if read_git_config(&[&format!("--{}", scope)], key).is_some() {
+                winner = Some(*scope);
+            }
+        }
+
+        for (scope, flag) in &scopes {
+            let value = read_git_config(&[flag], key);
+            let marker = if winner == Some(*scope) { " (in effect)".green().to_string() } else { String::new() };
+            println!(
+                "  {:<9} {}{}",
+                scope,
+                value.as_deref().unwrap_or("-"),
+                marker
+            );
+        }
+    }
+
+    if !in_repo {
+        println!();
+        println!("{}", "Not inside a git repository; only global config applies here.".dimmed());
+    }
+    Ok(())
+}
+
+fn last_commit_author() -> Result<String> {
+    let output = process::Command::new("git")
+        .args(["log", "-1", "--format=%an <%ae> (committer: %cn <%ce>)"])
+        .logged_output()
+        .external("Failed to run git log")?;
+    if !output.status.success() {
+        return Err(git_failed("git log", &output));
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).trim().to_string())
+}
+
+fn ensure_git_repo() -> Result<()> {
+    let inside_repo = process::Command::new("git")
+        .args(["rev-parse", "--is-inside-work-tree"])
+        .logged_output()
+        .external("Failed to run git")?;
+    if !inside_repo.status.success() {
+        return Err(anyhow!("Not inside a git repository"));
+    }
+    Ok(())
+}
+
+/// Where the active profile came from.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum ActiveSource {
+    Stored,
+    Env,
+}
+
+/// The profile reads should report: the one named in $GS_PROFILE if set and
+/// known, otherwise the one persisted as current. Never writes anything.
+fn active_profile(config: &Config) -> Option<(&Profile, ActiveSource)> {
+    if let Ok(name) = std::env::var(PROFILE_ENV)
+        && !name.is_empty()
+    {
+        match config.profiles.iter().find(|p| p.name == name) {
+            Some(profile) => return Some((profile, ActiveSource::Env)),
+            None => eprintln!(
+                "{} {} is set to unknown profile '{}'; using the stored current profile",
+                "Warning:".yellow(),
+                PROFILE_ENV,
+                name
+            ),
+        }
+    }
+
+    config
+        .profiles
+        .iter()
+        .find(|p| p.current)
+        .map(|p| (p, ActiveSource::Stored))
+}
+
+/// state.json when it alone can answer "which profile is current": it
+/// exists and GS_PROFILE isn't overriding it. Lets `gs current` and
+/// `gs prompt` skip parsing profiles.json.
+fn stored_state() -> Result<Option<State>> {
+    if std::env::var_os(PROFILE_ENV).is_some_and(|v| !v.is_empty()) {
+        return Ok(None);
+    }
+    load_state()
+}
+
+fn show_current(format: Option<&str>) -> Result<()> {
+    if let Some(format) = format {
+        let config = load_config()?;
+        match active_profile(&config) {
+            Some((profile, _)) => print!("{}", format_profile(format, profile, &profile.name)?),
+            None => eprintln!("No active profile."),
+        }
+        return Ok(());
+    }
+
+    if let Some(state) = stored_state()? {
+        match state.current {
+            Some(name) => println!("{}", name),
+            None => eprintln!("No active profile."),
+        }
+        return Ok(());
+    }
+
+    let config = load_config()?;
+    match active_profile(&config) {
+        Some((profile, ActiveSource::Env)) => {
+            println!("{}", profile.name);
+            eprintln!("{}", format!("(from {})", PROFILE_ENV).dimmed());
+        }
+        Some((profile, ActiveSource::Stored)) => println!("{}", profile.name),
+        None => eprintln!("No active profile."),
+    }
+    Ok(())
+}
+
+fn print_env(name: Option<&str>) -> Result<()> {
+    let config = load_config()?;
+    let (profile, source) = match name {
+        Some(name) => (&config.profiles[resolve_profile(&config, name)?], ActiveSource::Stored),
+        None => active_profile(&config).ok_or_else(|| failure(ExitCode::NotFound, "No active profile"))?,
+    };
+
+    if source == ActiveSource::Env {
+        println!("# {} (from {})", profile.name, PROFILE_ENV);
+    }
+    for line in env_exports(profile)? {
+        println!("{}", line);
+    }
+    Ok(())
+}
+
+/// `export` lines that make git commit and connect as `profile`.
+fn env_exports(profile: &Profile) -> Result<Vec<String>> {
+    Ok(env_vars(profile)?
+        .into_iter()
+        .map(|(var, value)| format!("export {}={}", var, shell_quote(&value)))
+        .collect())
+}
+
+/// The environment that makes git commit and connect as `profile`.
+fn env_vars(profile: &Profile) -> Result<Vec<(&'static str, String)>> {
+    let mut ssh_command = match profile.ssh_key.as_str() {
+        "" => "ssh -o IdentitiesOnly=no".to_string(),
+        key => format!("ssh -i {} -o IdentitiesOnly=yes", shell_quote(&expand_tilde(key)?)),
+    };
+    if let Some(agent) = &profile.identity_agent {
+        ssh_command.push_str(&format!(" -o IdentityAgent={}", shell_quote(&expand_tilde(agent)?)));
+    }
+    Ok(vec![
+        ("GIT_AUTHOR_NAME", profile.git_name().to_string()),
+        ("GIT_COMMITTER_NAME", profile.git_name().to_string()),
+        ("GIT_AUTHOR_EMAIL", profile.email.clone()),
+        ("GIT_COMMITTER_EMAIL", profile.email.clone()),
+        ("GIT_SSH_COMMAND", ssh_command),
+    ])
+}
+
+/// Run one command as `name` through the environment alone, leaving git
+/// config, SSH config and the profiles untouched.
+fn exec_as(name: &str, command: &[String]) -> Result<()> {
+    let config = load_config()?;
+    let profile = env_profile(&config, name)?;
+    let (program, args) = command.split_first().expect("clap requires a command");
+    let mut child = process::Command::new(program);
+    child.args(args).envs(env_vars(profile)?);
+    replace_process(child, program)
+}
+
+/// Start `$SHELL` with a profile's environment. `GS_ACTIVE_PROFILE` marks
+/// the shell for prompts, and `GS_PROFILE` makes `gs current` and friends
+/// inside it report the profile. Exiting the shell is all it takes to go
+/// back.
+fn profile_shell(name: &str) -> Result<()> {
+    let config = load_config()?;
+    let profile = env_profile(&config, name)?;
+    if let Ok(active) = std::env::var(ACTIVE_PROFILE_ENV)
+        && !active.is_empty()
+    {
+        eprintln!("{} already in a gs shell for '{}'", "Warning:".yellow(), active);
+    }
+
+    let shell = match std::env::var("SHELL") {
+        Ok(shell) if !shell.is_empty() => shell,
+        _ if cfg!(windows) => std::env::var("COMSPEC").unwrap_or_else(|_| "cmd.exe".to_string()),
+        _ => "/bin/sh".to_string(),
+    };
+    let mut child = process::Command::new(&shell);
+    child
+        .envs(env_vars(profile)?)
+        .env(ACTIVE_PROFILE_ENV, &profile.name)
+        .env(PROFILE_ENV, &profile.name);
+    eprintln!(
+        "Starting {} as {} ({}). Exit the shell to go back.",
+        shell,
+        profile.styled_name(),
+        profile.email
+    );
+    replace_process(child, &shell)
+}
+
+/// The profile `gs exec` and `gs shell` run as. Its key has to exist, since
+/// GIT_SSH_COMMAND names it directly, unless it uses the agent's keys.
+fn env_profile<'a>(config: &'a Config, name: &str) -> Result<&'a Profile> {
+    let profile = &config.profiles[resolve_profile(config, name)?];
+    let key = expand_tilde(&profile.ssh_key)?;
+    if !key.is_empty() && !Path::new(&key).exists() {
+        return Err(failure(
+            ExitCode::NotFound,
+            format!("SSH key for '{}' not found at: {}", profile.name, key),
+        ));
+    }
+    Ok(profile)
+}
+
+/// Run `child` in place of gs, so its exit status and signals are gs's.
+fn replace_process(mut child: process::Command, program: &str) -> Result<()> {
+    tracing::debug!("exec {}", trace::describe(&child));
+    #[cfg(unix)]
+    {
+        use std::os::unix::process::CommandExt;
+        // Only returns on failure
+        let err = child.exec();
+        Err(failure(ExitCode::External, format!("Failed to run {}: {}", program, err)))
+    }
+    #[cfg(not(unix))]
+    {
+        let status = child.logged_status().external(&format!("Failed to run {}", program))?;
+        process::exit(status.code().unwrap_or(1));
+    }
+}
+
+const ENVRC_FILE: &str = ".envrc";
+const ENVRC_BEGIN: &str = "# >>> gs profile ";
+const ENVRC_END: &str = "# <<< gs profile <<<";
+
+/// The `.envrc` snippet for a profile, between markers so `--update` can
+/// find it again.
+fn direnv_snippet(profile: &Profile) -> Result<String> {
+    let mut snippet = format!("{}{} >>>\n", ENVRC_BEGIN, profile.name);
+    for line in env_exports(profile)? {
+        snippet.push_str(&line);
+        snippet.push('\n');
+    }
+    snippet.push_str(ENVRC_END);
+    snippet.push('\n');
+    Ok(snippet)
+}
+
+/// The byte range of the gs region in an `.envrc`, and the profile it's for.
+fn find_direnv_region(content: &str) -> Option<(std::ops::Range<usize>, String)> {
+    let start = content.find(ENVRC_BEGIN)?;
+    let name_start = start + ENVRC_BEGIN.len();
+    let name_end = name_start + content[name_start..].find(" >>>")?;
+    let end_marker = name_end + content[name_end..].find(ENVRC_END)?;
+    let mut end = end_marker + ENVRC_END.len();
+    if content[end..].starts_with('\n') {
+        end += 1;
+    }
+    Some((start..end, content[name_start..name_end].to_string()))
+}
+
+/// Print a direnv snippet for a profile, add it to ./.envrc, or refresh the
+/// one already there after the profile changed.
+fn direnv(name: Option<&str>, write: bool, update: bool) -> Result<()> {
+    let config = load_config()?;
+    let path = Path::new(ENVRC_FILE);
+
+    if update {
+        let content = fs::read_to_string(path).with_context(|| format!("Failed to read {}", ENVRC_FILE))?;
+        let Some((range, name)) = find_direnv_region(&content) else {
+            return Err(failure(ExitCode::NotFound, format!("No gs section in {}", ENVRC_FILE)));
+        };
+        let Some(profile) = config.profiles.iter().find(|p| p.name == name) else {
+            return Err(failure(
+                ExitCode::NotFound,
+                format!("{} is for profile '{}', which no longer exists", ENVRC_FILE, name),
+            ));
+        };
+        let mut updated = content.clone();
+        updated.replace_range(range, &direnv_snippet(profile)?);
+        if updated == content {
+            eprintln!("{} is already up to date.", ENVRC_FILE);
+            return Ok(());
+        }
+        fs::write(path, updated).with_context(|| format!("Failed to write {}", ENVRC_FILE))?;
+        eprintln!("Updated the '{}' section of {}. Run 'direnv allow' to load it.", name, ENVRC_FILE);
+        return Ok(());
+    }
+
+    let name = name.expect("clap requires a profile without --update");
+    let profile = &config.profiles[resolve_profile(&config, name)?];
+    let snippet = direnv_snippet(profile)?;
+    if !write {
+        print!("{}", snippet);
+        return Ok(());
+    }
+
+    // Replace an existing gs section rather than stacking a second one
+    let mut content = if path.exists() {
+        fs::read_to_string(path).with_context(|| format!("Failed to read {}", ENVRC_FILE))?
+    } else {
+        String::new()
+    };
+    match find_direnv_region(&content) {
+        Some((range, _)) => content.replace_range(range, &snippet),
+        None => {
+            if !content.is_empty() && !content.ends_with('\n') {
+                content.push('\n');
+            }
+            content.push_str(&snippet);
+        }
+    }
+    fs::write(path, content).with_context(|| format!("Failed to write {}", ENVRC_FILE))?;
+    eprintln!("Wrote profile '{}' to {}. Run 'direnv allow' to load it.", profile.name, ENVRC_FILE);
+    Ok(())
+}
+
+const ZSH_HOOK: &str = r#"_gs_autoswitch() { @GS@ autoswitch --dir "$PWD"; }
+autoload -Uz add-zsh-hook
+add-zsh-hook chpwd _gs_autoswitch
+_gs_autoswitch"#;
+
+// bash has no chpwd hook, so check for a change before each prompt
+const BASH_HOOK: &str = r#"_gs_autoswitch() {
+  if [[ "$PWD" != "${_GS_LAST_DIR-}" ]]; then
+    _GS_LAST_DIR="$PWD"
+    @GS@ autoswitch --dir "$PWD"
+  fi
+}
+PROMPT_COMMAND="_gs_autoswitch${PROMPT_COMMAND:+;$PROMPT_COMMAND}""#;
+
+const FISH_HOOK: &str = r#"function __gs_autoswitch --on-variable PWD
+    @GS@ autoswitch --dir "$PWD"
+end
+__gs_autoswitch"#;
+
+/// Print a shell hook that runs `gs autoswitch` whenever the directory
+/// changes.
+fn print_hook(shell: HookShell) -> Result<()> {
+    let exe = std::env::current_exe().context("Failed to locate the gs executable")?;
+    let gs = shell_quote(path_text(&exe)?);
+    let hook = match shell {
+        HookShell::Zsh => ZSH_HOOK,
+        HookShell::Bash => BASH_HOOK,
+        HookShell::Fish => FISH_HOOK,
+    };
+    println!("{}", hook.replace("@GS@", &gs));
+    Ok(())
+}
+
+/// Switch to the profile mapped to `dir` in the `auto_switch` setting.
+/// This runs on every cd, so outside mapped directories it only loads the
+/// settings, and inside them it loads the profiles (with profiles.d and
+/// state.json) to find out whether a switch is needed at all.
+fn auto_switch(dir: &Path) -> Result<()> {
+    let settings = load_settings()?;
+    let Some(name) = settings
+        .auto_switch
+        .iter()
+        .map(|(mapped, name)| (expand_path(Path::new(mapped)), name))
+        .filter(|(mapped, _)| dir.starts_with(mapped))
+        .max_by_key(|(mapped, _)| mapped.components().count())
+        .map(|(_, name)| name)
+    else {
+        return Ok(());
+    };
+
+    let mut config = load_config()?;
+    let Some(index) = config.profiles.iter().position(|p| &p.name == name) else {
+        return Err(failure(
+            ExitCode::NotFound,
+            format!("auto_switch maps {} to '{}', which doesn't exist", dir.display(), name),
+        ));
+    };
+    if config.profiles[index].current {
+        return Ok(());
+    }
+
+    let profile = activate_profile(&mut config, index, &ApplyScope::default())?;
+    eprintln!("gs: switched to {} <{}>", profile.name.bright_green(), profile.email);
+    Ok(())
+}
+
+/// Prompt segments run on every prompt, so this only reads state.json, or
+/// profiles.json too when a format needs the profile's details.
+/// Without a template it prints the bare name; with none active, nothing,
+/// so the segment collapses.
+fn print_prompt(format: Option<&str>, plain: bool) -> Result<()> {
+    if format.is_none()
+        && let Some(state) = stored_state()?
+    {
+        if let Some(name) = state.current {
+            println!("{}", name);
+        }
+        return Ok(());
+    }
+
+    let config = load_config()?;
+    let Some((profile, source)) = active_profile(&config) else {
+        return Ok(());
+    };
+    let name = match source {
+        ActiveSource::Env => format!("{}*", profile.name),
+        ActiveSource::Stored => profile.name.clone(),
+    };
+
+    let Some(format) = format else {
+        println!("{}", name);
+        return Ok(());
+    };
+    let template = match format {
+        "starship" | "p10k" => "{icon} {name}",
+        template => template,
+    };
+    let segment = format_profile(template, profile, &name)?;
+    let segment = segment.trim();
+
+    match profile.color.as_deref().and_then(parse_color) {
+        // The prompt captures our stdout, so colored would otherwise turn
+        // itself off
+        Some(color) if !plain && !options().no_color && std::env::var_os("NO_COLOR").is_none() => {
+            colored::control::set_override(true);
+            println!("{}", segment.color(color));
+        }
+        _ => println!("{}", segment),
+    }
+    Ok(())
+}
+
+const FORMAT_PLACEHOLDERS: [&str; 8] = ["name", "git_name", "email", "ssh_key", "host", "current", "icon", "group"];
+
+/// Fill a `--format` template for one profile, ending in a newline unless
+/// it already does. `\t`, `\n` and `\\` are escapes and `{{`/`}}` are
+/// literal braces; `{current}` is `*` for the current profile and empty
+/// otherwise. `name` is the name to show, which `gs prompt` marks when it
+/// comes from the environment.
+fn format_profile(template: &str, profile: &Profile, name: &str) -> Result<String> {
+    let mut out = String::new();
+    let mut chars = template.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => match chars.next() {
+                Some('t') => out.push('\t'),
+                Some('n') => out.push('\n'),
+                Some('\\') => out.push('\\'),
+                Some(other) => {
+                    out.push('\\');
+                    out.push(other);
+                }
+                None => out.push('\\'),
+            },
+            '{' if chars.peek() == Some(&'{') => {
+                chars.next();
+                out.push('{');
+            }
+            '}' if chars.peek() == Some(&'}') => {
+                chars.next();
+                out.push('}');
+            }
+            '{' => {
+                let placeholder: String = chars.by_ref().take_while(|&c| c != '}').collect();
+                let value = match placeholder.as_str() {
+                    "name" => name,
+                    "git_name" => profile.git_name(),
+                    "email" => &profile.email,
+                    "ssh_key" => &profile.ssh_key,
+                    "host" => profile.primary_host(),
+                    "current" => if profile.current { "*" } else { "" },
+                    "icon" => profile.icon.as_deref().unwrap_or(""),
+                    "group" => profile.group.as_deref().unwrap_or(""),
+                    _ => {
+                        return Err(failure(
+                            ExitCode::Usage,
+                            format!(
+                                "Unknown placeholder '{{{}}}' in format. Valid placeholders: {}",
+                                placeholder,
+                                FORMAT_PLACEHOLDERS.map(|p| format!("{{{}}}", p)).join(", ")
+                            ),
+                        ));
+                    }
+                };
+                out.push_str(value);
+            }
+            c => out.push(c),
+        }
+    }
+    if !out.ends_with('\n') {
+        out.push('\n');
+    }
+    Ok(out)
+}
+
+/// Quote a value for POSIX shells.
+fn shell_quote(value: &str) -> String {
+    format!("'{}'", value.replace('\'', "'\\''"))
+}
+
+/// Point git and SSH at a profile.
+fn apply_profile_scoped(profile: &Profile, scope: &ApplyScope) -> Result<()> {
+    run_apply(&plan_apply(profile, scope)?)
+}
+
+/// Everything applying a profile will change, worked out and confirmed
+/// before anything is written. Flows that also save profiles.json plan
+/// first, save, then run the plan, so aborting at any prompt leaves git,
+/// SSH and the profiles exactly as they were.
+struct ApplyPlan {
+    profile: Profile,
+    ssh_edits: Vec<SshEdit>,
+    git: bool,
+}
+
+/// The new contents of one SSH config file. `old` is None when the file
+/// doesn't exist yet.
+struct SshEdit {
+    path: PathBuf,
+    hosts: Vec<String>,
+    old: Option<String>,
+    new: String,
+}
+
+/// Work out the SSH edits for a profile and ask for any confirmation they
+/// need. Declining is an Aborted error.
+fn plan_apply(profile: &Profile, scope: &ApplyScope) -> Result<ApplyPlan> {
+    if let Ok(name) = std::env::var(PROFILE_ENV)
+        && !name.is_empty()
+    {
+        eprintln!(
+            "{} {}={} masks the stored profile in this shell; reads will keep reporting '{}'",
+            "Warning:".yellow(),
+            PROFILE_ENV,
+            name,
+            name
+        );
+    }
+
+    let mut hosts = profile.ssh_hosts();
+    if let Some(only) = &scope.hosts {
+        for host in only {
+            if !hosts.iter().any(|(h, _)| h == host) {
+                return Err(failure(
+                    ExitCode::NotFound,
+                    format!("Host '{}' is not configured on profile '{}'", host, profile.name),
+                ));
+            }
+        }
+        hosts.retain(|(h, _)| only.iter().any(|o| o == h));
+    }
+
+    let mut ssh_edits = Vec::new();
+    for (host, key) in hosts {
+        plan_ssh_edit(&mut ssh_edits, host, &expand_tilde(key)?, profile.ssh_user(host), &profile.block_options(key))?;
+    }
+    ssh_edits.retain(|edit| {
+        let changed = edit.old.as_deref() != Some(edit.new.as_str());
+        if !changed {
+            tracing::debug!("{} already up to date", edit.path.display());
+        }
+        changed
+    });
+    if !ssh_edits.is_empty() && !options().dry_run {
+        confirm_first_ssh_edit(&ssh_edits)?;
+    }
+    // A dry run prints the diffs instead
+    for edit in ssh_edits.iter().filter(|_| !options().dry_run) {
+        if !review_ssh_change(&edit.path, edit.old.as_deref(), &edit.new)? {
+            return Err(failure(ExitCode::Aborted, "Aborted: SSH config was left unchanged"));
+        }
+    }
+    if !options().dry_run {
+        preflight(&ssh_edits, !scope.ssh_only)?;
+    }
+
+    Ok(ApplyPlan { profile: profile.clone(), ssh_edits, git: !scope.ssh_only })
+}
+
+/// Check that every file applying a plan and saving the profiles writes to
+/// can be written, so a permission problem stops gs before anything has
+/// changed rather than halfway through.
+fn preflight(ssh_edits: &[SshEdit], git: bool) -> Result<()> {
+    check_writable(&get_config_path()?)?;
+    check_writable(&state_path()?)?;
+    for edit in ssh_edits {
+        check_writable(&edit.path)?;
+    }
+    // git replaces its config through a lock file next to it, so it's the
+    // directory that has to be writable
+    if git
+        && let (Some(git_config), _) = resolve_git_global_config()
+        && let Some(dir) = git_config.parent().filter(|dir| dir.is_dir())
+    {
+        check_dir_writable(dir)?;
+    }
+    Ok(())
+}
+
+/// Fail unless `path` can be written: opened for writing if it exists,
+/// created in its directory if it doesn't. Nothing is changed either way.
+fn check_writable(path: &Path) -> Result<()> {
+    if path.is_dir() {
+        return Err(error::file_error(io::ErrorKind::IsADirectory.into(), "write", path));
+    }
+    if path.exists() {
+        fs::OpenOptions::new().append(true).open(path).file_context("write", path)?;
+        return Ok(());
+    }
+    match path.parent() {
+        Some(dir) if dir.is_dir() => check_dir_writable(dir),
+        _ => Ok(()),
+    }
+}
+
+fn check_dir_writable(dir: &Path) -> Result<()> {
+    let probe = dir.join(format!(".gs-preflight-{}", process::id()));
+    fs::OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .open(&probe)
+        .file_context("create files in", dir)?;
+    let _ = fs::remove_file(&probe);
+    Ok(())
+}
+
+/// Make the changes in a plan: SSH first, then git. If a step fails, the
+/// ones before it are undone, so the switch either happens or it doesn't.
+fn run_apply(plan: &ApplyPlan) -> Result<()> {
+    apply_with(plan, write_ssh_config)
+}
+
+/// Apply `plan`, if there is one, then save `config`. A failed apply has
+/// already put git and SSH back, and nothing is saved, so profiles.json and
+/// state.json never name a profile that didn't go live.
+fn apply_and_save(config: &Config, plan: Option<&ApplyPlan>) -> Result<()> {
+    let _critical = prompt::critical();
+    if let Some(plan) = plan {
+        run_apply(plan)?;
+    }
+    save_config(config)
+}
+
+/// `run_apply` with the function that writes SSH config files passed in,
+/// so tests can make a write fail.
+fn apply_with(plan: &ApplyPlan, write_ssh: impl Fn(&Path, &str) -> Result<()>) -> Result<()> {
+    if options().dry_run {
+        for edit in &plan.ssh_edits {
+            print_diff(&edit.path, edit.old.as_deref(), &edit.new);
+        }
+        if plan.git {
+            eprintln!(
+                "{} global git identity to {} <{}>",
+                "Would set".yellow(),
+                plan.profile.name,
+                plan.profile.email
+            );
+        }
+        return Ok(());
+    }
+
+    let _critical = prompt::critical();
+    let git_before = plan.git.then(global_settings).transpose()?;
+    let rewrites_before = plan.git.then(load_managed_rewrites).transpose()?;
+    for (i, edit) in plan.ssh_edits.iter().enumerate() {
+        if let Err(err) = write_ssh(&edit.path, &edit.new) {
+            restore_ssh_edits(&plan.ssh_edits[..i]);
+            return Err(err);
+        }
+        audit(AuditChange::Ssh {
+            path: edit.path.clone(),
+            hosts: edit.hosts.clone(),
+            lines: changed_lines(edit.old.as_deref().unwrap_or(""), &edit.new),
+            restored: false,
+        });
+    }
+    if let Some(before) = &git_before
+        && let Err(err) = update_git_config(&plan.profile, before)
+    {
+        restore_ssh_edits(&plan.ssh_edits);
+        return Err(err.context("Failed to update git config"));
+    }
+    // The switch itself went through either way
+    if let Err(err) = save_snapshot(plan, git_before.as_ref(), rewrites_before) {
+        eprintln!("{} couldn't save the state for `gs rollback`: {:#}", "Warning:".yellow(), err);
+    }
+    Ok(())
+}
+
+/// What an apply changed outside gs, as it was before, for `gs rollback`.
+#[derive(Debug, Serialize, Deserialize)]
+struct Snapshot {
+    taken_at: chrono::DateTime<chrono::Local>,
+    /// The profile that was applied.
+    profile: String,
+    /// Global git settings the apply changed, by lowercased name, with
+    /// their values before it. No values means the key wasn't set.
+    #[serde(default)]
+    git: BTreeMap<String, Vec<String>>,
+    #[serde(default)]
+    ssh: Vec<SshSnapshot>,
+    /// The insteadOf rules gs managed before the apply. None in snapshots
+    /// from before it was recorded.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    managed_rewrites: Option<Vec<UrlRewrite>>,
+}
+
+/// One SSH config file before and after an apply. `before` is None when
+/// the apply created it.
+#[derive(Debug, Serialize, Deserialize)]
+struct SshSnapshot {
+    path: PathBuf,
+    before: Option<String>,
+    after: String,
+}
+
+/// Record what `plan` changed, replacing the last snapshot. An apply that
+/// changed nothing leaves the last one in place, so reapplying the current
+/// profile doesn't lose the way back.
+fn save_snapshot(
+    plan: &ApplyPlan,
+    git_before: Option<&BTreeMap<String, Vec<String>>>,
+    managed_rewrites: Option<Vec<UrlRewrite>>,
+) -> Result<()> {
+    let mut git = BTreeMap::new();
+    if let Some(before) = git_before {
+        let after = global_settings()?;
+        for key in before.keys().chain(after.keys()) {
+            let old = before.get(key).cloned().unwrap_or_default();
+            if after.get(key).cloned().unwrap_or_default() != old {
+                git.insert(key.clone(), old);
+            }
+        }
+    }
+    if git.is_empty() && plan.ssh_edits.is_empty() {
+        tracing::debug!("nothing changed, keeping the previous rollback snapshot");
+        return Ok(());
+    }
+
+    let snapshot = Snapshot {
+        taken_at: chrono::Local::now(),
+        profile: plan.profile.name.clone(),
+        git,
+        ssh: plan
+            .ssh_edits
+            .iter()
+            .map(|edit| SshSnapshot { path: edit.path.clone(), before: edit.old.clone(), after: edit.new.clone() })
+            .collect(),
+        managed_rewrites,
+    };
+    let path = rollback_path()?;
+    tracing::debug!("write {}", path.display());
+    fs::write(&path, serde_json::to_string_pretty(&snapshot)?).file_context("write", &path)
+}
+
+/// Undo the last apply outside gs: restore the global git settings and SSH
+/// config files it changed, then mark no profile current, since the
+/// machine no longer matches any. The snapshot is used up.
+fn rollback() -> Result<()> {
+    let path = rollback_path()?;
+    if !path.exists() {
+        return Err(failure(
+            ExitCode::NotFound,
+            "Nothing to roll back: gs records the state before each switch, and there's been no switch since the last rollback",
+        ));
+    }
+    tracing::debug!("read {}", path.display());
+    let data = fs::read_to_string(&path).file_context("read", &path)?;
+    let snapshot: Snapshot = serde_json::from_str(&data).with_context(|| format!("Failed to parse {}", path.display()))?;
+
+    // Restoring a file edited since would throw those edits away
+    let edited: Vec<String> = snapshot
+        .ssh
+        .iter()
+        .filter(|ssh| fs::read_to_string(&ssh.path).ok().as_deref() != Some(ssh.after.as_str()))
+        .map(|ssh| ssh.path.display().to_string())
+        .collect();
+    if !edited.is_empty() && !options().dry_run {
+        eprintln!("{} changed since the switch, and rolling back loses those edits.", edited.join(", "));
+        if !confirm_destructive("Roll back anyway?")? {
+            return Err(failure(ExitCode::Aborted, "Aborted"));
+        }
+    }
+
+    let when = snapshot.taken_at.format("%Y-%m-%d %H:%M");
+    if options().dry_run {
+        for ssh in &snapshot.ssh {
+            let current = fs::read_to_string(&ssh.path).ok();
+            print_diff(&ssh.path, current.as_deref(), ssh.before.as_deref().unwrap_or(""));
+        }
+        for (key, values) in &snapshot.git {
+            match values.is_empty() {
+                true => eprintln!("{} {}", "Would unset".yellow(), key),
+                false => eprintln!("{} {} to {}", "Would set".yellow(), key, values.join(", ")),
+            }
+        }
+        eprintln!("Would roll back to before the switch to '{}' at {}", snapshot.profile, when);
+        return Ok(());
+    }
+
+    let _critical = prompt::critical();
+    for ssh in &snapshot.ssh {
+        let current = fs::read_to_string(&ssh.path).unwrap_or_default();
+        match &ssh.before {
+            Some(before) => write_ssh_config(&ssh.path, before)?,
+            None if ssh.path.exists() => {
+                tracing::debug!("remove {}", ssh.path.display());
+                fs::remove_file(&ssh.path).file_context("remove", &ssh.path)?;
+            }
+            None => {}
+        }
+        audit(AuditChange::Ssh {
+            path: ssh.path.clone(),
+            hosts: Vec::new(),
+            lines: changed_lines(&current, ssh.before.as_deref().unwrap_or("")),
+            restored: true,
+        });
+    }
+
+    let now = global_settings()?;
+    let mut edits = GlobalEdits::default();
+    for (key, values) in &snapshot.git {
+        let existing = now.get(key).cloned().unwrap_or_default();
+        if &existing != values {
+            edits.push(
+                git_config::Edit::Replace { key: key.clone(), values: values.clone() },
+                AuditChange::Git {
+                    key: key.clone(),
+                    old: (!existing.is_empty()).then(|| existing.join(", ")),
+                    new: (!values.is_empty()).then(|| values.join(", ")),
+                },
+            );
+        }
+    }
+    edits.write()?;
+    // The rules just put back are gs's to remove again, and the ones just
+    // taken out aren't anymore. Older snapshots only have the rules that
+    // are still there to go by
+    let managed = match &snapshot.managed_rewrites {
+        Some(managed) => managed.clone(),
+        None => {
+            let mut managed = load_managed_rewrites()?;
+            let now = global_settings()?;
+            managed.retain(|rule| now.get(&rule.key().to_lowercase()).is_some_and(|values| values.contains(&rule.instead_of)));
+            managed
+        }
+    };
+    save_managed_rewrites(&managed)?;
+
+    let mut config = load_config()?;
+    set_current(&mut config, None);
+    save_current(&config)?;
+    tracing::debug!("remove {}", path.display());
+    fs::remove_file(&path).file_context("remove", &path)?;
+
+    eprintln!(
+        "Rolled back to before the switch to '{}' at {}. No profile is current now.",
+        snapshot.profile, when
+    );
+    Ok(())
+}
+
+/// Put SSH config files back the way `plan_apply` found them.
+fn restore_ssh_edits(edits: &[SshEdit]) {
+    for edit in edits {
+        tracing::debug!("restore {}", edit.path.display());
+        let restored = match &edit.old {
+            Some(old) => fs::write(&edit.path, old),
+            None => fs::remove_file(&edit.path),
+        };
+        match restored {
+            Ok(()) => {
+                eprintln!("{} {}", "Restored".yellow(), edit.path.display());
+                audit(AuditChange::Ssh {
+                    path: edit.path.clone(),
+                    hosts: Vec::new(),
+                    lines: edit.old.as_deref().map(|old| changed_lines(&edit.new, old)).unwrap_or_default(),
+                    restored: true,
+                });
+            }
+            Err(err) => eprintln!("{} could not restore {}: {}", "Warning:".yellow(), edit.path.display(), err),
+        }
+    }
+}
+
+/// Point the global git config at `profile`, given the settings `before`
+/// from `global_settings`.
+fn update_git_config(profile: &Profile, before: &BTreeMap<String, Vec<String>>) -> Result<()> {
+    // SSH signing, verified against the allowed_signers file gs maintains
+    let (signing_key, allowed_signers) = match profile.signing {
+        true => (
+            Some(format!("{}.pub", expand_tilde(&profile.ssh_key)?)),
+            Some(path_text(&get_config_dir()?.join(ALLOWED_SIGNERS_FILE))?.to_string()),
+        ),
+        false => (None, None),
+    };
+
+    let changes = [
+        ("user.name", Some(profile.git_name().to_string())),
+        ("user.email", Some(profile.email.clone())),
+        ("commit.template", global_path("commit.template", profile.commit_template.as_deref())?),
+        ("core.excludesFile", global_path("core.excludesFile", profile.excludes_file.as_deref())?),
+        ("init.defaultBranch", profile.default_branch.clone()),
+        ("gpg.format", profile.signing.then(|| "ssh".to_string())),
+        ("user.signingkey", signing_key),
+        ("commit.gpgsign", profile.signing.then(|| "true".to_string())),
+        ("gpg.ssh.allowedSignersFile", allowed_signers),
+    ];
+    // One write for everything, so a failed switch changes nothing
+    let mut edits = GlobalEdits::default();
+    global_edits(before, &changes, &mut edits)?;
+    let managed = rewrite_edits(before, &profile.url_rewrites, &mut edits)?;
+    edits.write()?;
+    match managed {
+        Some(managed) => save_managed_rewrites(&managed),
+        None => Ok(()),
+    }
+}
+
+/// The value for a global git setting that names a file. A file that has
+/// gone missing is applied anyway, with a warning.
+fn global_path(key: &str, path: Option<&Path>) -> Result<Option<String>> {
+    let Some(path) = path else {
+        return Ok(None);
+    };
+    let expanded = expand_tilde(path_text(path)?)?;
+    if !Path::new(&expanded).exists() {
+        eprintln!("{} {} points to {}, which doesn't exist", "Warning:".yellow(), key, expanded);
+    }
+    Ok(Some(expanded))
+}
 
-    match matches.subcommand() {
-        Some(("setup", _)) => setup_flow()?,
-        Some(("list", _)) => list_profiles()?,
-        Some(("edit", _)) => edit_profile()?,
-        Some(("rm", _)) => remove_profile()?,  // Only need one match now
-        None => switch_profile()?,
-        _ => {
-            // For any other command, show our custom help
-            show_help();
-            return Ok(());
+/// Add the edits that bring global git settings in line with `changes`,
+/// unsetting keys whose value is None. `current` comes from a single
+/// `global_settings` call and only keys that differ are edited.
+fn global_edits(current: &BTreeMap<String, Vec<String>>, changes: &[(&str, Option<String>)], edits: &mut GlobalEdits) -> Result<()> {
+    for (key, value) in changes {
+        let existing = current.get(&key.to_lowercase()).map(Vec::as_slice).unwrap_or_default();
+        let unchanged = match value {
+            Some(value) => existing == std::slice::from_ref(value),
+            None => existing.is_empty(),
+        };
+        if unchanged {
+            continue;
         }
+        // As with `git config`, a key set more than once is neither
+        // overwritten nor unset
+        if existing.len() > 1 {
+            match value {
+                Some(_) => {
+                    return Err(failure(
+                        ExitCode::Error,
+                        format!("{} is set {} times in the global git config; remove all but one", key, existing.len()),
+                    ));
+                }
+                None => continue,
+            }
+        }
+        edits.push(
+            git_config::Edit::Replace { key: key.to_string(), values: value.iter().cloned().collect() },
+            AuditChange::Git { key: key.to_string(), old: existing.first().cloned(), new: value.clone() },
+        );
     }
-
     Ok(())
 }
 
-fn get_config_path() -> Result<PathBuf> {
-    let home_dir = dirs::home_dir().ok_or_else(|| anyhow!("Could not find home directory"))?;
-    let config_dir = home_dir.join(CONFIG_DIR);
-    let config_path = config_dir.join(CONFIG_FILE);
+/// Changes to the global git config, each with its audit log entry, to be
+/// written together.
+#[derive(Default)]
+struct GlobalEdits {
+    edits: Vec<git_config::Edit>,
+    changes: Vec<AuditChange>,
+}
 
-    // Create config directory if it doesn't exist
-    if !config_dir.exists() {
-        fs::create_dir_all(&config_dir).context("Failed to create config directory")?;
+impl GlobalEdits {
+    fn push(&mut self, edit: git_config::Edit, change: AuditChange) {
+        self.edits.push(edit);
+        self.changes.push(change);
     }
 
-    Ok(config_path)
+    fn write(self) -> Result<()> {
+        edit_global_config(&self.edits)?;
+        self.changes.into_iter().for_each(audit);
+        Ok(())
+    }
 }
 
-fn load_config() -> Result<Config> {
-    let config_path = get_config_path()?;
+/// Make `edits` to the global git config in a single write. As git does,
+/// the new file is written to `<file>.lock` and renamed into place, so a
+/// git running at the same time makes this fail instead of losing either
+/// change.
+fn edit_global_config(edits: &[git_config::Edit]) -> Result<()> {
+    if edits.is_empty() {
+        return Ok(());
+    }
+    let (Some(path), _) = resolve_git_global_config() else {
+        return Err(anyhow!("Could not find your global git config"));
+    };
+    // A symlinked file is edited where the link points
+    let path = fs::canonicalize(&path).unwrap_or(path);
+    let content = match fs::read_to_string(&path) {
+        Ok(content) => content,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => String::new(),
+        Err(err) => return Err(err).file_context("read", &path),
+    };
+    let updated = git_config::edit(&content, edits);
 
-    // If file doesn't exist, return empty config
-    if !config_path.exists() {
-        return Ok(Config { profiles: vec![] });
+    let mut lock = path.as_os_str().to_owned();
+    lock.push(".lock");
+    let lock = PathBuf::from(lock);
+    let mut file = fs::OpenOptions::new().write(true).create_new(true).open(&lock).map_err(|err| {
+        Failure::new(ExitCode::External, format!("could not lock config file {}: {}", lock.display(), err)).path(&path)
+    })?;
+    tracing::debug!("write {}: {:?}", path.display(), edits);
+    let written = file
+        .write_all(updated.as_bytes())
+        .and_then(|()| match fs::metadata(&path) {
+            Ok(metadata) => fs::set_permissions(&lock, metadata.permissions()),
+            Err(_) => Ok(()),
+        })
+        .and_then(|()| fs::rename(&lock, &path));
+    if written.is_err() {
+        let _ = fs::remove_file(&lock);
     }
+    written.file_context("write", &path)
+}
 
-    let file = File::open(config_path).context("Failed to open config file")?;
-    let reader = BufReader::new(file);
-    let config: Config = serde_json::from_reader(reader).context("Failed to parse config file")?;
+/// Every global git setting, keyed by lowercased name, with all the values
+/// of keys set more than once. Empty when there's no global config yet; a
+/// config git can't read is an error, not an empty one.
+fn global_settings() -> Result<BTreeMap<String, Vec<String>>> {
+    let output = process::Command::new("git")
+        .args(["config", "--global", "--list", "-z"])
+        .logged_output()
+        .external("Failed to run git config")?;
+    let mut settings: BTreeMap<String, Vec<String>> = BTreeMap::new();
+    if !output.status.success() {
+        if resolve_git_global_config().0.is_some_and(|path| !path.exists()) {
+            return Ok(settings);
+        }
+        return Err(git_failed("git config --global --list", &output));
+    }
+    for entry in String::from_utf8_lossy(&output.stdout).split('\0').filter(|e| !e.is_empty()) {
+        // A key without a value (`[section] key`) has no newline
+        let (key, value) = entry.split_once('\n').unwrap_or((entry, ""));
+        settings.entry(key.to_lowercase()).or_default().push(value.to_string());
+    }
+    Ok(settings)
+}
 
-    Ok(config)
+/// The error for a git command that ran but failed, with what git said.
+fn git_failed(command: &str, output: &process::Output) -> anyhow::Error {
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    let reason = match stderr.trim() {
+        "" => format!("exited with {}", output.status),
+        stderr => stderr.to_string(),
+    };
+    failure(ExitCode::External, format!("{} failed: {}", command, reason))
 }
 
-fn save_config(config: &Config) -> Result<()> {
-    let config_path = get_config_path()?;
-    let json_data = serde_json::to_string_pretty(config).context("Failed to serialize config")?;
-    fs::write(config_path, json_data).context("Failed to save config file")?;
-    Ok(())
+/// Make the global insteadOf rules gs manages match `wanted`.
+fn sync_url_rewrites(wanted: &[UrlRewrite]) -> Result<()> {
+    let mut edits = GlobalEdits::default();
+    let Some(managed) = rewrite_edits(&global_settings()?, wanted, &mut edits)? else {
+        return Ok(());
+    };
+    edits.write()?;
+    save_managed_rewrites(&managed)
 }
 
-fn switch_profile() -> Result<()> {
-    let mut config = load_config()?;
+/// Add the edits that make the global insteadOf rules gs manages match
+/// `wanted`, and return the managed list that results, or None when gs
+/// manages none and wants none. Rules gs added earlier but no longer wants
+/// are removed; rules the user wrote themselves are never touched, even
+/// when a profile lists the same one.
+fn rewrite_edits(
+    current: &BTreeMap<String, Vec<String>>,
+    wanted: &[UrlRewrite],
+    edits: &mut GlobalEdits,
+) -> Result<Option<Vec<UrlRewrite>>> {
+    let mut managed = load_managed_rewrites()?;
+    if managed.is_empty() && wanted.is_empty() {
+        return Ok(None);
+    }
+    let installed = |rule: &UrlRewrite| current.get(&rule.key().to_lowercase()).is_some_and(|values| values.contains(&rule.instead_of));
 
-    if config.profiles.is_empty() {
-        println!("No profiles found. Run 'gs setup' to create your first profile.");
-        return Ok(());
+    // A rule that's already gone is what we wanted
+    for rule in managed.iter().filter(|r| !wanted.contains(r) && installed(r)) {
+        edits.push(
+            git_config::Edit::Remove { key: rule.key(), value: rule.instead_of.clone() },
+            AuditChange::Git { key: rule.key(), old: Some(rule.instead_of.clone()), new: None },
+        );
     }
+    managed.retain(|r| wanted.contains(r));
 
-    if config.profiles.len() == 1 {
-        println!("Only one profile exists. Run 'gs setup' to create another profile.");
-        return Ok(());
+    for rule in wanted {
+        if managed.contains(rule) || installed(rule) {
+            continue;
+        }
+        edits.push(
+            git_config::Edit::Add { key: rule.key(), value: rule.instead_of.clone() },
+            AuditChange::Git { key: rule.key(), old: None, new: Some(rule.instead_of.clone()) },
+        );
+        managed.push(rule.clone());
     }
+    Ok(Some(managed))
+}
 
-    // Find current profile and switch to next
-    let mut current_index = 0;
-    let mut found_current = false;
+/// The insteadOf rules gs added and may remove again.
+fn load_managed_rewrites() -> Result<Vec<UrlRewrite>> {
+    let path = get_config_dir()?.join(MANAGED_REWRITES_FILE);
+    if !path.exists() {
+        return Ok(Vec::new());
+    }
+    let data = fs::read_to_string(&path).context("Failed to read managed URL rewrites")?;
+    serde_json::from_str(&data).context("Failed to parse managed URL rewrites")
+}
 
-    for (i, profile) in config.profiles.iter_mut().enumerate() {
-        if profile.current {
-            profile.current = false;
-            current_index = i;
-            found_current = true;
-            break;
-        }
+fn save_managed_rewrites(managed: &[UrlRewrite]) -> Result<()> {
+    let path = get_config_dir()?.join(MANAGED_REWRITES_FILE);
+    let json_data = serde_json::to_string_pretty(managed).context("Failed to serialize managed URL rewrites")?;
+    fs::write(&path, json_data).context("Failed to save managed URL rewrites")
+}
+
+/// Values of `url.<base>.insteadOf` in the global git config.
+fn installed_rewrites(base: &str) -> Result<Vec<String>> {
+    let key = format!("url.{}.insteadOf", base);
+    let output = process::Command::new("git")
+        .args(["config", "--global", "--get-all", &key])
+        .logged_output()
+        .external("Failed to run git config")?;
+    // 1 means there are none
+    if !output.status.success() && output.status.code() != Some(1) {
+        return Err(git_failed(&format!("git config --get-all {}", key), &output));
     }
+    Ok(String::from_utf8_lossy(&output.stdout).lines().map(str::to_string).collect())
+}
 
-    // If no current profile found, set first as current
-    let new_index = if found_current {
-        (current_index + 1) % config.profiles.len()
-    } else {
-        0
+fn get_ssh_dir() -> Result<PathBuf> {
+    let home_dir = dirs::home_dir().ok_or_else(|| anyhow!("Could not find home directory"))?;
+    Ok(home_dir.join(".ssh"))
+}
+
+fn get_ssh_config_path() -> Result<PathBuf> {
+    Ok(get_ssh_dir()?.join("config"))
+}
+
+/// IdentityFile currently configured in `host`'s Host section, wherever
+/// the Include chain puts it.
+fn read_ssh_identity_file(host: &str) -> Result<Option<String>> {
+    let Some(block) = locate_ssh_blocks(host)?.into_iter().next() else {
+        return Ok(None);
     };
 
-    config.profiles[new_index].current = true;
-    let new_profile = config.profiles[new_index].clone();
+    let content = fs::read_to_string(&block.path).context("Failed to read SSH config")?;
+    Ok(ssh_config::identity_file(&content, host))
+}
 
-    update_git_config(&new_profile).context("Failed to update git config")?;
-    update_ssh_config(&new_profile).context("Failed to update SSH config")?;
-    save_config(&config)?;
+/// Every Host block for `host`, in the order ssh reads them. Only the first
+/// one takes effect; the rest are shadowed.
+fn locate_ssh_blocks(host: &str) -> Result<Vec<ssh_config::BlockLocation>> {
+    ssh_config::locate_host_blocks(&get_ssh_config_path()?, &get_ssh_dir()?, host)
+}
 
-    clear_screen();
-    println!(
-        "Switched to profile: {} ({})",
-        new_profile.name.bright_green(),
-        new_profile.email
-    );
+fn describe_block(block: &ssh_config::BlockLocation) -> String {
+    format!("{}:{}", block.path.display(), block.line + 1)
+}
+
+/// Point `host`'s IdentityFile at `key`, on top of any edits already
+/// planned for the same file.
+fn plan_ssh_edit(edits: &mut Vec<SshEdit>, host: &str, key: &str, user: &str, options: &[String]) -> Result<()> {
+    // Edit the block in place in whichever included file holds it; only
+    // append to the main config when it exists nowhere. With duplicates,
+    // only the first block matters to ssh, so that's the one we edit.
+    let blocks = locate_ssh_blocks(host)?;
+    if blocks.len() > 1 {
+        let shadowed: Vec<String> = blocks[1..].iter().map(describe_block).collect();
+        eprintln!(
+            "{} Host {} is defined {} times; editing {} and ignoring the shadowed {}",
+            "Warning:".yellow(),
+            host,
+            blocks.len(),
+            describe_block(&blocks[0]),
+            shadowed.join(", ")
+        );
+    }
+    let config_path = match blocks.into_iter().next() {
+        Some(block) => {
+            tracing::debug!("Host {} is in {}", host, describe_block(&block));
+            block.path
+        }
+        None => get_ssh_config_path()?,
+    };
+
+    let index = match edits.iter().position(|edit| edit.path == config_path) {
+        Some(index) => index,
+        None => {
+            let old = if config_path.exists() {
+                tracing::debug!("read {}", config_path.display());
+                Some(fs::read_to_string(&config_path).file_context("read", &config_path)?)
+            } else {
+                None
+            };
+            let new = old.clone().unwrap_or_default();
+            edits.push(SshEdit { path: config_path, hosts: Vec::new(), old, new });
+            edits.len() - 1
+        }
+    };
 
+    let edit = &mut edits[index];
+    edit.hosts.push(host.to_string());
+    edit.new = ssh_config::set_identity_file(&edit.new, host, key, user, load_settings()?.identities_only);
+    edit.new = ssh_config::set_options(&edit.new, host, options);
+    tracing::trace!("{} before:\n{}", edit.path.display(), edit.old.as_deref().unwrap_or(""));
+    tracing::trace!("{} after:\n{}", edit.path.display(), edit.new);
     Ok(())
 }
 
-fn setup_flow() -> Result<()> {
-    println!("=== Git Profile Setup ===");
+fn write_ssh_config(path: &Path, content: &str) -> Result<()> {
+    // A symlinked config (say, into a dotfiles repo) is written through,
+    // never replaced by a plain file
+    let resolved = fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf());
+    if resolved != path {
+        tracing::debug!("{} resolves to {}", path.display(), resolved.display());
+    }
+    let path = resolved.as_path();
+
+    // Leave the mtime alone when there's nothing to change
+    if fs::read(path).is_ok_and(|existing| existing == content.as_bytes()) {
+        tracing::debug!("{} unchanged, not writing", path.display());
+        return Ok(());
+    }
+
+    // Create .ssh directory if it doesn't exist
+    if let Some(dir) = path.parent()
+        && !dir.exists()
+    {
+        fs::create_dir_all(dir).file_context("create", dir)?;
+    }
+    tracing::debug!("write {}", path.display());
+    fs::write(path, content).file_context("write", path)?;
 
-    // Get name
-    print!("Enter profile name: ");
-    io::stdout().flush()?;
-    let mut name = String::new();
-    io::stdin().read_line(&mut name)?;
-    let name = name.trim().to_string();
+    // Set permissions
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        let mut perms = fs::metadata(path).file_context("read", path)?.permissions();
+        perms.set_mode(0o600);
+        fs::set_permissions(path, perms).file_context("change permissions of", path)?;
+    }
 
-    // Get email
-    print!("Enter email: ");
-    io::stdout().flush()?;
-    let mut email = String::new();
-    io::stdin().read_line(&mut email)?;
-    let email = email.trim().to_string();
+    Ok(())
+}
 
-    // Get SSH key path
-    print!("Enter path to SSH private key: ");
-    io::stdout().flush()?;
-    let mut ssh_key = String::new();
-    io::stdin().read_line(&mut ssh_key)?;
-    let mut ssh_key = ssh_key.trim().to_string();
+/// Before gs changes SSH config on this machine for the first time, show
+/// the change and ask, so nobody finds their config edited by surprise. The
+/// answer is kept in settings.json; `--yes` gives it without asking.
+/// Machines gs has already switched profiles on don't ask.
+fn confirm_first_ssh_edit(edits: &[SshEdit]) -> Result<()> {
+    let mut settings = load_settings()?;
+    if settings.ssh_config_consent || load_state()?.is_some() {
+        return Ok(());
+    }
 
-    // Expand tilde to home directory
-    if ssh_key.starts_with('~') {
-        if let Some(home_dir) = dirs::home_dir() {
-            ssh_key = ssh_key.replacen('~', home_dir.to_str().unwrap(), 1);
+    if !options().yes {
+        eprintln!("gs keeps a Host block for each profile's hosts in your SSH config, and is about to change it for the first time:");
+        for edit in edits {
+            if let Ok(target) = fs::read_link(&edit.path) {
+                eprintln!(
+                    "{} is a symlink to {}; gs writes through it, so the change lands there",
+                    edit.path.display(),
+                    target.display()
+                );
+            }
+            print_diff(&edit.path, edit.old.as_deref(), &edit.new);
+        }
+        if !confirm_destructive("Let gs make this change, and others like it from now on?")? {
+            return Err(failure(ExitCode::Aborted, "Aborted: SSH config was left unchanged"));
         }
     }
+    settings.ssh_config_consent = true;
+    save_settings(&settings)
+}
 
-    // Validate SSH key exists
-    if !Path::new(&ssh_key).exists() {
-        return Err(anyhow!("SSH key not found at: {}", ssh_key));
+/// Show the pending SSH config change when asked to, and confirm it when the
+/// confirm_ssh_edits setting is on. Returns false if the user declined.
+fn review_ssh_change(path: &Path, old: Option<&str>, new: &str) -> Result<bool> {
+    if old == Some(new) {
+        return Ok(true);
     }
 
-    // Load existing config
-    let mut config = load_config()?;
+    let confirm = io::stdin().is_terminal() && load_settings()?.confirm_ssh_edits;
+    if !confirm && !options().show_diff {
+        return Ok(true);
+    }
 
-    // Check if profile with same name exists
-    for profile in &config.profiles {
-        if profile.name == name {
-            return Err(anyhow!("Profile with name '{}' already exists", name));
-        }
+    print_diff(path, old, new);
+    if !confirm {
+        return Ok(true);
     }
 
-    // Create new profile
-    let is_first_profile = config.profiles.is_empty();
-    let new_profile = Profile {
-        name,
-        email,
-        ssh_key,
-        current: true, // New profile is set as current
+    prompt::confirm(&format!("Apply these changes to {}?", path.display()))
+}
+
+fn print_diff(path: &Path, old: Option<&str>, new: &str) {
+    let old_header = match old {
+        Some(_) => path.display().to_string(),
+        None => "/dev/null".to_string(),
     };
+    let new_header = format!("{} (proposed)", path.display());
 
-    // If this is not the first profile, make others non-current
-    if !is_first_profile {
-        for profile in &mut config.profiles {
-            profile.current = false;
+    let diff = similar::TextDiff::from_lines(old.unwrap_or(""), new);
+    let rendered = diff
+        .unified_diff()
+        .context_radius(3)
+        .missing_newline_hint(false)
+        .header(&old_header, &new_header)
+        .to_string();
+
+    for line in rendered.lines() {
+        if line.starts_with("+++") || line.starts_with("---") {
+            eprintln!("{}", line.bold());
+        } else if line.starts_with('+') {
+            eprintln!("{}", line.green());
+        } else if line.starts_with('-') {
+            eprintln!("{}", line.red());
+        } else if line.starts_with("@@") {
+            eprintln!("{}", line.cyan());
+        } else {
+            eprintln!("{}", line);
         }
     }
+}
 
-    // Update git and SSH configs for the new profile
-    update_git_config(&new_profile)?;
-    update_ssh_config(&new_profile)?;
+/// Everything gs touches on this machine, with where each location came from.
+#[derive(Debug, Serialize)]
+struct Paths {
+    config_dir: PathBuf,
+    config_dir_source: String,
+    profiles: PathBuf,
+    profiles_source: String,
+    profiles_dir: PathBuf,
+    settings: PathBuf,
+    state: PathBuf,
+    rollback: PathBuf,
+    history_dir: PathBuf,
+    backups_dir: PathBuf,
+    switch_log: PathBuf,
+    audit_log: PathBuf,
+    ssh_config: PathBuf,
+    git_config: Option<PathBuf>,
+    git_config_source: String,
+}
 
-    config.profiles.push(new_profile.clone());
+fn resolve_paths() -> Result<Paths> {
+    let config_dir = get_config_dir()?;
+    let config_dir_source = config_dir_resolution()?.1.label().to_string();
+    let (git_config, git_config_source) = resolve_git_global_config();
 
-    // Save config
-    save_config(&config)?;
+    Ok(Paths {
+        profiles: config_path_location()?,
+        profiles_source: match options().config {
+            Some(_) => "--config".to_string(),
+            None => "default".to_string(),
+        },
+        profiles_dir: profiles_dir()?,
+        settings: config_dir.join(SETTINGS_FILE),
+        state: state_path()?,
+        rollback: rollback_path()?,
+        history_dir: config_dir.join(HISTORY_DIR),
+        backups_dir: backups_dir()?,
+        switch_log: config_dir.join(SWITCH_LOG_FILE),
+        audit_log: config_dir.join(AUDIT_LOG_FILE),
+        ssh_config: get_ssh_config_path()?,
+        git_config,
+        git_config_source,
+        config_dir,
+        config_dir_source,
+    })
+}
 
-    println!("Profile '{}' created successfully!", new_profile.name);
-    Ok(())
+/// The file `git config --global` writes to, and how we found out. This is
+/// git's own rule: $GIT_CONFIG_GLOBAL, else ~/.gitconfig if it exists, else
+/// the XDG file ($XDG_CONFIG_HOME/git/config, or ~/.config/git/config) if
+/// that exists, else ~/.gitconfig, which git creates.
+fn resolve_git_global_config() -> (Option<PathBuf>, String) {
+    if let Some(path) = std::env::var_os("GIT_CONFIG_GLOBAL").filter(|p| !p.is_empty()) {
+        return (Some(PathBuf::from(path)), "GIT_CONFIG_GLOBAL".to_string());
+    }
+    let Some(home) = dirs::home_dir() else {
+        return (None, "unknown".to_string());
+    };
+
+    let gitconfig = home.join(".gitconfig");
+    if gitconfig.exists() {
+        return (Some(gitconfig), "default".to_string());
+    }
+    let xdg = match std::env::var_os("XDG_CONFIG_HOME").filter(|p| !p.is_empty()) {
+        Some(dir) => PathBuf::from(dir).join("git/config"),
+        None => home.join(".config/git/config"),
+    };
+    if xdg.exists() {
+        return (Some(xdg), "XDG, no ~/.gitconfig".to_string());
+    }
+    (Some(gitconfig), "default, not created yet".to_string())
 }
 
-fn list_profiles() -> Result<()> {
-    let config = load_config()?;
+fn show_paths(json: bool) -> Result<()> {
+    let paths = resolve_paths()?;
 
-    if config.profiles.is_empty() {
-        println!("No profiles found. Run 'gs setup' to create your first profile.");
+    if json {
+        println!("{}", serde_json::to_string_pretty(&paths)?);
         return Ok(());
     }
 
-    println!("=== Git Profiles ===");
-    for profile in &config.profiles {
-        let current = if profile.current {
-            " (current)".green().to_string()
-        } else {
-            String::new()
-        };
-        println!("• {} <{}>{}",
-            profile.name,
-            profile.email,
-            current
-        );
-        println!("  SSH Key: {}", profile.ssh_key);
-        println!();
-    }
+    let git_config = paths
+        .git_config
+        .as_ref()
+        .map(|p| p.display().to_string())
+        .unwrap_or_else(|| "unknown".to_string());
 
+    println!("Config directory: {} {}", paths.config_dir.display(), format!("({})", paths.config_dir_source).dimmed());
+    println!("Profiles:         {} {}", paths.profiles.display(), format!("({})", paths.profiles_source).dimmed());
+    let profiles_dir_note = if paths.profiles_dir.is_dir() { "" } else { " (not created)" };
+    println!("Profile files:    {}{}", paths.profiles_dir.display(), profiles_dir_note.dimmed());
+    println!("Settings:         {}", paths.settings.display());
+    println!("State:            {}", paths.state.display());
+    println!("Rollback:         {}", paths.rollback.display());
+    println!("Undo history:     {}", paths.history_dir.display());
+    println!("Backups:          {}", paths.backups_dir.display());
+    println!("Switch log:       {}", paths.switch_log.display());
+    println!("Audit log:        {}", paths.audit_log.display());
+    println!("SSH config:       {}", paths.ssh_config.display());
+    println!("Git config:       {} {}", git_config, format!("({})", paths.git_config_source).dimmed());
     Ok(())
 }
 
-fn edit_profile() -> Result<()> {
-    // First, load the config and get necessary information
-    let config = load_config()?;
+fn install_git_alias(link_dir: Option<PathBuf>) -> Result<()> {
+    let exe = std::env::current_exe().context("Failed to locate the gs executable")?;
 
-    if config.profiles.is_empty() {
-        println!("No profiles found. Run 'gs setup' to create your first profile.");
-        return Ok(());
-    }
+    match link_dir {
+        Some(dir) => {
+            let link = dir.join(if cfg!(windows) { "git-profile.exe" } else { GIT_SUBCOMMAND_BIN });
+            if link.exists() {
+                return Err(anyhow!("{} already exists", link.display()));
+            }
 
-    // Show profiles
-    println!("=== Select Profile to Edit ===");
-    for (i, profile) in config.profiles.iter().enumerate() {
-        println!("{}. {} <{}>", i + 1, profile.name, profile.email);
-    }
+            #[cfg(unix)]
+            std::os::unix::fs::symlink(&exe, &link)
+                .with_context(|| format!("Failed to create link at {}", link.display()))?;
+            #[cfg(not(unix))]
+            fs::copy(&exe, &link)
+                .with_context(|| format!("Failed to copy gs to {}", link.display()))?;
 
-    // Get profile selection
-    print!("Enter profile number: ");
-    io::stdout().flush()?;
-    let mut input = String::new();
-    io::stdin().read_line(&mut input)?;
-    let input = input.trim();
+            eprintln!("Created {} -> {}", link.display(), exe.display());
+            eprintln!("Make sure {} is on your PATH, then run 'git profile'.", dir.display());
+        }
+        None => {
+            let alias = format!("!{}", shell_quote(path_text(&exe)?));
+            let status = process::Command::new("git")
+                .args(["config", "--global", "alias.profile", &alias])
+                .logged_status()
+                .external("Failed to set git alias")?;
+            if !status.success() {
+                return Err(failure(ExitCode::External, format!("git config exited with {}", status)));
+            }
 
-    let profile_index: usize = match input.parse::<usize>() {
-        Ok(n) if n > 0 && n <= config.profiles.len() => n - 1,
-        _ => return Err(anyhow!("Invalid profile number")),
-    };
+            eprintln!("Added git alias 'profile'. Run 'git profile' to use it.");
+        }
+    }
 
-    // Clone the profile we want to edit and check if it's current
-    let original_profile = config.profiles[profile_index].clone();
-    let was_current = original_profile.current;
-    let mut updated_profile = original_profile.clone();
+    Ok(())
+}
 
-    // Edit name
-    println!("Current name: {}", updated_profile.name);
-    print!("New name (press Enter to keep current): ");
-    io::stdout().flush()?;
-    let mut name = String::new();
-    io::stdin().read_line(&mut name)?;
-    let name = name.trim();
-    if !name.is_empty() {
-        updated_profile.name = name.to_string();
+/// The repository's hooks directory, wherever core.hooksPath or a linked
+/// worktree puts it.
+fn hooks_dir() -> Result<PathBuf> {
+    let output = process::Command::new("git")
+        .args(["rev-parse", "--git-path", "hooks"])
+        .logged_output()
+        .external("Failed to run git rev-parse")?;
+    if !output.status.success() {
+        return Err(failure(ExitCode::External, "git rev-parse couldn't find the hooks directory"));
     }
+    Ok(PathBuf::from(String::from_utf8_lossy(&output.stdout).trim()))
+}
 
-    // Edit email
-    println!("Current email: {}", updated_profile.email);
-    print!("New email (press Enter to keep current): ");
-    io::stdout().flush()?;
-    let mut email = String::new();
-    io::stdin().read_line(&mut email)?;
-    let email = email.trim();
-    if !email.is_empty() {
-        updated_profile.email = email.to_string();
+/// Set or unset a key in the repository's own git config.
+fn set_repo_config(key: &str, value: Option<&str>) -> Result<()> {
+    let mut command = process::Command::new("git");
+    match value {
+        Some(value) => command.args(["config", "--local", key, value]),
+        None => command.args(["config", "--local", "--unset", key]),
+    };
+    let output = command.logged_output().external("Failed to run git config")?;
+    // Unsetting a key that isn't there exits with 5
+    let already_unset = value.is_none() && output.status.code() == Some(5);
+    if !output.status.success() && !already_unset {
+        return Err(failure(
+            ExitCode::External,
+            format!("git config {} failed: {}", key, String::from_utf8_lossy(&output.stderr).trim()),
+        ));
     }
+    Ok(())
+}
+
+/// Install the hooks that run `gs check-repo` after checkouts and merges,
+/// or before commits with `pre_commit`, optionally recording the profile the
+/// repository expects. With `remove`, take them out again, and the
+/// expectation with the last of them.
+fn install_hook(profile: Option<&str>, pre_commit: bool, enforce: bool, remove: bool) -> Result<()> {
+    ensure_git_repo()?;
+    let dir = hooks_dir()?;
+    let hooks: &[&str] = match pre_commit {
+        true => &[PRE_COMMIT_HOOK],
+        false => &CHECK_HOOKS,
+    };
 
-    // Edit SSH key
-    println!("Current SSH key: {}", updated_profile.ssh_key);
-    print!("New SSH key path (press Enter to keep current): ");
-    io::stdout().flush()?;
-    let mut ssh_key = String::new();
-    io::stdin().read_line(&mut ssh_key)?;
-    let ssh_key = ssh_key.trim();
-    
-    if !ssh_key.is_empty() {
-        let mut expanded_key = ssh_key.to_string();
-        // Expand tilde
-        if expanded_key.starts_with('~') {
-            if let Some(home_dir) = dirs::home_dir() {
-                expanded_key = expanded_key.replacen('~', home_dir.to_str().unwrap(), 1);
+    if remove {
+        let mut removed = Vec::new();
+        // Without --pre-commit, every gs hook goes
+        let targets = if pre_commit { hooks.to_vec() } else { [&CHECK_HOOKS[..], &[PRE_COMMIT_HOOK]].concat() };
+        for hook in targets {
+            if repo_hook::remove(&dir, hook)? {
+                removed.push(hook);
             }
         }
-
-        // Validate SSH key exists
-        if !Path::new(&expanded_key).exists() {
-            return Err(anyhow!("SSH key not found at: {}", expanded_key));
+        let remaining = CHECK_HOOKS.iter().chain([&PRE_COMMIT_HOOK]).any(|hook| repo_hook::is_installed(&dir, hook));
+        if !remaining {
+            set_repo_config(REPO_PROFILE_KEY, None)?;
         }
-        
-        updated_profile.ssh_key = expanded_key;
+        match removed.is_empty() {
+            true => eprintln!("No gs hooks were installed in {}", dir.display()),
+            false => eprintln!("Removed the gs hooks ({}) from {}", removed.join(", "), dir.display()),
+        }
+        return Ok(());
     }
 
-    // Update git and SSH configs if this is the current profile
-    if was_current {
-        update_git_config(&updated_profile)?;
-        update_ssh_config(&updated_profile)?;
+    let config = load_config()?;
+    if let Some(name) = profile {
+        let profile = &config.profiles[resolve_profile(&config, name)?];
+        set_repo_config(REPO_PROFILE_KEY, Some(&profile.name))?;
     }
 
-    // Now create a new config with the updated profile
-    let mut new_config = config;
-    new_config.profiles[profile_index] = updated_profile;
-
-    // Save config
-    save_config(&new_config)?;
-
-    println!("Profile '{}' updated successfully!", new_config.profiles[profile_index].name);
+    let exe = std::env::current_exe().context("Failed to locate the gs executable")?;
+    let exe = shell_quote(path_text(&exe)?);
+    // Only an enforcing hook lets a mismatch stop git
+    let command = match enforce {
+        true => format!("{} check-repo --quiet --enforce || exit 1", exe),
+        false => format!("{} check-repo --quiet || true", exe),
+    };
+    for &hook in hooks {
+        match repo_hook::install(&dir, hook, &command)? {
+            repo_hook::Installed::Created => eprintln!("Installed {}", dir.join(hook).display()),
+            repo_hook::Installed::Chained => eprintln!(
+                "Installed {}; the hook that was there runs after it",
+                dir.join(hook).display()
+            ),
+            repo_hook::Installed::Updated => eprintln!("Updated {}", dir.join(hook).display()),
+        }
+    }
+    match read_git_config(&[], REPO_PROFILE_KEY) {
+        Some(name) => eprintln!("This repository expects profile '{}'.", name.bright_green()),
+        None => eprintln!("No profile is expected here (--profile sets one), so any profile's email passes."),
+    }
+    if enforce {
+        eprintln!("Commits with the wrong identity are refused; {}=1 skips the check.", SKIP_CHECK_ENV);
+    }
     Ok(())
 }
 
-fn remove_profile() -> Result<()> {
-    let mut config = load_config()?;
-
-    if config.profiles.is_empty() {
-        println!("No profiles found.");
+/// Compare the repository's effective user.email with the profile it
+/// expects, or with every profile when it doesn't name one, and warn
+/// loudly on a mismatch. Hooks run it on every commit, so it only reads.
+fn check_repo(quiet: bool, enforce: bool) -> Result<()> {
+    if std::env::var_os(SKIP_CHECK_ENV).is_some_and(|v| !v.is_empty() && v != "0") {
+        eprintln!("{}", format!("Skipping the identity check ({} is set)", SKIP_CHECK_ENV).dimmed());
         return Ok(());
     }
+    ensure_git_repo()?;
+    let config = load_config()?;
+    let email = read_git_config(&[], "user.email");
+    let owner = |email: &str| config.profiles.iter().find(|p| p.email.eq_ignore_ascii_case(email));
 
-    // Show profiles
-    println!("=== Select Profile to Remove ===");
-    for (i, profile) in config.profiles.iter().enumerate() {
-        let current = if profile.current { " (current)" } else { "" };
-        println!("{}. {} <{}>{}",
-            i + 1,
+    let expected = match read_git_config(&[], REPO_PROFILE_KEY) {
+        Some(name) => match config.profiles.iter().find(|p| p.name == name) {
+            Some(profile) => Some(profile),
+            None => {
+                return Err(Failure::new(
+                    ExitCode::NotFound,
+                    format!("This repository expects profile '{}' ({}), which doesn't exist", name, REPO_PROFILE_KEY),
+                )
+                .profile(&name)
+                .into());
+            }
+        },
+        None => None,
+    };
+
+    let problem = match (&email, expected) {
+        (None, _) => "no user.email is set, so git will refuse to commit or guess one".to_string(),
+        (Some(email), Some(profile)) if !profile.email.eq_ignore_ascii_case(email) => {
+            format!("this repository expects profile '{}' <{}>", profile.name, profile.email)
+        }
+        (Some(email), None) if owner(email).is_none() => "user.email isn't the email of any gs profile".to_string(),
+        (Some(email), _) => {
+            if !quiet {
+                let name = expected.or_else(|| owner(email)).map_or("", |p| p.name.as_str());
+                eprintln!("{} Commits here use profile '{}' <{}>.", "✓".green(), name, email);
+            }
+            return Ok(());
+        }
+    };
+
+    // The details go first; the mismatch itself is the error that ends it
+    if let Some(email) = &email {
+        let using = match owner(email) {
+            Some(profile) => format!(" (profile '{}')", profile.name),
+            None => String::new(),
+        };
+        eprintln!("  Commits here would use {}{}", email.red(), using);
+    }
+    if let Some(profile) = expected {
+        eprintln!(
+            "  Switch with '{} {}', or '{} --worktree {}' for this checkout only.",
+            program_name(),
             profile.name,
-            profile.email,
-            current
+            program_name(),
+            profile.name
+        );
+    }
+    if enforce {
+        eprintln!(
+            "{} Set {}=1 (or use git commit --no-verify) to commit anyway.",
+            "Commit refused.".red().bold(),
+            SKIP_CHECK_ENV
         );
     }
+    let mut mismatch = Failure::new(ExitCode::Error, format!("Wrong git identity: {}", problem));
+    if let Some(profile) = expected {
+        mismatch = mismatch.profile(&profile.name);
+    }
+    Err(mismatch.into())
+}
 
-    // Get profile selection
-    print!("Enter profile number: ");
-    io::stdout().flush()?;
-    let mut input = String::new();
-    io::stdin().read_line(&mut input)?;
-    let input = input.trim();
+/// Clear the screen and move the cursor home. crossterm uses the console
+/// API on Windows consoles that don't understand escapes; a terminal that
+/// understands neither (`TERM=dumb`) gets scrolled clear with newlines.
+fn clear_screen() {
+    use ratatui::crossterm::{cursor::MoveTo, execute, terminal};
 
-    let profile_index: usize = match input.parse::<usize>() {
-        Ok(n) if n > 0 && n <= config.profiles.len() => n - 1,
-        _ => return Err(anyhow!("Invalid profile number")),
-    };
+    let mut err = io::stderr();
+    if !err.is_terminal() {
+        return;
+    }
+    if std::env::var("TERM").is_ok_and(|term| term == "dumb") {
+        let (_, rows) = terminal::size().unwrap_or((80, 24));
+        let _ = write!(err, "{}", "\n".repeat(rows as usize));
+        let _ = err.flush();
+        return;
+    }
+    let _ = execute!(err, terminal::Clear(terminal::ClearType::All), MoveTo(0, 0));
+}
 
-    // Clone the profile information we need before removing it
-    let profile_name = config.profiles[profile_index].name.clone();
-    let was_current = config.profiles[profile_index].current;
-    
-    // First confirmation
-    print!("Are you sure you want to remove profile '{}' <{}>? (y/N): ",
-        profile_name,
-        config.profiles[profile_index].email
-    );
-    io::stdout().flush()?;
-    let mut confirm1 = String::new();
-    io::stdin().read_line(&mut confirm1)?;
-    let confirm1 = confirm1.trim().to_lowercase();
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-    if confirm1 != "y" && confirm1 != "yes" {
-        println!("Aborted.");
-        return Ok(());
+    /// A fresh directory under the system temp dir, unique to this call.
+    fn scratch_dir(label: &str) -> PathBuf {
+        static NEXT: std::sync::atomic::AtomicUsize = std::sync::atomic::AtomicUsize::new(0);
+        let n = NEXT.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+        let dir = std::env::temp_dir().join(format!("gs-test-{}-{}-{}", process::id(), label, n));
+        fs::create_dir_all(&dir).unwrap();
+        dir
     }
 
-    // Second confirmation
-    print!("This action cannot be undone. Are you absolutely sure? (y/N): ");
-    io::stdout().flush()?;
-    let mut confirm2 = String::new();
-    io::stdin().read_line(&mut confirm2)?;
-    let confirm2 = confirm2.trim().to_lowercase();
+    /// Keep the audit log and anything else gs writes while applying out of
+    /// the real config directory.
+    fn isolate_config_dir() {
+        static ONCE: std::sync::Once = std::sync::Once::new();
+        ONCE.call_once(|| {
+            let dir = scratch_dir("config");
+            // SAFETY: set once, before any test that reads it runs apply
+            // code, and nothing else in these tests reads the environment
+            unsafe { std::env::set_var(CONFIG_DIR_ENV, &dir) };
+        });
+    }
 
-    if confirm2 != "y" && confirm2 != "yes" {
-        println!("Aborted.");
-        return Ok(());
+    fn ssh_edit(path: PathBuf, old: &str, new: &str) -> SshEdit {
+        SshEdit { path, hosts: vec!["github.com".to_string()], old: Some(old.to_string()), new: new.to_string() }
     }
 
-    // Remove profile
-    config.profiles.remove(profile_index);
+    #[test]
+    fn failed_ssh_write_restores_earlier_files() {
+        isolate_config_dir();
+        let dir = scratch_dir("apply");
+        let first = dir.join("config");
+        let second = dir.join("config.d-work");
+        let created = dir.join("config.d-new");
+        fs::write(&first, "Host github.com\n    IdentityFile ~/.ssh/a\n").unwrap();
+        fs::write(&second, "Host work\n    IdentityFile ~/.ssh/a\n").unwrap();
+        let plan = ApplyPlan {
+            profile: Profile { name: "b".to_string(), email: "b@example.com".to_string(), ..Default::default() },
+            ssh_edits: vec![
+                ssh_edit(first.clone(), "Host github.com\n    IdentityFile ~/.ssh/a\n", "Host github.com\n    IdentityFile ~/.ssh/b\n"),
+                SshEdit { path: created.clone(), hosts: Vec::new(), old: None, new: "Host new\n".to_string() },
+                ssh_edit(second.clone(), "Host work\n    IdentityFile ~/.ssh/a\n", "Host work\n    IdentityFile ~/.ssh/b\n"),
+            ],
+            git: false,
+        };
+
+        let failing = |path: &Path, content: &str| match path == second {
+            true => Err(failure(ExitCode::Error, "disk full")),
+            false => write_ssh_config(path, content),
+        };
+        let err = apply_with(&plan, failing).unwrap_err();
 
-    // If removed profile was current, make first profile current
-    if was_current && !config.profiles.is_empty() {
-        config.profiles[0].current = true;
-        update_git_config(&config.profiles[0])?;
-        update_ssh_config(&config.profiles[0])?;
+        assert_eq!(err.to_string(), "disk full");
+        assert_eq!(fs::read_to_string(&first).unwrap(), "Host github.com\n    IdentityFile ~/.ssh/a\n");
+        assert!(!created.exists(), "a file the apply created is removed again");
+        assert_eq!(fs::read_to_string(&second).unwrap(), "Host work\n    IdentityFile ~/.ssh/a\n");
+        fs::remove_dir_all(dir).unwrap();
     }
 
-    // Save config
-    save_config(&config)?;
+    #[test]
+    fn unknown_keys_survive_an_edit() {
+        isolate_config_dir();
+        let dir = config_dir_location().unwrap();
+        fs::create_dir_all(dir.join("profiles.d")).unwrap();
+        fs::write(
+            dir.join(CONFIG_FILE),
+            r#"{"version": 1, "team": {"budget": 3}, "profiles": [{"name": "work", "email": "a@corp.example", "ssh_key": "~/.ssh/work", "cost_center": "R&D-42"}]}"#,
+        )
+        .unwrap();
+        fs::write(dir.join("profiles.d/oss.json"), r#"{"name": "oss", "email": "a@oss.example", "ssh_key": "", "tags": ["x", 1]}"#).unwrap();
 
-    println!("Profile '{}' removed successfully!", profile_name);
-    Ok(())
-}
+        let mut config = load_config().unwrap();
+        for profile in &mut config.profiles {
+            profile.email = profile.email.replace("a@", "b@");
+        }
+        save_config(&config).unwrap();
 
-fn update_git_config(profile: &Profile) -> Result<()> {
-    // Set global git config
-    process::Command::new("git")
-        .args(["config", "--global", "user.name", &profile.name])
-        .output()
-        .context("Failed to set git user.name")?;
+        let read = |path: PathBuf| -> serde_json::Value { serde_json::from_str(&fs::read_to_string(path).unwrap()).unwrap() };
+        let main = read(dir.join(CONFIG_FILE));
+        assert_eq!(main["team"], serde_json::json!({"budget": 3}));
+        assert_eq!(main["profiles"][0]["email"], "b@corp.example");
+        assert_eq!(main["profiles"][0]["cost_center"], "R&D-42");
+        let oss = read(dir.join("profiles.d/oss.json"));
+        assert_eq!(oss["email"], "b@oss.example");
+        assert_eq!(oss["tags"], serde_json::json!(["x", 1]));
+    }
 
-    process::Command::new("git")
-        .args(["config", "--global", "user.email", &profile.email])
-        .output()
-        .context("Failed to set git user.email")?;
+    #[test]
+    fn ssh_hosts_fall_back_to_github_only_without_hosts() {
+        let mut profile = Profile { ssh_key: "~/.ssh/work".to_string(), ..Default::default() };
+        assert_eq!(profile.ssh_hosts(), [("github.com", "~/.ssh/work")]);
 
-    Ok(())
-}
+        profile.hosts.insert("codeberg.org".to_string(), "~/.ssh/work".to_string());
+        assert_eq!(profile.ssh_hosts(), [("codeberg.org", "~/.ssh/work")]);
 
-fn update_ssh_config(profile: &Profile) -> Result<()> {
-    let home_dir = dirs::home_dir().ok_or_else(|| anyhow!("Could not find home directory"))?;
-    let ssh_dir = home_dir.join(".ssh");
-    let config_path = ssh_dir.join("config");
+        profile.hosts.insert("github.com".to_string(), "~/.ssh/gh".to_string());
+        assert_eq!(profile.ssh_hosts(), [("codeberg.org", "~/.ssh/work"), ("github.com", "~/.ssh/gh")]);
+    }
 
-    // Create .ssh directory if it doesn't exist
-    if !ssh_dir.exists() {
-        fs::create_dir_all(&ssh_dir).context("Failed to create .ssh directory")?;
+    fn config_with(names: &[&str]) -> Config {
+        Config {
+            profiles: names.iter().map(|name| Profile { name: name.to_string(), ..Default::default() }).collect(),
+            ..Config::default()
+        }
     }
 
-    // Read existing SSH config if it exists
-    let content = if config_path.exists() {
-        let mut file = File::open(&config_path).context("Failed to open SSH config")?;
-        let mut content = String::new();
-        file.read_to_string(&mut content)?;
-        content
-    } else {
-        String::new()
-    };
+    #[test]
+    fn resolve_profile_matches_names() {
+        let config = config_with(&["work", "Work-Old", "personal", "oss", "oss-foundation"]);
+        let cases = [
+            // Exact, even when it's also a prefix of another name
+            ("work", 0),
+            ("oss", 3),
+            // Case-insensitive
+            ("PERSONAL", 2),
+            ("work-old", 1),
+            // A unique prefix, in any case
+            ("pers", 2),
+            ("P", 2),
+            ("oss-f", 4),
+        ];
+        for (query, expected) in cases {
+            assert_eq!(resolve_profile(&config, query).unwrap(), expected, "resolving {:?}", query);
+        }
+    }
 
-    let lines: Vec<&str> = content.lines().collect();
-    let mut new_lines = Vec::new();
-    let mut in_github_section = false;
-    let mut updated = false;
+    #[test]
+    fn resolve_profile_exact_case_wins_over_other_cases() {
+        let config = config_with(&["Work", "work"]);
+        assert_eq!(resolve_profile(&config, "work").unwrap(), 1);
+        assert_eq!(resolve_profile(&config, "Work").unwrap(), 0);
+        // Neither is exact, and both match without case
+        let err = resolve_profile(&config, "WORK").unwrap_err();
+        assert_eq!(error::exit_code(&err), ExitCode::Usage);
+    }
 
-    for line in &lines {
-        let trimmed = line.trim();
+    #[test]
+    fn resolve_profile_refuses_ambiguous_and_unknown_names() {
+        let config = config_with(&["work", "Work-Old", "personal", "oss", "oss-foundation"]);
+        let cases = [
+            ("w", ExitCode::Usage, "'w' matches several profiles: work, Work-Old"),
+            ("wo", ExitCode::Usage, "'wo' matches several profiles: work, Work-Old"),
+            ("nope", ExitCode::NotFound, "Profile 'nope' not found"),
+            ("work-older", ExitCode::NotFound, "Profile 'work-older' not found"),
+            ("", ExitCode::Usage, "'' matches several profiles: work, Work-Old, personal, oss, oss-foundation"),
+        ];
+        for (query, code, message) in cases {
+            let err = resolve_profile(&config, query).unwrap_err();
+            assert_eq!(error::exit_code(&err), code, "resolving {:?}", query);
+            assert_eq!(err.to_string(), message);
+        }
+        let err = resolve_profile(&config_with(&[]), "work").unwrap_err();
+        assert_eq!(error::exit_code(&err), ExitCode::NotFound);
+    }
 
-        if trimmed.starts_with("Host ") && trimmed.contains("github.com") {
-            in_github_section = true;
-            new_lines.push(line.to_string());
-            continue;
+    #[test]
+    fn gitdir_conditions_name_one_directory() {
+        let base = Path::new("/etc/gitconfigs");
+        let cases = [
+            ("gitdir:~/work/", "~/work"),
+            ("gitdir:~/work/**", "~/work"),
+            ("gitdir/i:~/Work/", "~/Work"),
+            ("gitdir:/srv/repos/app/.git", "/srv/repos/app"),
+            ("gitdir:./oss/", "/etc/gitconfigs/oss"),
+        ];
+        for (condition, expected) in cases {
+            assert_eq!(gitdir_directory(condition, base).as_deref(), Ok(expected), "{}", condition);
         }
 
-        if in_github_section && trimmed.starts_with("Host ") {
-            in_github_section = false;
+        let refused = [
+            ("onbranch:main", "only gitdir conditions"),
+            ("hasconfig:remote.*.url:https://corp/**", "only gitdir conditions"),
+            ("gitdir:~/work/*/src/", "wildcard pattern"),
+            ("gitdir/i:~/[Ww]ork/", "wildcard pattern"),
+            ("gitdir:work/", "matches in any directory"),
+        ];
+        for (condition, reason) in refused {
+            let err = gitdir_directory(condition, base).unwrap_err();
+            assert!(err.contains(reason), "{}: {}", condition, err);
         }
+    }
 
-        if in_github_section && trimmed.contains("IdentityFile") {
-            new_lines.push(format!("    IdentityFile {}", profile.ssh_key));
-            updated = true;
-        } else {
-            new_lines.push(line.to_string());
+    #[test]
+    fn fragment_names_come_from_the_file_name() {
+        let cases = [
+            ("/home/me/.gitconfig-work", Some("work")),
+            ("/home/me/work.gitconfig", Some("work")),
+            ("/home/me/.config/git/oss.inc", Some("oss")),
+            ("/home/me/.gitconfig_client-a", Some("client-a")),
+            ("/home/me/.config/git/config", None),
+            ("/home/me/.gitconfig", None),
+        ];
+        for (path, expected) in cases {
+            assert_eq!(fragment_name(Path::new(path)).as_deref(), expected, "{}", path);
         }
     }
 
-    // If no github.com section found, append one
-    if !updated {
-        if !new_lines.is_empty() && !new_lines.last().unwrap().is_empty() {
-            new_lines.push(String::new()); // Add empty line for spacing
+    #[test]
+    fn ssh_command_key_takes_the_identity_file() {
+        let cases = [
+            ("ssh -i ~/.ssh/work", Some("~/.ssh/work")),
+            ("ssh -i ~/.ssh/work -o IdentitiesOnly=yes", Some("~/.ssh/work")),
+            ("ssh -o IdentitiesOnly=yes -i '~/.ssh/work'", Some("~/.ssh/work")),
+            ("ssh -i\"~/.ssh/work\"", Some("~/.ssh/work")),
+            ("ssh -F ~/.ssh/work-config", None),
+            ("ssh -i", None),
+            ("", None),
+        ];
+        for (command, expected) in cases {
+            assert_eq!(ssh_command_key(command).as_deref(), expected, "{}", command);
         }
-        
-        new_lines.push("Host github.com".to_string());
-        new_lines.push("    HostName github.com".to_string());
-        new_lines.push("    User git".to_string());
-        new_lines.push(format!("    IdentityFile {}", profile.ssh_key));
     }
 
-    let updated_content = new_lines.join("\n");
-    fs::write(&config_path, updated_content).context("Failed to write SSH config")?;
-    
-    // Set permissions
-    #[cfg(unix)]
-    {
-        use std::os::unix::fs::PermissionsExt;
-        let mut perms = fs::metadata(&config_path)?.permissions();
-        perms.set_mode(0o600);
-        fs::set_permissions(&config_path, perms)?;
+    /// (severity, profile, message) of each finding for `profiles`.
+    fn findings(profiles: serde_json::Value, no_fs: bool) -> Vec<(Severity, Option<String>, String)> {
+        let data = serde_json::json!({ "version": 1, "profiles": profiles }).to_string();
+        check_profiles_data(&data, FileFormat::Json, no_fs).into_iter().map(|f| (f.severity, f.profile, f.message)).collect()
     }
 
-    Ok(())
-}
+    #[test]
+    fn check_finds_duplicates_current_profiles_and_missing_keys() {
+        let dir = scratch_dir("check");
+        let key = dir.join("id_a");
+        fs::write(&key, "").unwrap();
+        let key = key.to_str().unwrap();
+        let missing = dir.join("id_missing");
+        let missing = missing.to_str().unwrap();
 
-fn clear_screen() {
-    
-    #[cfg(not(target_os = "windows"))]
-    {
-        let _ = process::Command::new("clear").status();
+        let profiles = serde_json::json!([
+            { "name": "a", "email": "a@example.com", "ssh_key": key, "current": true },
+            { "name": "a", "email": "b@example.com", "ssh_key": "" },
+            { "name": "c", "email": "c@example.com", "ssh_key": missing, "current": true },
+        ]);
+        let a = Some("a".to_string());
+        assert_eq!(
+            findings(profiles.clone(), false),
+            [
+                (Severity::Error, a.clone(), "Another profile has the same name".to_string()),
+                (Severity::Error, Some("c".to_string()), format!("SSH key for github.com not found at {}", missing)),
+                (Severity::Error, None, "More than one profile is marked current: a, c".to_string()),
+            ]
+        );
+        // Without looking at the disk, the missing key goes unnoticed
+        assert!(findings(profiles, true).iter().all(|(_, _, message)| !message.contains("not found")));
+
+        // A name differing only in case, and a shared email, are warnings
+        let profiles = serde_json::json!([
+            { "name": "Work", "email": "me@example.com", "ssh_key": "" },
+            { "name": "work", "email": "ME@example.com", "ssh_key": "" },
+        ]);
+        let work = Some("Work".to_string());
+        assert_eq!(
+            findings(profiles, true),
+            [
+                (Severity::Warning, work.clone(), "Differs from 'work' only in case".to_string()),
+                (Severity::Warning, work, "Shares the email me@example.com with 'work'".to_string()),
+            ]
+        );
     }
-}
 
-fn show_help() {
-    println!("gs - Git Profile Switcher
+    #[test]
+    fn format_profile_fills_placeholders_and_escapes() {
+        let profile = Profile {
+            name: "work".to_string(),
+            email: "jane@corp.example".to_string(),
+            ssh_key: "~/.ssh/work".to_string(),
+            group: Some("office".to_string()),
+            current: true,
+            ..Default::default()
+        };
+        let cases = [
+            ("{name} <{email}>", "work <jane@corp.example>\n"),
+            ("{current}{name}\\t{group}", "*work\toffice\n"),
+            ("{name}\\n", "work\n"),
+            ("{name}\\n{email}", "work\njane@corp.example\n"),
+            ("a\\\\tb", "a\\tb\n"),
+            // Unknown escapes and a trailing backslash stay as written
+            ("\\x{name}\\", "\\xwork\\\n"),
+            ("{{{name}}}", "{work}\n"),
+            ("{{name}} {icon}.", "{name} .\n"),
+            ("}{{", "}{\n"),
+        ];
+        for (template, expected) in cases {
+            assert_eq!(format_profile(template, &profile, "work").unwrap(), expected, "{}", template);
+        }
+        // The shown name can differ from the profile's
+        assert_eq!(format_profile("{name}", &profile, "work (env)").unwrap(), "work (env)\n");
 
-USAGE:
-    gs               Switch between profiles (toggle if only 2 profiles)
-    gs setup         Set up a new profile
-    gs list          List all profiles
-    gs edit          Edit an existing profile
-    gs rm            Remove a profile
-    gs help          Show this help message
+        let err = format_profile("{name} {nope}", &profile, "work").unwrap_err();
+        assert_eq!(error::exit_code(&err), ExitCode::Usage);
+        assert!(err.to_string().starts_with("Unknown placeholder '{nope}' in format. Valid placeholders: {name}, "), "{}", err);
+    }
 
-DESCRIPTION:
-    gs helps you manage multiple Git profiles for different accounts.
-    Each profile includes a name, email, and SSH key.
-    
-    Profiles are stored in ~/.config/gs/profiles.json");
+    #[test]
+    fn authenticated_user_reads_each_forge_greeting() {
+        let cases = [
+            ("github.com", "Hi octocat! You've successfully authenticated, but GitHub does not provide shell access.", Some("octocat")),
+            ("gitlab.com", "Welcome to GitLab, @octocat!", Some("octocat")),
+            ("codeberg.org", "Hi there, octocat! You've successfully authenticated with the key named laptop, but Forgejo does not provide shell access.", Some("octocat")),
+            ("bitbucket.org", "authenticated via ssh key.\n\nYou can use git to connect to Bitbucket. Shell access is disabled\nlogged in as octocat.", Some("octocat")),
+            ("git.sr.ht", "Hi ~octocat! You've successfully authenticated, but I do not provide an interactive shell.", Some("octocat")),
+            // Self-hosted servers, known only by their greeting
+            ("gitea.corp.example", "Hi there, jane! You've successfully authenticated with the key named work, but Gitea does not provide shell access.", Some("jane")),
+            ("gitlab.corp.example", "Welcome to GitLab, @jane.doe!", Some("jane.doe")),
+            ("git.corp.example", "Hi jane! You've successfully authenticated, but GitHub does not provide shell access.", Some("jane")),
+            ("git.corp.example", "You've successfully authenticated, but this server does not provide shell access.", Some("(unknown user)")),
+            ("git.corp.example", "Permission denied (publickey).", None),
+            ("git.corp.example", "", None),
+        ];
+        for (host, greeting, expected) in cases {
+            assert_eq!(authenticated_user(host, greeting).as_deref(), expected, "{}: {}", host, greeting);
+        }
+    }
 }
@@ -0,0 +1,201 @@
+//! Reading the profiles of other identity switchers, for `gs migrate-from`:
+//! the ini file shell-script switchers keep in ~/.git-profiles, and the JSON
+//! file of the npm `git-profile` package.
+//!
+//! Parsing only pulls out what each entry says. Turning entries into gs
+//! profiles, and checking them, is the caller's job.
+
+use serde_json::Value;
+
+/// A switcher gs can migrate from, and where it keeps its profiles.
+pub struct Tool {
+    pub name: &'static str,
+    pub path: &'static str,
+}
+
+pub const TOOLS: &[Tool] = &[
+    Tool { name: "git-profiles", path: "~/.git-profiles" },
+    Tool { name: "git-profile", path: "~/.git-profile.json" },
+];
+
+pub fn find_tool(name: &str) -> Option<&'static Tool> {
+    TOOLS.iter().find(|tool| tool.name.eq_ignore_ascii_case(name))
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Format {
+    Ini,
+    GitProfileJson,
+}
+
+impl Format {
+    pub fn label(self) -> &'static str {
+        match self {
+            Format::Ini => "git-profiles ini",
+            Format::GitProfileJson => "git-profile JSON",
+        }
+    }
+}
+
+/// One profile as the other tool stored it.
+#[derive(Debug, Default)]
+pub struct Entry {
+    /// The ini section or the JSON profile's title.
+    pub label: String,
+    /// The git user.name.
+    pub name: Option<String>,
+    pub email: Option<String>,
+    pub key: Option<String>,
+}
+
+/// Field names the tools use for the SSH key.
+const KEY_FIELDS: [&str; 6] = ["key", "ssh_key", "sshkey", "identity", "identityfile", "identity_file"];
+
+/// Field names for the git user.name.
+const NAME_FIELDS: [&str; 3] = ["name", "user", "username"];
+
+/// Read `content` in whichever format it's in, or None if it's neither.
+pub fn parse(content: &str) -> Option<(Format, Vec<Entry>)> {
+    if let Ok(json) = serde_json::from_str::<Value>(content) {
+        return parse_json(&json).map(|entries| (Format::GitProfileJson, entries));
+    }
+    parse_ini(content).map(|entries| (Format::Ini, entries))
+}
+
+/// `[label]` sections of `field = value` lines. `#` and `;` start comments,
+/// and values may be quoted.
+fn parse_ini(content: &str) -> Option<Vec<Entry>> {
+    let mut entries: Vec<Entry> = Vec::new();
+    for line in content.lines().map(str::trim) {
+        if line.is_empty() || line.starts_with(['#', ';']) {
+            continue;
+        }
+        if let Some(label) = line.strip_prefix('[').and_then(|l| l.strip_suffix(']')) {
+            entries.push(Entry { label: label.trim().to_string(), ..Default::default() });
+            continue;
+        }
+        // A field before any section, or a line that isn't one, means this
+        // isn't the format
+        let (field, value) = line.split_once('=')?;
+        let entry = entries.last_mut()?;
+        let value = value.trim().trim_matches(|c| c == '"' || c == '\'').to_string();
+        set_field(entry, &field.trim().to_lowercase(), value);
+    }
+    (!entries.is_empty()).then_some(entries)
+}
+
+/// git-profile's layouts: `{"profiles": [...]}`, a bare list of profile
+/// objects with a `title`, or an object of profiles keyed by title.
+fn parse_json(json: &Value) -> Option<Vec<Entry>> {
+    let list = json.get("profiles").unwrap_or(json);
+    let objects: Vec<(String, &serde_json::Map<String, Value>)> = match list {
+        Value::Array(items) => items
+            .iter()
+            .enumerate()
+            .map(|(i, item)| {
+                let object = item.as_object()?;
+                let title = ["title", "id", "profile"]
+                    .iter()
+                    .find_map(|field| object.get(*field).and_then(Value::as_str))
+                    .map_or_else(|| format!("#{}", i + 1), str::to_string);
+                Some((title, object))
+            })
+            .collect::<Option<_>>()?,
+        Value::Object(map) => map
+            .iter()
+            .map(|(title, item)| Some((title.clone(), item.as_object()?)))
+            .collect::<Option<_>>()?,
+        _ => return None,
+    };
+
+    let entries: Vec<Entry> = objects
+        .into_iter()
+        .map(|(label, object)| {
+            let mut entry = Entry { label, ..Default::default() };
+            for (field, value) in object {
+                if let Some(value) = value.as_str() {
+                    // sshKey and friends
+                    set_field(&mut entry, &field.to_lowercase().replace('-', "_"), value.to_string());
+                }
+            }
+            entry
+        })
+        .collect();
+    (!entries.is_empty()).then_some(entries)
+}
+
+fn set_field(entry: &mut Entry, field: &str, value: String) {
+    if value.is_empty() {
+        return;
+    }
+    if NAME_FIELDS.contains(&field) {
+        entry.name = Some(value);
+    } else if field == "email" {
+        entry.email = Some(value);
+    } else if KEY_FIELDS.contains(&field) || KEY_FIELDS.contains(&field.replace('_', "").as_str()) {
+        entry.key = Some(value);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// An entry as (label, name, email, key).
+    type Summary<'a> = (&'a str, Option<&'a str>, Option<&'a str>, Option<&'a str>);
+
+    fn summary(entries: &[Entry]) -> Vec<Summary<'_>> {
+        entries
+            .iter()
+            .map(|e| (e.label.as_str(), e.name.as_deref(), e.email.as_deref(), e.key.as_deref()))
+            .collect()
+    }
+
+    #[test]
+    fn ini_skips_comments_and_unquotes_values() {
+        let content = "# profiles\n; also a comment\n\n[ work ]\nname = \"Jane Doe\"\nEmail='jane@corp.com'\nssh_key = ~/.ssh/work\n\n[personal]\nuser=jane\nemail = jane@example.com\nIdentityFile = \"~/.ssh/id personal\"\nsigningkey = ignored\n";
+        let (format, entries) = parse(content).unwrap();
+        assert_eq!(format, Format::Ini);
+        assert_eq!(
+            summary(&entries),
+            [
+                ("work", Some("Jane Doe"), Some("jane@corp.com"), Some("~/.ssh/work")),
+                ("personal", Some("jane"), Some("jane@example.com"), Some("~/.ssh/id personal")),
+            ]
+        );
+    }
+
+    #[test]
+    fn ini_fields_before_any_section_are_not_the_format() {
+        assert!(parse("email = jane@example.com\n[work]\nname = Jane\n").is_none());
+        assert!(parse("[work]\nthis is not a field\n").is_none());
+        // Only comments
+        assert!(parse("# nothing here\n").is_none());
+    }
+
+    #[test]
+    fn json_reads_all_three_git_profile_layouts() {
+        let expected = [("work", Some("Jane Doe"), Some("jane@corp.com"), Some("~/.ssh/work"))];
+        let layouts = [
+            r#"{"profiles": [{"title": "work", "name": "Jane Doe", "email": "jane@corp.com", "sshKey": "~/.ssh/work"}]}"#,
+            r#"[{"title": "work", "name": "Jane Doe", "email": "jane@corp.com", "ssh-key": "~/.ssh/work"}]"#,
+            r#"{"work": {"name": "Jane Doe", "email": "jane@corp.com", "identity_file": "~/.ssh/work"}}"#,
+        ];
+        for content in layouts {
+            let (format, entries) = parse(content).unwrap_or_else(|| panic!("not parsed: {}", content));
+            assert_eq!(format, Format::GitProfileJson);
+            assert_eq!(summary(&entries), expected, "parsing {}", content);
+        }
+
+        // Untitled profiles in a list are numbered
+        let (_, entries) = parse(r#"[{"email": "a@example.com"}, {"id": "b", "email": "b@example.com"}]"#).unwrap();
+        assert_eq!(entries.iter().map(|e| e.label.as_str()).collect::<Vec<_>>(), ["#1", "b"]);
+    }
+
+    #[test]
+    fn other_formats_are_unknown() {
+        for content in ["", "42", r#""work""#, r#"{"profiles": ["work"]}"#, "[]", "{}", "name: Jane\nemail: jane@example.com\n"] {
+            assert!(parse(content).is_none(), "parsed {:?}", content);
+        }
+    }
+}
@@ -0,0 +1,329 @@
+//! Choosing profiles from a list, for `gs --pick`, `gs edit` and `gs rm`
+//! without a name.
+//!
+//! At a terminal that supports it, typing narrows the list to profiles whose
+//! name, email or group contains the typed letters in order, with the
+//! matching letters highlighted. Anywhere else (piped input, `TERM=dumb`)
+//! it's the same numbered prompt as always, so scripts keep working.
+
+use crate::error::{failure, ExitCode};
+use crate::{prompt, Profile};
+use anyhow::Result;
+use colored::Colorize;
+use ratatui::crossterm::cursor::{Hide, MoveToColumn, MoveUp, Show};
+use ratatui::crossterm::event::{self, Event, KeyCode, KeyEventKind, KeyModifiers};
+use ratatui::crossterm::terminal::{self, Clear, ClearType, DisableLineWrap, EnableLineWrap};
+use ratatui::crossterm::{execute, queue};
+use std::io::{self, IsTerminal, Write};
+
+/// Most entries shown at once; the list scrolls past that.
+const VISIBLE: usize = 10;
+
+/// Pick one profile. `query` pre-fills the filter.
+pub fn pick_one(title: &str, profiles: &[Profile], query: &str) -> Result<usize> {
+    Ok(pick(title, profiles, query, false)?[0])
+}
+
+/// Pick one or more profiles: tab marks entries, enter takes the marked
+/// ones (or the highlighted one if none are marked).
+pub fn pick_many(title: &str, profiles: &[Profile]) -> Result<Vec<usize>> {
+    pick(title, profiles, "", true)
+}
+
+fn pick(title: &str, profiles: &[Profile], query: &str, multi: bool) -> Result<Vec<usize>> {
+    let capable = io::stdin().is_terminal()
+        && io::stderr().is_terminal()
+        && std::env::var("TERM").map_or(true, |term| term != "dumb");
+    if capable && terminal::enable_raw_mode().is_ok() {
+        let result = Picker::new(profiles, query, multi).run(title);
+        let _ = terminal::disable_raw_mode();
+        let _ = execute!(io::stderr(), Show, EnableLineWrap);
+        return result;
+    }
+    numbered(title, profiles, multi)
+}
+
+/// The prompt for terminals that can't do the interactive list.
+fn numbered(title: &str, profiles: &[Profile], multi: bool) -> Result<Vec<usize>> {
+    eprintln!("=== {} ===", title);
+    for (i, profile) in profiles.iter().enumerate() {
+        let current = if profile.current { " (current)" } else { "" };
+        eprintln!("{}. {} <{}>{}", i + 1, profile.name, profile.email, current);
+    }
+
+    let input = match multi {
+        true => prompt::prompt("profile numbers", "Enter profile numbers (e.g. 1,3): ")?,
+        false => prompt::prompt("profile number", "Enter profile number: ")?,
+    };
+    let mut indices = Vec::new();
+    for part in input.split(|c: char| c == ',' || c.is_whitespace()).filter(|p| !p.is_empty()) {
+        match part.parse::<usize>() {
+            Ok(n) if n > 0 && n <= profiles.len() => indices.push(n - 1),
+            _ => return Err(failure(ExitCode::Usage, format!("Invalid profile number: {}", part))),
+        }
+    }
+
+    match indices.len() {
+        0 if multi => Err(failure(ExitCode::Usage, "No profiles selected")),
+        0 => Err(failure(ExitCode::Usage, "Invalid profile number")),
+        1 => Ok(indices),
+        _ if multi => Ok(indices),
+        _ => Err(failure(ExitCode::Usage, "Enter a single profile number")),
+    }
+}
+
+/// Where a query matched a profile: the field it matched in (0 name,
+/// 1 email, 2 group) and the char positions of the matched letters.
+struct Match {
+    index: usize,
+    field: usize,
+    positions: Vec<usize>,
+    score: usize,
+}
+
+/// Match `query`'s letters in order, ignoring case. Lower scores are
+/// better: an early start and few gaps.
+fn subsequence(query: &str, text: &str) -> Option<(Vec<usize>, usize)> {
+    let mut positions = Vec::new();
+    let mut chars = text.chars().enumerate();
+    for q in query.chars().flat_map(char::to_lowercase) {
+        let (i, _) = chars.find(|(_, c)| c.to_lowercase().eq([q]))?;
+        positions.push(i);
+    }
+    let start = positions.first().copied().unwrap_or(0);
+    let gaps = positions.windows(2).map(|w| w[1] - w[0] - 1).sum::<usize>();
+    Some((positions, start + gaps * 2))
+}
+
+fn fields(profile: &Profile) -> [&str; 3] {
+    [&profile.name, &profile.email, profile.group.as_deref().unwrap_or("")]
+}
+
+struct Picker<'a> {
+    profiles: &'a [Profile],
+    query: String,
+    multi: bool,
+    marked: Vec<usize>,
+    matches: Vec<Match>,
+    selected: usize,
+    offset: usize,
+    drawn: u16,
+}
+
+impl<'a> Picker<'a> {
+    fn new(profiles: &'a [Profile], query: &str, multi: bool) -> Picker<'a> {
+        let mut picker = Picker {
+            profiles,
+            query: query.to_string(),
+            multi,
+            marked: Vec::new(),
+            matches: Vec::new(),
+            selected: 0,
+            offset: 0,
+            drawn: 0,
+        };
+        picker.filter();
+        picker
+    }
+
+    /// Recompute the matches for the query, best first. Name matches beat
+    /// email matches beat group matches; the list order breaks ties.
+    fn filter(&mut self) {
+        self.matches = self
+            .profiles
+            .iter()
+            .enumerate()
+            .filter_map(|(index, profile)| {
+                fields(profile)
+                    .iter()
+                    .enumerate()
+                    .filter_map(|(field, text)| {
+                        let (positions, score) = subsequence(&self.query, text)?;
+                        Some(Match { index, field, positions, score })
+                    })
+                    .min_by_key(|m| (m.score, m.field))
+            })
+            .collect();
+        self.matches.sort_by_key(|m| (m.score, m.field, m.index));
+        self.selected = 0;
+        self.offset = 0;
+    }
+
+    fn run(&mut self, title: &str) -> Result<Vec<usize>> {
+        let mut err = io::stderr();
+        // Unwrapped, every entry takes exactly one line to redraw
+        execute!(err, Hide, DisableLineWrap)?;
+        loop {
+            self.draw(title)?;
+            let Event::Key(key) = event::read()? else { continue };
+            if key.kind != KeyEventKind::Press {
+                continue;
+            }
+            match key.code {
+                KeyCode::Char('c') if key.modifiers.contains(KeyModifiers::CONTROL) => {
+                    self.erase()?;
+                    return Err(failure(ExitCode::Aborted, "Aborted, no changes made"));
+                }
+                KeyCode::Esc => {
+                    self.erase()?;
+                    return Err(failure(ExitCode::Aborted, "Aborted"));
+                }
+                KeyCode::Enter => {
+                    let chosen = match (self.marked.is_empty(), self.matches.get(self.selected)) {
+                        (false, _) => self.marked.clone(),
+                        (true, Some(m)) => vec![m.index],
+                        (true, None) => continue,
+                    };
+                    self.erase()?;
+                    return Ok(chosen);
+                }
+                KeyCode::Up if self.selected > 0 => self.selected -= 1,
+                KeyCode::Down if self.selected + 1 < self.matches.len() => self.selected += 1,
+                KeyCode::Tab if self.multi => {
+                    if let Some(m) = self.matches.get(self.selected) {
+                        match self.marked.iter().position(|&i| i == m.index) {
+                            Some(pos) => {
+                                self.marked.remove(pos);
+                            }
+                            None => self.marked.push(m.index),
+                        }
+                    }
+                }
+                KeyCode::Backspace => {
+                    self.query.pop();
+                    self.filter();
+                }
+                KeyCode::Char(c) if !key.modifiers.contains(KeyModifiers::CONTROL) => {
+                    self.query.push(c);
+                    self.filter();
+                }
+                _ => {}
+            }
+        }
+    }
+
+    fn draw(&mut self, title: &str) -> Result<()> {
+        let (_, height) = terminal::size()?;
+        let visible = VISIBLE.min((height as usize).saturating_sub(3)).max(1);
+        if self.selected < self.offset {
+            self.offset = self.selected;
+        } else if self.selected >= self.offset + visible {
+            self.offset = self.selected + 1 - visible;
+        }
+
+        let hint = match self.multi {
+            true => "type to filter, ↑↓ move, tab mark, enter choose, esc cancel",
+            false => "type to filter, ↑↓ move, enter choose, esc cancel",
+        };
+        let mut lines = vec![format!("{} {}", title.bold(), format!("({})", hint).dimmed()), format!("> {}_", self.query)];
+        for (row, m) in self.matches.iter().enumerate().skip(self.offset).take(visible) {
+            let profile = &self.profiles[m.index];
+            let pointer = if row == self.selected { "▸".cyan().bold().to_string() } else { " ".to_string() };
+            let mark = match self.multi {
+                true if self.marked.contains(&m.index) => "● ".red().to_string(),
+                true => "○ ".dimmed().to_string(),
+                false => String::new(),
+            };
+            let [name, email, group] = fields(profile).map(|text| text.to_string());
+            let highlight = |field: usize, text: &str| match field == m.field {
+                true => highlight(text, &m.positions),
+                false => text.to_string(),
+            };
+            let mut line = format!("{} {}{} <{}>", pointer, mark, highlight(0, &name), highlight(1, &email));
+            if !group.is_empty() {
+                line.push_str(&format!(" [{}]", highlight(2, &group)));
+            }
+            if profile.current {
+                line.push_str(&" (current)".green().to_string());
+            }
+            lines.push(line);
+        }
+        if self.matches.is_empty() {
+            lines.push(format!("  {}", "No matching profiles".dimmed()));
+        }
+
+        let mut err = io::stderr();
+        self.clear_drawn(&mut err)?;
+        write!(err, "{}", lines.join("\r\n"))?;
+        self.drawn = lines.len() as u16;
+        err.flush()?;
+        Ok(())
+    }
+
+    /// Move back to the first line drawn and clear everything below it.
+    fn clear_drawn(&self, err: &mut io::Stderr) -> Result<()> {
+        if self.drawn > 1 {
+            queue!(err, MoveUp(self.drawn - 1))?;
+        }
+        queue!(err, MoveToColumn(0), Clear(ClearType::FromCursorDown))?;
+        Ok(())
+    }
+
+    fn erase(&mut self) -> Result<()> {
+        let mut err = io::stderr();
+        self.clear_drawn(&mut err)?;
+        self.drawn = 0;
+        err.flush()?;
+        Ok(())
+    }
+}
+
+/// `text` with the chars at `positions` picked out.
+fn highlight(text: &str, positions: &[usize]) -> String {
+    text.chars()
+        .enumerate()
+        .map(|(i, c)| match positions.contains(&i) {
+            true => c.to_string().yellow().bold().underline().to_string(),
+            false => c.to_string(),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn profile(name: &str, email: &str, group: Option<&str>) -> Profile {
+        Profile { name: name.to_string(), email: email.to_string(), group: group.map(str::to_string), ..Default::default() }
+    }
+
+    #[test]
+    fn subsequence_prefers_early_and_tight_matches() {
+        assert_eq!(subsequence("wk", "work"), Some((vec![0, 3], 4)));
+        assert_eq!(subsequence("WOR", "work"), Some((vec![0, 1, 2], 0)));
+        assert_eq!(subsequence("or", "Work"), Some((vec![1, 2], 1)));
+        // Letters out of order, or missing, don't match
+        assert_eq!(subsequence("kw", "work"), None);
+        assert_eq!(subsequence("wx", "work"), None);
+        // Everything matches an empty query equally
+        assert_eq!(subsequence("", "work"), Some((vec![], 0)));
+
+        // Positions count chars, not bytes
+        assert_eq!(subsequence("é", "café").map(|(positions, _)| positions), Some(vec![3]));
+    }
+
+    #[test]
+    fn filter_orders_by_score_then_field_then_list_order() {
+        let profiles = [
+            profile("personal", "me@home.example", None),
+            profile("oss", "work@oss.example", None),
+            profile("work", "jane@corp.example", Some("office")),
+            profile("client", "jane@client.example", Some("work")),
+            profile("web", "w@x.example", None),
+        ];
+        let ranked = |query: &str| {
+            let picker = Picker::new(&profiles, query, false);
+            picker.matches.iter().map(|m| (profiles[m.index].name.as_str(), m.field)).collect::<Vec<_>>()
+        };
+
+        // A name match beats an email and a group match with the same score
+        assert_eq!(ranked("work"), [("work", 0), ("oss", 1), ("client", 2)]);
+        assert_eq!(ranked("wb"), [("web", 0)]);
+        // The score comes first, and each profile counts its best field
+        assert_eq!(ranked("oe"), [("personal", 1), ("work", 2), ("oss", 1)]);
+        // Equal scores in the same field keep the list order
+        assert_eq!(ranked("w"), [("work", 0), ("web", 0), ("oss", 1), ("client", 2)]);
+        // No query keeps the list order
+        assert_eq!(ranked("").iter().map(|(name, _)| *name).collect::<Vec<_>>(), ["personal", "oss", "work", "client", "web"]);
+    }
+}
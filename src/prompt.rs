@@ -0,0 +1,226 @@
+use crate::error::{failure, ExitCode};
+use anyhow::Result;
+use std::io::{self, BufRead, IsTerminal, Write};
+use std::process;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+
+/// Whether we're blocked reading an answer. Flows ask everything before they
+/// write anything, so Ctrl-C at a prompt never leaves a change half-made.
+static WAITING: AtomicBool = AtomicBool::new(false);
+
+/// How many critical sections are open, and whether Ctrl-C came during
+/// one. The exit waits for the last of them to close.
+static CRITICAL: AtomicUsize = AtomicUsize::new(0);
+static INTERRUPTED: AtomicBool = AtomicBool::new(false);
+
+/// What Ctrl-C does, given where we are.
+#[derive(Debug, PartialEq, Eq)]
+enum Interrupt {
+    /// Nothing has been written yet: exit with the Aborted code.
+    Abort,
+    /// Git, SSH or the profiles are being written: finish first.
+    Defer,
+    /// Exit with 130 like any interrupted program.
+    Exit,
+}
+
+fn on_interrupt(waiting: bool, critical: bool) -> Interrupt {
+    match (waiting, critical) {
+        (true, _) => Interrupt::Abort,
+        (false, true) => Interrupt::Defer,
+        (false, false) => Interrupt::Exit,
+    }
+}
+
+/// Exit cleanly on Ctrl-C: with the Aborted code while waiting at a prompt,
+/// after the change being written when inside a `critical` section, or 130
+/// like any interrupted program otherwise.
+pub fn handle_interrupts() {
+    // Failure only means a handler is already installed
+    let _ = ctrlc::set_handler(|| {
+        match on_interrupt(WAITING.load(Ordering::SeqCst), CRITICAL.load(Ordering::SeqCst) > 0) {
+            Interrupt::Abort => {
+                eprintln!("\nAborted, no changes made");
+                process::exit(ExitCode::Aborted as i32);
+            }
+            Interrupt::Defer => {
+                eprintln!("\nInterrupted, finishing the current change first");
+                INTERRUPTED.store(true, Ordering::SeqCst);
+            }
+            Interrupt::Exit => {
+                eprintln!("\nInterrupted");
+                process::exit(130);
+            }
+        }
+    });
+}
+
+/// Hold off Ctrl-C until the returned guard is dropped, so an apply or save
+/// is never cut off halfway. Sections nest; an interrupt that came in the
+/// meantime exits with 130 when the outermost one ends.
+#[must_use]
+pub fn critical() -> Critical {
+    CRITICAL.fetch_add(1, Ordering::SeqCst);
+    Critical
+}
+
+pub struct Critical;
+
+impl Drop for Critical {
+    fn drop(&mut self) {
+        if CRITICAL.fetch_sub(1, Ordering::SeqCst) == 1 && INTERRUPTED.load(Ordering::SeqCst) {
+            process::exit(130);
+        }
+    }
+}
+
+/// Print a question and read one line of input.
+///
+/// When stdin is piped, answers are consumed line by line and each question
+/// is echoed to stderr together with the answer it got, so provisioning
+/// scripts leave a readable log. End of input is an error rather than an
+/// empty answer: "Aborted (EOF)" at a terminal, "missing answer for '<field>'"
+/// for a pipe.
+pub fn prompt(field: &str, question: &str) -> Result<String> {
+    Answers::stdin().prompt(field, question)
+}
+
+/// Like `prompt`, but the answer must not be empty. A terminal user is
+/// asked again; piped input fails, since re-asking would consume the
+/// answer meant for the next question.
+pub fn prompt_required(field: &str, question: &str) -> Result<String> {
+    Answers::stdin().prompt_required(field, question)
+}
+
+/// Ask a yes/no question that defaults to no.
+pub fn confirm(question: &str) -> Result<bool> {
+    Answers::stdin().confirm(question)
+}
+
+/// Where answers come from: stdin, or given lines in tests.
+struct Answers<R> {
+    input: R,
+    interactive: bool,
+}
+
+impl Answers<io::StdinLock<'static>> {
+    fn stdin() -> Self {
+        Answers { input: io::stdin().lock(), interactive: io::stdin().is_terminal() }
+    }
+}
+
+impl<R: BufRead> Answers<R> {
+    fn prompt(&mut self, field: &str, question: &str) -> Result<String> {
+        if self.interactive {
+            eprint!("{}", question);
+            io::stderr().flush()?;
+        }
+
+        let mut answer = String::new();
+        WAITING.store(true, Ordering::SeqCst);
+        let read = self.input.read_line(&mut answer);
+        WAITING.store(false, Ordering::SeqCst);
+        if read? == 0 {
+            if self.interactive {
+                eprintln!();
+                return Err(failure(ExitCode::Aborted, "Aborted (EOF)"));
+            }
+            return Err(failure(ExitCode::Aborted, format!("missing answer for '{}'", field)));
+        }
+
+        let answer = answer.trim().to_string();
+        if !self.interactive {
+            eprintln!("{}{}", question, answer);
+        }
+        Ok(answer)
+    }
+
+    fn prompt_required(&mut self, field: &str, question: &str) -> Result<String> {
+        loop {
+            let answer = self.prompt(field, question)?;
+            if !answer.is_empty() {
+                return Ok(answer);
+            }
+            if !self.interactive {
+                return Err(failure(ExitCode::Usage, format!("invalid answer for '{}': a value is required", field)));
+            }
+            eprintln!("A value is required.");
+        }
+    }
+
+    fn confirm(&mut self, question: &str) -> Result<bool> {
+        let answer = self.prompt("confirmation", &format!("{} (y/N): ", question))?.to_lowercase();
+        Ok(answer == "y" || answer == "yes")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::exit_code;
+
+    fn answers(input: &str, interactive: bool) -> Answers<&[u8]> {
+        Answers { input: input.as_bytes(), interactive }
+    }
+
+    #[test]
+    fn answers_are_read_line_by_line_and_trimmed() {
+        let mut input = answers("  work \r\nJane Doe\n\nlast", false);
+        assert_eq!(input.prompt("name", "Name: ").unwrap(), "work");
+        assert_eq!(input.prompt("git name", "Git name: ").unwrap(), "Jane Doe");
+        assert_eq!(input.prompt("description", "Description: ").unwrap(), "");
+        // A last line without a newline still counts
+        assert_eq!(input.prompt("group", "Group: ").unwrap(), "last");
+    }
+
+    #[test]
+    fn end_of_input_is_an_abort() {
+        let err = answers("", false).prompt("email", "Email: ").unwrap_err();
+        assert_eq!(err.to_string(), "missing answer for 'email'");
+        assert_eq!(exit_code(&err), ExitCode::Aborted);
+
+        let err = answers("", true).prompt("email", "Email: ").unwrap_err();
+        assert_eq!(err.to_string(), "Aborted (EOF)");
+        assert_eq!(exit_code(&err), ExitCode::Aborted);
+
+        let err = answers("", true).confirm("Remove?").unwrap_err();
+        assert_eq!(exit_code(&err), ExitCode::Aborted);
+    }
+
+    #[test]
+    fn required_answers_are_asked_again_at_a_terminal() {
+        let mut input = answers("\n  \nwork\nnext\n", true);
+        assert_eq!(input.prompt_required("name", "Name: ").unwrap(), "work");
+        assert_eq!(input.prompt("email", "Email: ").unwrap(), "next");
+
+        // Until the input runs out
+        let err = answers("\n\n", true).prompt_required("name", "Name: ").unwrap_err();
+        assert_eq!(err.to_string(), "Aborted (EOF)");
+    }
+
+    #[test]
+    fn required_answers_missing_from_a_pipe_fail() {
+        let mut input = answers("\nwork\n", false);
+        let err = input.prompt_required("name", "Name: ").unwrap_err();
+        assert_eq!(err.to_string(), "invalid answer for 'name': a value is required");
+        assert_eq!(exit_code(&err), ExitCode::Usage);
+        // The next answer is still there for the next question
+        assert_eq!(input.prompt("email", "Email: ").unwrap(), "work");
+    }
+
+    #[test]
+    fn interrupts_wait_for_critical_sections() {
+        assert_eq!(on_interrupt(true, false), Interrupt::Abort);
+        assert_eq!(on_interrupt(false, true), Interrupt::Defer);
+        assert_eq!(on_interrupt(false, false), Interrupt::Exit);
+    }
+
+    #[test]
+    fn confirm_defaults_to_no() {
+        let cases = [("y", true), ("Y", true), ("yes", true), (" YES ", true), ("", false), ("n", false), ("no", false), ("yep", false)];
+        for (answer, expected) in cases {
+            let input = format!("{}\n", answer);
+            assert_eq!(answers(&input, true).confirm("Go?").unwrap(), expected, "answer {:?}", answer);
+        }
+    }
+}
@@ -0,0 +1,91 @@
+//! The git hooks `gs install-hook` puts in a repository: small sh scripts
+//! that run one gs command.
+//!
+//! A hook that was already there isn't overwritten. It's renamed to
+//! `<hook>.gs-chained` and run after gs's command, with the same arguments,
+//! and removing gs's hook puts it back.
+
+use crate::error::{failure, ExitCode, FileContext};
+use anyhow::Result;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// The line that marks a hook as gs's.
+const MARKER: &str = "# Installed by gs install-hook.";
+const CHAINED_SUFFIX: &str = ".gs-chained";
+
+/// What installing one hook did.
+pub enum Installed {
+    /// There was no hook; now there's gs's.
+    Created,
+    /// Another hook was there; it now runs after gs's.
+    Chained,
+    /// gs's hook was there already and was rewritten.
+    Updated,
+}
+
+fn chained_path(dir: &Path, hook: &str) -> PathBuf {
+    dir.join(format!("{}{}", hook, CHAINED_SUFFIX))
+}
+
+/// Whether `hook` in `dir` is one gs installed.
+pub fn is_installed(dir: &Path, hook: &str) -> bool {
+    fs::read_to_string(dir.join(hook)).is_ok_and(|script| script.contains(MARKER))
+}
+
+/// Install `hook` in `dir` to run `command`, a line of sh that decides
+/// itself whether a failure stops git.
+pub fn install(dir: &Path, hook: &str, command: &str) -> Result<Installed> {
+    let path = dir.join(hook);
+    let chained = chained_path(dir, hook);
+    let installed = if is_installed(dir, hook) {
+        Installed::Updated
+    } else if path.exists() {
+        if chained.exists() {
+            return Err(failure(
+                ExitCode::Error,
+                format!("Both {} and {} exist; move one of them out of the way", path.display(), chained.display()),
+            ));
+        }
+        tracing::debug!("rename {} to {}", path.display(), chained.display());
+        fs::rename(&path, &chained).file_context("rename", &path)?;
+        Installed::Chained
+    } else {
+        if !dir.exists() {
+            fs::create_dir_all(dir).file_context("create", dir)?;
+        }
+        Installed::Created
+    };
+
+    let script = format!(
+        "#!/bin/sh\n{}\n# `gs install-hook --remove` takes it out again.\n{}\nif [ -x \"$0{}\" ]; then\n    exec \"$0{}\" \"$@\"\nfi\n",
+        MARKER, command, CHAINED_SUFFIX, CHAINED_SUFFIX
+    );
+    tracing::debug!("write {}", path.display());
+    fs::write(&path, script).file_context("write", &path)?;
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o755)).file_context("change permissions of", &path)?;
+    }
+    Ok(installed)
+}
+
+/// Take out gs's `hook` from `dir`, restoring the hook it chained to.
+/// Returns whether there was one to remove; another tool's hook is left
+/// alone.
+pub fn remove(dir: &Path, hook: &str) -> Result<bool> {
+    if !is_installed(dir, hook) {
+        return Ok(false);
+    }
+    let path = dir.join(hook);
+    let chained = chained_path(dir, hook);
+    if chained.exists() {
+        tracing::debug!("rename {} to {}", chained.display(), path.display());
+        fs::rename(&chained, &path).file_context("rename", &chained)?;
+    } else {
+        tracing::debug!("remove {}", path.display());
+        fs::remove_file(&path).file_context("remove", &path)?;
+    }
+    Ok(true)
+}
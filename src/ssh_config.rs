@@ -0,0 +1,868 @@
+//! Just enough of the ssh_config(5) grammar to find and edit one Host block
+//! without disturbing anything around it.
+//!
+//! Keywords are case-insensitive and separated from their arguments by
+//! whitespace or a single `=`. Arguments may be quoted with `"` or `'`, and a
+//! backslash escapes a quote, a backslash or whitespace. Lines whose
+//! first non-blank character is `#` are comments. Every `Host` or `Match`
+//! line ends the previous section. `Include` is followed the way OpenSSH
+//! does: globs are expanded in lexical order and relative paths resolve
+//! against ~/.ssh.
+
+use crate::error::FileContext;
+use anyhow::Result;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// OpenSSH refuses to nest Includes deeper than this.
+const MAX_INCLUDE_DEPTH: usize = 16;
+
+/// Comment lines around the options gs writes for a profile inside a Host
+/// block. Only lines between them are ever replaced.
+const OPTIONS_BEGIN: &str = "# >>> gs profile options >>>";
+const OPTIONS_END: &str = "# <<< gs profile options <<<";
+
+/// Keywords a profile's extra options may not use: they would start
+/// another section, pull in other files, or fight with the IdentityFile
+/// gs manages.
+pub const FORBIDDEN_OPTIONS: [&str; 4] = ["host", "match", "include", "identityfile"];
+
+/// One line of the file, tokenized.
+#[derive(Debug, Clone)]
+pub struct Line<'a> {
+    pub raw: &'a str,
+    /// Lowercased keyword, or None for blank lines and comments.
+    pub keyword: Option<String>,
+    pub args: Vec<String>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum SectionKind {
+    /// Options before the first Host or Match line.
+    Global,
+    Host(Vec<String>),
+    Match,
+}
+
+/// A run of lines governed by one Host/Match line (or the global preamble).
+#[derive(Debug, Clone)]
+pub struct Section {
+    pub kind: SectionKind,
+    /// Index of the Host/Match line itself (or 0 for the preamble).
+    pub start: usize,
+    /// One past the last line of the section.
+    pub end: usize,
+}
+
+impl Section {
+    /// True for a `Host` line that names exactly this host and nothing else.
+    /// Blocks shared with other patterns are left alone, since editing them
+    /// would change those hosts too.
+    pub fn is_exclusively_for(&self, host: &str) -> bool {
+        match &self.kind {
+            SectionKind::Host(patterns) => patterns.len() == 1 && patterns[0].eq_ignore_ascii_case(host),
+            _ => false,
+        }
+    }
+}
+
+pub fn tokenize(raw: &str) -> Line<'_> {
+    let trimmed = raw.trim();
+    if trimmed.is_empty() || trimmed.starts_with('#') {
+        return Line { raw, keyword: None, args: Vec::new() };
+    }
+
+    let (keyword, rest) = split_keyword(trimmed);
+    Line { raw, keyword: Some(keyword.to_lowercase()), args: split_args(rest) }
+}
+
+/// The keyword of a trimmed, non-comment line and the text of its arguments.
+fn split_keyword(trimmed: &str) -> (&str, &str) {
+    let keyword_end = trimmed
+        .find(|c: char| c.is_whitespace() || c == '=')
+        .unwrap_or(trimmed.len());
+
+    // Skip the separator: whitespace with at most one '='
+    let mut rest = trimmed[keyword_end..].trim_start();
+    if let Some(after_eq) = rest.strip_prefix('=') {
+        rest = after_eq.trim_start();
+    }
+    (&trimmed[..keyword_end], rest)
+}
+
+/// Split arguments on whitespace, honoring quotes and backslash escapes the
+/// way OpenSSH does.
+fn split_args(input: &str) -> Vec<String> {
+    let mut args = Vec::new();
+    let mut current = String::new();
+    let mut quote = None;
+    let mut has_token = false;
+    let mut chars = input.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        match c {
+            // Anything else after a backslash is literal, backslash
+            // included, so Windows paths read as written
+            '\\' if chars.peek().is_some_and(|&next| matches!(next, '\\' | '"' | '\'') || next.is_whitespace()) => {
+                current.extend(chars.next());
+                has_token = true;
+            }
+            '"' | '\'' if quote.is_none() => {
+                quote = Some(c);
+                has_token = true;
+            }
+            c if quote == Some(c) => quote = None,
+            c if c.is_whitespace() && quote.is_none() => {
+                if has_token {
+                    args.push(std::mem::take(&mut current));
+                    has_token = false;
+                }
+            }
+            c => {
+                current.push(c);
+                has_token = true;
+            }
+        }
+    }
+    if has_token {
+        args.push(current);
+    }
+
+    args
+}
+
+pub fn parse_lines(content: &str) -> Vec<Line<'_>> {
+    content.lines().map(tokenize).collect()
+}
+
+pub fn sections(lines: &[Line]) -> Vec<Section> {
+    let mut sections = vec![Section { kind: SectionKind::Global, start: 0, end: lines.len() }];
+
+    for (i, line) in lines.iter().enumerate() {
+        let kind = match line.keyword.as_deref() {
+            Some("host") => SectionKind::Host(line.args.clone()),
+            Some("match") => SectionKind::Match,
+            _ => continue,
+        };
+
+        sections.last_mut().unwrap().end = i;
+        sections.push(Section { kind, start: i, end: lines.len() });
+    }
+
+    sections
+}
+
+/// The Host block for `host`, if there is one.
+pub fn find_host_block(lines: &[Line], host: &str) -> Option<Section> {
+    sections(lines).into_iter().find(|s| s.is_exclusively_for(host))
+}
+
+/// The IdentityFile configured in `host`'s block.
+pub fn identity_file(content: &str, host: &str) -> Option<String> {
+    let lines = parse_lines(content);
+    let block = find_host_block(&lines, host)?;
+
+    let line = lines[block.start + 1..block.end].iter().find(|l| l.keyword.as_deref() == Some("identityfile"))?;
+    let rest = split_keyword(line.raw.trim()).1;
+    match line.args.as_slice() {
+        // IdentityFile takes one path. Several words are an unquoted path
+        // with spaces, as earlier versions of gs wrote it
+        [_, _, ..] if !rest.starts_with(['"', '\'']) => Some(rest.to_string()),
+        args => args.first().cloned(),
+    }
+}
+
+/// `value` as one ssh_config argument: quoted, with quotes and backslashes
+/// escaped, when it has whitespace, quotes, a `#` or a doubled backslash
+/// (which would read back as one), else as it is.
+pub fn quote_arg(value: &str) -> String {
+    if !value.is_empty() && !value.contains("\\\\") && !value.contains(|c: char| c.is_whitespace() || "\"'#".contains(c)) {
+        return value.to_string();
+    }
+    let mut quoted = String::from("\"");
+    for c in value.chars() {
+        if c == '"' || c == '\\' {
+            quoted.push('\\');
+        }
+        quoted.push(c);
+    }
+    quoted.push('"');
+    quoted
+}
+
+/// Return `content` with `host`'s IdentityFile pointing at `key`, adding the
+/// line to an existing block or appending a whole new block as needed. A new
+/// block connects as `user`; an existing block's User is left alone. A path
+/// with spaces is quoted.
+///
+/// With `identities_only`, the block also gets `IdentitiesOnly yes` so ssh
+/// doesn't offer other agent keys first. An IdentitiesOnly line the user
+/// already has is left alone, whatever its value.
+///
+/// An empty `key` is a profile that uses the agent's keys: the block's
+/// IdentityFile is removed and no IdentitiesOnly line is added.
+///
+/// The change is spliced into the original text, so every byte outside the
+/// lines it touches, line endings and a missing final newline included,
+/// comes back exactly as it was.
+pub fn set_identity_file(content: &str, host: &str, key: &str, user: &str, identities_only: bool) -> String {
+    let lines = parse_lines(content);
+    let newline = line_ending(content);
+    // Byte offset of line `i`, or the end of the file past the last line
+    let offset = |i: usize| lines.get(i).map_or(content.len(), |l| l.raw.as_ptr() as usize - content.as_ptr() as usize);
+    let mut updated = content.to_string();
+
+    match find_host_block(&lines, host) {
+        Some(block) => {
+            let body = block.start + 1..block.end;
+            let indent = block_indent(&lines[body.clone()]);
+            let find = |keyword: &str| body.clone().find(|&i| lines[i].keyword.as_deref() == Some(keyword));
+
+            // Insert after the block's last option so trailing blank lines
+            // and comments stay where they were, but never among the
+            // profile options, which get replaced wholesale
+            let region = options_region(&lines, &block);
+            let insert_at = body
+                .clone()
+                .rev()
+                .find(|&i| lines[i].keyword.is_some())
+                .map(|i| match region {
+                    Some((begin, end)) if (begin..=end).contains(&i) => begin,
+                    _ => i + 1,
+                })
+                .unwrap_or(block.start + 1);
+
+            let existing = find("identityfile");
+            tracing::debug!(
+                "Host {} block found at lines {}-{}, IdentityFile {}",
+                host,
+                block.start + 1,
+                block.end,
+                if existing.is_some() { "replaced" } else { "added" }
+            );
+            let mut inserted = Vec::new();
+            if existing.is_none() && !key.is_empty() {
+                inserted.push(format!("{}IdentityFile {}", indent, quote_arg(key)));
+            }
+            if identities_only && !key.is_empty() && find("identitiesonly").is_none() {
+                inserted.push(format!("{}IdentitiesOnly yes", indent));
+            }
+
+            // Insert first: it's never before the replaced line, so the
+            // replacement's offset stays valid
+            if !inserted.is_empty() {
+                let at = offset(insert_at);
+                let text = if at == content.len() && !content.ends_with('\n') {
+                    format!("{}{}", newline, inserted.join(newline))
+                } else {
+                    format!("{}{}", inserted.join(newline), newline)
+                };
+                updated.insert_str(at, &text);
+            }
+            match existing {
+                // Nothing was inserted, so the offsets all still hold
+                Some(i) if key.is_empty() => updated.replace_range(offset(i)..offset(i + 1), ""),
+                Some(i) => {
+                    let at = offset(i);
+                    updated.replace_range(at..at + lines[i].raw.len(), &format!("{}IdentityFile {}", indent, quote_arg(key)));
+                }
+                None => {}
+            }
+        }
+        None => {
+            tracing::debug!("no Host {} block found, appending one", host);
+            let mut block = Vec::new();
+            if lines.last().is_some_and(|l| !l.raw.trim().is_empty()) {
+                block.push(String::new()); // Add empty line for spacing
+            }
+
+            block.push(format!("Host {}", host));
+            block.push(format!("    HostName {}", host));
+            block.push(format!("    User {}", user));
+            if !key.is_empty() {
+                block.push(format!("    IdentityFile {}", quote_arg(key)));
+                if identities_only {
+                    block.push("    IdentitiesOnly yes".to_string());
+                }
+            }
+            // A file that didn't end with a newline still doesn't
+            if content.is_empty() || content.ends_with('\n') {
+                updated.push_str(&format!("{}{}", block.join(newline), newline));
+            } else {
+                updated.push_str(&format!("{}{}", newline, block.join(newline)));
+            }
+        }
+    }
+
+    updated
+}
+
+/// The line ending most of `content`'s lines use, for lines gs adds: CRLF
+/// in files edited on Windows, else LF.
+fn line_ending(content: &str) -> &'static str {
+    let crlf = content.matches("\r\n").count();
+    if crlf > content.matches('\n').count() - crlf { "\r\n" } else { "\n" }
+}
+
+/// The line indices of the options markers in a block, if both are there.
+fn options_region(lines: &[Line], block: &Section) -> Option<(usize, usize)> {
+    let begin = (block.start + 1..block.end).find(|&i| lines[i].raw.trim() == OPTIONS_BEGIN)?;
+    let end = (begin + 1..block.end).find(|&i| lines[i].raw.trim() == OPTIONS_END)?;
+    Some((begin, end))
+}
+
+/// Return `content` with the profile options in `host`'s block replaced by
+/// `options`, between marker comments right after its IdentityFile (or its
+/// User or HostName, without one). Lines
+/// outside the markers are never touched; with no options the markers go
+/// too.
+pub fn set_options(content: &str, host: &str, options: &[String]) -> String {
+    let lines = parse_lines(content);
+    let Some(block) = find_host_block(&lines, host) else {
+        return content.to_string();
+    };
+    let newline = line_ending(content);
+    let offset = |i: usize| lines.get(i).map_or(content.len(), |l| l.raw.as_ptr() as usize - content.as_ptr() as usize);
+    let indent = block_indent(&lines[block.start + 1..block.end]);
+
+    let mut region = String::new();
+    if !options.is_empty() {
+        for line in std::iter::once(OPTIONS_BEGIN).chain(options.iter().map(|o| o.trim())).chain([OPTIONS_END]) {
+            region.push_str(&format!("{}{}{}", indent, line, newline));
+        }
+    }
+
+    let mut updated = content.to_string();
+    match options_region(&lines, &block) {
+        Some((begin, end)) => {
+            tracing::debug!("Host {} options replaced at lines {}-{}", host, begin + 1, end + 1);
+            updated.replace_range(offset(begin)..offset(end + 1), &region);
+        }
+        None if options.is_empty() => {}
+        None => {
+            // A block without an IdentityFile, for the agent's keys, gets
+            // them after its User or HostName instead
+            let find = |keyword: &str| (block.start + 1..block.end).find(|&i| lines[i].keyword.as_deref() == Some(keyword));
+            let after = find("identityfile").or_else(|| find("user")).or_else(|| find("hostname")).unwrap_or(block.start);
+            let at = offset(after + 1);
+            tracing::debug!("Host {} options added after line {}", host, after + 1);
+            if at == content.len() && !content.ends_with('\n') {
+                updated.insert_str(at, &format!("{}{}", newline, region.trim_end_matches(newline)));
+            } else {
+                updated.insert_str(at, &region);
+            }
+        }
+    }
+    updated
+}
+
+/// Indentation used by a block's existing options, defaulting to 4 spaces.
+fn block_indent(body: &[Line]) -> String {
+    body.iter()
+        .find(|l| l.keyword.is_some())
+        .map(|l| l.raw[..l.raw.len() - l.raw.trim_start().len()].to_string())
+        .unwrap_or_else(|| "    ".to_string())
+}
+
+/// Where a Host block lives: which file, and the line of its Host keyword.
+#[derive(Debug, Clone, PartialEq)]
+pub struct BlockLocation {
+    pub path: PathBuf,
+    pub line: usize,
+}
+
+/// Every Host block exclusively for `host`, in the order ssh reads them,
+/// descending into Include directives along the way.
+pub fn locate_host_blocks(main_path: &Path, ssh_dir: &Path, host: &str) -> Result<Vec<BlockLocation>> {
+    let mut found = Vec::new();
+    walk(main_path, ssh_dir, host, 0, &mut found)?;
+    Ok(found)
+}
+
+fn walk(path: &Path, ssh_dir: &Path, host: &str, depth: usize, found: &mut Vec<BlockLocation>) -> Result<()> {
+    if depth > MAX_INCLUDE_DEPTH || !path.is_file() {
+        return Ok(());
+    }
+
+    let content = fs::read_to_string(path).file_context("read", path)?;
+    let lines = parse_lines(&content);
+    let blocks: Vec<usize> = sections(&lines)
+        .iter()
+        .filter(|s| s.is_exclusively_for(host))
+        .map(|s| s.start)
+        .collect();
+
+    for (i, line) in lines.iter().enumerate() {
+        if blocks.contains(&i) {
+            found.push(BlockLocation { path: path.to_path_buf(), line: i });
+        }
+
+        if line.keyword.as_deref() == Some("include") {
+            for pattern in &line.args {
+                for included in expand_include(pattern, ssh_dir) {
+                    walk(&included, ssh_dir, host, depth + 1, found)?;
+                }
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Resolve one Include argument to the files it names, sorted.
+fn expand_include(pattern: &str, ssh_dir: &Path) -> Vec<PathBuf> {
+    let path = if let Some(rest) = pattern.strip_prefix("~/") {
+        match dirs::home_dir() {
+            Some(home) => home.join(rest),
+            None => return Vec::new(),
+        }
+    } else if Path::new(pattern).is_absolute() {
+        PathBuf::from(pattern)
+    } else {
+        ssh_dir.join(pattern)
+    };
+
+    let Some(path) = path.to_str() else {
+        return Vec::new();
+    };
+    let mut paths: Vec<PathBuf> = match glob::glob(path) {
+        Ok(matches) => matches.filter_map(|m| m.ok()).collect(),
+        Err(_) => vec![PathBuf::from(path)],
+    };
+    paths.sort();
+    paths
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn host_block(content: &str, host: &str) -> Option<(usize, usize)> {
+        find_host_block(&parse_lines(content), host).map(|s| (s.start, s.end))
+    }
+
+    #[test]
+    fn tokenize_splits_keyword_and_args() {
+        let cases: &[(&str, Option<&str>, &[&str])] = &[
+            ("Host github.com", Some("host"), &["github.com"]),
+            ("host github.com", Some("host"), &["github.com"]),
+            ("  HOST   a   b  ", Some("host"), &["a", "b"]),
+            ("Host=github.com", Some("host"), &["github.com"]),
+            ("Host = github.com", Some("host"), &["github.com"]),
+            ("IdentityFile \"~/my keys/id\"", Some("identityfile"), &["~/my keys/id"]),
+            ("Match host github.com", Some("match"), &["host", "github.com"]),
+            ("# Host github.com", None, &[]),
+            ("   # indented comment", None, &[]),
+            ("", None, &[]),
+            ("\t", None, &[]),
+        ];
+        for (raw, keyword, args) in cases {
+            let line = tokenize(raw);
+            assert_eq!(line.keyword.as_deref(), *keyword, "keyword of {:?}", raw);
+            assert_eq!(line.args, *args, "args of {:?}", raw);
+            assert_eq!(line.raw, *raw);
+        }
+    }
+
+    #[test]
+    fn sections_start_at_every_host_and_match_line() {
+        let content = "User me\nHost a b\n  User x\n# Host c\nmatch host d\n  User y\nHost=e\n";
+        let kinds: Vec<(SectionKind, usize, usize)> =
+            sections(&parse_lines(content)).into_iter().map(|s| (s.kind, s.start, s.end)).collect();
+        assert_eq!(
+            kinds,
+            vec![
+                (SectionKind::Global, 0, 1),
+                (SectionKind::Host(vec!["a".to_string(), "b".to_string()]), 1, 4),
+                (SectionKind::Match, 4, 6),
+                (SectionKind::Host(vec!["e".to_string()]), 6, 7),
+            ]
+        );
+    }
+
+    #[test]
+    fn find_host_block_takes_only_blocks_exclusively_for_the_host() {
+        let cases: &[(&str, Option<(usize, usize)>)] = &[
+            ("Host github.com\n  User git\n", Some((0, 2))),
+            ("host GitHub.com\n  User git\n", Some((0, 2))),
+            ("Host=github.com\n  User git\n", Some((0, 2))),
+            ("Host other\n  User x\nHost github.com\n  User git\nHost *\n", Some((2, 4))),
+            // Shared blocks would change the other hosts too
+            ("Host github.com gitlab.com\n  User git\n", None),
+            ("Host github.com*\n", None),
+            ("Match host github.com\n  User git\n", None),
+            ("# Host github.com\n  User git\n", None),
+            ("", None),
+        ];
+        for (content, expected) in cases {
+            assert_eq!(host_block(content, "github.com"), *expected, "in {:?}", content);
+        }
+    }
+
+    #[test]
+    fn set_identity_file_edits_only_the_host_block() {
+        let cases: &[(&str, &str, bool, &str)] = &[
+            // A new block is appended, set apart by a blank line
+            ("", "~/.ssh/b", true, "Host github.com\n    HostName github.com\n    User git\n    IdentityFile ~/.ssh/b\n    IdentitiesOnly yes\n"),
+            (
+                "Host other\n  User x\n",
+                "~/.ssh/b",
+                false,
+                "Host other\n  User x\n\nHost github.com\n    HostName github.com\n    User git\n    IdentityFile ~/.ssh/b\n",
+            ),
+            // An existing IdentityFile is replaced in place, in the
+            // block's own indentation
+            (
+                "Host github.com\n  User git\n  IdentityFile ~/.ssh/a\nHost other\n  IdentityFile ~/.ssh/x\n",
+                "~/.ssh/b",
+                false,
+                "Host github.com\n  User git\n  IdentityFile ~/.ssh/b\nHost other\n  IdentityFile ~/.ssh/x\n",
+            ),
+            // Missing lines go after the last option, before trailing
+            // blank lines and comments
+            (
+                "host github.com\n\tUser git\n\n# work\nHost other\n",
+                "~/.ssh/b",
+                true,
+                "host github.com\n\tUser git\n\tIdentityFile ~/.ssh/b\n\tIdentitiesOnly yes\n\n# work\nHost other\n",
+            ),
+            // An IdentitiesOnly the user wrote stays as it is
+            (
+                "Host github.com\n  IdentitiesOnly no\n  IdentityFile ~/.ssh/a\n",
+                "~/.ssh/b",
+                true,
+                "Host github.com\n  IdentitiesOnly no\n  IdentityFile ~/.ssh/b\n",
+            ),
+            // No key: the IdentityFile goes, for the agent's keys
+            ("Host github.com\n  User git\n  IdentityFile ~/.ssh/a\n  Port 22\n", "", true, "Host github.com\n  User git\n  Port 22\n"),
+            // Shared blocks, Match blocks and comments aren't the host's
+            // block, so a block of its own is added
+            (
+                "Host github.com gitlab.com\n  IdentityFile ~/.ssh/a\n# Host github.com\nMatch host github.com\n  User x\n",
+                "~/.ssh/b",
+                false,
+                "Host github.com gitlab.com\n  IdentityFile ~/.ssh/a\n# Host github.com\nMatch host github.com\n  User x\n\nHost github.com\n    HostName github.com\n    User git\n    IdentityFile ~/.ssh/b\n",
+            ),
+            (
+                "Host=github.com\n  User git\n",
+                "~/my keys/b",
+                false,
+                "Host=github.com\n  User git\n  IdentityFile \"~/my keys/b\"\n",
+            ),
+        ];
+        for (content, key, identities_only, expected) in cases {
+            assert_eq!(set_identity_file(content, "github.com", key, "git", *identities_only), *expected, "editing {:?}", content);
+        }
+    }
+
+    /// A small xorshift generator, so the generated files are the same on
+    /// every run.
+    struct Rng(u64);
+
+    impl Rng {
+        fn pick<'a>(&mut self, choices: &[&'a str]) -> &'a str {
+            self.0 ^= self.0 << 13;
+            self.0 ^= self.0 >> 7;
+            self.0 ^= self.0 << 17;
+            choices[(self.0 % choices.len() as u64) as usize]
+        }
+    }
+
+    /// Lines that can come before the managed block. Each ends its line.
+    const PREFIX_LINES: [&str; 9] = [
+        "# a comment\n",
+        "\n",
+        "ServerAliveInterval 30\n",
+        "Include config.d/*\r\n",
+        "Host github.com gitlab.com\n    IdentityFile ~/.ssh/shared\n",
+        "Host other\r\n\tUser x\r\n",
+        "Match host github.com\n  User y\n",
+        "# Host github.com\n",
+        "Host github.com.evil\n  IdentityFile \"~/odd name\"\n",
+    ];
+
+    /// Lines that can come after it. The first always starts a section, or
+    /// it would be part of the block.
+    const SUFFIX_STARTS: [&str; 4] = ["Host other\n", "Match all\r\n", "host *\n", "Host=gitlab.com\n"];
+    const SUFFIX_LINES: [&str; 6] = ["  User z\n", "# trailing\n", "\n", "\tIdentityFile ~/.ssh/z\r\n", "  Port 2222", "    ForwardAgent no\n"];
+
+    /// The block under test, in a few shapes.
+    const BLOCKS: [&str; 5] = [
+        "Host github.com\n    IdentityFile ~/.ssh/a\n",
+        "host github.com\r\n  User git\r\n",
+        "Host=github.com\n\tIdentityFile \"~/my keys/a\"\n\tIdentitiesOnly no\n\n# mine\n",
+        "Host github.com\n    IdentityFile ~/.ssh/a\n    # >>> gs profile options >>>\n    Port 22\n    # <<< gs profile options <<<\n",
+        "Host github.com\n",
+    ];
+
+    fn generated_files() -> Vec<(String, String, String)> {
+        let mut rng = Rng(0x9e37_79b9_7f4a_7c15);
+        let mut files = Vec::new();
+        for _ in 0..300 {
+            let prefix: String = (0..rng.0 % 4).map(|_| rng.pick(&PREFIX_LINES)).collect();
+            let block = rng.pick(&BLOCKS).to_string();
+            let mut suffix = String::new();
+            if rng.pick(&["with suffix", "without"]) == "with suffix" {
+                suffix.push_str(rng.pick(&SUFFIX_STARTS));
+                for _ in 0..rng.0 % 3 {
+                    suffix.push_str(rng.pick(&SUFFIX_LINES));
+                }
+            }
+            files.push((prefix, block, suffix));
+        }
+        files
+    }
+
+    /// What an edit did to `prefix + block + suffix` must stay between the
+    /// two: every byte before and after the block comes back unchanged.
+    fn assert_only_block_changed(prefix: &str, suffix: &str, edited: &str, edit: &str) {
+        assert!(edited.starts_with(prefix), "{} changed the text before the block:\n{:?}", edit, edited);
+        assert!(edited.ends_with(suffix), "{} changed the text after the block:\n{:?}", edit, edited);
+        assert!(edited.len() >= prefix.len() + suffix.len(), "{} overlapped prefix and suffix", edit);
+    }
+
+    #[test]
+    fn edits_leave_every_byte_outside_the_block_alone() {
+        for (prefix, block, suffix) in generated_files() {
+            let content = format!("{}{}{}", prefix, block, suffix);
+            assert_eq!(host_block(&content, "github.com").map(|(start, _)| start), Some(parse_lines(&prefix).len()));
+
+            for (key, identities_only) in [("~/.ssh/b", true), ("~/.ssh/b", false), ("~/my keys/b", true), ("", true)] {
+                let edited = set_identity_file(&content, "github.com", key, "git", identities_only);
+                assert_only_block_changed(&prefix, &suffix, &edited, &format!("set_identity_file({:?}) on {:?}", key, content));
+            }
+            for options in [vec!["ProxyJump bastion".to_string()], vec!["Port 22".to_string(), "Compression yes".to_string()], Vec::new()] {
+                let edited = set_options(&content, "github.com", &options);
+                assert_only_block_changed(&prefix, &suffix, &edited, &format!("set_options({:?}) on {:?}", options, content));
+            }
+        }
+    }
+
+    #[test]
+    fn line_ending_follows_most_lines() {
+        let cases = [
+            ("", "\n"),
+            ("Host a", "\n"),
+            ("Host a\n  User x\n", "\n"),
+            ("Host a\r\n  User x\r\n", "\r\n"),
+            ("Host a\r\n  User x", "\r\n"),
+            ("Host a\r\n  User x\r\n  Port 22\n", "\r\n"),
+            ("Host a\r\n  User x\n  Port 22\n", "\n"),
+            // A tie keeps LF
+            ("Host a\r\n  User x\n", "\n"),
+        ];
+        for (content, expected) in cases {
+            assert_eq!(line_ending(content), expected, "in {:?}", content);
+        }
+    }
+
+    #[test]
+    fn added_lines_match_the_file_line_endings() {
+        let cases: &[(&str, &str)] = &[
+            // LF
+            ("Host github.com\n  User git\n", "Host github.com\n  User git\n  IdentityFile ~/.ssh/b\n  IdentitiesOnly yes\n"),
+            // CRLF
+            ("Host github.com\r\n  User git\r\n", "Host github.com\r\n  User git\r\n  IdentityFile ~/.ssh/b\r\n  IdentitiesOnly yes\r\n"),
+            // Mixed: added lines use what most lines use, existing ones
+            // keep theirs
+            (
+                "Host github.com\r\n  User git\r\n  Port 22\n",
+                "Host github.com\r\n  User git\r\n  Port 22\n  IdentityFile ~/.ssh/b\r\n  IdentitiesOnly yes\r\n",
+            ),
+            // No final newline: still none after the edit
+            ("Host github.com\n  User git", "Host github.com\n  User git\n  IdentityFile ~/.ssh/b\n  IdentitiesOnly yes"),
+            ("Host github.com\r\n  User git", "Host github.com\r\n  User git\r\n  IdentityFile ~/.ssh/b\r\n  IdentitiesOnly yes"),
+            // Replacing a line keeps its line ending, including none
+            ("Host github.com\r\n  IdentityFile ~/.ssh/a\r\n", "Host github.com\r\n  IdentityFile ~/.ssh/b\r\n  IdentitiesOnly yes\r\n"),
+            ("Host github.com\r\n  IdentitiesOnly yes\r\n  IdentityFile ~/.ssh/a", "Host github.com\r\n  IdentitiesOnly yes\r\n  IdentityFile ~/.ssh/b"),
+            // New blocks too
+            ("Host other\r\n", "Host other\r\n\r\nHost github.com\r\n    HostName github.com\r\n    User git\r\n    IdentityFile ~/.ssh/b\r\n    IdentitiesOnly yes\r\n"),
+            ("Host other", "Host other\n\nHost github.com\n    HostName github.com\n    User git\n    IdentityFile ~/.ssh/b\n    IdentitiesOnly yes"),
+        ];
+        for (content, expected) in cases {
+            assert_eq!(set_identity_file(content, "github.com", "~/.ssh/b", "git", true), *expected, "editing {:?}", content);
+        }
+    }
+
+    #[test]
+    fn options_match_the_file_line_endings() {
+        let options = vec!["Port 2222".to_string()];
+        let cases: &[(&str, &str)] = &[
+            (
+                "Host github.com\n  IdentityFile ~/.ssh/a\n",
+                "Host github.com\n  IdentityFile ~/.ssh/a\n  # >>> gs profile options >>>\n  Port 2222\n  # <<< gs profile options <<<\n",
+            ),
+            (
+                "Host github.com\r\n  IdentityFile ~/.ssh/a\r\nHost other\r\n",
+                "Host github.com\r\n  IdentityFile ~/.ssh/a\r\n  # >>> gs profile options >>>\r\n  Port 2222\r\n  # <<< gs profile options <<<\r\nHost other\r\n",
+            ),
+            (
+                "Host github.com\r\n  IdentityFile ~/.ssh/a",
+                "Host github.com\r\n  IdentityFile ~/.ssh/a\r\n  # >>> gs profile options >>>\r\n  Port 2222\r\n  # <<< gs profile options <<<",
+            ),
+            // Replacing the region, markers and all
+            (
+                "Host github.com\r\n  IdentityFile ~/.ssh/a\r\n  # >>> gs profile options >>>\r\n  Port 22\r\n  # <<< gs profile options <<<\r\n",
+                "Host github.com\r\n  IdentityFile ~/.ssh/a\r\n  # >>> gs profile options >>>\r\n  Port 2222\r\n  # <<< gs profile options <<<\r\n",
+            ),
+        ];
+        for (content, expected) in cases {
+            assert_eq!(set_options(content, "github.com", &options), *expected, "editing {:?}", content);
+        }
+    }
+
+    #[test]
+    fn set_options_replaces_the_previous_profile_options() {
+        let options = |lines: &[&str]| lines.iter().map(|l| l.to_string()).collect::<Vec<_>>();
+        let block = "Host github.com\n    User git\n    IdentityFile ~/.ssh/a\n";
+        let other = "Host other\n    Port 22\n";
+        let with_jump = set_options(
+            &format!("{}{}", block, other),
+            "github.com",
+            &options(&["Port 2222", "ProxyJump bastion.example"]),
+        );
+        assert_eq!(
+            with_jump,
+            format!(
+                "{}    # >>> gs profile options >>>\n    Port 2222\n    ProxyJump bastion.example\n    # <<< gs profile options <<<\n{}",
+                block, other
+            )
+        );
+
+        // Different options take the old ones' place
+        let with_port = set_options(&with_jump, "github.com", &options(&["Port 443"]));
+        assert_eq!(
+            with_port,
+            format!("{}    # >>> gs profile options >>>\n    Port 443\n    # <<< gs profile options <<<\n{}", block, other)
+        );
+        assert!(!with_port.contains("ProxyJump"), "{}", with_port);
+
+        // None at all take the markers with them, and other hosts' options
+        // are never touched
+        assert_eq!(set_options(&with_jump, "github.com", &[]), format!("{}{}", block, other));
+        assert_eq!(set_options(&with_port, "github.com", &[]), format!("{}{}", block, other));
+    }
+
+    #[test]
+    fn identity_file_reads_quoted_and_legacy_paths() {
+        let cases: &[(&str, Option<&str>)] = &[
+            ("Host github.com\n  IdentityFile ~/.ssh/a\n", Some("~/.ssh/a")),
+            ("Host github.com\n  identityfile=~/.ssh/a\n", Some("~/.ssh/a")),
+            ("Host github.com\n  IdentityFile \"~/my keys/a\"\n", Some("~/my keys/a")),
+            ("Host github.com\n  IdentityFile '~/my keys/a'\n", Some("~/my keys/a")),
+            ("Host github.com\n  IdentityFile \"~/say \\\"hi\\\"/a\"\n", Some("~/say \"hi\"/a")),
+            ("Host github.com\n  IdentityFile ~/my\\ keys/a\n", Some("~/my keys/a")),
+            // Earlier versions wrote paths with spaces unquoted
+            ("Host github.com\n  IdentityFile /home/me/my keys/a\n", Some("/home/me/my keys/a")),
+            ("Host github.com\r\n  IdentityFile C:\\Users\\me\\my keys\\a\r\n", Some("C:\\Users\\me\\my keys\\a")),
+            ("Host github.com\n  User git\n", None),
+            ("Host other\n  IdentityFile ~/.ssh/a\n", None),
+        ];
+        for (content, expected) in cases {
+            assert_eq!(identity_file(content, "github.com").as_deref(), *expected, "in {:?}", content);
+        }
+    }
+
+    #[test]
+    fn quote_arg_only_quotes_when_needed() {
+        let cases = [
+            ("~/.ssh/a", "~/.ssh/a"),
+            ("C:\\Users\\me\\a", "C:\\Users\\me\\a"),
+            ("~/my keys/a", "\"~/my keys/a\""),
+            ("~/say \"hi\"", "\"~/say \\\"hi\\\"\""),
+            ("it's", "\"it's\""),
+            ("a#b", "\"a#b\""),
+            ("\\\\server\\share", "\"\\\\\\\\server\\\\share\""),
+            ("", "\"\""),
+        ];
+        for (value, expected) in cases {
+            assert_eq!(quote_arg(value), expected, "quoting {:?}", value);
+        }
+    }
+
+    #[test]
+    fn quote_arg_round_trips_through_split_args() {
+        let values = [
+            "~/.ssh/a",
+            "~/my keys/a",
+            "  leading and trailing  ",
+            "tab\there",
+            "~/say \"hi\"",
+            "it's",
+            "both \" and '",
+            "a#b",
+            "C:\\Users\\me\\my keys\\a",
+            "C:\\Users\\me\\a",
+            "\\\\server\\share\\a",
+            "trailing\\",
+            "back\\\"slash",
+            "",
+        ];
+        for value in values {
+            let quoted = quote_arg(value);
+            assert_eq!(split_args(&quoted), vec![value.to_string()], "{:?} quoted as {}", value, quoted);
+            let content = format!("Host github.com\n  IdentityFile {}\n", quoted);
+            assert_eq!(identity_file(&content, "github.com").as_deref(), Some(value), "reading back {}", quoted);
+        }
+    }
+
+    /// A scratch ~/.ssh with the given files, relative to it.
+    fn ssh_dir(files: &[(&str, &str)]) -> PathBuf {
+        static NEXT: std::sync::atomic::AtomicUsize = std::sync::atomic::AtomicUsize::new(0);
+        let n = NEXT.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+        let dir = std::env::temp_dir().join(format!("gs-test-ssh-{}-{}", std::process::id(), n));
+        for (name, content) in files {
+            let path = dir.join(name);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, content).unwrap();
+        }
+        dir
+    }
+
+    fn located(dir: &Path) -> Vec<(String, usize)> {
+        locate_host_blocks(&dir.join("config"), dir, "github.com")
+            .unwrap()
+            .into_iter()
+            .map(|l| (l.path.strip_prefix(dir).unwrap().display().to_string(), l.line))
+            .collect()
+    }
+
+    #[test]
+    fn locate_host_blocks_finds_duplicates_in_one_file() {
+        let dir = ssh_dir(&[(
+            "config",
+            "Host github.com\n  User git\nHost other\nhost GITHUB.COM\n  User git\nHost github.com gitlab.com\nMatch host github.com\n",
+        )]);
+        assert_eq!(located(&dir), vec![("config".to_string(), 0), ("config".to_string(), 3)]);
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn locate_host_blocks_follows_includes_in_reading_order() {
+        let dir = ssh_dir(&[
+            ("config", "Include config.d/*\nHost github.com\n  User git\nInclude ~/missing/*\n"),
+            // Glob matches are read in lexical order
+            ("config.d/20-personal", "Host github.com\n  IdentityFile ~/.ssh/personal\n"),
+            ("config.d/10-work", "# Host github.com\nHost work\nInclude nested.conf\n"),
+            ("nested.conf", "Host=github.com\n  IdentityFile ~/.ssh/work\n"),
+            ("config.d/30-shared", "Host github.com gitlab.com\n"),
+        ]);
+        assert_eq!(
+            located(&dir),
+            vec![
+                ("nested.conf".to_string(), 0),
+                ("config.d/20-personal".to_string(), 0),
+                ("config".to_string(), 1),
+            ]
+        );
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn locate_host_blocks_stops_at_include_loops() {
+        let dir = ssh_dir(&[("config", "Host github.com\nInclude config\n")]);
+        // Each level of the loop finds the block again, up to OpenSSH's limit
+        assert_eq!(located(&dir).len(), MAX_INCLUDE_DEPTH + 1);
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,71 @@
+//! `-v`/`-vv` logging to stderr: the files gs reads and writes, the
+//! programs it runs and the decisions it makes along the way. Without
+//! either flag nothing is logged, so normal output is unchanged.
+
+use std::io;
+use std::process::{Command, ExitStatus, Output};
+use tracing::level_filters::LevelFilter;
+use tracing_subscriber::filter::Targets;
+use tracing_subscriber::prelude::*;
+
+/// Start logging at the verbosity given by the number of `-v` flags: one
+/// shows gs's own actions, two adds file contents and the logs of the
+/// libraries gs uses. Level names are colored when `color` is set.
+pub fn init(verbosity: u8, color: bool) {
+    let targets = match verbosity {
+        0 => return,
+        1 => Targets::new().with_target(env!("CARGO_CRATE_NAME"), LevelFilter::DEBUG),
+        _ => Targets::new().with_default(LevelFilter::TRACE),
+    };
+    let format = tracing_subscriber::fmt::layer().with_writer(io::stderr).without_time().with_target(false).with_ansi(color);
+    // Fails only if logging was already set up
+    let _ = tracing_subscriber::registry().with(format.with_filter(targets)).try_init();
+}
+
+/// A command line as it would be typed, for the log.
+pub fn describe(command: &Command) -> String {
+    let mut line = command.get_program().to_string_lossy().into_owned();
+    for arg in command.get_args() {
+        let arg = arg.to_string_lossy();
+        if arg.is_empty() || arg.contains(char::is_whitespace) {
+            line.push_str(&format!(" '{}'", arg));
+        } else {
+            line.push(' ');
+            line.push_str(&arg);
+        }
+    }
+    line
+}
+
+/// `output` and `status` that log the command and how it exited.
+pub trait LoggedCommand {
+    fn logged_output(&mut self) -> io::Result<Output>;
+    fn logged_status(&mut self) -> io::Result<ExitStatus>;
+}
+
+impl LoggedCommand for Command {
+    fn logged_output(&mut self) -> io::Result<Output> {
+        tracing::debug!("run {}", describe(self));
+        let output = self.output();
+        match &output {
+            Ok(output) => {
+                tracing::debug!("  -> {}", output.status);
+                if !output.stderr.is_empty() {
+                    tracing::trace!("  stderr: {}", String::from_utf8_lossy(&output.stderr).trim_end());
+                }
+            }
+            Err(err) => tracing::debug!("  -> failed to start: {}", err),
+        }
+        output
+    }
+
+    fn logged_status(&mut self) -> io::Result<ExitStatus> {
+        tracing::debug!("run {}", describe(self));
+        let status = self.status();
+        match &status {
+            Ok(status) => tracing::debug!("  -> {}", status),
+            Err(err) => tracing::debug!("  -> failed to start: {}", err),
+        }
+        status
+    }
+}
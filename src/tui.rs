@@ -0,0 +1,325 @@
+//! `gs ui`: a full-screen view of the profiles.
+//!
+//! The TUI only collects choices. Every change runs through the same
+//! functions as the matching CLI command, with the terminal handed back for
+//! the duration so warnings and confirmations behave exactly as they do
+//! there.
+
+use crate::error::{failure, ExitCode};
+use crate::{
+    activate_profile, delete_profiles, edit_profile, key_label, load_config, setup_flow, ApplyScope, Config, ProfileFields,
+};
+use anyhow::Result;
+use ratatui::crossterm::event::{self, Event, KeyCode, KeyEvent, KeyEventKind, KeyModifiers};
+use ratatui::crossterm::terminal::{self, EnterAlternateScreen};
+use ratatui::layout::{Constraint, Layout, Rect};
+use ratatui::style::{Color, Modifier, Style, Stylize};
+use ratatui::text::{Line, Span};
+use ratatui::widgets::{Block, Clear, List, ListItem, ListState, Paragraph};
+use ratatui::{DefaultTerminal, Frame};
+use std::io::{self, IsTerminal};
+
+const MIN_WIDTH: u16 = 50;
+const MIN_HEIGHT: u16 = 12;
+const FIELD_LABELS: [&str; 4] = ["Name", "Email", "SSH key", "Description"];
+
+pub fn run() -> Result<()> {
+    if !io::stdin().is_terminal() || !io::stdout().is_terminal() {
+        return Err(failure(ExitCode::Usage, "gs ui needs an interactive terminal"));
+    }
+    let (width, height) = terminal::size()?;
+    if width < MIN_WIDTH || height < MIN_HEIGHT {
+        return Err(failure(
+            ExitCode::Usage,
+            format!(
+                "The terminal is too small for gs ui ({}x{}, need at least {}x{})",
+                width, height, MIN_WIDTH, MIN_HEIGHT
+            ),
+        ));
+    }
+
+    let mut app = App::new()?;
+    // Installs a panic hook that restores the terminal before unwinding
+    let mut terminal = ratatui::init();
+    let result = app.run(&mut terminal);
+    ratatui::restore();
+    result
+}
+
+enum Mode {
+    Browse,
+    ConfirmDelete,
+    Form(Form),
+}
+
+/// The new/edit form. `original` is the name of the profile being edited,
+/// or None when creating one.
+struct Form {
+    original: Option<String>,
+    values: [String; 4],
+    focus: usize,
+}
+
+struct App {
+    config: Config,
+    selected: ListState,
+    mode: Mode,
+    status: Option<(String, bool)>,
+    quit: bool,
+}
+
+impl App {
+    fn new() -> Result<App> {
+        let config = load_config()?;
+        let mut selected = ListState::default();
+        selected.select(Some(config.profiles.iter().position(|p| p.current).unwrap_or(0)));
+        Ok(App { config, selected, mode: Mode::Browse, status: None, quit: false })
+    }
+
+    fn run(&mut self, terminal: &mut DefaultTerminal) -> Result<()> {
+        while !self.quit {
+            terminal.draw(|frame| self.draw(frame))?;
+            if let Event::Key(key) = event::read()?
+                && key.kind == KeyEventKind::Press
+            {
+                self.handle_key(key, terminal)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn selected_index(&self) -> Option<usize> {
+        self.selected.selected().filter(|&i| i < self.config.profiles.len())
+    }
+
+    fn handle_key(&mut self, key: KeyEvent, terminal: &mut DefaultTerminal) -> Result<()> {
+        if key.modifiers.contains(KeyModifiers::CONTROL) && key.code == KeyCode::Char('c') {
+            self.quit = true;
+            return Ok(());
+        }
+
+        match &mut self.mode {
+            Mode::Browse => self.handle_browse_key(key, terminal)?,
+            Mode::ConfirmDelete => {
+                self.mode = Mode::Browse;
+                if key.code == KeyCode::Char('y') {
+                    self.delete_selected(terminal)?;
+                } else {
+                    self.status = Some(("Nothing deleted".to_string(), false));
+                }
+            }
+            Mode::Form(form) => match key.code {
+                KeyCode::Esc => self.mode = Mode::Browse,
+                KeyCode::Tab | KeyCode::Down => form.focus = (form.focus + 1) % FIELD_LABELS.len(),
+                KeyCode::BackTab | KeyCode::Up => {
+                    form.focus = (form.focus + FIELD_LABELS.len() - 1) % FIELD_LABELS.len()
+                }
+                KeyCode::Backspace => {
+                    form.values[form.focus].pop();
+                }
+                KeyCode::Char(c) => form.values[form.focus].push(c),
+                KeyCode::Enter => {
+                    let Mode::Form(form) = std::mem::replace(&mut self.mode, Mode::Browse) else {
+                        unreachable!()
+                    };
+                    self.submit(form, terminal)?;
+                }
+                _ => {}
+            },
+        }
+        Ok(())
+    }
+
+    fn handle_browse_key(&mut self, key: KeyEvent, terminal: &mut DefaultTerminal) -> Result<()> {
+        let len = self.config.profiles.len();
+        match key.code {
+            KeyCode::Char('q') | KeyCode::Esc => self.quit = true,
+            KeyCode::Down | KeyCode::Char('j') if len > 0 => {
+                self.selected.select(Some(self.selected_index().map_or(0, |i| (i + 1) % len)));
+            }
+            KeyCode::Up | KeyCode::Char('k') if len > 0 => {
+                self.selected.select(Some(self.selected_index().map_or(0, |i| (i + len - 1) % len)));
+            }
+            KeyCode::Enter => {
+                if let Some(index) = self.selected_index() {
+                    let name = self.config.profiles[index].name.clone();
+                    self.mutate(terminal, &name, |config| {
+                        activate_profile(config, index, &ApplyScope::default()).map(|p| format!("Switched to {}", p.name))
+                    })?;
+                }
+            }
+            KeyCode::Char('n') => {
+                self.mode = Mode::Form(Form { original: None, values: Default::default(), focus: 0 });
+            }
+            KeyCode::Char('e') => {
+                if let Some(index) = self.selected_index() {
+                    let profile = &self.config.profiles[index];
+                    self.mode = Mode::Form(Form {
+                        original: Some(profile.name.clone()),
+                        values: [
+                            profile.name.clone(),
+                            profile.email.clone(),
+                            // '-' stands for the agent's keys, as at the prompts
+                            if profile.ssh_key.is_empty() { "-".to_string() } else { profile.ssh_key.clone() },
+                            profile.description.clone().unwrap_or_default(),
+                        ],
+                        focus: 0,
+                    });
+                }
+            }
+            KeyCode::Char('d') if self.selected_index().is_some() => self.mode = Mode::ConfirmDelete,
+            _ => {}
+        }
+        Ok(())
+    }
+
+    fn delete_selected(&mut self, terminal: &mut DefaultTerminal) -> Result<()> {
+        let Some(index) = self.selected_index() else {
+            return Ok(());
+        };
+        self.mutate(terminal, "", |config| {
+            let (removed, new_current) = delete_profiles(config, &[index])?;
+            Ok(match new_current {
+                Some(current) => format!("Removed {}; switched to {}", removed[0].name, current),
+                None => format!("Removed {}", removed[0].name),
+            })
+        })
+    }
+
+    fn submit(&mut self, form: Form, terminal: &mut DefaultTerminal) -> Result<()> {
+        let [name, email, ssh_key, description] = form.values.map(|v| v.trim().to_string());
+        if name.is_empty() || email.is_empty() || ssh_key.is_empty() {
+            self.status = Some(("Name, email and SSH key ('-' for the agent's) are required".to_string(), true));
+            return Ok(());
+        }
+
+        let fields = ProfileFields {
+            name: Some(name.clone()),
+            email: Some(email),
+            ssh_key: Some(if ssh_key == "-" { String::new() } else { ssh_key }),
+            description: Some(description),
+            ..Default::default()
+        };
+        self.mutate(terminal, &name.clone(), move |_| match &form.original {
+            Some(original) => edit_profile(Some(original), fields).map(|_| format!("Updated {}", name)),
+            None => setup_flow(fields, None, None, None, false).map(|_| format!("Created {}", name)),
+        })
+    }
+
+    /// Hand the terminal back, run a change the way the CLI would, reload
+    /// the profiles and report the outcome in the status line. `select` is
+    /// the profile to highlight afterwards.
+    fn mutate(
+        &mut self,
+        terminal: &mut DefaultTerminal,
+        select: &str,
+        change: impl FnOnce(&mut Config) -> Result<String>,
+    ) -> Result<()> {
+        ratatui::restore();
+        let outcome = change(&mut self.config);
+        terminal::enable_raw_mode()?;
+        ratatui::crossterm::execute!(io::stdout(), EnterAlternateScreen)?;
+        terminal.clear()?;
+
+        self.config = load_config()?;
+        self.status = Some(match outcome {
+            Ok(message) => (message, false),
+            Err(err) => (format!("{:#}", err), true),
+        });
+        let index = self.config.profiles.iter().position(|p| p.name == select);
+        let len = self.config.profiles.len();
+        self.selected.select(match index {
+            Some(index) => Some(index),
+            None if len == 0 => None,
+            None => Some(self.selected_index().unwrap_or(len - 1).min(len - 1)),
+        });
+        Ok(())
+    }
+
+    fn draw(&mut self, frame: &mut Frame) {
+        let area = frame.area();
+        if area.width < MIN_WIDTH || area.height < MIN_HEIGHT {
+            frame.render_widget(
+                Paragraph::new(format!("Terminal too small (need {}x{})", MIN_WIDTH, MIN_HEIGHT)),
+                area,
+            );
+            return;
+        }
+
+        let [list_area, details_area, footer_area] =
+            Layout::vertical([Constraint::Min(3), Constraint::Length(5), Constraint::Length(1)]).areas(area);
+
+        let items: Vec<ListItem> = self
+            .config
+            .profiles
+            .iter()
+            .map(|p| {
+                let line = Line::from(vec![
+                    Span::raw(if p.current { "● " } else { "  " }),
+                    Span::raw(p.name.clone()),
+                    Span::raw(format!(" <{}>", p.email)).dim(),
+                ]);
+                let style = if p.current { Style::new().fg(Color::Green).bold() } else { Style::new() };
+                ListItem::new(line).style(style)
+            })
+            .collect();
+        let list = List::new(items)
+            .block(Block::bordered().title(" gs profiles "))
+            .highlight_style(Style::new().add_modifier(Modifier::REVERSED))
+            .highlight_symbol("> ");
+        frame.render_stateful_widget(list, list_area, &mut self.selected);
+
+        let details = match self.selected_index().map(|i| &self.config.profiles[i]) {
+            Some(profile) => {
+                let mut lines = vec![Line::from(format!("SSH key: {}", key_label(&profile.ssh_key)))];
+                if let Some(description) = &profile.description {
+                    lines.push(Line::from(description.clone()).dim());
+                }
+                for (host, key) in &profile.hosts {
+                    lines.push(Line::from(format!("{}: {}", host, key)).dim());
+                }
+                lines
+            }
+            None => vec![Line::from("No profiles yet. Press n to create one.")],
+        };
+        frame.render_widget(Paragraph::new(details).block(Block::bordered()), details_area);
+
+        let footer = match (&self.mode, &self.status) {
+            (Mode::ConfirmDelete, _) => {
+                Line::from("Delete this profile? y to confirm, any other key to cancel").fg(Color::Yellow)
+            }
+            (Mode::Browse, Some((message, true))) => Line::from(message.clone()).fg(Color::Red),
+            (Mode::Browse, Some((message, false))) => Line::from(message.clone()).fg(Color::Green),
+            _ => Line::from("enter switch · n new · e edit · d delete · q quit").dim(),
+        };
+        frame.render_widget(Paragraph::new(footer), footer_area);
+
+        if let Mode::Form(form) = &self.mode {
+            draw_form(frame, form, area);
+        }
+    }
+}
+
+fn draw_form(frame: &mut Frame, form: &Form, area: Rect) {
+    let popup = area.centered(Constraint::Length(area.width.min(70)), Constraint::Length(8));
+    let title = match &form.original {
+        Some(name) => format!(" Edit {} ", name),
+        None => " New profile ".to_string(),
+    };
+
+    let mut lines: Vec<Line> = FIELD_LABELS
+        .iter()
+        .zip(&form.values)
+        .enumerate()
+        .map(|(i, (label, value))| {
+            let cursor = if i == form.focus { "_" } else { "" };
+            let line = Line::from(format!("{:<12} {}{}", label, value, cursor));
+            if i == form.focus { line.bold() } else { line }
+        })
+        .collect();
+    lines.push(Line::from(""));
+    lines.push(Line::from("tab next field · enter save · esc cancel").dim());
+
+    frame.render_widget(Clear, popup);
+    frame.render_widget(Paragraph::new(lines).block(Block::bordered().title(title)), popup);
+}
@@ -0,0 +1,365 @@
+//! The everyday commands, run as a user would run them.
+
+mod common;
+
+use common::{stderr, stdout, Sandbox};
+
+fn names(sandbox: &Sandbox) -> String {
+    stdout(&sandbox.gs_ok(&["list", "--format", "{name} <{email}>{current}"]))
+}
+
+#[test]
+fn bare_gs_without_profiles_says_how_to_start() {
+    let sandbox = Sandbox::new();
+    let output = sandbox.gs(&[]);
+    assert_eq!(output.status.code(), Some(3));
+    assert!(stderr(&output).contains("gs setup"), "{}", stderr(&output));
+}
+
+#[test]
+fn bare_gs_toggles_to_the_previous_profile() {
+    let sandbox = Sandbox::new();
+    sandbox.profile("a", &[]);
+    let output = sandbox.gs_ok(&[]);
+    assert!(stderr(&output).contains("Only one profile exists"), "{}", stderr(&output));
+
+    sandbox.profile("b", &[]);
+    let output = sandbox.gs_ok(&[]);
+    assert!(stderr(&output).contains("Switched to profile: a"), "{}", stderr(&output));
+    assert_eq!(sandbox.git_global("user.email").as_deref(), Some("a@example.com"));
+    sandbox.gs_ok(&[]);
+    assert_eq!(sandbox.git_global("user.email").as_deref(), Some("b@example.com"));
+}
+
+#[test]
+fn setup_creates_and_applies_a_profile() {
+    let sandbox = Sandbox::new();
+    sandbox.profile("work", &["--git-name", "Jane Doe"]);
+
+    assert_eq!(names(&sandbox), "work <work@example.com>*\n");
+    assert_eq!(sandbox.git_global("user.name").as_deref(), Some("Jane Doe"));
+    assert_eq!(sandbox.git_global("user.email").as_deref(), Some("work@example.com"));
+    assert!(sandbox.read(".ssh/config").contains(".ssh/work"));
+}
+
+#[test]
+fn setup_refuses_missing_flags_and_taken_names() {
+    let sandbox = Sandbox::new();
+    let output = sandbox.gs(&["setup", "--name", "work", "--email", "me@example.com"]);
+    assert_eq!(output.status.code(), Some(2), "{}", stderr(&output));
+
+    sandbox.profile("work", &[]);
+    let key = sandbox.key("other");
+    let output = sandbox.gs(&["-y", "setup", "--name", "WORK", "--email", "x@example.com", "--key", key.to_str().unwrap()]);
+    assert_eq!(output.status.code(), Some(1), "{}", stderr(&output));
+    assert!(stderr(&output).contains("--update-existing"), "{}", stderr(&output));
+    assert_eq!(names(&sandbox), "work <work@example.com>*\n");
+}
+
+#[test]
+fn setup_reads_answers_from_a_pipe() {
+    let sandbox = Sandbox::new();
+    sandbox.key("id_work");
+    // The README's example, which leaves every optional question blank
+    let output = sandbox.gs_with_input(&["-y", "setup"], "work\nJane Doe\nme@corp.com\n~/.ssh/id_work\n\n\n\n\n\n\n");
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert!(stderr(&output).contains("me@corp.com"), "answers aren't echoed: {}", stderr(&output));
+
+    assert_eq!(names(&sandbox), "work <me@corp.com>*\n");
+    assert_eq!(sandbox.git_global("user.name").as_deref(), Some("Jane Doe"));
+    assert!(sandbox.read(".ssh/config").contains(".ssh/id_work"));
+}
+
+#[test]
+fn piped_setup_fails_on_missing_or_invalid_answers() {
+    let sandbox = Sandbox::new();
+    let output = sandbox.gs_with_input(&["setup"], "work\nJane Doe\n");
+    assert_eq!(output.status.code(), Some(5), "{}", stderr(&output));
+    assert!(stderr(&output).contains("missing answer for 'email'"), "{}", stderr(&output));
+
+    let output = sandbox.gs_with_input(&["setup"], "work\nJane Doe\nnot an email\n");
+    assert_eq!(output.status.code(), Some(2), "{}", stderr(&output));
+    assert!(stderr(&output).contains("invalid answer for 'email'"), "{}", stderr(&output));
+
+    let output = sandbox.gs(&["list"]);
+    assert!(stderr(&output).contains("No profiles found"), "{}", stderr(&output));
+}
+
+#[test]
+fn list_shows_every_profile() {
+    let sandbox = Sandbox::new();
+    sandbox.profile("work", &[]);
+    sandbox.profile("personal", &[]);
+
+    assert_eq!(names(&sandbox), "work <work@example.com>\npersonal <personal@example.com>*\n");
+    let output = sandbox.gs_ok(&["list"]);
+    assert!(stdout(&output).contains("• personal <personal@example.com> (current)"), "{}", stdout(&output));
+}
+
+#[test]
+fn verbose_logs_to_stderr_wherever_it_goes() {
+    let sandbox = Sandbox::new();
+    sandbox.profile("work", &[]);
+    assert!(!stderr(&sandbox.gs_ok(&["work"])).contains("run git"));
+
+    for args in [&["-v", "work"], &["work", "-v"]] {
+        let output = sandbox.gs_ok(args);
+        assert!(stderr(&output).contains("run git config --global"), "{}", stderr(&output));
+        assert!(stderr(&output).contains("DEBUG"), "{}", stderr(&output));
+    }
+    let output = sandbox.gs_ok(&["list", "-v"]);
+    assert!(stderr(&output).contains("read "), "{}", stderr(&output));
+    assert!(!stdout(&output).contains("DEBUG"), "{}", stdout(&output));
+}
+
+#[test]
+fn edit_changes_fields_and_reapplies_the_current_profile() {
+    let sandbox = Sandbox::new();
+    sandbox.profile("work", &[]);
+    sandbox.profile("personal", &[]);
+
+    sandbox.gs_ok(&["edit", "work", "--email", "jane@corp.example"]);
+    assert_eq!(sandbox.git_global("user.email").as_deref(), Some("personal@example.com"));
+    sandbox.gs_ok(&["edit", "personal", "--email", "me@home.example"]);
+    assert_eq!(sandbox.git_global("user.email").as_deref(), Some("me@home.example"));
+    assert_eq!(names(&sandbox), "work <jane@corp.example>\npersonal <me@home.example>*\n");
+
+    let output = sandbox.gs(&["edit", "--email", "x@example.com"]);
+    assert_eq!(output.status.code(), Some(2), "{}", stderr(&output));
+}
+
+#[test]
+fn rm_and_remove_delete_profiles() {
+    let sandbox = Sandbox::new();
+    for name in ["a", "b", "c"] {
+        sandbox.profile(name, &[]);
+    }
+
+    sandbox.gs_ok(&["-y", "rm", "a"]);
+    assert_eq!(names(&sandbox), "b <b@example.com>\nc <c@example.com>*\n");
+    // Removing the current profile switches to another
+    let output = sandbox.gs_ok(&["-y", "remove", "c"]);
+    assert!(stderr(&output).contains("Switched to profile: b"), "{}", stderr(&output));
+    assert_eq!(names(&sandbox), "b <b@example.com>*\n");
+    assert_eq!(sandbox.git_global("user.email").as_deref(), Some("b@example.com"));
+
+    let output = sandbox.gs(&["-y", "rm", "nope"]);
+    assert_eq!(output.status.code(), Some(4), "{}", stderr(&output));
+    // Without --yes and without a terminal, nothing is removed
+    let output = sandbox.gs(&["rm", "b"]);
+    assert_ne!(output.status.code(), Some(0));
+    assert_eq!(names(&sandbox), "b <b@example.com>*\n");
+}
+
+#[test]
+fn switching_by_name_prefix_and_case() {
+    let sandbox = Sandbox::new();
+    sandbox.profile("work", &[]);
+    sandbox.profile("personal", &[]);
+
+    for (query, email) in [("work", "work@example.com"), ("PERS", "personal@example.com"), ("Wo", "work@example.com")] {
+        let output = sandbox.gs_ok(&[query]);
+        assert!(stderr(&output).contains(&format!("({})", email)), "{}", stderr(&output));
+        assert_eq!(sandbox.git_global("user.email").as_deref(), Some(email));
+    }
+    assert_eq!(stdout(&sandbox.gs_ok(&["current"])), "work\n");
+
+    let output = sandbox.gs(&["nope"]);
+    assert_eq!(output.status.code(), Some(4), "{}", stderr(&output));
+    assert_eq!(sandbox.git_global("user.email").as_deref(), Some("work@example.com"));
+}
+
+#[test]
+fn undo_keeps_the_active_profile() {
+    let sandbox = Sandbox::new();
+    sandbox.profile("a", &[]);
+    sandbox.profile("b", &[]);
+
+    // Edit, switch, undo: the edit is reverted, the switch isn't
+    sandbox.gs_ok(&["edit", "a", "--description", "changed"]);
+    sandbox.gs_ok(&["a"]);
+    sandbox.gs_ok(&["undo"]);
+    assert!(!sandbox.read(".config/gs/profiles.json").contains("changed"));
+    assert_eq!(stdout(&sandbox.gs_ok(&["current"])), "a\n");
+    assert_eq!(sandbox.git_global("user.email").as_deref(), Some("a@example.com"));
+
+    // Undoing an edit of the active profile puts its old settings back live
+    sandbox.gs_ok(&["edit", "a", "--email", "new@example.com"]);
+    assert_eq!(sandbox.git_global("user.email").as_deref(), Some("new@example.com"));
+    sandbox.gs_ok(&["undo"]);
+    assert_eq!(sandbox.git_global("user.email").as_deref(), Some("a@example.com"));
+    assert_eq!(stdout(&sandbox.gs_ok(&["current"])), "a\n");
+
+    // Undoing the removal of the active profile brings it back as active
+    sandbox.gs_ok(&["-y", "rm", "a"]);
+    assert_eq!(stdout(&sandbox.gs_ok(&["current"])), "b\n");
+    sandbox.gs_ok(&["undo"]);
+    assert_eq!(stdout(&sandbox.gs_ok(&["current"])), "b\n");
+    assert!(names(&sandbox).contains("a <a@example.com>"));
+}
+
+#[test]
+fn config_files_keep_their_own_backups() {
+    let sandbox = Sandbox::new();
+    sandbox.profile("a", &[]);
+    sandbox.gs_ok(&["settings", "backup_count", "2"]);
+    sandbox.gs_ok(&["edit", "a", "--description", "main 1"]);
+
+    // Named like the main file, which its backups mustn't be mixed up with
+    let alt = sandbox.home.join("alt/profiles.json");
+    let alt = alt.to_str().unwrap();
+    let key = sandbox.key("w");
+    sandbox.gs_ok(&["--config", alt, "-y", "setup", "--name", "w", "--email", "w@example.com", "--key", key.to_str().unwrap()]);
+    for n in 1..=4 {
+        sandbox.gs_ok(&["--config", alt, "edit", "w", "--description", &format!("alt {}", n)]);
+    }
+
+    // Four saves of the other file didn't push out profiles.json's backup
+    let backups = |args: &[&str]| stdout(&sandbox.gs_ok(args)).lines().filter(|l| !l.starts_with(' ') && !l.starts_with('=')).count();
+    assert_eq!(backups(&["backups", "list"]), 1);
+    assert_eq!(backups(&["--config", alt, "backups", "list"]), 2);
+    assert!(sandbox.home.join("alt/backups").is_dir());
+
+    sandbox.gs_ok(&["backups", "restore", "latest"]);
+    assert_eq!(names(&sandbox), "a <a@example.com>*\n");
+    assert!(!sandbox.read(".config/gs/profiles.json").contains("main 1"));
+}
+
+#[cfg(unix)]
+#[test]
+fn edit_raw_uses_a_private_file() {
+    let sandbox = Sandbox::new();
+    sandbox.profile("a", &[]);
+    // Note where the file is and who may read it, then make an edit
+    sandbox.fake_command(
+        "fake-editor",
+        "echo \"$1\" > \"$HOME/edited-path\"\nstat -c %a \"$1\" > \"$HOME/edited-mode\"\nsed -i 's/\"a@example.com\"/\"raw@example.com\"/' \"$1\"\n",
+    );
+    let output = sandbox.command().env("VISUAL", "fake-editor").args(["edit", "--raw"]).output().unwrap();
+    assert!(output.status.success(), "{}", stderr(&output));
+
+    let path = sandbox.read("edited-path");
+    assert!(path.starts_with(sandbox.config_dir().to_str().unwrap()), "{}", path);
+    assert_eq!(sandbox.read("edited-mode"), "600\n");
+    assert!(!std::path::Path::new(path.trim()).exists());
+    assert_eq!(sandbox.git_global("user.email").as_deref(), Some("raw@example.com"));
+}
+
+#[test]
+fn import_keys_takes_each_key_once_and_checks_emails() {
+    let sandbox = Sandbox::new();
+    for name in ["k1", "k2", "k3"] {
+        sandbox.key(name);
+    }
+    // Per key: name, git name, email and host, defaults for all but email
+    let output = sandbox.gs_with_input(&["import-keys"], "1,3,1\n\n\none@example.com\n\n\n\nthree@example.com\n\n");
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert_eq!(
+        stdout(&sandbox.gs_ok(&["list", "--format", "{name} <{email}>"])),
+        "k1 <one@example.com>\nk3 <three@example.com>\n"
+    );
+
+    let output = sandbox.gs_with_input(&["import-keys"], "1\n\n\nnot an email\n\n");
+    assert_eq!(output.status.code(), Some(2), "{}", stderr(&output));
+    assert!(stderr(&output).contains("'not an email' is not a valid email address"), "{}", stderr(&output));
+}
+
+#[test]
+fn shared_emails_and_keys_are_confirmed_once() {
+    let sandbox = Sandbox::new();
+    sandbox.profile("a", &[]);
+    let key = sandbox.home.join(".ssh/a");
+    let key = key.to_str().unwrap();
+
+    // Without a terminal, only --yes or --allow-shared-key answer
+    let output = sandbox.gs(&["setup", "--name", "b", "--email", "a@example.com", "--key", key]);
+    assert_eq!(output.status.code(), Some(2), "{}", stderr(&output));
+    assert!(stderr(&output).contains("Profile 'a' already uses the email a@example.com"), "{}", stderr(&output));
+    assert!(stderr(&output).contains("is already the key of 'a'"), "{}", stderr(&output));
+    assert!(stderr(&output).contains("--allow-shared-key"), "{}", stderr(&output));
+
+    let output = sandbox.gs(&["setup", "--name", "b", "--email", "a@example.com", "--key", key, "--allow-shared-key"]);
+    assert_eq!(output.status.code(), Some(2), "{}", stderr(&output));
+    assert!(stderr(&output).contains("Can't ask \"Continue anyway?\""), "{}", stderr(&output));
+
+    let output = sandbox.gs_ok(&["-y", "setup", "--name", "b", "--email", "a@example.com", "--key", key]);
+    assert!(stderr(&output).contains("Profile 'a' already uses the email"), "{}", stderr(&output));
+    assert!(stderr(&output).contains("is already the key of 'a'"), "{}", stderr(&output));
+    assert_eq!(names(&sandbox), "a <a@example.com>\nb <a@example.com>*\n");
+}
+
+#[test]
+fn import_from_gitconfig_leaves_gitconfig_alone() {
+    let sandbox = Sandbox::new();
+    let key = sandbox.key("work");
+    std::fs::write(
+        sandbox.home.join(".gitconfig-work"),
+        format!("[user]\n    email = jane@corp.com\n[core]\n    sshCommand = ssh -i {} -o IdentitiesOnly=yes\n", key.display()),
+    )
+    .unwrap();
+    // Only an email: the global name and the agent's keys
+    std::fs::write(sandbox.home.join("oss.gitconfig"), "[user]\n    email = jane@example.com\n").unwrap();
+    std::fs::write(sandbox.home.join(".gitconfig-broken"), "[user\n    email = broken@example.com\n").unwrap();
+    let gitconfig = "[user]\n    name = Jane Doe\n\
+        [includeIf \"gitdir:~/work/\"]\n    path = ~/.gitconfig-work\n\
+        [includeIf \"gitdir/i:~/OSS/\"]\n    path = oss.gitconfig\n\
+        [includeIf \"gitdir:~/broken/\"]\n    path = .gitconfig-broken\n\
+        [includeIf \"onbranch:main\"]\n    path = ~/.gitconfig-work\n";
+    std::fs::write(sandbox.home.join(".gitconfig"), gitconfig).unwrap();
+
+    let output = sandbox.gs_ok(&["-y", "import", "--from-gitconfig"]);
+    assert_eq!(sandbox.read(".gitconfig"), gitconfig);
+    let err = stderr(&output);
+    assert!(err.contains("Skipped ~/.gitconfig-broken"), "{}", err);
+    assert!(err.contains("Skipped includeIf \"onbranch:main\" (only gitdir conditions"), "{}", err);
+
+    assert_eq!(
+        stdout(&sandbox.gs_ok(&["list", "--format", "{name} {git_name} <{email}> {ssh_key}"])),
+        "work Jane Doe <jane@corp.com> ~/.ssh/work\noss Jane Doe <jane@example.com> \n"
+    );
+    let settings = sandbox.read(".config/gs/settings.json");
+    assert!(settings.contains("\"~/work\": \"work\"") && settings.contains("\"~/OSS\": \"oss\""), "{}", settings);
+}
+
+#[test]
+fn check_reports_every_problem_in_a_file() {
+    let sandbox = Sandbox::new();
+    let file = sandbox.home.join("profiles.json");
+    let profiles = serde_json::json!({"version": 1, "profiles": [
+        {"name": "a", "email": "a@example.com", "ssh_key": "~/.ssh/a", "current": true},
+        {"name": "a", "email": "b@example.com", "ssh_key": "", "current": true},
+    ]});
+    std::fs::write(&file, profiles.to_string()).unwrap();
+
+    let output = sandbox.gs(&["--json", "check", file.to_str().unwrap()]);
+    assert_eq!(output.status.code(), Some(1), "{}", stderr(&output));
+    let report: serde_json::Value = serde_json::from_str(&stdout(&output)).unwrap();
+    let messages: Vec<&str> = report["findings"].as_array().unwrap().iter().map(|f| f["message"].as_str().unwrap()).collect();
+    assert_eq!(
+        messages,
+        [
+            "SSH key for github.com not found at ~/.ssh/a",
+            "Another profile has the same name",
+            "More than one profile is marked current: a, a",
+        ]
+    );
+
+    // --no-fs leaves the key alone
+    let output = sandbox.gs(&["check", "--no-fs", file.to_str().unwrap()]);
+    assert!(stdout(&output).contains("2 error(s), 0 warning(s)."), "{}", stdout(&output));
+}
+
+#[test]
+fn dry_runs_write_and_journal_nothing() {
+    let sandbox = Sandbox::new();
+    sandbox.profile("a", &[]);
+    let history = || std::fs::read_dir(sandbox.config_dir().join("history")).map_or(0, |dir| dir.count());
+    let profiles_before = sandbox.read(".config/gs/profiles.json");
+    let journaled = history();
+
+    for args in [&["--dry-run", "rename", "a", "b"][..], &["--dry-run", "edit", "a", "--email", "new@example.com"], &["--dry-run", "-y", "rm", "a"]] {
+        sandbox.gs_ok(args);
+        assert_eq!(sandbox.read(".config/gs/profiles.json"), profiles_before, "gs {:?}", args);
+        assert_eq!(history(), journaled, "gs {:?}", args);
+    }
+}
@@ -0,0 +1,144 @@
+//! One check per command of what it prints where: results on stdout, so
+//! they can be captured, and prompts, progress and warnings on stderr.
+
+mod common;
+
+use common::{stderr, stdout, Sandbox};
+
+/// A sandbox with profiles a and b, b current.
+fn two_profiles() -> Sandbox {
+    let sandbox = Sandbox::new();
+    sandbox.profile("a", &["--icon", "💼"]);
+    sandbox.profile("b", &[]);
+    sandbox
+}
+
+#[test]
+fn current_prints_only_the_name() {
+    let sandbox = Sandbox::new();
+    let output = sandbox.gs_ok(&["current"]);
+    assert_eq!(stdout(&output), "");
+    assert_eq!(stderr(&output), "No active profile.\n");
+
+    let sandbox = two_profiles();
+    let output = sandbox.gs_ok(&["current"]);
+    assert_eq!((stdout(&output).as_str(), stderr(&output).as_str()), ("b\n", ""));
+    let output = sandbox.gs_ok(&["current", "--format", "{name} <{email}>"]);
+    assert_eq!(stdout(&output), "b <b@example.com>\n");
+}
+
+#[test]
+fn prompt_prints_nothing_else() {
+    let sandbox = Sandbox::new();
+    let output = sandbox.gs_ok(&["prompt"]);
+    assert_eq!((stdout(&output).as_str(), stderr(&output).as_str()), ("", ""));
+
+    let sandbox = two_profiles();
+    sandbox.gs_ok(&["a"]);
+    let output = sandbox.gs_ok(&["prompt", "--format", "[{icon} {name}]"]);
+    assert_eq!((stdout(&output).as_str(), stderr(&output).as_str()), ("[💼 a]\n", ""));
+}
+
+#[test]
+fn switching_reports_on_stderr() {
+    let sandbox = two_profiles();
+    let output = sandbox.gs_ok(&["a"]);
+    assert_eq!(stdout(&output), "");
+    assert!(stderr(&output).contains("Switched to profile: 💼 a (a@example.com)"), "{}", stderr(&output));
+}
+
+#[test]
+fn list_and_export_print_results() {
+    let sandbox = two_profiles();
+    let output = sandbox.gs_ok(&["list", "--format", "{name}"]);
+    assert_eq!((stdout(&output).as_str(), stderr(&output).as_str()), ("a\nb\n", ""));
+
+    let output = sandbox.gs_ok(&["export"]);
+    assert_eq!(stderr(&output), "");
+    let exported: serde_json::Value = serde_json::from_str(&stdout(&output)).unwrap();
+    assert_eq!(exported["profiles"].as_array().unwrap().len(), 2);
+
+    // To a file, only the confirmation is left, on stderr
+    let file = sandbox.home.join("out.yaml");
+    let output = sandbox.gs_ok(&["export", file.to_str().unwrap()]);
+    assert_eq!(stdout(&output), "");
+    assert!(stderr(&output).starts_with("Exported 2 profiles to"), "{}", stderr(&output));
+    assert!(std::fs::read_to_string(&file).unwrap().contains("name: b"));
+}
+
+#[test]
+fn env_prints_shell_lines() {
+    let sandbox = two_profiles();
+    let output = sandbox.gs_ok(&["env", "a"]);
+    assert_eq!(stderr(&output), "");
+    let lines: Vec<String> = stdout(&output).lines().map(str::to_string).collect();
+    assert!(lines.contains(&"export GIT_AUTHOR_EMAIL='a@example.com'".to_string()), "{:?}", lines);
+    assert!(lines.iter().all(|line| line.starts_with("export ")), "{:?}", lines);
+}
+
+#[test]
+fn doctor_reports_on_stdout() {
+    let sandbox = two_profiles();
+    let output = sandbox.gs_ok(&["doctor"]);
+    assert_eq!(stderr(&output), "");
+    // The sandbox has no known_hosts yet
+    assert!(stdout(&output).contains("has no host key for github.com"), "{}", stdout(&output));
+    assert!(stdout(&output).contains("problem(s) found"), "{}", stdout(&output));
+}
+
+#[test]
+fn doctor_fix_seeds_host_keys_on_the_profile_port_and_jump_host() {
+    let sandbox = Sandbox::new();
+    sandbox.profile("work", &["--host", "git.corp.example", "--ssh-option", "Port 2222"]);
+    sandbox.profile("jumped", &["--host", "git.lab.example", "--ssh-option", "ProxyJump bastion.example"]);
+    let key = std::fs::read_to_string(sandbox.key("host").with_extension("pub")).unwrap();
+    // Prints a key for the last argument, on the port after -p
+    let scan = format!(
+        "port=22\nwhile [ $# -gt 1 ]; do [ \"$1\" = -p ] && port=$2; shift; done\n\
+         if [ $port = 22 ]; then echo \"$1 {key}\"; else echo \"[$1]:$port {key}\"; fi\n",
+        key = key.trim()
+    );
+    sandbox.fake_command("ssh-keyscan", &format!("echo \"ssh-keyscan $*\" >> \"$HOME/calls\"\n{}", scan));
+    sandbox.fake_command("ssh", "echo \"ssh $*\" >> \"$HOME/calls\"\nshift 3\nexec \"$@\"\n");
+
+    let output = sandbox.gs_ok(&["doctor"]);
+    assert!(stdout(&output).contains("has no host key for [git.corp.example]:2222"), "{}", stdout(&output));
+    assert!(stdout(&output).contains("has no host key for git.lab.example;"), "{}", stdout(&output));
+
+    let output = sandbox.gs_ok(&["doctor", "--fix"]);
+    assert!(stdout(&output).contains("Added 1 host key(s) for [git.corp.example]:2222"), "{}", stdout(&output));
+    assert!(stdout(&output).contains("Added 1 host key(s) for git.lab.example"), "{}", stdout(&output));
+    let known_hosts = sandbox.read(".ssh/known_hosts");
+    assert!(known_hosts.contains(&format!("[git.corp.example]:2222 {}", key.trim())), "{}", known_hosts);
+    assert!(known_hosts.contains(&format!("git.lab.example {}", key.trim())), "{}", known_hosts);
+    let calls = sandbox.read("calls");
+    assert!(calls.contains("ssh -o BatchMode=yes ssh://bastion.example ssh-keyscan -T 10 -t ed25519,ecdsa,rsa git.lab.example\n"), "{}", calls);
+    assert!(calls.contains("ssh-keyscan -T 10 -p 2222 -t ed25519,ecdsa,rsa git.corp.example\n"), "{}", calls);
+
+    let output = sandbox.gs_ok(&["doctor"]);
+    assert!(!stdout(&output).contains("corp.example"), "{}", stdout(&output));
+    assert!(!stdout(&output).contains("lab.example"), "{}", stdout(&output));
+}
+
+#[test]
+fn undo_lists_on_stdout_and_reports_on_stderr() {
+    let sandbox = two_profiles();
+    sandbox.gs_ok(&["edit", "a", "--description", "changed"]);
+
+    let output = sandbox.gs_ok(&["undo", "--list"]);
+    assert_eq!(stderr(&output), "");
+    assert!(stdout(&output).contains("before 'gs edit a --description changed'"), "{}", stdout(&output));
+
+    let output = sandbox.gs_ok(&["undo"]);
+    assert_eq!(stdout(&output), "");
+    assert!(stderr(&output).starts_with("Undid 'gs edit a --description changed'"), "{}", stderr(&output));
+}
+
+#[test]
+fn errors_go_to_stderr_only() {
+    let sandbox = two_profiles();
+    let output = sandbox.gs(&["nope"]);
+    assert_eq!(output.status.code(), Some(4));
+    assert_eq!(stdout(&output), "");
+    assert!(stderr(&output).starts_with("Error: "), "{}", stderr(&output));
+}
@@ -0,0 +1,157 @@
+//! A scratch home directory per test, and ways to run gs and git in it.
+//! Every test gets its own, so they run in parallel without sharing any
+//! real config.
+
+#![allow(dead_code)]
+
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output, Stdio};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+pub struct Sandbox {
+    pub home: PathBuf,
+    /// Put first on PATH for gs, for fake commands.
+    pub bin: PathBuf,
+}
+
+impl Sandbox {
+    pub fn new() -> Sandbox {
+        static NEXT: AtomicUsize = AtomicUsize::new(0);
+        let n = NEXT.fetch_add(1, Ordering::SeqCst);
+        let home = std::env::temp_dir().join(format!("gs-it-{}-{}", std::process::id(), n));
+        let _ = fs::remove_dir_all(&home);
+        fs::create_dir_all(home.join(".ssh")).unwrap();
+        let bin = home.join("bin");
+        fs::create_dir_all(&bin).unwrap();
+        Sandbox { home, bin }
+    }
+
+    /// gs with this sandbox as its whole world: home, config directory and
+    /// global git config, and no agent or terminal.
+    pub fn command(&self) -> Command {
+        let mut command = Command::new(env!("CARGO_BIN_EXE_gs"));
+        self.isolate(&mut command);
+        command.stdin(Stdio::null());
+        command
+    }
+
+    fn isolate(&self, command: &mut Command) {
+        let path = std::env::var_os("PATH").unwrap_or_default();
+        let mut paths = vec![self.bin.clone()];
+        paths.extend(std::env::split_paths(&path));
+        command
+            .current_dir(&self.home)
+            .env("HOME", &self.home)
+            .env("XDG_CONFIG_HOME", self.home.join(".config"))
+            .env("PATH", std::env::join_paths(paths).unwrap())
+            .env("GIT_CONFIG_NOSYSTEM", "1")
+            .env("NO_COLOR", "1")
+            .env("RUST_BACKTRACE", "0")
+            .env_remove("GIT_CONFIG_GLOBAL")
+            .env_remove("GS_CONFIG_DIR")
+            .env_remove("GS_PROFILE")
+            .env_remove("GS_ACTIVE_PROFILE")
+            .env_remove("SSH_AUTH_SOCK");
+    }
+
+    pub fn gs(&self, args: &[&str]) -> Output {
+        self.command().args(args).output().unwrap()
+    }
+
+    /// gs with `input` piped to its stdin.
+    pub fn gs_with_input(&self, args: &[&str], input: &str) -> Output {
+        let mut child = self.command().args(args).stdin(Stdio::piped()).stdout(Stdio::piped()).stderr(Stdio::piped()).spawn().unwrap();
+        child.stdin.take().unwrap().write_all(input.as_bytes()).unwrap();
+        child.wait_with_output().unwrap()
+    }
+
+    /// Run gs and fail the test unless it succeeds.
+    pub fn gs_ok(&self, args: &[&str]) -> Output {
+        let output = self.gs(args);
+        assert!(output.status.success(), "gs {:?} failed:\n{}", args, stderr(&output));
+        output
+    }
+
+    /// Real git, in the sandbox's home.
+    pub fn git(&self, args: &[&str]) -> Output {
+        let mut command = Command::new("git");
+        self.isolate(&mut command);
+        command.env("PATH", std::env::var_os("PATH").unwrap_or_default());
+        command.args(args).output().unwrap()
+    }
+
+    /// A global git setting, or None when it isn't set.
+    pub fn git_global(&self, key: &str) -> Option<String> {
+        let output = self.git(&["config", "--global", "--get", key]);
+        output.status.success().then(|| String::from_utf8_lossy(&output.stdout).trim().to_string())
+    }
+
+    /// A new passphrase-less key pair at ~/.ssh/<name>.
+    pub fn key(&self, name: &str) -> PathBuf {
+        let path = self.home.join(".ssh").join(name);
+        let status = Command::new("ssh-keygen")
+            .args(["-q", "-t", "ed25519", "-N", "", "-C", name, "-f"])
+            .arg(&path)
+            .status()
+            .unwrap();
+        assert!(status.success(), "ssh-keygen failed");
+        path
+    }
+
+    /// A profile `name` with its own key and `<name>@example.com`, made
+    /// current like any new profile.
+    pub fn profile(&self, name: &str, extra: &[&str]) {
+        let key = self.key(name);
+        let email = format!("{}@example.com", name);
+        let mut args = vec!["-y", "setup", "--name", name, "--email", &email, "--key", key.to_str().unwrap()];
+        args.extend_from_slice(extra);
+        self.gs_ok(&args);
+    }
+
+    /// A fake command on gs's PATH, as a shell script.
+    pub fn fake_command(&self, name: &str, script: &str) {
+        let path = self.bin.join(name);
+        fs::write(&path, format!("#!/bin/sh\n{}", script)).unwrap();
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            fs::set_permissions(&path, fs::Permissions::from_mode(0o755)).unwrap();
+        }
+    }
+
+    /// Hold the global git config's lock, as a git running at the same
+    /// time would, so writes to it fail.
+    pub fn lock_git_config(&self) {
+        fs::write(self.home.join(".gitconfig.lock"), "").unwrap();
+    }
+
+    pub fn config_dir(&self) -> PathBuf {
+        self.home.join(".config").join("gs")
+    }
+
+    pub fn read(&self, path: impl AsRef<Path>) -> String {
+        fs::read_to_string(self.home.join(path)).unwrap()
+    }
+}
+
+impl Drop for Sandbox {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.home);
+    }
+}
+
+pub fn stdout(output: &Output) -> String {
+    String::from_utf8_lossy(&output.stdout).into_owned()
+}
+
+pub fn stderr(output: &Output) -> String {
+    String::from_utf8_lossy(&output.stderr).into_owned()
+}
+
+/// Where the real git lives, for fakes that hand most calls on to it.
+pub fn real_git() -> PathBuf {
+    let path = std::env::var_os("PATH").unwrap_or_default();
+    std::env::split_paths(&path).map(|dir| dir.join("git")).find(|git| git.is_file()).expect("git is on PATH")
+}
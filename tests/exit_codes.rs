@@ -0,0 +1,167 @@
+//! The exit code and `--json` error for each kind of failure. Scripts rely
+//! on these; see the Exit codes section of the README.
+
+mod common;
+
+use common::{stderr, stdout, Sandbox};
+
+/// The `--json` error gs printed last on stderr.
+fn json_error(output: &std::process::Output) -> serde_json::Value {
+    let text = stderr(output);
+    let line = text.lines().last().unwrap_or_default();
+    serde_json::from_str(line).unwrap_or_else(|err| panic!("no JSON error in {:?}: {}", text, err))
+}
+
+/// A repository in the sandbox with one commit by `email`.
+fn repo_with_commit(sandbox: &Sandbox, name: &str, email: &str) -> std::path::PathBuf {
+    let repo = sandbox.home.join(name);
+    std::fs::create_dir_all(&repo).unwrap();
+    let dir = repo.to_str().unwrap();
+    for args in [
+        vec!["-C", dir, "init", "-q"],
+        vec!["-C", dir, "-c", &format!("user.email={}", email), "-c", "user.name=x", "commit", "-q", "--allow-empty", "-m", "x"],
+    ] {
+        assert!(sandbox.git(&args).status.success());
+    }
+    repo
+}
+
+#[test]
+fn verify_commits_with_unexpected_identities_is_an_error() {
+    let sandbox = Sandbox::new();
+    sandbox.profile("a", &[]);
+    let repo = repo_with_commit(&sandbox, "theirs", "stranger@example.com");
+
+    let output = sandbox.command().current_dir(&repo).arg("verify-commits").output().unwrap();
+    assert_eq!(output.status.code(), Some(1));
+    assert!(stdout(&output).contains("stranger@example.com"), "{}", stdout(&output));
+    assert!(stderr(&output).contains("Error: 1 of the last 1 commits use an identity other than a known profile"), "{}", stderr(&output));
+
+    let output = sandbox.command().current_dir(&repo).args(["--json", "verify-commits"]).output().unwrap();
+    assert_eq!(output.status.code(), Some(1));
+    assert_eq!(json_error(&output)["code"], "error");
+    assert_eq!(json_error(&output)["exit_code"], 1);
+
+    let repo = repo_with_commit(&sandbox, "ours", "a@example.com");
+    let output = sandbox.command().current_dir(&repo).arg("verify-commits").output().unwrap();
+    assert_eq!(output.status.code(), Some(0), "{}", stderr(&output));
+}
+
+#[test]
+fn check_repo_mismatch_is_an_error() {
+    let sandbox = Sandbox::new();
+    sandbox.profile("a", &[]);
+    let repo = repo_with_commit(&sandbox, "repo", "a@example.com");
+    let dir = repo.to_str().unwrap();
+    sandbox.git(&["-C", dir, "config", "user.email", "someone@example.com"]);
+    sandbox.git(&["-C", dir, "config", "gs.profile", "a"]);
+
+    let output = sandbox.command().current_dir(&repo).args(["--json", "check-repo"]).output().unwrap();
+    assert_eq!(output.status.code(), Some(1));
+    let error = json_error(&output);
+    assert_eq!(error["message"], "Wrong git identity: this repository expects profile 'a' <a@example.com>");
+    assert_eq!(error["profile"], "a");
+
+    sandbox.git(&["-C", dir, "config", "user.email", "a@example.com"]);
+    let output = sandbox.command().current_dir(&repo).arg("check-repo").output().unwrap();
+    assert_eq!(output.status.code(), Some(0), "{}", stderr(&output));
+}
+
+#[test]
+fn failed_auth_test_is_an_error() {
+    let sandbox = Sandbox::new();
+    sandbox.profile("a", &[]);
+    // An ssh that always fails, rather than one that tries the network
+    sandbox.fake_command("ssh", "echo 'ssh: connect to host github.com port 22: Connection refused' >&2\nexit 255\n");
+
+    let output = sandbox.gs(&["--json", "test", "a", "--timeout", "5"]);
+    assert_eq!(output.status.code(), Some(1), "{}", stderr(&output));
+    assert_eq!(json_error(&output)["message"], "Authentication failed for a");
+    assert!(stdout(&output).contains("FAIL"), "{}", stdout(&output));
+}
+
+#[test]
+fn each_kind_of_failure_has_its_code() {
+    let sandbox = Sandbox::new();
+    let cases: &[(&[&str], i32, &str)] = &[(&[], 3, "no_profiles")];
+    for (args, code, name) in cases {
+        let mut json_args = vec!["--json"];
+        json_args.extend_from_slice(args);
+        let output = sandbox.gs(&json_args);
+        assert_eq!(output.status.code(), Some(*code), "gs {:?}: {}", args, stderr(&output));
+        assert_eq!(json_error(&output)["code"], *name, "gs {:?}", args);
+    }
+
+    sandbox.profile("work", &[]);
+    sandbox.profile("web", &[]);
+    // A profile whose key has since been deleted
+    sandbox.profile("gone", &[]);
+    std::fs::remove_file(sandbox.home.join(".ssh/gone")).unwrap();
+    let missing = sandbox.home.join(".ssh/missing");
+    let missing = missing.to_str().unwrap();
+    let key = sandbox.home.join(".ssh/work");
+    let key = key.to_str().unwrap();
+    let yaml = sandbox.home.join("profiles.yaml");
+    std::fs::write(&yaml, "work:\n  email: x@example.com\n").unwrap();
+    let yaml = yaml.to_str().unwrap();
+    let cases: &[(&[&str], i32, &str)] = &[
+        // A profile, key or file that doesn't exist
+        (&["nope"], 4, "not_found"),
+        (&["edit", "nope", "--email", "x@example.com"], 4, "not_found"),
+        (&["setup", "--name", "x", "--email", "x@example.com", "--key", missing], 4, "not_found"),
+        (&["edit", "work", "--key", missing], 4, "not_found"),
+        (&["edit", "work", "--excludes-file", missing], 4, "not_found"),
+        (&["exec", "gone", "--", "true"], 4, "not_found"),
+        (&["key", "rotate", "gone"], 4, "not_found"),
+        // A name that's taken
+        (&["setup", "--name", "WORK", "--email", "x@example.com", "--key", key, "--allow-shared-key"], 1, "error"),
+        (&["rename", "web", "work"], 1, "error"),
+        (&["copy", "web", "Work"], 1, "error"),
+        // Bad input
+        (&["w"], 2, "usage"),
+        (&["setup", "--name", "x"], 2, "usage"),
+        (&["edit", "work", "--email", "not an email"], 2, "usage"),
+        (&["migrate-from", yaml], 2, "usage"),
+        (&["rename", "web", "list"], 2, "usage"),
+        (&["verify-commits"], 1, "error"),
+    ];
+    for (args, code, name) in cases {
+        let mut json_args = vec!["--json"];
+        json_args.extend_from_slice(args);
+        let output = sandbox.gs(&json_args);
+        assert_eq!(output.status.code(), Some(*code), "gs {:?}: {}", args, stderr(&output));
+        assert_eq!(json_error(&output)["code"], *name, "gs {:?}", args);
+    }
+
+    // Input that runs out is an abort
+    let output = sandbox.gs_with_input(&["setup"], "new\n");
+    assert_eq!(output.status.code(), Some(5), "{}", stderr(&output));
+}
+
+#[test]
+fn interrupt_at_a_prompt_is_an_abort() {
+    use std::io::{BufRead, BufReader, Write};
+    use std::process::Stdio;
+
+    let sandbox = Sandbox::new();
+    let mut child = sandbox.command().arg("setup").stdin(Stdio::piped()).stderr(Stdio::piped()).spawn().unwrap();
+    let mut input = child.stdin.take().unwrap();
+    input.write_all(b"work\n").unwrap();
+
+    // The first answer is echoed once it's read; the next question is then
+    // waiting for input that never comes
+    let mut echo = BufReader::new(child.stderr.take().unwrap());
+    let mut line = String::new();
+    while !line.trim_end().ends_with("work") {
+        line.clear();
+        assert_ne!(echo.read_line(&mut line).unwrap(), 0, "gs exited before the second question");
+    }
+    std::thread::sleep(std::time::Duration::from_millis(200));
+    let kill = std::process::Command::new("kill").args(["-INT", &child.id().to_string()]).status().unwrap();
+    assert!(kill.success());
+
+    let status = child.wait().unwrap();
+    drop(input);
+    assert_eq!(status.code(), Some(5));
+    assert!(!sandbox.config_dir().join("profiles.json").exists());
+}
@@ -0,0 +1,73 @@
+//! Which global git config gs reports and writes: the one git itself would
+//! use.
+
+mod common;
+
+use common::{stderr, stdout, Sandbox};
+use std::path::PathBuf;
+use std::process::Output;
+
+/// gs with `GIT_CONFIG_GLOBAL` set to `global`, when given.
+fn gs(sandbox: &Sandbox, global: Option<&PathBuf>, args: &[&str]) -> Output {
+    let mut command = sandbox.command();
+    if let Some(global) = global {
+        command.env("GIT_CONFIG_GLOBAL", global);
+    }
+    let output = command.args(args).output().unwrap();
+    assert!(output.status.success(), "gs {:?} failed:\n{}", args, stderr(&output));
+    output
+}
+
+/// The global git config and where it came from, as `gs paths` reports them.
+fn reported(sandbox: &Sandbox, global: Option<&PathBuf>) -> (PathBuf, String) {
+    let paths: serde_json::Value = serde_json::from_str(&stdout(&gs(sandbox, global, &["paths", "--json"]))).unwrap();
+    (PathBuf::from(paths["git_config"].as_str().unwrap()), paths["git_config_source"].as_str().unwrap().to_string())
+}
+
+/// Make a profile, which switches to it, and return the global config
+/// files that exist afterwards.
+fn switch(sandbox: &Sandbox, global: Option<&PathBuf>) -> Vec<PathBuf> {
+    let key = sandbox.key("a");
+    gs(sandbox, global, &["-y", "setup", "--name", "a", "--email", "a@example.com", "--key", key.to_str().unwrap()]);
+    let candidates = [sandbox.home.join(".gitconfig"), sandbox.home.join(".config/git/config"), sandbox.home.join("work-config")];
+    candidates.into_iter().filter(|path| path.exists()).collect()
+}
+
+#[test]
+fn git_config_global_comes_first() {
+    let sandbox = Sandbox::new();
+    let global = sandbox.home.join("work-config");
+    std::fs::write(sandbox.home.join(".gitconfig"), "[user]\n\tname = home\n").unwrap();
+
+    assert_eq!(reported(&sandbox, Some(&global)), (global.clone(), "GIT_CONFIG_GLOBAL".to_string()));
+    assert_eq!(switch(&sandbox, Some(&global)), [sandbox.home.join(".gitconfig"), global.clone()]);
+    assert!(std::fs::read_to_string(&global).unwrap().contains("a@example.com"));
+    assert_eq!(sandbox.read(".gitconfig"), "[user]\n\tname = home\n");
+}
+
+#[test]
+fn xdg_config_is_used_without_a_gitconfig() {
+    let sandbox = Sandbox::new();
+    let xdg = sandbox.home.join(".config/git/config");
+    std::fs::create_dir_all(xdg.parent().unwrap()).unwrap();
+    std::fs::write(&xdg, "").unwrap();
+
+    assert_eq!(reported(&sandbox, None), (xdg.clone(), "XDG, no ~/.gitconfig".to_string()));
+    assert_eq!(switch(&sandbox, None), std::slice::from_ref(&xdg));
+    assert!(std::fs::read_to_string(&xdg).unwrap().contains("a@example.com"));
+
+    // Once there is a ~/.gitconfig it wins, as it does for git
+    std::fs::write(sandbox.home.join(".gitconfig"), "").unwrap();
+    assert_eq!(reported(&sandbox, None), (sandbox.home.join(".gitconfig"), "default".to_string()));
+}
+
+#[test]
+fn gitconfig_is_the_default() {
+    let sandbox = Sandbox::new();
+    let gitconfig = sandbox.home.join(".gitconfig");
+
+    assert_eq!(reported(&sandbox, None), (gitconfig.clone(), "default, not created yet".to_string()));
+    assert_eq!(switch(&sandbox, None), std::slice::from_ref(&gitconfig));
+    assert_eq!(reported(&sandbox, None), (gitconfig, "default".to_string()));
+    assert_eq!(sandbox.git_global("user.email").as_deref(), Some("a@example.com"));
+}
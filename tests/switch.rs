@@ -0,0 +1,237 @@
+//! Switching profiles end to end: what lands in the global git config and
+//! SSH config, and what's put back when a switch fails.
+
+mod common;
+
+use common::{real_git, stderr, stdout, Sandbox};
+
+#[test]
+fn failed_git_write_restores_git_and_ssh_config() {
+    let sandbox = Sandbox::new();
+    sandbox.profile("b", &["--git-name", "B Person"]);
+    sandbox.profile("a", &[]);
+    let ssh_before = sandbox.read(".ssh/config");
+
+    sandbox.lock_git_config();
+    let output = sandbox.gs(&["--json", "b"]);
+    assert_eq!(output.status.code(), Some(6), "{}", stderr(&output));
+    let error: serde_json::Value = serde_json::from_str(stderr(&output).lines().last().unwrap()).unwrap();
+    assert_eq!(error["exit_code"], 6);
+    assert!(error["causes"][0].as_str().unwrap().starts_with("could not lock config file"), "{}", error);
+    assert_eq!(sandbox.git_global("user.name").as_deref(), Some("a"));
+    assert_eq!(sandbox.git_global("user.email").as_deref(), Some("a@example.com"));
+    assert_eq!(sandbox.read(".ssh/config"), ssh_before);
+    assert!(sandbox.read(".config/gs/state.json").contains("\"current\": \"a\""));
+}
+
+#[cfg(unix)]
+#[test]
+fn git_failures_carry_what_git_said() {
+    let sandbox = Sandbox::new();
+    sandbox.profile("b", &[]);
+    sandbox.profile("a", &[]);
+
+    // Reading the global config fails before anything is written
+    sandbox.fake_command(
+        "git",
+        &format!(
+            "case \"$*\" in *--global*--list*) echo \"fatal: bad config line 3 in file $HOME/.gitconfig\" >&2; exit 128;; esac\nexec '{}' \"$@\"\n",
+            real_git().display()
+        ),
+    );
+    let output = sandbox.gs(&["b"]);
+    assert_eq!(output.status.code(), Some(6), "{}", stderr(&output));
+    assert!(stderr(&output).contains("git config --global --list failed: fatal: bad config line 3"), "{}", stderr(&output));
+    assert_eq!(sandbox.git_global("user.email").as_deref(), Some("a@example.com"));
+    assert!(sandbox.read(".config/gs/state.json").contains("\"current\": \"a\""));
+}
+
+/// Give profile `name` an insteadOf rule. There's no flag for these; hand
+/// edits are how they're made.
+fn rewrite_urls(sandbox: &Sandbox, name: &str, base: &str, instead_of: &str) {
+    let mut profiles: serde_json::Value = serde_json::from_str(&sandbox.read(".config/gs/profiles.json")).unwrap();
+    for profile in profiles["profiles"].as_array_mut().unwrap() {
+        if profile["name"] == name {
+            profile["url_rewrites"] = serde_json::json!([{"base": base, "instead_of": instead_of}]);
+        }
+    }
+    std::fs::write(sandbox.config_dir().join("profiles.json"), serde_json::to_string_pretty(&profiles).unwrap()).unwrap();
+}
+
+/// The global settings a switch to each kind of profile leaves behind.
+fn settings(sandbox: &Sandbox, keys: &[&str]) -> Vec<Option<String>> {
+    keys.iter().map(|key| sandbox.git_global(key)).collect()
+}
+
+#[test]
+fn optional_settings_follow_the_profile() {
+    let sandbox = Sandbox::new();
+    let home = sandbox.home.to_str().unwrap().to_string();
+    for file in ["ignore-work", "ignore-oss", "msg-work", "msg-oss"] {
+        std::fs::write(sandbox.home.join(file), "").unwrap();
+    }
+    sandbox.profile("plain", &[]);
+    sandbox.profile("oss", &["--excludes-file", "~/ignore-oss", "--commit-template", "~/msg-oss", "--default-branch", "trunk"]);
+    sandbox.profile(
+        "work",
+        &["--signing", "true", "--excludes-file", "~/ignore-work", "--commit-template", "~/msg-work", "--default-branch", "main"],
+    );
+    // Gone by the time oss is switched to, which is only worth a warning
+    std::fs::remove_file(sandbox.home.join("ignore-oss")).unwrap();
+    rewrite_urls(&sandbox, "work", "git@gitlab.corp.example:", "https://gitlab.corp.example/");
+    rewrite_urls(&sandbox, "oss", "git@codeberg.org:", "https://codeberg.org/");
+
+    let keys = [
+        "core.excludesFile",
+        "commit.template",
+        "init.defaultBranch",
+        "gpg.format",
+        "user.signingkey",
+        "commit.gpgsign",
+        "url.git@gitlab.corp.example:.insteadOf",
+        "url.git@codeberg.org:.insteadOf",
+    ];
+    let some = |value: &str| Some(value.replace('~', &home));
+    let plain = vec![None; keys.len()];
+    let work = vec![
+        some("~/ignore-work"),
+        some("~/msg-work"),
+        some("main"),
+        some("ssh"),
+        some("~/.ssh/work.pub"),
+        some("true"),
+        some("https://gitlab.corp.example/"),
+        None,
+    ];
+    let oss = vec![some("~/ignore-oss"), some("~/msg-oss"), some("trunk"), None, None, None, None, some("https://codeberg.org/")];
+
+    // unset → set, set → different, set → unset, and back
+    for (name, expected) in [("plain", &plain), ("work", &work), ("oss", &oss), ("plain", &plain), ("oss", &oss), ("work", &work), ("plain", &plain)] {
+        let output = sandbox.gs_ok(&[name]);
+        assert_eq!(settings(&sandbox, &keys), *expected, "after switching to {}", name);
+        let warned = stderr(&output).contains("core.excludesFile points to");
+        assert_eq!(warned, name == "oss", "warning for a missing excludes file: {}", stderr(&output));
+    }
+    let listed = stdout(&sandbox.gs_ok(&["list", "--long"]));
+    assert!(listed.contains("Default branch: trunk") && listed.contains("Default branch: main"), "{}", listed);
+}
+
+#[test]
+fn profile_for_another_host_leaves_github_alone() {
+    let sandbox = Sandbox::new();
+    let github = "Host github.com\n    HostName github.com\n    IdentityFile ~/.ssh/personal\n";
+    std::fs::write(sandbox.home.join(".ssh/config"), github).unwrap();
+    sandbox.profile("work", &["--host", "codeberg.org"]);
+
+    let ssh = sandbox.read(".ssh/config");
+    assert!(ssh.starts_with(github), "{}", ssh);
+    assert!(ssh.contains("Host codeberg.org"), "{}", ssh);
+    assert!(ssh.contains(".ssh/work"), "{}", ssh);
+    assert_eq!(ssh.matches(".ssh/work").count(), 1, "{}", ssh);
+}
+
+#[test]
+fn failed_apply_saves_nothing() {
+    let sandbox = Sandbox::new();
+    sandbox.profile("a", &[]);
+    let profiles_before = sandbox.read(".config/gs/profiles.json");
+    let ssh_before = sandbox.read(".ssh/config");
+
+    // Writing b's email fails, and so does a's new one
+    sandbox.lock_git_config();
+    let key = sandbox.key("b");
+    let output = sandbox.gs(&["-y", "setup", "--name", "b", "--email", "b@example.com", "--key", key.to_str().unwrap()]);
+    assert_eq!(output.status.code(), Some(6), "{}", stderr(&output));
+    assert_eq!(stdout(&sandbox.gs_ok(&["current"])), "a\n");
+    assert_eq!(sandbox.read(".config/gs/profiles.json"), profiles_before);
+    assert_eq!(sandbox.read(".ssh/config"), ssh_before);
+    assert_eq!(sandbox.git_global("user.email").as_deref(), Some("a@example.com"));
+
+    let output = sandbox.gs(&["edit", "a", "--email", "new@example.com"]);
+    assert_eq!(output.status.code(), Some(6), "{}", stderr(&output));
+    assert_eq!(sandbox.read(".config/gs/profiles.json"), profiles_before);
+    assert_eq!(sandbox.git_global("user.email").as_deref(), Some("a@example.com"));
+}
+
+/// The insteadOf rules gs considers its own.
+fn managed_rewrites(sandbox: &Sandbox) -> serde_json::Value {
+    std::fs::read_to_string(sandbox.config_dir().join("managed-rewrites.json"))
+        .map_or(serde_json::json!([]), |data| serde_json::from_str(&data).unwrap())
+}
+
+#[test]
+fn rollback_restores_git_ssh_and_managed_rewrites() {
+    let sandbox = Sandbox::new();
+    let rule = "url.git@gitlab.corp.example:.insteadOf";
+    sandbox.profile("plain", &["--host", "gitlab.corp.example", "--ssh-user", "git"]);
+    sandbox.profile("work", &[]);
+    rewrite_urls(&sandbox, "work", "git@gitlab.corp.example:", "https://gitlab.corp.example/");
+    sandbox.gs_ok(&["plain"]);
+    let ssh_at_plain = sandbox.read(".ssh/config");
+
+    // Rolling back a switch that added a rule takes it out of the managed list
+    sandbox.gs_ok(&["work"]);
+    assert_eq!(sandbox.git_global(rule).as_deref(), Some("https://gitlab.corp.example/"));
+    assert_eq!(managed_rewrites(&sandbox).as_array().unwrap().len(), 1);
+    sandbox.gs_ok(&["rollback"]);
+    assert_eq!(sandbox.git_global(rule), None);
+    assert_eq!(managed_rewrites(&sandbox), serde_json::json!([]));
+    assert_eq!(sandbox.git_global("user.email").as_deref(), Some("plain@example.com"));
+    assert_eq!(sandbox.read(".ssh/config"), ssh_at_plain);
+    assert!(stdout(&sandbox.gs(&["current"])).is_empty());
+
+    // Nothing is left to roll back
+    let output = sandbox.gs(&["rollback"]);
+    assert_eq!(output.status.code(), Some(4), "{}", stderr(&output));
+
+    // Rolling back a switch that removed one makes it managed again, so
+    // the next switch away removes it
+    sandbox.gs_ok(&["work"]);
+    sandbox.gs_ok(&["plain"]);
+    assert_eq!(sandbox.git_global(rule), None);
+    sandbox.gs_ok(&["rollback"]);
+    assert_eq!(sandbox.git_global(rule).as_deref(), Some("https://gitlab.corp.example/"));
+    assert_eq!(managed_rewrites(&sandbox).as_array().unwrap().len(), 1);
+    sandbox.gs_ok(&["plain"]);
+    assert_eq!(sandbox.git_global(rule), None);
+}
+
+#[test]
+fn user_rewrite_rules_survive_a_switch_away() {
+    let sandbox = Sandbox::new();
+    let rule = "url.git@gitlab.corp.example:.insteadOf";
+    sandbox.profile("plain", &[]);
+    sandbox.profile("work", &[]);
+    rewrite_urls(&sandbox, "work", "git@gitlab.corp.example:", "https://gitlab.corp.example/");
+    // The user wrote the same rule themselves before gs ever would
+    assert!(sandbox.git(&["config", "--global", "--add", rule, "https://gitlab.corp.example/"]).status.success());
+
+    sandbox.gs_ok(&["work"]);
+    assert_eq!(managed_rewrites(&sandbox), serde_json::json!([]));
+    sandbox.gs_ok(&["plain"]);
+    assert_eq!(sandbox.git_global(rule).as_deref(), Some("https://gitlab.corp.example/"));
+    sandbox.gs_ok(&["work"]);
+    sandbox.gs_ok(&["plain"]);
+    assert_eq!(sandbox.git_global(rule).as_deref(), Some("https://gitlab.corp.example/"));
+}
+
+#[cfg(unix)]
+#[test]
+fn switch_writes_the_global_config_in_one_go() {
+    let sandbox = Sandbox::new();
+    sandbox.profile("plain", &[]);
+    sandbox.profile("work", &["--signing", "true", "--default-branch", "main"]);
+    rewrite_urls(&sandbox, "work", "git@gitlab.corp.example:", "https://gitlab.corp.example/");
+    sandbox.gs_ok(&["plain"]);
+
+    // Every git gs runs, in order
+    sandbox.fake_command("git", &format!("echo \"$*\" >> \"$HOME/git-calls\"\nexec '{}' \"$@\"\n", real_git().display()));
+    sandbox.gs_ok(&["work"]);
+    let calls = sandbox.read("git-calls");
+    // Once before, once after for `gs rollback`, and no writes
+    assert_eq!(calls, "config --global --list -z\nconfig --global --list -z\n");
+    assert_eq!(
+        settings(&sandbox, &["user.email", "commit.gpgsign", "init.defaultBranch", "url.git@gitlab.corp.example:.insteadOf"]),
+        [Some("work@example.com"), Some("true"), Some("main"), Some("https://gitlab.corp.example/")].map(|v| v.map(str::to_string))
+    );
+}
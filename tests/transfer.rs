@@ -0,0 +1,174 @@
+//! Moving profiles between machines with export and import.
+
+mod common;
+
+use common::{stderr, stdout, Sandbox};
+
+/// profiles.json with every field a profile can have, and one this
+/// version doesn't know.
+const PROFILES: &str = r##"{
+  "version": 1,
+  "profiles": [
+    {
+      "name": "work",
+      "git_name": "Jane Doe",
+      "email": "jane@corp.example",
+      "ssh_key": "~/.ssh/work",
+      "description": "Day job",
+      "group": "office",
+      "hosts": {
+        "gitlab.corp.example": "~/.ssh/work"
+      },
+      "ssh_users": {
+        "gitlab.corp.example": "gitlab"
+      },
+      "url_rewrites": [
+        {
+          "base": "git@gitlab.corp.example:",
+          "instead_of": "https://gitlab.corp.example/"
+        }
+      ],
+      "commit_template": "~/.gitmessage",
+      "excludes_file": "~/.gitignore_work",
+      "default_branch": "main",
+      "signing": true,
+      "icon": "💼",
+      "color": "#ff8800",
+      "ssh_options": [
+        "ProxyJump bastion"
+      ],
+      "identity_agent": "~/.1password/agent.sock",
+      "username": "jdoe",
+      "created_at": "2026-01-02T03:04:05Z",
+      "updated_at": "2026-02-03T04:05:06Z",
+      "default": true,
+      "from_the_future": {
+        "nested": [1, 2]
+      }
+    },
+    {
+      "name": "home",
+      "email": "jane@home.example",
+      "ssh_key": "~/.ssh/home",
+      "created_at": "2026-01-01T00:00:00Z"
+    }
+  ]
+}
+"##;
+
+fn exported(sandbox: &Sandbox) -> serde_json::Value {
+    let output = sandbox.gs_ok(&["export", "--format", "json"]);
+    serde_json::from_str(&stdout(&output)).unwrap()
+}
+
+fn round_trip(extension: &str, format: Option<&str>) {
+    let from = Sandbox::new();
+    from.key("work");
+    from.key("home");
+    std::fs::create_dir_all(from.config_dir()).unwrap();
+    std::fs::write(from.config_dir().join("profiles.json"), PROFILES).unwrap();
+    let file = from.home.join(format!("profiles.{}", extension));
+    let mut args = vec!["export", file.to_str().unwrap()];
+    args.extend(format.iter().flat_map(|format| ["--format", format]));
+    from.gs_ok(&args);
+
+    let to = Sandbox::new();
+    to.key("work");
+    to.key("home");
+    let mut args = vec!["import", file.to_str().unwrap()];
+    args.extend(format.iter().flat_map(|format| ["--format", format]));
+    let output = to.gs(&args);
+    assert!(output.status.success(), "{}", stderr(&output));
+
+    assert_eq!(exported(&to), exported(&from), "{}", std::fs::read_to_string(&file).unwrap());
+    assert_eq!(exported(&to)["profiles"][0]["from_the_future"]["nested"][1], 2);
+}
+
+#[test]
+fn yaml_round_trip_keeps_every_field() {
+    round_trip("yaml", None);
+    round_trip("yml", None);
+}
+
+#[test]
+fn json_round_trip_keeps_every_field() {
+    round_trip("json", None);
+}
+
+#[test]
+fn format_flag_overrides_the_extension() {
+    round_trip("txt", Some("yaml"));
+    round_trip("txt", Some("json"));
+}
+
+/// A sandbox with profiles `home` and `work`, `work` current, and a file
+/// to import that has its own `work`, `home` and `new`.
+fn conflicting_import() -> (Sandbox, String) {
+    let sandbox = Sandbox::new();
+    sandbox.profile("home", &[]);
+    sandbox.profile("work", &[]);
+    sandbox.key("new");
+    let file = sandbox.home.join("import.json");
+    let profiles = ["work", "home", "new"].map(|name| {
+        serde_json::json!({"name": name, "email": format!("{}@imported.example", name), "ssh_key": format!("~/.ssh/{}", name)})
+    });
+    std::fs::write(&file, serde_json::json!({"version": 1, "profiles": profiles}).to_string()).unwrap();
+    (sandbox, file.to_str().unwrap().to_string())
+}
+
+fn emails(sandbox: &Sandbox) -> String {
+    stdout(&sandbox.gs_ok(&["list", "--format", "{name} <{email}>"]))
+}
+
+fn current(sandbox: &Sandbox) -> String {
+    stdout(&sandbox.gs_ok(&["current"]))
+}
+
+#[test]
+fn conflicts_can_be_skipped() {
+    let (sandbox, file) = conflicting_import();
+    let output = sandbox.gs_ok(&["import", &file, "--on-conflict", "skip"]);
+    assert!(stderr(&output).contains("Skipped work (kept the local profile)"), "{}", stderr(&output));
+    assert_eq!(emails(&sandbox), "home <home@example.com>\nwork <work@example.com>\nnew <new@imported.example>\n");
+    assert_eq!(sandbox.git_global("user.email").as_deref(), Some("work@example.com"));
+    assert_eq!(current(&sandbox), "work\n");
+}
+
+#[test]
+fn conflicts_can_be_overwritten() {
+    let (sandbox, file) = conflicting_import();
+    let output = sandbox.gs_ok(&["import", &file, "--on-conflict", "overwrite"]);
+    assert!(stderr(&output).contains("Overwrote work with the imported profile"), "{}", stderr(&output));
+    assert_eq!(emails(&sandbox), "home <home@imported.example>\nwork <work@imported.example>\nnew <new@imported.example>\n");
+    // The current profile changed, so it's applied again
+    assert_eq!(sandbox.git_global("user.email").as_deref(), Some("work@imported.example"));
+    assert_eq!(current(&sandbox), "work\n");
+}
+
+#[test]
+fn conflicts_can_be_renamed() {
+    let (sandbox, file) = conflicting_import();
+    let output = sandbox.gs_ok(&["import", &file, "--on-conflict", "rename"]);
+    assert!(stderr(&output).contains("Imported work as work-imported"), "{}", stderr(&output));
+    assert_eq!(
+        emails(&sandbox),
+        "home <home@example.com>\nwork <work@example.com>\nwork-imported <work@imported.example>\n\
+         home-imported <home@imported.example>\nnew <new@imported.example>\n"
+    );
+    assert_eq!(sandbox.git_global("user.email").as_deref(), Some("work@example.com"));
+    assert_eq!(current(&sandbox), "work\n");
+}
+
+#[test]
+fn overwriting_the_current_profile_imports_nothing_if_it_cant_be_applied() {
+    let (sandbox, file) = conflicting_import();
+    let profiles_before = sandbox.read(".config/gs/profiles.json");
+    sandbox.lock_git_config();
+
+    let output = sandbox.gs(&["import", &file, "--on-conflict", "overwrite"]);
+    assert_eq!(output.status.code(), Some(6), "{}", stderr(&output));
+    assert!(stderr(&output).contains("nothing was imported"), "{}", stderr(&output));
+    assert_eq!(sandbox.read(".config/gs/profiles.json"), profiles_before);
+    assert_eq!(sandbox.git_global("user.email").as_deref(), Some("work@example.com"));
+    assert_eq!(current(&sandbox), "work\n");
+}